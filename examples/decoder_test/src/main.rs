@@ -1,19 +1,19 @@
+//! Decodes `cchlop.nv12`, a 1920x1080 NV12 frame, and saves it as `cchlop_out_nv12.png`.
+//!
+//! The decoders are checked against golden images by the tests in `nokhwa-core/tests`.
+
+use image::Rgb;
+use nokhwa_core::decoder::yuv::PlanarYuvDecoder;
+use nokhwa_core::decoder::StaticDecoder;
 use nokhwa_core::frame_buffer::FrameBuffer;
-use nokhwa_core::pixel_format::RgbFormat;
-use nokhwa_core::types::{FrameFormat, Resolution};
-use std::fs::File;
-use std::io::Read;
+use nokhwa_core::frame_format::FrameFormat;
+use nokhwa_core::types::Resolution;
 
 fn main() {
-    let mut nv12 = Vec::new();
-    File::open("cchlop.nv12")
-        .unwrap()
-        .read_to_end(&mut nv12)
-        .unwrap();
+    let nv12 = std::fs::read("cchlop.nv12").unwrap();
 
-    let buffer = FrameBuffer::new(Resolution::new(1920, 1080), &nv12, FrameFormat::NV12);
-    buffer
-        .decode_image::<RgbFormat>()
+    let buffer = FrameBuffer::new(Resolution::new(1920, 1080), &nv12, FrameFormat::Nv12);
+    PlanarYuvDecoder::<Rgb<u8>>::decode_static(&buffer)
         .unwrap()
         .save("cchlop_out_nv12.png")
         .unwrap();
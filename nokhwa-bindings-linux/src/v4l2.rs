@@ -13,7 +13,7 @@ use nokhwa_core::camera::{Camera, Open, Setting, Capture};
 use nokhwa_core::properties::{CameraProperties, CameraPropertyFlag, CameraPropertyId, CameraPropertyValue};
 use nokhwa_core::{define_back_and_fourth_control, define_back_and_fourth_frame_format};
 use nokhwa_core::error::{NokhwaError, NokhwaResult};
-use nokhwa_core::frame_format::FrameFormat;
+use nokhwa_core::frame_format::{BayerPattern, FrameFormat};
 use nokhwa_core::types::{CameraFormat, CameraIndex, CameraInformation, FrameRate, Resolution};
 
 const NULL_FCC: &'static [u8; 4] = &[0x00, 0x00, 0x00, 0x00];
@@ -55,7 +55,12 @@ define_back_and_fourth_frame_format!([u8;4], {
     FrameFormat::Yv12 => b"YV12",
     FrameFormat::I420 => b"YU12",
     FrameFormat::Yvu9 => b"YVU9",
+    // V4L2_PIX_FMT_P010. V4L2 has no FourCC for P016, so it is not mapped.
+    FrameFormat::P010 => b"P010",
     FrameFormat::Luma8 => b"GREY",
+    FrameFormat::Luma10 => b"Y10 ",
+    FrameFormat::Luma12 => b"Y12 ",
+    FrameFormat::Luma10Packed => b"Y10P",
     FrameFormat::Luma16 => b"Y16 ",
     FrameFormat::Depth16 => b"Z16 ",
     FrameFormat::Rgb332 => b"RGB3",
@@ -63,8 +68,14 @@ define_back_and_fourth_frame_format!([u8;4], {
     FrameFormat::ARgb8888 => b"BA24",
     FrameFormat::Rgb555 => b"RX15",
     FrameFormat::Rgb565 => b"RGBP",
-    FrameFormat::Bayer8 => b"BA81",
-    FrameFormat::Bayer16 => b"BYR2",
+    FrameFormat::Bayer8(BayerPattern::Bggr) => b"BA81",
+    FrameFormat::Bayer8(BayerPattern::Gbrg) => b"GBRG",
+    FrameFormat::Bayer8(BayerPattern::Grbg) => b"GRBG",
+    FrameFormat::Bayer8(BayerPattern::Rggb) => b"RGGB",
+    FrameFormat::Bayer16(BayerPattern::Bggr) => b"BYR2",
+    FrameFormat::Bayer16(BayerPattern::Gbrg) => b"GB16",
+    FrameFormat::Bayer16(BayerPattern::Grbg) => b"GR16",
+    FrameFormat::Bayer16(BayerPattern::Rggb) => b"RG16",
 }, func_u8_8_to_fcc, func_fcc_to_u8_8, value_to_fcc_type);
 
 fn linux_id_to_str(id: u32) -> String {
@@ -0,0 +1,198 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Reusable frame memory for backends.
+//!
+//! A backend draws a [`PooledBuffer`] from a [`BufferPool`], fills it and turns it into a
+//! [`FrameBuffer`](crate::frame_buffer::FrameBuffer) without copying. Once every clone of that
+//! frame has been dropped, the memory goes back into the pool for the next frame.
+
+use bytes::Bytes;
+use std::{
+    fmt::{Debug, Formatter},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, Weak},
+};
+
+#[derive(Debug)]
+struct PoolInner {
+    free: Mutex<Vec<Vec<u8>>>,
+    max_pooled: usize,
+}
+
+impl PoolInner {
+    fn give_back(&self, buffer: Vec<u8>) {
+        // A poisoned pool only loses the chance to reuse this buffer.
+        if let Ok(mut free) = self.free.lock() {
+            if free.len() < self.max_pooled {
+                free.push(buffer);
+            }
+        }
+    }
+}
+
+/// A pool of frame sized byte buffers. Cloning the pool is cheap, clones share their buffers.
+#[derive(Clone, Debug)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    /// Creates a new, empty [`BufferPool`] that keeps at most `max_pooled` unused buffers around.
+    /// Buffers returned while the pool is full are freed.
+    #[must_use]
+    pub fn new(max_pooled: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                free: Mutex::new(Vec::with_capacity(max_pooled)),
+                max_pooled,
+            }),
+        }
+    }
+
+    /// Takes a buffer of exactly `len` bytes out of the pool, allocating a new one if none is
+    /// free. The contents of a reused buffer are whatever the previous frame left in it.
+    #[must_use]
+    pub fn get(&self, len: usize) -> PooledBuffer {
+        let reused = self.inner.free.lock().ok().and_then(|mut free| {
+            let fitting = free.iter().position(|buffer| buffer.capacity() >= len);
+            fitting
+                .map(|idx| free.swap_remove(idx))
+                .or_else(|| free.pop())
+        });
+
+        let mut data = reused.unwrap_or_default();
+        if data.len() > len {
+            data.truncate(len);
+        } else {
+            data.resize(len, 0);
+        }
+
+        PooledBuffer {
+            data,
+            pool: Arc::downgrade(&self.inner),
+        }
+    }
+
+    /// How many unused buffers are currently waiting in the pool.
+    #[must_use]
+    pub fn available(&self) -> usize {
+        self.inner.free.lock().map_or(0, |free| free.len())
+    }
+}
+
+/// A buffer taken from a [`BufferPool`]. Goes back to the pool when dropped, or when the last
+/// [`Bytes`] made from it by [`PooledBuffer::freeze`] is dropped.
+pub struct PooledBuffer {
+    data: Vec<u8>,
+    pool: Weak<PoolInner>,
+}
+
+impl PooledBuffer {
+    /// Turns this buffer into [`Bytes`] without copying it.
+    #[must_use]
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Debug for PooledBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("len", &self.data.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.upgrade() {
+            pool.give_back(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame_buffer::FrameBuffer, frame_format::FrameFormat, types::Resolution};
+
+    #[test]
+    fn frames_give_their_memory_back_once_every_clone_is_dropped() {
+        let pool = BufferPool::new(2);
+        let mut buffer = pool.get(16);
+        buffer.fill(7);
+        let memory = buffer.as_ptr();
+
+        let frame = FrameBuffer::from_pooled(Resolution::new(4, 4), buffer, FrameFormat::Luma8);
+        assert_eq!(frame.buffer().as_ptr(), memory);
+        let clone = frame.clone();
+        drop(frame);
+        assert_eq!(pool.available(), 0);
+        drop(clone);
+        assert_eq!(pool.available(), 1);
+
+        let reused = pool.get(16);
+        assert_eq!(reused.as_ptr(), memory);
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[test]
+    fn reused_buffers_have_the_requested_length() {
+        let pool = BufferPool::new(1);
+        drop(pool.get(16));
+        assert_eq!(pool.get(8).len(), 8);
+        assert_eq!(pool.get(32).len(), 32);
+    }
+
+    #[test]
+    fn keeps_at_most_max_pooled_buffers() {
+        let pool = BufferPool::new(1);
+        let first = pool.get(4);
+        let second = pool.get(4);
+        drop(first);
+        drop(second);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn buffers_can_outlive_their_pool() {
+        let pool = BufferPool::new(1);
+        let buffer = pool.get(4);
+        drop(pool);
+        assert_eq!(buffer.freeze().len(), 4);
+    }
+}
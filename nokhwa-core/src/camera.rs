@@ -3,20 +3,36 @@ use crate::frame_format::FrameFormat;
 use crate::properties::{ControlId, ControlValue, Properties};
 use crate::types::{CameraFormat, FrameRate, Resolution};
 use std::collections::HashMap;
-use crate::stream::Stream;
+use crate::stream::{BackpressurePolicy, Stream};
 
 pub trait Setting {
+    /// Every [`CameraFormat`] the camera supports.
+    ///
+    /// # Errors
+    /// If the formats cannot be read from the device.
     fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError>;
 
+    /// The resolutions the camera supports in `frame_format`, with the frame rates of each.
+    ///
+    /// # Errors
+    /// If the formats cannot be read from the device.
     fn enumerate_resolution_and_frame_rates(
         &self,
         frame_format: FrameFormat,
     ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError>;
 
+    /// Sets the format the camera captures in.
+    ///
+    /// # Errors
+    /// If the camera does not support `camera_format`, or the device refuses it.
     fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError>;
 
     fn properties(&self) -> &Properties;
 
+    /// Sets the control `property` to `value`.
+    ///
+    /// # Errors
+    /// If the camera has no such control, `value` is not valid for it, or the device refuses it.
     fn set_property(
         &mut self,
         property: &ControlId,
@@ -46,9 +62,32 @@ pub trait AsyncSetting {
 
 pub trait Capture {
     // Implementations MUST guarantee that there can only ever be one stream open at once.
+    /// Opens a stream that queues frames with the backend's default [`BackpressurePolicy`].
+    ///
+    /// # Errors
+    /// If a stream is already open, or the device refuses to start streaming.
     fn open_stream(&mut self) -> Result<Stream, NokhwaError>;
 
+    /// Opens a stream that queues frames according to `policy`.
+    ///
+    /// Backends that do not override this ignore `policy` and fall back to
+    /// [`Capture::open_stream`]. [`Stream::backpressure_policy`] tells which policy is in effect.
+    ///
+    /// # Errors
+    /// If a stream is already open, or the device refuses to start streaming.
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        let _ = policy;
+        self.open_stream()
+    }
+
     // Implementations MUST be multi-close tolerant.
+    /// Closes the open stream, if there is one.
+    ///
+    /// # Errors
+    /// If the device fails to stop streaming.
     fn close_stream(&mut self) -> Result<(), NokhwaError>;
 }
 
@@ -56,6 +95,15 @@ pub trait Capture {
 pub trait AsyncStream {
     async fn open_stream_async(&mut self) -> Result<Stream, NokhwaError>;
 
+    /// See [`Capture::open_stream_with_policy`].
+    async fn open_stream_with_policy_async(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        let _ = policy;
+        self.open_stream_async().await
+    }
+
     async fn close_stream_async(&mut self) -> Result<(), NokhwaError>;
 }
 
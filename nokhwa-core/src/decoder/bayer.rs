@@ -0,0 +1,342 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Demosaicing decoders for [`FrameFormat::Bayer8`] and [`FrameFormat::Bayer16`].
+//!
+//! `Bayer16` samples are little endian. Samples are interpolated at 16 bit precision regardless of
+//! the source, so any Bayer format can be decoded into both [`Rgb<u8>`] and [`Rgb<u16>`].
+
+use crate::{
+    decoder::{
+        check_format_and_layout, check_output_len, decode_to_image, unsupported_format, Decoder,
+        StaticDecoder,
+    },
+    error::NokhwaError,
+    frame_buffer::FrameBuffer,
+    frame_format::{BayerPattern, BayerSite, FrameFormat},
+};
+use image::{ImageBuffer, Pixel, Rgb};
+use std::{marker::PhantomData, ops::ControlFlow};
+
+/// How the missing color samples of each site are interpolated.
+#[derive(Copy, Clone, Debug, Default, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub enum DemosaicMethod {
+    /// Averages the nearest samples of each color. Fast, but produces color fringes on edges.
+    #[default]
+    Bilinear,
+    /// Gradient-corrected linear interpolation as described by Malvar, He and Cutler
+    /// ("High-quality linear interpolation for demosaicing of Bayer-patterned color images", 2004).
+    MalvarHeCutler,
+}
+
+/// A pixel type that the [`BayerDecoder`] can write into.
+pub trait BayerOutputPixel: Pixel {
+    /// Writes a 16 bit RGB triple into `output`, which is exactly
+    /// [`CHANNEL_COUNT`](Pixel::CHANNEL_COUNT) long.
+    fn write_from_rgb16(rgb: [u16; 3], output: &mut [Self::Subpixel]);
+}
+
+impl BayerOutputPixel for Rgb<u8> {
+    #[inline]
+    fn write_from_rgb16(rgb: [u16; 3], output: &mut [u8]) {
+        for (out, channel) in output.iter_mut().zip(rgb) {
+            *out = (channel >> 8) as u8;
+        }
+    }
+}
+
+impl BayerOutputPixel for Rgb<u16> {
+    #[inline]
+    fn write_from_rgb16(rgb: [u16; 3], output: &mut [u16]) {
+        output.copy_from_slice(&rgb);
+    }
+}
+
+/// Weights of a 5x5 interpolation kernel as (`dx`, `dy`, weight). All kernels sum to 16.
+type Kernel = [(isize, isize, i32)];
+
+/// Green at a red or blue site.
+const MHC_GREEN: &Kernel = &[
+    (0, -2, -2),
+    (0, -1, 4),
+    (-2, 0, -2),
+    (-1, 0, 4),
+    (0, 0, 8),
+    (1, 0, 4),
+    (2, 0, -2),
+    (0, 1, 4),
+    (0, 2, -2),
+];
+
+/// The color whose samples are left and right of a green site.
+const MHC_HORIZONTAL: &Kernel = &[
+    (0, -2, 1),
+    (-1, -1, -2),
+    (1, -1, -2),
+    (-2, 0, -2),
+    (-1, 0, 8),
+    (0, 0, 10),
+    (1, 0, 8),
+    (2, 0, -2),
+    (-1, 1, -2),
+    (1, 1, -2),
+    (0, 2, 1),
+];
+
+/// The color whose samples are above and below a green site.
+const MHC_VERTICAL: &Kernel = &[
+    (-2, 0, 1),
+    (-1, -1, -2),
+    (-1, 1, -2),
+    (0, -2, -2),
+    (0, -1, 8),
+    (0, 0, 10),
+    (0, 1, 8),
+    (0, 2, -2),
+    (1, -1, -2),
+    (1, 1, -2),
+    (2, 0, 1),
+];
+
+/// Red at a blue site, or blue at a red site.
+const MHC_DIAGONAL: &Kernel = &[
+    (0, -2, -3),
+    (-1, -1, 4),
+    (1, -1, 4),
+    (-2, 0, -3),
+    (0, 0, 12),
+    (2, 0, -3),
+    (-1, 1, 4),
+    (1, 1, 4),
+    (0, 2, -3),
+];
+
+/// A borrowed Bayer frame with mirrored borders.
+struct Mosaic<'a> {
+    rows: Vec<&'a [u8]>,
+    width: usize,
+    wide: bool,
+}
+
+impl Mosaic<'_> {
+    /// Mirrors `index` into `0..len` without repeating the edge, which keeps the site color of
+    /// mirrored samples intact.
+    #[allow(clippy::cast_possible_wrap)]
+    fn reflect(index: isize, len: usize) -> usize {
+        let last = len as isize - 1;
+        let index = index.abs();
+        let index = if index > last {
+            2 * last - index
+        } else {
+            index
+        };
+        index.clamp(0, last) as usize
+    }
+
+    /// Reads the sample at `x`, `y`, scaled to 16 bits.
+    fn sample(&self, x: isize, y: isize) -> i32 {
+        let row = self.rows[Self::reflect(y, self.rows.len())];
+        let x = Self::reflect(x, self.width);
+        if self.wide {
+            i32::from(u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]))
+        } else {
+            i32::from(row[x]) * 257
+        }
+    }
+
+    fn convolve(&self, x: isize, y: isize, kernel: &Kernel) -> i32 {
+        let sum: i32 = kernel
+            .iter()
+            .map(|(dx, dy, weight)| self.sample(x + dx, y + dy) * weight)
+            .sum();
+        (sum + 8) >> 4
+    }
+
+    fn bilinear(&self, x: isize, y: isize, site: BayerSite) -> [i32; 3] {
+        let center = self.sample(x, y);
+        let horizontal = (self.sample(x - 1, y) + self.sample(x + 1, y) + 1) >> 1;
+        let vertical = (self.sample(x, y - 1) + self.sample(x, y + 1) + 1) >> 1;
+        let cross = (horizontal + vertical + 1) >> 1;
+        let diagonal = (self.sample(x - 1, y - 1)
+            + self.sample(x + 1, y - 1)
+            + self.sample(x - 1, y + 1)
+            + self.sample(x + 1, y + 1)
+            + 2)
+            >> 2;
+
+        match site {
+            BayerSite::Red => [center, cross, diagonal],
+            BayerSite::Blue => [diagonal, cross, center],
+            BayerSite::GreenRed => [horizontal, center, vertical],
+            BayerSite::GreenBlue => [vertical, center, horizontal],
+        }
+    }
+
+    fn malvar_he_cutler(&self, x: isize, y: isize, site: BayerSite) -> [i32; 3] {
+        let center = self.sample(x, y);
+        match site {
+            BayerSite::Red => [
+                center,
+                self.convolve(x, y, MHC_GREEN),
+                self.convolve(x, y, MHC_DIAGONAL),
+            ],
+            BayerSite::Blue => [
+                self.convolve(x, y, MHC_DIAGONAL),
+                self.convolve(x, y, MHC_GREEN),
+                center,
+            ],
+            BayerSite::GreenRed => [
+                self.convolve(x, y, MHC_HORIZONTAL),
+                center,
+                self.convolve(x, y, MHC_VERTICAL),
+            ],
+            BayerSite::GreenBlue => [
+                self.convolve(x, y, MHC_VERTICAL),
+                center,
+                self.convolve(x, y, MHC_HORIZONTAL),
+            ],
+        }
+    }
+}
+
+fn split_format(frame_format: FrameFormat) -> Option<(BayerPattern, bool)> {
+    match frame_format {
+        FrameFormat::Bayer8(pattern) => Some((pattern, false)),
+        FrameFormat::Bayer16(pattern) => Some((pattern, true)),
+        _ => None,
+    }
+}
+
+/// Demosaics [`FrameFormat::Bayer8`] and [`FrameFormat::Bayer16`] frames of any [`BayerPattern`]
+/// into [`Rgb<u8>`] or [`Rgb<u16>`].
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct BayerDecoder<P> {
+    method: DemosaicMethod,
+    _pixel: PhantomData<P>,
+}
+
+impl<P> BayerDecoder<P> {
+    /// Creates a new [`BayerDecoder`] that uses `method`.
+    #[must_use]
+    pub fn new(method: DemosaicMethod) -> Self {
+        Self {
+            method,
+            _pixel: PhantomData,
+        }
+    }
+
+    /// Get the [`DemosaicMethod`] of this decoder.
+    #[must_use]
+    pub fn method(&self) -> DemosaicMethod {
+        self.method
+    }
+
+    /// Set the [`DemosaicMethod`] of this decoder.
+    pub fn set_method(&mut self, method: DemosaicMethod) {
+        self.method = method;
+    }
+}
+
+impl<P> Default for BayerDecoder<P> {
+    fn default() -> Self {
+        Self::new(DemosaicMethod::default())
+    }
+}
+
+impl<P: BayerOutputPixel> Decoder for BayerDecoder<P> {
+    const ALLOWED_FORMATS: &'static [FrameFormat] = &[
+        FrameFormat::Bayer8(BayerPattern::Rggb),
+        FrameFormat::Bayer8(BayerPattern::Bggr),
+        FrameFormat::Bayer8(BayerPattern::Grbg),
+        FrameFormat::Bayer8(BayerPattern::Gbrg),
+        FrameFormat::Bayer16(BayerPattern::Rggb),
+        FrameFormat::Bayer16(BayerPattern::Bggr),
+        FrameFormat::Bayer16(BayerPattern::Grbg),
+        FrameFormat::Bayer16(BayerPattern::Gbrg),
+    ];
+    type OutputPixels = P;
+    type PixelContainer = Vec<P::Subpixel>;
+
+    fn check_format(buffer: &FrameBuffer) -> ControlFlow<NokhwaError> {
+        check_format_and_layout::<Self>(buffer, P::COLOR_MODEL)
+    }
+
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        decode_to_image::<Self>(buffer, P::COLOR_MODEL, |output| {
+            self.decode_buffer(buffer, output)
+        })
+    }
+
+    // Frame dimensions come from a `u32`, so they always fit in an `isize`.
+    #[allow(clippy::cast_possible_wrap)]
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [P::Subpixel],
+    ) -> Result<(), NokhwaError> {
+        if let ControlFlow::Break(why) = Self::check_format(buffer) {
+            return Err(why);
+        }
+
+        let frame_format = buffer.source_frame_format();
+        let required = check_output_len::<Self>(buffer, output.len(), P::COLOR_MODEL)?;
+        if required == 0 {
+            return Ok(());
+        }
+
+        let Some((pattern, wide)) = split_format(frame_format) else {
+            return Err(unsupported_format::<Self>(frame_format));
+        };
+        let mosaic = Mosaic {
+            rows: buffer.plane_rows(0).collect(),
+            width: buffer.resolution().width() as usize,
+            wide,
+        };
+        let channels = usize::from(P::CHANNEL_COUNT);
+
+        for (idx, pixel) in output[..required].chunks_exact_mut(channels).enumerate() {
+            let (x, y) = (idx % mosaic.width, idx / mosaic.width);
+            let site = pattern.site_at(x, y);
+            let (x, y) = (x as isize, y as isize);
+            let rgb = match self.method {
+                DemosaicMethod::Bilinear => mosaic.bilinear(x, y, site),
+                DemosaicMethod::MalvarHeCutler => mosaic.malvar_he_cutler(x, y, site),
+            }
+            .map(|channel| channel.clamp(0, i32::from(u16::MAX)) as u16);
+            P::write_from_rgb16(rgb, pixel);
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: BayerOutputPixel> StaticDecoder for BayerDecoder<P> {
+    fn decode_static(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        Self::default().decode(buffer)
+    }
+
+    fn decode_static_to_buffer(
+        buffer: &FrameBuffer,
+        output: &mut [P::Subpixel],
+    ) -> Result<(), NokhwaError> {
+        Self::default().decode_buffer(buffer, output)
+    }
+}
@@ -0,0 +1,365 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Software decoder for [`FrameFormat::H264`] and [`FrameFormat::Avc1`], using Cisco's
+//! `openh264`.
+//!
+//! Unlike MJPEG, an H.264 frame usually depends on the frames before it, so an [`H264Decoder`]
+//! has to be handed every frame of a stream, in order. It only starts decoding at a keyframe
+//! (an IDR picture), and once a frame is lost or fails to decode, it skips frames until the next
+//! keyframe instead of showing pictures built on missing references. Frames skipped this way are
+//! reported as [`NokhwaError::MissingKeyframeError`], which callers can treat as dropped frames.
+//!
+//! Not every frame holds a picture: some only carry parameter sets, and the decoder may hold a
+//! picture back until a later frame. [`H264Decoder::decode_i420`] returns `Ok(None)` for those,
+//! [`Decoder`] reports them as [`NokhwaError::NoPictureError`]. Neither is a failure.
+//!
+//! Frames may be in Annex B byte stream format, with start codes, or in the length-prefixed AVC
+//! format some backends deliver [`FrameFormat::Avc1`] in. Parameter sets (SPS and PPS) have to be
+//! part of the stream; the first keyframe after them starts decoding.
+
+use crate::{
+    decoder::{
+        unsupported_format,
+        yuv::{PlanarYuvDecoder, YuvOutputPixel},
+        Decoder, StaticDecoder,
+    },
+    error::NokhwaError,
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    types::Resolution,
+};
+use image::ImageBuffer;
+use openh264::{decoder::Decoder as OpenH264Decoder, formats::YUVSource};
+use std::{borrow::Cow, marker::PhantomData};
+
+const H264_FORMATS: &[FrameFormat] = &[FrameFormat::H264, FrameFormat::Avc1];
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+const NAL_IDR_SLICE: u8 = 5;
+/// NAL units that carry no picture data: SEI, SPS, PPS and access unit delimiters.
+const NAL_NON_PICTURE: &[u8] = &[6, 7, 8, 9];
+
+/// Decodes [`FrameFormat::H264`] and [`FrameFormat::Avc1`] frames into [`FrameFormat::I420`]
+/// [`FrameBuffer`]s with [`H264Decoder::decode_i420`], or into [`Rgb<u8>`](image::Rgb),
+/// [`Rgba<u8>`](image::Rgba) or [`Luma<u8>`](image::Luma) images through [`Decoder`].
+///
+/// H.265 is not supported.
+pub struct H264Decoder<P> {
+    decoder: OpenH264Decoder,
+    awaiting_keyframe: bool,
+    skipped_frames: u64,
+    _pixel: PhantomData<P>,
+}
+
+impl<P> H264Decoder<P> {
+    /// Creates a new [`H264Decoder`], which waits for a keyframe before it decodes anything.
+    ///
+    /// # Errors
+    /// If `openh264` fails to initialize, this will error.
+    pub fn new() -> Result<Self, NokhwaError> {
+        let decoder = OpenH264Decoder::new().map_err(|why| {
+            NokhwaError::GeneralError(format!("failed to initialize openh264: {why}"))
+        })?;
+        Ok(Self {
+            decoder,
+            awaiting_keyframe: true,
+            skipped_frames: 0,
+            _pixel: PhantomData,
+        })
+    }
+
+    /// Whether the decoder is skipping frames until the next keyframe, because it has not seen
+    /// one yet or a frame failed to decode.
+    #[must_use]
+    pub fn is_awaiting_keyframe(&self) -> bool {
+        self.awaiting_keyframe
+    }
+
+    /// How many frames this decoder has skipped while waiting for a keyframe so far.
+    #[must_use]
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped_frames
+    }
+
+    /// Skips every frame until the next keyframe, e.g. after the caller dropped a frame. Frames
+    /// decoded afterwards would otherwise refer to the dropped one.
+    pub fn resync(&mut self) {
+        self.awaiting_keyframe = true;
+    }
+
+    /// Decodes the next frame of the stream into a [`FrameFormat::I420`] [`FrameBuffer`], with
+    /// the resolution of the decoded picture. The timestamps and sequence number of `buffer` are
+    /// kept.
+    ///
+    /// Returns `Ok(None)` if the frame holds no picture yet: it only carries parameter sets, or
+    /// the decoder holds its picture back for a later frame.
+    ///
+    /// # Errors
+    /// [`NokhwaError::MissingKeyframeError`] if the frame was skipped while waiting for a
+    /// keyframe, [`NokhwaError::CorruptFrameError`] if it failed to decode, or
+    /// [`NokhwaError::ConversionError`] if it is not an H.264 frame.
+    pub fn decode_i420(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<Option<FrameBuffer>, NokhwaError> {
+        let src = buffer.source_frame_format();
+        if !H264_FORMATS.contains(&src) {
+            return Err(unsupported_format::<Self>(src));
+        }
+
+        let data = annex_b(buffer.buffer());
+        if self.awaiting_keyframe {
+            if nal_unit_types(&data).any(|nal_type| nal_type == NAL_IDR_SLICE) {
+                self.awaiting_keyframe = false;
+            } else if !holds_no_picture_data(&data) {
+                self.skipped_frames += 1;
+                return Err(NokhwaError::MissingKeyframeError(src));
+            }
+            // Parameter sets on their own are passed on, the keyframe after them needs them.
+        }
+
+        let picture = match self.decoder.decode(&data) {
+            Ok(Some(picture)) => picture,
+            Ok(None) => return Ok(None),
+            Err(why) => {
+                self.awaiting_keyframe = true;
+                return Err(corrupt(src, why.to_string()));
+            }
+        };
+
+        let (width, height) = picture.dimensions();
+        let (y_stride, u_stride, v_stride) = picture.strides();
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let size = width * height + 2 * chroma_width * chroma_height;
+        let mut i420 = Vec::with_capacity(size);
+        copy_plane(picture.y(), y_stride, width, height, &mut i420);
+        copy_plane(
+            picture.u(),
+            u_stride,
+            chroma_width,
+            chroma_height,
+            &mut i420,
+        );
+        copy_plane(
+            picture.v(),
+            v_stride,
+            chroma_width,
+            chroma_height,
+            &mut i420,
+        );
+        if i420.len() != size {
+            return Err(corrupt(
+                src,
+                "decoded picture is smaller than its resolution",
+            ));
+        }
+
+        let (Ok(width), Ok(height)) = (u32::try_from(width), u32::try_from(height)) else {
+            return Err(corrupt(src, "decoded picture is too large"));
+        };
+        let mut frame =
+            FrameBuffer::from_vec(Resolution::new(width, height), i420, FrameFormat::I420);
+        frame.set_sequence(buffer.sequence());
+        frame.set_timestamp(buffer.timestamp());
+        frame.set_driver_timestamp(buffer.driver_timestamp());
+        Ok(Some(frame))
+    }
+
+    /// [`H264Decoder::decode_i420`] for [`Decoder`], which has to return a picture.
+    fn decode_picture(&mut self, buffer: &FrameBuffer) -> Result<FrameBuffer, NokhwaError> {
+        self.decode_i420(buffer)?
+            .ok_or(NokhwaError::NoPictureError(buffer.source_frame_format()))
+    }
+}
+
+impl<P: YuvOutputPixel> Decoder for H264Decoder<P> {
+    const ALLOWED_FORMATS: &'static [FrameFormat] = H264_FORMATS;
+    type OutputPixels = P;
+    type PixelContainer = Vec<u8>;
+
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        PlanarYuvDecoder::<P>::decode_static(&self.decode_picture(buffer)?)
+    }
+
+    /// Decodes into `output`, which has to fit the resolution of the decoded picture. This is
+    /// the resolution of `buffer`, unless the stream changed it.
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [u8],
+    ) -> Result<(), NokhwaError> {
+        PlanarYuvDecoder::<P>::decode_static_to_buffer(&self.decode_picture(buffer)?, output)
+    }
+}
+
+fn corrupt(src: FrameFormat, error: impl Into<String>) -> NokhwaError {
+    NokhwaError::CorruptFrameError {
+        src,
+        error: error.into(),
+    }
+}
+
+/// Appends the first `width` bytes of each of the first `height` rows of `plane`, stopping early
+/// at a row that is not inside it.
+fn copy_plane(plane: &[u8], stride: usize, width: usize, height: usize, output: &mut Vec<u8>) {
+    for row in 0..height {
+        let start = row * stride;
+        match plane.get(start..start + width) {
+            Some(row) => output.extend_from_slice(row),
+            None => return,
+        }
+    }
+}
+
+/// Turns a frame in the length-prefixed AVC format into an Annex B byte stream, which is what
+/// `openh264` reads. Frames that already have start codes, or that are not valid AVC, are
+/// returned as they are.
+fn annex_b(data: &[u8]) -> Cow<'_, [u8]> {
+    if data.starts_with(&START_CODE) || data.starts_with(&START_CODE[1..]) {
+        return Cow::Borrowed(data);
+    }
+
+    let mut converted = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while !rest.is_empty() {
+        let Some((length, tail)) = rest.split_first_chunk::<4>() else {
+            return Cow::Borrowed(data);
+        };
+        let length = u32::from_be_bytes(*length) as usize;
+        let Some(nal_unit) = tail.get(..length) else {
+            return Cow::Borrowed(data);
+        };
+        converted.extend_from_slice(&START_CODE);
+        converted.extend_from_slice(nal_unit);
+        rest = &tail[length..];
+    }
+    Cow::Owned(converted)
+}
+
+/// Whether an Annex B byte stream has NAL units, and none of them carry picture data.
+fn holds_no_picture_data(data: &[u8]) -> bool {
+    let mut nal_types = nal_unit_types(data).peekable();
+    nal_types.peek().is_some() && nal_types.all(|nal_type| NAL_NON_PICTURE.contains(&nal_type))
+}
+
+/// The types of the NAL units of an Annex B byte stream.
+fn nal_unit_types(data: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    data.windows(3)
+        .enumerate()
+        .filter(|(_, window)| *window == [0, 0, 1])
+        .filter_map(move |(start, _)| data.get(start + 3))
+        .map(|header| header & 0x1F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use openh264::{encoder::Encoder, formats::YUVBuffer};
+
+    const SPS: &[u8] = &[0, 0, 0, 1, 0x67, 0x42, 0xC0, 0x1E];
+    const PPS: &[u8] = &[0, 0, 0, 1, 0x68, 0xCE, 0x3C, 0x80];
+    const NON_IDR_SLICE: &[u8] = &[0, 0, 0, 1, 0x41, 0x9A, 0x02];
+    const IDR_SLICE: &[u8] = &[0, 0, 1, 0x65, 0x88, 0x84];
+
+    fn frame(data: &[u8], format: FrameFormat) -> FrameBuffer {
+        FrameBuffer::new(Resolution::new(16, 16), data, format)
+    }
+
+    #[test]
+    fn avc_frames_are_converted_to_annex_b() {
+        let avc = [0, 0, 0, 2, 0x67, 0x42, 0, 0, 0, 3, 0x65, 0x88, 0x84];
+        assert_eq!(
+            annex_b(&avc).as_ref(),
+            &[0, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x65, 0x88, 0x84]
+        );
+        assert!(matches!(annex_b(IDR_SLICE), Cow::Borrowed(_)));
+        // A length running past the end is not AVC.
+        let truncated = [0, 0, 0, 9, 0x65, 0x88];
+        assert_eq!(annex_b(&truncated).as_ref(), &truncated);
+    }
+
+    #[test]
+    fn nal_unit_types_are_read_after_start_codes() {
+        let stream = [SPS, PPS, IDR_SLICE].concat();
+        assert_eq!(nal_unit_types(&stream).collect::<Vec<_>>(), vec![7, 8, 5]);
+        assert!(holds_no_picture_data(&[SPS, PPS].concat()));
+        assert!(!holds_no_picture_data(&stream));
+        assert!(!holds_no_picture_data(&[]));
+    }
+
+    #[test]
+    fn planes_are_copied_without_padding() {
+        let plane = [1, 2, 0, 3, 4, 0, 5];
+        let mut output = Vec::new();
+        copy_plane(&plane, 3, 2, 3, &mut output);
+        assert_eq!(output, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn frames_before_the_first_keyframe_are_skipped() {
+        let mut decoder = H264Decoder::<Rgb<u8>>::new().unwrap();
+        for _ in 0..3 {
+            assert!(matches!(
+                decoder.decode_i420(&frame(NON_IDR_SLICE, FrameFormat::H264)),
+                Err(NokhwaError::MissingKeyframeError(FrameFormat::H264))
+            ));
+        }
+        assert!(decoder.is_awaiting_keyframe());
+        assert_eq!(decoder.skipped_frames(), 3);
+
+        assert!(matches!(
+            decoder.decode_i420(&frame(&[], FrameFormat::Avc1)),
+            Err(NokhwaError::MissingKeyframeError(FrameFormat::Avc1))
+        ));
+        assert_eq!(decoder.skipped_frames(), 4);
+    }
+
+    #[test]
+    fn encoded_pictures_decode_to_their_resolution() {
+        let (width, height) = (32, 16);
+        let gray = YUVBuffer::from_vec(vec![128; width * height * 3 / 2], width, height);
+        let stream = Encoder::new().unwrap().encode(&gray).unwrap().to_vec();
+
+        let mut decoder = H264Decoder::<Rgb<u8>>::new().unwrap();
+        let mut buffer = frame(&stream, FrameFormat::H264);
+        buffer.set_sequence(7);
+        let picture = decoder.decode_i420(&buffer).unwrap().unwrap();
+        assert_eq!(picture.resolution(), Resolution::new(32, 16));
+        assert_eq!(picture.source_frame_format(), FrameFormat::I420);
+        assert_eq!(picture.buffer().len(), width * height * 3 / 2);
+        assert_eq!(picture.sequence(), 7);
+        assert!(!decoder.is_awaiting_keyframe());
+
+        let image = decoder.decode(&buffer).unwrap();
+        assert_eq!(image.dimensions(), (32, 16));
+    }
+
+    #[test]
+    fn other_formats_are_rejected() {
+        let mut decoder = H264Decoder::<Rgb<u8>>::new().unwrap();
+        match decoder.decode_i420(&frame(&[0; 768], FrameFormat::Rgb888)) {
+            Err(NokhwaError::ConversionError(why)) => {
+                assert_eq!(why, "H264Decoder cannot decode Rgb888 frames");
+            }
+            other => panic!("expected a conversion error, got {other:?}"),
+        }
+        assert_eq!(decoder.skipped_frames(), 0);
+    }
+}
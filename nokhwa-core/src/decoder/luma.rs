@@ -0,0 +1,195 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Decoders for high bit depth grayscale frame formats.
+//!
+//! Samples are scaled up to the full 16 bit range by repeating their most significant bits, so
+//! that the brightest 10 or 12 bit value decodes to [`u16::MAX`].
+
+use crate::{
+    decoder::{check_format_and_layout, check_output_len, decode_to_image, Decoder, StaticDecoder},
+    error::NokhwaError,
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+};
+use image::{ImageBuffer, Luma, Pixel, Rgb};
+use std::{marker::PhantomData, ops::ControlFlow};
+
+/// A pixel type that the [`DeepLumaDecoder`] can write into.
+pub trait Luma16OutputPixel: Pixel<Subpixel = u16> {
+    /// Writes the pixel for the 16 bit `luma` sample into `output`, which is exactly
+    /// [`CHANNEL_COUNT`](Pixel::CHANNEL_COUNT) long.
+    fn write_from_luma16(luma: u16, output: &mut [u16]);
+}
+
+impl Luma16OutputPixel for Luma<u16> {
+    #[inline]
+    fn write_from_luma16(luma: u16, output: &mut [u16]) {
+        output[0] = luma;
+    }
+}
+
+impl Luma16OutputPixel for Rgb<u16> {
+    #[inline]
+    fn write_from_luma16(luma: u16, output: &mut [u16]) {
+        output.fill(luma);
+    }
+}
+
+/// Scales a `bits` wide sample up to 16 bits.
+#[inline]
+fn scale_to_16(sample: u16, bits: u32) -> u16 {
+    let sample = sample & ((1 << bits) - 1);
+    (sample << (16 - bits)) | (sample >> (2 * bits - 16))
+}
+
+/// Decodes high bit depth grayscale frames ([`FrameFormat::Luma10`], [`FrameFormat::Luma12`],
+/// [`FrameFormat::Luma10Packed`] and [`FrameFormat::Luma16`]) into [`Luma<u16>`] or [`Rgb<u16>`].
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct DeepLumaDecoder<P> {
+    _pixel: PhantomData<P>,
+}
+
+impl<P> DeepLumaDecoder<P> {
+    /// Creates a new [`DeepLumaDecoder`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            _pixel: PhantomData,
+        }
+    }
+}
+
+impl<P> Default for DeepLumaDecoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Luma16OutputPixel> Decoder for DeepLumaDecoder<P> {
+    const ALLOWED_FORMATS: &'static [FrameFormat] = &[
+        FrameFormat::Luma10,
+        FrameFormat::Luma12,
+        FrameFormat::Luma10Packed,
+        FrameFormat::Luma16,
+    ];
+    type OutputPixels = P;
+    type PixelContainer = Vec<u16>;
+
+    fn check_format(buffer: &FrameBuffer) -> ControlFlow<NokhwaError> {
+        check_format_and_layout::<Self>(buffer, P::COLOR_MODEL)
+    }
+
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        Self::decode_static(buffer)
+    }
+
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [u16],
+    ) -> Result<(), NokhwaError> {
+        Self::decode_static_to_buffer(buffer, output)
+    }
+}
+
+impl<P: Luma16OutputPixel> StaticDecoder for DeepLumaDecoder<P> {
+    fn decode_static(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        decode_to_image::<Self>(buffer, P::COLOR_MODEL, |output| {
+            Self::decode_static_to_buffer(buffer, output)
+        })
+    }
+
+    fn decode_static_to_buffer(
+        buffer: &FrameBuffer,
+        output: &mut [u16],
+    ) -> Result<(), NokhwaError> {
+        if let ControlFlow::Break(why) = Self::check_format(buffer) {
+            return Err(why);
+        }
+
+        let frame_format = buffer.source_frame_format();
+        let required = check_output_len::<Self>(buffer, output.len(), P::COLOR_MODEL)?;
+        if required == 0 {
+            return Ok(());
+        }
+
+        let channels = usize::from(P::CHANNEL_COUNT);
+        let width = buffer.resolution().width() as usize;
+
+        for (row_in, row_out) in buffer
+            .plane_rows(0)
+            .zip(output[..required].chunks_exact_mut(width * channels))
+        {
+            for (x, pixel) in row_out.chunks_exact_mut(channels).enumerate() {
+                let luma = if frame_format == FrameFormat::Luma10Packed {
+                    let group = &row_in[(x / 4) * 5..(x / 4) * 5 + 5];
+                    let low_bits = (group[4] >> ((x % 4) * 2)) & 0b11;
+                    scale_to_16((u16::from(group[x % 4]) << 2) | u16::from(low_bits), 10)
+                } else {
+                    let sample = u16::from_le_bytes([row_in[x * 2], row_in[x * 2 + 1]]);
+                    match frame_format {
+                        FrameFormat::Luma10 => scale_to_16(sample, 10),
+                        FrameFormat::Luma12 => scale_to_16(sample, 12),
+                        _ => sample,
+                    }
+                };
+                P::write_from_luma16(luma, pixel);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Resolution;
+
+    /// A 4x2 frame whose samples count up in steps of 1000.
+    fn luma16() -> FrameBuffer {
+        let data = (0..8_u16)
+            .flat_map(|sample| (sample * 1000).to_le_bytes())
+            .collect::<Vec<_>>();
+        FrameBuffer::new(Resolution::new(4, 2), &data, FrameFormat::Luma16)
+    }
+
+    #[test]
+    fn predicted_size_counts_bytes_and_output_counts_subpixels() {
+        let frame = luma16();
+        assert_eq!(
+            DeepLumaDecoder::<Rgb<u16>>::predicted_size_of_frame(&frame),
+            Some(4 * 2 * 3 * 2)
+        );
+
+        // An output holding one subpixel per channel of every pixel is enough.
+        let mut output = [0; 4 * 2 * 3];
+        DeepLumaDecoder::<Rgb<u16>>::decode_static_to_buffer(&frame, &mut output).unwrap();
+        assert_eq!(output[3..6], [1000; 3]);
+        assert!(
+            DeepLumaDecoder::<Rgb<u16>>::decode_static_to_buffer(&frame, &mut output[1..]).is_err()
+        );
+
+        let image = DeepLumaDecoder::<Luma<u16>>::decode_static(&frame).unwrap();
+        assert_eq!(image.as_raw().len(), 4 * 2);
+    }
+}
@@ -0,0 +1,267 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Decoder for [`FrameFormat::MJpeg`].
+//!
+//! Uses `mozjpeg` when `decoding-mozjpeg` is enabled, and the pure-Rust `zune-jpeg` when only
+//! `decoding-zune` is.
+//!
+//! Cheap UVC webcams often emit frames that are truncated or padded with zeroes. Instead of
+//! failing the whole frame, [`MJpegDecoder`] decodes as much of them as it can and reports them as
+//! [`MJpegFrameStatus::Recovered`]. Frames that cannot be decoded at all are reported as
+//! [`NokhwaError::CorruptFrameError`], which callers can treat as a dropped frame.
+
+use crate::{
+    decoder::{check_output_len, decode_to_image, Decoder, StaticDecoder},
+    error::NokhwaError,
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    types::Resolution,
+};
+use image::{ImageBuffer, Luma, Pixel, Rgb, Rgba};
+use std::{borrow::Cow, marker::PhantomData};
+
+const START_OF_IMAGE: [u8; 2] = [0xFF, 0xD8];
+const END_OF_IMAGE: [u8; 2] = [0xFF, 0xD9];
+
+/// A pixel type that the [`MJpegDecoder`] can write into.
+pub trait JpegOutputPixel: Pixel<Subpixel = u8> {}
+
+impl JpegOutputPixel for Rgb<u8> {}
+
+impl JpegOutputPixel for Rgba<u8> {}
+
+impl JpegOutputPixel for Luma<u8> {}
+
+/// How the last frame handed to a [`MJpegDecoder`] was decoded.
+#[derive(Copy, Clone, Debug, Default, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub enum MJpegFrameStatus {
+    /// The frame was a complete JPEG image.
+    #[default]
+    Complete,
+    /// The frame was truncated or missing its end of image marker. It has been decoded as far as
+    /// the data went, the rest of the image is filled in by the JPEG backend.
+    Recovered,
+}
+
+/// Decodes [`FrameFormat::MJpeg`] frames into [`Rgb<u8>`], [`Rgba<u8>`] or [`Luma<u8>`].
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct MJpegDecoder<P> {
+    last_frame_status: MJpegFrameStatus,
+    recovered_frames: u64,
+    _pixel: PhantomData<P>,
+}
+
+impl<P> MJpegDecoder<P> {
+    /// Creates a new [`MJpegDecoder`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            last_frame_status: MJpegFrameStatus::Complete,
+            recovered_frames: 0,
+            _pixel: PhantomData,
+        }
+    }
+
+    /// The [`MJpegFrameStatus`] of the last successfully decoded frame.
+    #[must_use]
+    pub fn last_frame_status(&self) -> MJpegFrameStatus {
+        self.last_frame_status
+    }
+
+    /// How many frames this decoder has had to recover so far.
+    #[must_use]
+    pub fn recovered_frames(&self) -> u64 {
+        self.recovered_frames
+    }
+}
+
+impl<P> Default for MJpegDecoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: JpegOutputPixel> Decoder for MJpegDecoder<P> {
+    const ALLOWED_FORMATS: &'static [FrameFormat] = &[FrameFormat::MJpeg];
+    type OutputPixels = P;
+    type PixelContainer = Vec<u8>;
+
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        decode_to_image::<Self>(buffer, P::COLOR_MODEL, |output| {
+            self.decode_buffer(buffer, output)
+        })
+    }
+
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [u8],
+    ) -> Result<(), NokhwaError> {
+        if let std::ops::ControlFlow::Break(why) = Self::check_format(buffer) {
+            return Err(why);
+        }
+
+        let required = check_output_len::<Self>(buffer, output.len(), P::COLOR_MODEL)?;
+
+        let (data, status) = repair_frame(buffer.buffer())?;
+        decode_jpeg(
+            &data,
+            buffer.resolution(),
+            P::CHANNEL_COUNT,
+            P::COLOR_MODEL,
+            &mut output[..required],
+        )?;
+
+        self.last_frame_status = status;
+        if status == MJpegFrameStatus::Recovered {
+            self.recovered_frames += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<P: JpegOutputPixel> StaticDecoder for MJpegDecoder<P> {
+    fn decode_static(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        Self::new().decode(buffer)
+    }
+
+    fn decode_static_to_buffer(buffer: &FrameBuffer, output: &mut [u8]) -> Result<(), NokhwaError> {
+        Self::new().decode_buffer(buffer, output)
+    }
+}
+
+fn corrupt(error: impl Into<String>) -> NokhwaError {
+    NokhwaError::CorruptFrameError {
+        src: FrameFormat::MJpeg,
+        error: error.into(),
+    }
+}
+
+/// Strips the zero padding some drivers leave after the image and terminates truncated frames
+/// with an end of image marker.
+fn repair_frame(data: &[u8]) -> Result<(Cow<'_, [u8]>, MJpegFrameStatus), NokhwaError> {
+    let end = data
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |last| last + 1);
+    let data = &data[..end];
+
+    if !data.starts_with(&START_OF_IMAGE) {
+        return Err(corrupt("missing start of image marker"));
+    }
+    if data.ends_with(&END_OF_IMAGE) {
+        return Ok((Cow::Borrowed(data), MJpegFrameStatus::Complete));
+    }
+
+    let mut repaired = Vec::with_capacity(data.len() + END_OF_IMAGE.len());
+    repaired.extend_from_slice(data);
+    repaired.extend_from_slice(&END_OF_IMAGE);
+    Ok((Cow::Owned(repaired), MJpegFrameStatus::Recovered))
+}
+
+fn check_dimensions(
+    resolution: Resolution,
+    width: usize,
+    height: usize,
+    color_model: &str,
+) -> Result<(), NokhwaError> {
+    if resolution.width() as usize != width || resolution.height() as usize != height {
+        return Err(NokhwaError::ProcessFrameError {
+            src: FrameFormat::MJpeg,
+            destination: color_model.to_string(),
+            error: format!("frame is {width}x{height}, expected {resolution}"),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "decoding-mozjpeg")]
+fn decode_jpeg(
+    data: &[u8],
+    resolution: Resolution,
+    channels: u8,
+    color_model: &str,
+    output: &mut [u8],
+) -> Result<(), NokhwaError> {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    // libjpeg reports fatal errors by unwinding, so it has to be caught here.
+    catch_unwind(AssertUnwindSafe(|| {
+        let decompress =
+            mozjpeg::Decompress::new_mem(data).map_err(|why| corrupt(why.to_string()))?;
+        let mut started = match channels {
+            1 => decompress.grayscale(),
+            3 => decompress.rgb(),
+            _ => decompress.rgba(),
+        }
+        .map_err(|why| corrupt(why.to_string()))?;
+
+        check_dimensions(resolution, started.width(), started.height(), color_model)?;
+        started
+            .read_scanlines_into::<u8>(output)
+            .map_err(|why| corrupt(why.to_string()))?;
+        started.finish().map_err(|why| corrupt(why.to_string()))
+    }))
+    .unwrap_or_else(|panic| {
+        let why = panic
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "libjpeg error".to_string());
+        Err(corrupt(why))
+    })
+}
+
+#[cfg(all(feature = "decoding-zune", not(feature = "decoding-mozjpeg")))]
+fn decode_jpeg(
+    data: &[u8],
+    resolution: Resolution,
+    channels: u8,
+    color_model: &str,
+    output: &mut [u8],
+) -> Result<(), NokhwaError> {
+    use zune_jpeg::{
+        zune_core::{bytestream::ZCursor, colorspace::ColorSpace, options::DecoderOptions},
+        JpegDecoder,
+    };
+
+    let color_space = match channels {
+        1 => ColorSpace::Luma,
+        3 => ColorSpace::RGB,
+        _ => ColorSpace::RGBA,
+    };
+    let options = DecoderOptions::default()
+        .jpeg_set_out_colorspace(color_space)
+        .set_strict_mode(false);
+    let mut decoder = JpegDecoder::new_with_options(ZCursor::new(data), options);
+
+    decoder
+        .decode_headers()
+        .map_err(|why| corrupt(why.to_string()))?;
+    let (width, height) = decoder
+        .dimensions()
+        .ok_or_else(|| corrupt("missing frame header"))?;
+    check_dimensions(resolution, width, height, color_model)?;
+
+    decoder
+        .decode_into(output)
+        .map_err(|why| corrupt(why.to_string()))
+}
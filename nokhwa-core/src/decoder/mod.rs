@@ -0,0 +1,241 @@
+use crate::{error::NokhwaError, frame_buffer::FrameBuffer, frame_format::FrameFormat};
+use image::{ImageBuffer, Pixel};
+use std::{
+    any::type_name,
+    ops::{ControlFlow, Deref},
+};
+
+/// Trait to define a struct that can decode a [`FrameBuffer`]
+pub trait Decoder {
+    /// Formats that the decoder can decode.
+    const ALLOWED_FORMATS: &'static [FrameFormat];
+    /// Output pixel type (e.g. [`Rgb<u8>`](image::Rgb))
+    type OutputPixels: Pixel;
+
+    /// Container type for the decoder. Will be used for [`ImageBuffer`]
+    type PixelContainer: Deref<Target = [<<Self as Decoder>::OutputPixels as Pixel>::Subpixel]>;
+
+    fn check_format(buffer: &FrameBuffer) -> ControlFlow<NokhwaError> {
+        let format = buffer.source_frame_format();
+        if !Self::ALLOWED_FORMATS.contains(&format) {
+            return ControlFlow::Break(unsupported_format::<Self>(format));
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Decode function.
+    ///
+    /// # Errors
+    /// If `buffer` is not in one of the [`Decoder::ALLOWED_FORMATS`], or fails to decode.
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError>;
+
+    /// Decode to user-provided Buffer
+    ///
+    /// Incase that the buffer is not large enough this should error.
+    ///
+    /// # Errors
+    /// If `output` is too small, `buffer` is not in one of the [`Decoder::ALLOWED_FORMATS`], or
+    /// it fails to decode.
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [<<Self as Decoder>::OutputPixels as Pixel>::Subpixel],
+    ) -> Result<(), NokhwaError>;
+
+    /// Decoder Predicted Size
+    fn predicted_size_of_frame(buffer: &FrameBuffer) -> Option<usize> {
+        if !Self::ALLOWED_FORMATS.contains(&buffer.source_frame_format()) {
+            return None;
+        }
+        let res = buffer.resolution();
+        Some(
+            res.x() as usize
+                * res.y() as usize
+                * size_of::<<<Self as Decoder>::OutputPixels as Pixel>::Subpixel>()
+                * <<Self as Decoder>::OutputPixels as Pixel>::CHANNEL_COUNT as usize,
+        )
+    }
+}
+
+/// The error for a `format` frame handed to decoder `D`, which does not decode it.
+pub(crate) fn unsupported_format<D: ?Sized>(format: FrameFormat) -> NokhwaError {
+    // `D` without its module path or pixel type, e.g. `Yuv422Decoder`.
+    let name = type_name::<D>();
+    let name = name.split('<').next().unwrap_or(name);
+    let name = name.rsplit("::").next().unwrap_or(name);
+    NokhwaError::ConversionError(format!("{name} cannot decode {format} frames"))
+}
+
+/// The number of subpixels `D` decodes `buffer` into, which is what an output buffer is sized
+/// in. [`Decoder::predicted_size_of_frame`] counts bytes, which differ for wider subpixels.
+#[cfg(any(
+    feature = "decoding-yuv",
+    feature = "decoding-bayer",
+    feature = "decoding-mozjpeg",
+    feature = "decoding-zune"
+))]
+pub(crate) fn predicted_subpixels<D: Decoder>(buffer: &FrameBuffer) -> Option<usize> {
+    Some(D::predicted_size_of_frame(buffer)? / size_of::<<D::OutputPixels as Pixel>::Subpixel>())
+}
+
+/// [`Decoder::check_format`] for decoders that read the planes of a frame: checks that `buffer`
+/// is in one of the [`Decoder::ALLOWED_FORMATS`] of `D`, and that every plane is inside its data,
+/// see [`FrameBuffer::check_planes`].
+#[cfg(any(feature = "decoding-yuv", feature = "decoding-bayer"))]
+pub(crate) fn check_format_and_layout<D: Decoder>(
+    buffer: &FrameBuffer,
+    destination: &str,
+) -> ControlFlow<NokhwaError> {
+    let format = buffer.source_frame_format();
+    if !D::ALLOWED_FORMATS.contains(&format) {
+        return ControlFlow::Break(unsupported_format::<D>(format));
+    }
+    match buffer.check_planes() {
+        Ok(()) => ControlFlow::Continue(()),
+        Err(error) => ControlFlow::Break(NokhwaError::ProcessFrameError {
+            src: format,
+            destination: destination.to_string(),
+            error,
+        }),
+    }
+}
+
+/// Checks that an output buffer of `output_len` subpixels can hold `buffer` decoded by `D`, and
+/// returns how many of them it takes.
+#[cfg(any(
+    feature = "decoding-yuv",
+    feature = "decoding-bayer",
+    feature = "decoding-mozjpeg",
+    feature = "decoding-zune"
+))]
+pub(crate) fn check_output_len<D: Decoder>(
+    buffer: &FrameBuffer,
+    output_len: usize,
+    destination: &str,
+) -> Result<usize, NokhwaError> {
+    let required = predicted_subpixels::<D>(buffer).unwrap_or_default();
+    if output_len < required {
+        return Err(NokhwaError::ProcessFrameError {
+            src: buffer.source_frame_format(),
+            destination: destination.to_string(),
+            error: format!("output buffer too small: need {required}, got {output_len}"),
+        });
+    }
+    Ok(required)
+}
+
+/// Decodes `buffer` with `decode_to_buffer` into a new image, for [`Decoder::decode`] or
+/// [`StaticDecoder::decode_static`].
+#[cfg(any(
+    feature = "decoding-yuv",
+    feature = "decoding-bayer",
+    feature = "decoding-mozjpeg",
+    feature = "decoding-zune"
+))]
+pub(crate) fn decode_to_image<D>(
+    buffer: &FrameBuffer,
+    destination: &str,
+    decode_to_buffer: impl FnOnce(
+        &mut [<D::OutputPixels as Pixel>::Subpixel],
+    ) -> Result<(), NokhwaError>,
+) -> Result<ImageBuffer<D::OutputPixels, D::PixelContainer>, NokhwaError>
+where
+    D: Decoder<PixelContainer = Vec<<<D as Decoder>::OutputPixels as Pixel>::Subpixel>>,
+{
+    let format = buffer.source_frame_format();
+    let size = predicted_subpixels::<D>(buffer).ok_or_else(|| unsupported_format::<D>(format))?;
+    let mut output =
+        vec![<<D::OutputPixels as Pixel>::Subpixel as image::Primitive>::DEFAULT_MIN_VALUE; size];
+    decode_to_buffer(&mut output)?;
+
+    let resolution = buffer.resolution();
+    ImageBuffer::from_raw(resolution.width(), resolution.height(), output).ok_or_else(|| {
+        NokhwaError::ProcessFrameError {
+            src: format,
+            destination: destination.to_string(),
+            error: "decoded buffer does not fit resolution".to_string(),
+        }
+    })
+}
+
+/// Decoder that can be used statically (struct contains no state)
+///
+/// This is useful for times that a simple function is all that is required.
+pub trait StaticDecoder: Decoder {
+    /// [`Decoder::decode`], without a decoder.
+    ///
+    /// # Errors
+    /// See [`Decoder::decode`].
+    fn decode_static(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError>;
+
+    /// [`Decoder::decode_buffer`], without a decoder.
+    ///
+    /// # Errors
+    /// See [`Decoder::decode_buffer`].
+    fn decode_static_to_buffer(
+        buffer: &FrameBuffer,
+        output: &mut [<<Self as Decoder>::OutputPixels as Pixel>::Subpixel],
+    ) -> Result<(), NokhwaError>;
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(feature = "async", async_trait::async_trait)]
+pub trait AsyncDecoder: Decoder {
+    /// Asynchronous decoder
+    async fn decode_async(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError>;
+
+    /// Asynchronous decoder to user buffer.
+    async fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [<<Self as Decoder>::OutputPixels as Pixel>::Subpixel],
+    ) -> Result<(), NokhwaError>;
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(feature = "async", async_trait::async_trait)]
+pub trait AsyncStaticDecoder: Decoder {
+    /// Asynchronous decoder
+    async fn decode_static_async(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError>;
+
+    /// Asynchronous decoder to user buffer.
+    async fn decode_static_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [<<Self as Decoder>::OutputPixels as Pixel>::Subpixel],
+    ) -> Result<(), NokhwaError>;
+}
+
+#[cfg(feature = "decoding-yuv")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "decoding-yuv")))]
+pub mod yuv;
+
+#[cfg(feature = "decoding-yuv")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "decoding-yuv")))]
+pub mod luma;
+
+#[cfg(any(feature = "decoding-mozjpeg", feature = "decoding-zune"))]
+#[cfg_attr(
+    feature = "docs-features",
+    doc(cfg(any(feature = "decoding-mozjpeg", feature = "decoding-zune")))
+)]
+pub mod mjpeg;
+
+#[cfg(feature = "decoding-bayer")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "decoding-bayer")))]
+pub mod bayer;
+
+#[cfg(feature = "decoding-h264")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "decoding-h264")))]
+pub mod h264;
@@ -0,0 +1,486 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Decoders for YCbCr frame formats.
+//!
+//! All conversions use BT.601 limited ("studio") range, which is what UVC webcams emit.
+
+use crate::{
+    decoder::{
+        check_format_and_layout, check_output_len, decode_to_image, unsupported_format, Decoder,
+        StaticDecoder,
+    },
+    error::NokhwaError,
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+};
+use image::{ImageBuffer, Luma, Pixel, Rgb, Rgba};
+use std::{marker::PhantomData, ops::ControlFlow};
+
+/// Converts a single BT.601 limited range YCbCr sample to RGB888.
+#[must_use]
+#[inline]
+#[allow(clippy::many_single_char_names)]
+pub fn yuv444_to_rgb888(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = (i32::from(y) - 16) * 298;
+    let d = i32::from(u) - 128;
+    let e = i32::from(v) - 128;
+
+    let r = (c + 409 * e + 128) >> 8;
+    let g = (c - 100 * d - 208 * e + 128) >> 8;
+    let b = (c + 516 * d + 128) >> 8;
+
+    [
+        r.clamp(0, 255) as u8,
+        g.clamp(0, 255) as u8,
+        b.clamp(0, 255) as u8,
+    ]
+}
+
+/// Converts a single BT.601 limited range YCbCr sample with 16 bit precision to RGB with 16 bits
+/// per channel.
+///
+/// Uses 16 bit fixed point coefficients, so that nominal white decodes to [`u16::MAX`].
+#[must_use]
+#[inline]
+#[allow(clippy::many_single_char_names)]
+pub fn yuv444_to_rgb48(y: u16, u: u16, v: u16) -> [u16; 3] {
+    let c = (i64::from(y) - (16 << 8)) * 76607;
+    let d = i64::from(u) - (128 << 8);
+    let e = i64::from(v) - (128 << 8);
+
+    let r = (c + 105_006 * e + 32768) >> 16;
+    let g = (c - 25775 * d - 53487 * e + 32768) >> 16;
+    let b = (c + 132_718 * d + 32768) >> 16;
+
+    [
+        r.clamp(0, 65535) as u16,
+        g.clamp(0, 65535) as u16,
+        b.clamp(0, 65535) as u16,
+    ]
+}
+
+/// A pixel type that a YCbCr decoder can write into.
+pub trait YuvOutputPixel: Pixel<Subpixel = u8> {
+    /// Writes the pixel for the sample (`y`, `u`, `v`) into `output`, which is exactly
+    /// [`CHANNEL_COUNT`](Pixel::CHANNEL_COUNT) long.
+    fn write_from_yuv(y: u8, u: u8, v: u8, output: &mut [u8]);
+}
+
+impl YuvOutputPixel for Rgb<u8> {
+    #[inline]
+    fn write_from_yuv(y: u8, u: u8, v: u8, output: &mut [u8]) {
+        output.copy_from_slice(&yuv444_to_rgb888(y, u, v));
+    }
+}
+
+impl YuvOutputPixel for Rgba<u8> {
+    #[inline]
+    fn write_from_yuv(y: u8, u: u8, v: u8, output: &mut [u8]) {
+        output[..3].copy_from_slice(&yuv444_to_rgb888(y, u, v));
+        output[3] = u8::MAX;
+    }
+}
+
+impl YuvOutputPixel for Luma<u8> {
+    #[inline]
+    fn write_from_yuv(y: u8, _: u8, _: u8, output: &mut [u8]) {
+        output[0] = y;
+    }
+}
+
+/// A pixel type that a 16 bit YCbCr decoder can write into.
+pub trait Yuv16OutputPixel: Pixel<Subpixel = u16> {
+    /// Writes the pixel for the sample (`y`, `u`, `v`) into `output`, which is exactly
+    /// [`CHANNEL_COUNT`](Pixel::CHANNEL_COUNT) long.
+    fn write_from_yuv16(y: u16, u: u16, v: u16, output: &mut [u16]);
+}
+
+impl Yuv16OutputPixel for Rgb<u16> {
+    #[inline]
+    fn write_from_yuv16(y: u16, u: u16, v: u16, output: &mut [u16]) {
+        output.copy_from_slice(&yuv444_to_rgb48(y, u, v));
+    }
+}
+
+impl Yuv16OutputPixel for Luma<u16> {
+    #[inline]
+    fn write_from_yuv16(y: u16, _: u16, _: u16, output: &mut [u16]) {
+        output[0] = y;
+    }
+}
+
+/// Byte offsets of `[Y0, Y1, U, V]` inside a 4 byte macropixel.
+fn packed_422_offsets(frame_format: FrameFormat) -> Option<[usize; 4]> {
+    match frame_format {
+        FrameFormat::Yuyv422 => Some([0, 2, 1, 3]),
+        FrameFormat::Uyvy422 => Some([1, 3, 0, 2]),
+        FrameFormat::Yvyu422 => Some([0, 2, 3, 1]),
+        _ => None,
+    }
+}
+
+/// Chroma plane arrangement of a planar or semi-planar YCbCr format.
+#[derive(Copy, Clone, Debug)]
+struct PlanarLayout {
+    /// Cb and Cr share one plane (`NV12`/`NV21`).
+    interleaved: bool,
+    /// Cb comes before Cr, either in the plane order or inside an interleaved pair.
+    cb_first: bool,
+}
+
+impl PlanarLayout {
+    fn of(frame_format: FrameFormat) -> Option<Self> {
+        let (interleaved, cb_first) = match frame_format {
+            FrameFormat::Nv12 => (true, true),
+            FrameFormat::Nv21 => (true, false),
+            FrameFormat::I420 => (false, true),
+            FrameFormat::Yv12 | FrameFormat::Yvu9 => (false, false),
+            _ => return None,
+        };
+        Some(Self {
+            interleaved,
+            cb_first,
+        })
+    }
+}
+
+/// Decodes packed YCbCr 4:2:2 frames ([`FrameFormat::Yuyv422`], [`FrameFormat::Uyvy422`] and
+/// [`FrameFormat::Yvyu422`]) into [`Rgb<u8>`], [`Rgba<u8>`] or [`Luma<u8>`].
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Yuv422Decoder<P> {
+    _pixel: PhantomData<P>,
+}
+
+impl<P> Yuv422Decoder<P> {
+    /// Creates a new [`Yuv422Decoder`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            _pixel: PhantomData,
+        }
+    }
+}
+
+impl<P> Default for Yuv422Decoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: YuvOutputPixel> Decoder for Yuv422Decoder<P> {
+    const ALLOWED_FORMATS: &'static [FrameFormat] = &[
+        FrameFormat::Yuyv422,
+        FrameFormat::Uyvy422,
+        FrameFormat::Yvyu422,
+    ];
+    type OutputPixels = P;
+    type PixelContainer = Vec<u8>;
+
+    fn check_format(buffer: &FrameBuffer) -> ControlFlow<NokhwaError> {
+        check_format_and_layout::<Self>(buffer, P::COLOR_MODEL)
+    }
+
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        Self::decode_static(buffer)
+    }
+
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [u8],
+    ) -> Result<(), NokhwaError> {
+        Self::decode_static_to_buffer(buffer, output)
+    }
+}
+
+impl<P: YuvOutputPixel> StaticDecoder for Yuv422Decoder<P> {
+    fn decode_static(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        decode_to_image::<Self>(buffer, P::COLOR_MODEL, |output| {
+            Self::decode_static_to_buffer(buffer, output)
+        })
+    }
+
+    fn decode_static_to_buffer(buffer: &FrameBuffer, output: &mut [u8]) -> Result<(), NokhwaError> {
+        if let ControlFlow::Break(why) = Self::check_format(buffer) {
+            return Err(why);
+        }
+
+        let frame_format = buffer.source_frame_format();
+        let required = check_output_len::<Self>(buffer, output.len(), P::COLOR_MODEL)?;
+        if required == 0 {
+            return Ok(());
+        }
+
+        let Some([y0, y1, u, v]) = packed_422_offsets(frame_format) else {
+            return Err(unsupported_format::<Self>(frame_format));
+        };
+        let width = buffer.resolution().width() as usize;
+        let channels = usize::from(P::CHANNEL_COUNT);
+
+        for (row_in, row_out) in buffer
+            .plane_rows(0)
+            .zip(output[..required].chunks_exact_mut(width * channels))
+        {
+            for (x, pixel) in row_out.chunks_exact_mut(channels).enumerate() {
+                let macropixel = &row_in[(x / 2) * 4..(x / 2) * 4 + 4];
+                let luma = if x % 2 == 0 {
+                    macropixel[y0]
+                } else {
+                    macropixel[y1]
+                };
+                P::write_from_yuv(luma, macropixel[u], macropixel[v], pixel);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes planar and semi-planar YCbCr frames ([`FrameFormat::Nv12`], [`FrameFormat::Nv21`],
+/// [`FrameFormat::I420`], [`FrameFormat::Yv12`] and [`FrameFormat::Yvu9`]) into [`Rgb<u8>`],
+/// [`Rgba<u8>`] or [`Luma<u8>`].
+///
+/// Odd widths and heights are supported, the last chroma sample of a row or column then only
+/// covers a single luma sample.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct PlanarYuvDecoder<P> {
+    _pixel: PhantomData<P>,
+}
+
+impl<P> PlanarYuvDecoder<P> {
+    /// Creates a new [`PlanarYuvDecoder`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            _pixel: PhantomData,
+        }
+    }
+}
+
+impl<P> Default for PlanarYuvDecoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: YuvOutputPixel> Decoder for PlanarYuvDecoder<P> {
+    const ALLOWED_FORMATS: &'static [FrameFormat] = &[
+        FrameFormat::Nv12,
+        FrameFormat::Nv21,
+        FrameFormat::I420,
+        FrameFormat::Yv12,
+        FrameFormat::Yvu9,
+    ];
+    type OutputPixels = P;
+    type PixelContainer = Vec<u8>;
+
+    fn check_format(buffer: &FrameBuffer) -> ControlFlow<NokhwaError> {
+        check_format_and_layout::<Self>(buffer, P::COLOR_MODEL)
+    }
+
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        Self::decode_static(buffer)
+    }
+
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [u8],
+    ) -> Result<(), NokhwaError> {
+        Self::decode_static_to_buffer(buffer, output)
+    }
+}
+
+impl<P: YuvOutputPixel> StaticDecoder for PlanarYuvDecoder<P> {
+    fn decode_static(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        decode_to_image::<Self>(buffer, P::COLOR_MODEL, |output| {
+            Self::decode_static_to_buffer(buffer, output)
+        })
+    }
+
+    fn decode_static_to_buffer(buffer: &FrameBuffer, output: &mut [u8]) -> Result<(), NokhwaError> {
+        if let ControlFlow::Break(why) = Self::check_format(buffer) {
+            return Err(why);
+        }
+
+        let frame_format = buffer.source_frame_format();
+        let required = check_output_len::<Self>(buffer, output.len(), P::COLOR_MODEL)?;
+        if required == 0 {
+            return Ok(());
+        }
+
+        let Some(layout) = PlanarLayout::of(frame_format) else {
+            return Err(unsupported_format::<Self>(frame_format));
+        };
+        let width = buffer.resolution().width() as usize;
+        let channels = usize::from(P::CHANNEL_COUNT);
+        let (horizontal_subsampling, vertical_subsampling) =
+            frame_format.descriptor().chroma_subsampling();
+        let (horizontal_subsampling, vertical_subsampling) = (
+            horizontal_subsampling as usize,
+            vertical_subsampling as usize,
+        );
+        let (blue_plane, red_plane) = match (layout.interleaved, layout.cb_first) {
+            (true, _) => (1, 1),
+            (false, true) => (1, 2),
+            (false, false) => (2, 1),
+        };
+        let blue_rows = buffer.plane_rows(blue_plane).collect::<Vec<_>>();
+        let red_rows = buffer.plane_rows(red_plane).collect::<Vec<_>>();
+        let (blue_offset, red_offset) = match (layout.interleaved, layout.cb_first) {
+            (false, _) => (0, 0),
+            (true, true) => (0, 1),
+            (true, false) => (1, 0),
+        };
+        let chroma_step = if layout.interleaved { 2 } else { 1 };
+
+        for (y, (row_in, row_out)) in buffer
+            .plane_rows(0)
+            .zip(output[..required].chunks_exact_mut(width * channels))
+            .enumerate()
+        {
+            let (blue_row, red_row) = (
+                blue_rows[y / vertical_subsampling],
+                red_rows[y / vertical_subsampling],
+            );
+            for (x, (luma, pixel)) in row_in
+                .iter()
+                .zip(row_out.chunks_exact_mut(channels))
+                .enumerate()
+            {
+                let chroma = (x / horizontal_subsampling) * chroma_step;
+                P::write_from_yuv(
+                    *luma,
+                    blue_row[chroma + blue_offset],
+                    red_row[chroma + red_offset],
+                    pixel,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes 16 bit semi-planar YCbCr 4:2:0 frames ([`FrameFormat::P010`] and
+/// [`FrameFormat::P016`]) into [`Rgb<u16>`] or [`Luma<u16>`].
+///
+/// `P010` keeps its 10 bits in the most significant bits of each sample, so both formats are
+/// decoded the same way.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct P010Decoder<P> {
+    _pixel: PhantomData<P>,
+}
+
+impl<P> P010Decoder<P> {
+    /// Creates a new [`P010Decoder`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            _pixel: PhantomData,
+        }
+    }
+}
+
+impl<P> Default for P010Decoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Yuv16OutputPixel> Decoder for P010Decoder<P> {
+    const ALLOWED_FORMATS: &'static [FrameFormat] = &[FrameFormat::P010, FrameFormat::P016];
+    type OutputPixels = P;
+    type PixelContainer = Vec<u16>;
+
+    fn check_format(buffer: &FrameBuffer) -> ControlFlow<NokhwaError> {
+        check_format_and_layout::<Self>(buffer, P::COLOR_MODEL)
+    }
+
+    fn decode(
+        &mut self,
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        Self::decode_static(buffer)
+    }
+
+    fn decode_buffer(
+        &mut self,
+        buffer: &FrameBuffer,
+        output: &mut [u16],
+    ) -> Result<(), NokhwaError> {
+        Self::decode_static_to_buffer(buffer, output)
+    }
+}
+
+impl<P: Yuv16OutputPixel> StaticDecoder for P010Decoder<P> {
+    fn decode_static(
+        buffer: &FrameBuffer,
+    ) -> Result<ImageBuffer<Self::OutputPixels, Self::PixelContainer>, NokhwaError> {
+        decode_to_image::<Self>(buffer, P::COLOR_MODEL, |output| {
+            Self::decode_static_to_buffer(buffer, output)
+        })
+    }
+
+    fn decode_static_to_buffer(
+        buffer: &FrameBuffer,
+        output: &mut [u16],
+    ) -> Result<(), NokhwaError> {
+        if let ControlFlow::Break(why) = Self::check_format(buffer) {
+            return Err(why);
+        }
+
+        let required = check_output_len::<Self>(buffer, output.len(), P::COLOR_MODEL)?;
+        if required == 0 {
+            return Ok(());
+        }
+
+        let width = buffer.resolution().width() as usize;
+        let channels = usize::from(P::CHANNEL_COUNT);
+        let sample = |row: &[u8], idx: usize| u16::from_le_bytes([row[idx * 2], row[idx * 2 + 1]]);
+        let chroma_rows = buffer.plane_rows(1).collect::<Vec<_>>();
+
+        for (y, (row_in, row_out)) in buffer
+            .plane_rows(0)
+            .zip(output[..required].chunks_exact_mut(width * channels))
+            .enumerate()
+        {
+            let chroma_row = chroma_rows[y / 2];
+            for (x, pixel) in row_out.chunks_exact_mut(channels).enumerate() {
+                let chroma = (x / 2) * 2;
+                P::write_from_yuv16(
+                    sample(row_in, x),
+                    sample(chroma_row, chroma),
+                    sample(chroma_row, chroma + 1),
+                    pixel,
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Encoders that turn RGB images into [`FrameBuffer`]s of other [`FrameFormat`]s, the reverse of
+//! [`decoder`](crate::decoder). Useful for software cameras and for converting frames before
+//! writing them out.
+//!
+//! YCbCr formats use BT.601 limited range, matching the decoders. Chroma is averaged over the
+//! pixels that share a sample.
+
+use crate::{
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::{BayerSite, FrameFormat},
+    types::Resolution,
+};
+
+/// Converts a single RGB pixel to a BT.601 limited range YCbCr sample, the inverse of the
+/// conversion the YUV decoders use.
+#[must_use]
+#[inline]
+#[allow(clippy::many_single_char_names)]
+pub fn rgb888_to_yuv444(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+
+    [
+        y.clamp(0, 255) as u8,
+        u.clamp(0, 255) as u8,
+        v.clamp(0, 255) as u8,
+    ]
+}
+
+/// Whether [`encode_rgb888`] can produce `frame_format`.
+#[must_use]
+pub fn can_encode(frame_format: FrameFormat) -> bool {
+    matches!(
+        frame_format,
+        FrameFormat::Rgb888
+            | FrameFormat::RgbA8888
+            | FrameFormat::ARgb8888
+            | FrameFormat::Luma8
+            | FrameFormat::Luma16
+            | FrameFormat::Yuyv422
+            | FrameFormat::Uyvy422
+            | FrameFormat::Yvyu422
+            | FrameFormat::Nv12
+            | FrameFormat::Nv21
+            | FrameFormat::I420
+            | FrameFormat::Yv12
+            | FrameFormat::P010
+            | FrameFormat::P016
+            | FrameFormat::Bayer8(_)
+            | FrameFormat::Bayer16(_)
+    )
+}
+
+/// Encodes a tightly packed RGB image into a `frame_format` frame, with tightly packed planes.
+///
+/// # Errors
+/// If `rgb` does not hold exactly `resolution` pixels, or `frame_format` cannot be encoded, see
+/// [`can_encode`].
+pub fn encode_rgb888(
+    resolution: Resolution,
+    rgb: &[u8],
+    frame_format: FrameFormat,
+) -> NokhwaResult<FrameBuffer> {
+    let error = |error: String| NokhwaError::ProcessFrameError {
+        src: FrameFormat::Rgb888,
+        destination: frame_format.to_string(),
+        error,
+    };
+
+    let (width, height) = (resolution.width() as usize, resolution.height() as usize);
+    if rgb.len() != width * height * 3 {
+        return Err(error(format!(
+            "expected {} bytes of RGB, got {}",
+            width * height * 3,
+            rgb.len()
+        )));
+    }
+    if !can_encode(frame_format) {
+        return Err(error("no encoder for this format".to_string()));
+    }
+
+    let image = RgbImage { rgb, width, height };
+    let data = match frame_format {
+        FrameFormat::Rgb888 => rgb.to_vec(),
+        FrameFormat::RgbA8888 => rgb
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], u8::MAX])
+            .collect(),
+        FrameFormat::ARgb8888 => rgb
+            .chunks_exact(3)
+            .flat_map(|pixel| [u8::MAX, pixel[0], pixel[1], pixel[2]])
+            .collect(),
+        FrameFormat::Luma8 => image.luma().collect(),
+        FrameFormat::Luma16 => image
+            .luma()
+            .flat_map(|luma| (u16::from(luma) * 257).to_le_bytes())
+            .collect(),
+        FrameFormat::Yuyv422 | FrameFormat::Uyvy422 | FrameFormat::Yvyu422 => {
+            image.packed_422(frame_format)
+        }
+        FrameFormat::Nv12 | FrameFormat::Nv21 | FrameFormat::I420 | FrameFormat::Yv12 => {
+            image.planar_420(frame_format)
+        }
+        FrameFormat::P010 | FrameFormat::P016 => {
+            // 8 bit samples widen by repeating them, P010 keeps the top 10 bits.
+            let mask = if frame_format == FrameFormat::P010 {
+                0xFFC0
+            } else {
+                0xFFFF
+            };
+            image
+                .planar_420(FrameFormat::Nv12)
+                .into_iter()
+                .flat_map(|sample| ((u16::from(sample) * 257) & mask).to_le_bytes())
+                .collect()
+        }
+        FrameFormat::Bayer8(pattern) | FrameFormat::Bayer16(pattern) => {
+            let wide = matches!(frame_format, FrameFormat::Bayer16(_));
+            let mut data = Vec::with_capacity(width * height * if wide { 2 } else { 1 });
+            for y in 0..height {
+                for x in 0..width {
+                    let [r, g, b] = image.pixel(x, y);
+                    let sample = match pattern.site_at(x, y) {
+                        BayerSite::Red => r,
+                        BayerSite::GreenRed | BayerSite::GreenBlue => g,
+                        BayerSite::Blue => b,
+                    };
+                    if wide {
+                        data.extend_from_slice(&(u16::from(sample) * 257).to_le_bytes());
+                    } else {
+                        data.push(sample);
+                    }
+                }
+            }
+            data
+        }
+        _ => unreachable!("checked by can_encode"),
+    };
+
+    Ok(FrameBuffer::from_vec(resolution, data, frame_format))
+}
+
+struct RgbImage<'a> {
+    rgb: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+#[allow(clippy::many_single_char_names)]
+impl RgbImage<'_> {
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let idx = (y * self.width + x) * 3;
+        [self.rgb[idx], self.rgb[idx + 1], self.rgb[idx + 2]]
+    }
+
+    fn luma(&self) -> impl Iterator<Item = u8> + '_ {
+        self.rgb
+            .chunks_exact(3)
+            .map(|pixel| rgb888_to_yuv444(pixel[0], pixel[1], pixel[2])[0])
+    }
+
+    /// The averaged chroma of the block of `block_width` by `block_height` pixels at `x`, `y`,
+    /// clipped to the image.
+    fn chroma(&self, x: usize, y: usize, block_width: usize, block_height: usize) -> [u8; 2] {
+        let (mut u, mut v, mut count) = (0_u32, 0_u32, 0_u32);
+        for block_y in y..(y + block_height).min(self.height) {
+            for block_x in x..(x + block_width).min(self.width) {
+                let [r, g, b] = self.pixel(block_x, block_y);
+                let [_, pixel_u, pixel_v] = rgb888_to_yuv444(r, g, b);
+                u += u32::from(pixel_u);
+                v += u32::from(pixel_v);
+                count += 1;
+            }
+        }
+        [
+            ((u + count / 2) / count) as u8,
+            ((v + count / 2) / count) as u8,
+        ]
+    }
+
+    fn packed_422(&self, frame_format: FrameFormat) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.width.div_ceil(2) * 4 * self.height);
+        for y in 0..self.height {
+            for x in (0..self.width).step_by(2) {
+                let [r, g, b] = self.pixel(x, y);
+                let y0 = rgb888_to_yuv444(r, g, b)[0];
+                // An odd last column repeats its pixel.
+                let [r, g, b] = self.pixel((x + 1).min(self.width - 1), y);
+                let y1 = rgb888_to_yuv444(r, g, b)[0];
+                let [u, v] = self.chroma(x, y, 2, 1);
+                data.extend_from_slice(&match frame_format {
+                    FrameFormat::Uyvy422 => [u, y0, v, y1],
+                    FrameFormat::Yvyu422 => [y0, v, y1, u],
+                    _ => [y0, u, y1, v],
+                });
+            }
+        }
+        data
+    }
+
+    fn planar_420(&self, frame_format: FrameFormat) -> Vec<u8> {
+        let (chroma_width, chroma_height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut luma: Vec<u8> = self.luma().collect();
+        let (mut cb, mut cr) = (
+            Vec::with_capacity(chroma_width * chroma_height),
+            Vec::with_capacity(chroma_width * chroma_height),
+        );
+        for y in 0..chroma_height {
+            for x in 0..chroma_width {
+                let [u, v] = self.chroma(x * 2, y * 2, 2, 2);
+                cb.push(u);
+                cr.push(v);
+            }
+        }
+
+        match frame_format {
+            FrameFormat::Nv12 => luma.extend(cb.into_iter().zip(cr).flat_map(|(u, v)| [u, v])),
+            FrameFormat::Nv21 => luma.extend(cr.into_iter().zip(cb).flat_map(|(v, u)| [v, u])),
+            FrameFormat::Yv12 => {
+                luma.extend(cr);
+                luma.extend(cb);
+            }
+            _ => {
+                luma.extend(cb);
+                luma.extend(cr);
+            }
+        }
+        luma
+    }
+}
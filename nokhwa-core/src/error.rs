@@ -13,8 +13,9 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use crate::{frame_format::FrameFormat, types::ApiBackend};
+use crate::frame_format::FrameFormat;
 use std::fmt::{Debug};
+use std::time::Duration;
 use thiserror::Error;
 use crate::platform::Backends;
 
@@ -27,9 +28,9 @@ pub enum NokhwaError {
     #[error("Unitialized Camera. Call `init()` first!")]
     UnitializedError,
     #[error("Could not initialize {backend}: {error}")]
-    InitializeError { backend: ApiBackend, error: String },
+    InitializeError { backend: Backends, error: String },
     #[error("Could not shutdown {backend}: {error}")]
-    ShutdownError { backend: ApiBackend, error: String },
+    ShutdownError { backend: Backends, error: String },
     #[error("Error: {0}")]
     GeneralError(String),
     #[error("Could not generate required structure {structure}: {error}")]
@@ -48,12 +49,20 @@ pub enum NokhwaError {
     OpenStreamError(String),
     #[error("Could not capture frame: {0}")]
     ReadFrameError(String),
+    #[error("Timed out after {0:?} waiting for a frame")]
+    FrameTimeoutError(Duration),
     #[error("Could not process frame {src} to {destination}: {error}")]
     ProcessFrameError {
         src: FrameFormat,
         destination: String,
         error: String,
     },
+    #[error("Corrupt {src} frame: {error}")]
+    CorruptFrameError { src: FrameFormat, error: String },
+    #[error("Cannot decode {0} frame before the next keyframe")]
+    MissingKeyframeError(FrameFormat),
+    #[error("{0} frame holds no picture yet")]
+    NoPictureError(FrameFormat),
     #[error("Could not stop stream: {0}")]
     StreamShutdownError(String),
     #[error("This operation is not supported by backend {0}.")]
@@ -7,14 +7,6 @@ use crate::{
 use std::cmp::Ordering;
 use crate::ranges::ValidatableRange;
 
-#[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
-enum ClosestType {
-    Resolution,
-    FrameRate,
-    Both,
-    None,
-}
-
 #[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
 pub enum CustomFormatRequestType {
     HighestFrameRate,
@@ -50,6 +42,8 @@ pub enum FormatRequest {
 }
 
 impl FormatRequest {
+    /// The formats of `list_of_formats` that fit this request, best first.
+    #[must_use]
     pub fn sort_formats(&self, list_of_formats: &[CameraFormat]) -> Vec<CameraFormat> {
         if list_of_formats.is_empty() {
             return vec![];
@@ -130,7 +124,7 @@ impl FormatRequest {
         }
     }
 
-    ///
+    /// The format of `list_of_formats` that fits this request best, if any does.
     #[must_use]
     pub fn resolve(&self, list_of_formats: &[CameraFormat]) -> Option<CameraFormat> {
         if list_of_formats.is_empty() {
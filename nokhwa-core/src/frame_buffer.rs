@@ -14,30 +14,206 @@
  * limitations under the License.
  */
 
+use crate::buffer_pool::PooledBuffer;
 use crate::frame_format::FrameFormat;
 use crate::types::Resolution;
 use bytes::Bytes;
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+/// Where a single plane of a [`FrameBuffer`] lives inside its data.
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, PartialEq, Eq)]
+pub struct FramePlane {
+    offset: usize,
+    stride: usize,
+}
+
+impl FramePlane {
+    /// Creates a new plane starting `offset` bytes into the buffer, with rows `stride` bytes apart.
+    #[must_use]
+    pub const fn new(offset: usize, stride: usize) -> Self {
+        Self { offset, stride }
+    }
+
+    /// Lays out the planes of a `source_frame_format` frame one after another. `strides` holds
+    /// the stride of each plane, like `bytesperline` on V4L2.
+    ///
+    /// Planes without a stride get one derived from the first stride, the same way V4L2 does for
+    /// single-planar formats. Planes without any stride at all are tightly packed.
+    #[must_use]
+    pub fn contiguous(
+        res: Resolution,
+        source_frame_format: FrameFormat,
+        strides: &[usize],
+    ) -> Vec<Self> {
+        let layouts = source_frame_format.descriptor().planes();
+        let mut offset = 0;
+        layouts
+            .iter()
+            .enumerate()
+            .map(|(idx, layout)| {
+                let stride = strides.get(idx).copied().unwrap_or_else(|| {
+                    let first = layouts[0];
+                    let first_stride = strides
+                        .first()
+                        .copied()
+                        .unwrap_or_else(|| first.row_size(res));
+                    let derived = first_stride
+                        .saturating_mul(layout.bits_per_pixel() as usize)
+                        .div_ceil(
+                            first.bits_per_pixel() as usize
+                                * layout.horizontal_subsampling() as usize,
+                        );
+                    derived.max(layout.row_size(res))
+                });
+                let plane = Self::new(offset, stride);
+                // Saturated rather than wrapped, so that a layout too large for memory fails
+                // `FrameBuffer::check_planes`.
+                offset = offset.saturating_add(stride.saturating_mul(layout.dimensions(res).1));
+                plane
+            })
+            .collect()
+    }
+
+    /// Get the offset in bytes of the first row of this plane.
+    #[must_use]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the distance in bytes between the starts of two rows of this plane. This may be larger
+    /// than the row itself if the driver pads rows.
+    #[must_use]
+    pub const fn stride(&self) -> usize {
+        self.stride
+    }
+}
 
 /// A buffer returned by a camera to accommodate custom decoding.
-/// Contains information of Resolution, the buffer's [`FrameFormat`], and the buffer.
+/// Contains information of Resolution, the buffer's [`FrameFormat`], the buffer, and where each
+/// plane of the frame is inside the buffer.
+///
+/// Every frame also carries a monotonic capture timestamp, the driver's own timestamp if it has
+/// one, and a sequence number. Sequence numbers are assigned by the stream's
+/// [`FrameSender`](crate::stream::FrameSender), so a frame dropped anywhere between the driver and
+/// the consumer shows up as a gap in them.
+///
+/// Frames compare, order and hash by their picture only: resolution, format, planes and data. The
+/// sequence number and timestamps are left out, so the same picture captured twice is equal.
 ///
 /// Note that decoding on the main thread **will** decrease your performance and lead to dropped frames.
-#[derive(Clone, Debug, Hash, PartialOrd, PartialEq, Eq)]
+#[derive(Clone, Debug)]
 pub struct FrameBuffer {
     resolution: Resolution,
     buffer: Bytes,
     source_frame_format: FrameFormat,
+    planes: Vec<FramePlane>,
+    sequence: u64,
+    timestamp: Instant,
+    driver_timestamp: Option<Duration>,
 }
 
 impl FrameBuffer {
-    /// Creates a new buffer with a [`&[u8]`].
+    /// The fields that make up the picture, which is what frames are compared by.
+    fn picture(&self) -> (Resolution, &Bytes, FrameFormat, &[FramePlane]) {
+        (
+            self.resolution,
+            &self.buffer,
+            self.source_frame_format,
+            &self.planes,
+        )
+    }
+
+    /// Creates a new buffer with a [`&[u8]`], timestamped now. The planes of the frame are assumed
+    /// to be tightly packed one after another, in the order of [`FrameFormat::descriptor`].
+    ///
+    /// This copies `buf`, see [`FrameBuffer::from_bytes`] to avoid that.
     #[must_use]
     #[inline]
     pub fn new(res: Resolution, buf: &[u8], source_frame_format: FrameFormat) -> Self {
+        Self::from_bytes(res, Bytes::copy_from_slice(buf), source_frame_format)
+    }
+
+    /// Creates a new buffer with a [`&[u8]`] whose planes follow each other, but whose rows may be
+    /// padded. See [`FramePlane::contiguous`] for the meaning of `strides`.
+    #[must_use]
+    pub fn with_strides(
+        res: Resolution,
+        buf: &[u8],
+        source_frame_format: FrameFormat,
+        strides: &[usize],
+    ) -> Self {
+        Self::with_planes(
+            res,
+            buf,
+            source_frame_format,
+            FramePlane::contiguous(res, source_frame_format, strides),
+        )
+    }
+
+    /// Creates a new buffer with a [`&[u8]`] and the location of every plane inside it, for
+    /// frames whose planes are not contiguous.
+    #[must_use]
+    pub fn with_planes(
+        res: Resolution,
+        buf: &[u8],
+        source_frame_format: FrameFormat,
+        planes: Vec<FramePlane>,
+    ) -> Self {
+        Self::from_bytes_with_planes(
+            res,
+            Bytes::copy_from_slice(buf),
+            source_frame_format,
+            planes,
+        )
+    }
+
+    /// Creates a new buffer that takes ownership of `buf` without copying it. The planes of the
+    /// frame are assumed to be tightly packed.
+    #[must_use]
+    pub fn from_bytes(res: Resolution, buf: Bytes, source_frame_format: FrameFormat) -> Self {
+        let planes = FramePlane::contiguous(res, source_frame_format, &[]);
+        Self::from_bytes_with_planes(res, buf, source_frame_format, planes)
+    }
+
+    /// Creates a new buffer that takes ownership of `buf` without copying it. The planes of the
+    /// frame are assumed to be tightly packed.
+    #[must_use]
+    pub fn from_vec(res: Resolution, buf: Vec<u8>, source_frame_format: FrameFormat) -> Self {
+        Self::from_bytes(res, Bytes::from(buf), source_frame_format)
+    }
+
+    /// Creates a new buffer from a [`PooledBuffer`] without copying it. The memory goes back to
+    /// its pool once this frame and all of its clones are dropped.
+    #[must_use]
+    pub fn from_pooled(
+        res: Resolution,
+        buf: PooledBuffer,
+        source_frame_format: FrameFormat,
+    ) -> Self {
+        Self::from_bytes(res, buf.freeze(), source_frame_format)
+    }
+
+    /// Creates a new buffer that takes ownership of `buf` without copying it, with the location
+    /// of every plane inside it.
+    #[must_use]
+    pub fn from_bytes_with_planes(
+        res: Resolution,
+        buf: Bytes,
+        source_frame_format: FrameFormat,
+        planes: Vec<FramePlane>,
+    ) -> Self {
         Self {
             resolution: res,
-            buffer: Bytes::copy_from_slice(buf),
+            buffer: buf,
             source_frame_format,
+            planes,
+            sequence: 0,
+            timestamp: Instant::now(),
+            driver_timestamp: None,
         }
     }
 
@@ -53,15 +229,304 @@ impl FrameBuffer {
         &self.buffer
     }
 
-    /// Get an owned version of this buffer. Note: This is the equivalent
+    /// Get an owned version of this buffer. This does not copy the data, the returned [`Bytes`]
+    /// shares it with this frame.
     #[must_use]
     pub fn buffer_bytes(&self) -> Bytes {
         self.buffer.clone()
     }
 
-    /// Get the [`SourceFrameFormat`] of this buffer.
+    /// Get the [`FrameFormat`] of this buffer.
     #[must_use]
     pub fn source_frame_format(&self) -> FrameFormat {
         self.source_frame_format
     }
+
+    /// Get the sequence number of this frame.
+    #[must_use]
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Set the sequence number of this frame. This is normally done by
+    /// [`FrameSender::send`](crate::stream::FrameSender::send).
+    pub fn set_sequence(&mut self, sequence: u64) {
+        self.sequence = sequence;
+    }
+
+    /// Get the monotonic time at which this frame was captured.
+    #[must_use]
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+
+    /// Set the monotonic time at which this frame was captured, for backends that know it more
+    /// precisely than the time the [`FrameBuffer`] was created.
+    pub fn set_timestamp(&mut self, timestamp: Instant) {
+        self.timestamp = timestamp;
+    }
+
+    /// Get the timestamp the driver attached to this frame, if any. Its epoch depends on the
+    /// driver (e.g. `CLOCK_MONOTONIC` on V4L2), so it is only meaningful relative to the driver
+    /// timestamps of other frames.
+    #[must_use]
+    pub fn driver_timestamp(&self) -> Option<Duration> {
+        self.driver_timestamp
+    }
+
+    /// Set the timestamp the driver attached to this frame.
+    pub fn set_driver_timestamp(&mut self, driver_timestamp: Option<Duration>) {
+        self.driver_timestamp = driver_timestamp;
+    }
+
+    /// Get the [`FramePlane`]s of this buffer. Empty for compressed formats.
+    #[must_use]
+    pub fn planes(&self) -> &[FramePlane] {
+        &self.planes
+    }
+
+    /// Get row `row` of plane `plane`, without any padding. Returns [`None`] if the plane or row
+    /// does not exist or is not inside the buffer.
+    #[must_use]
+    pub fn plane_row(&self, plane: usize, row: usize) -> Option<&[u8]> {
+        let layout = self
+            .source_frame_format
+            .descriptor()
+            .planes()
+            .get(plane)
+            .copied()?;
+        let location = self.planes.get(plane)?;
+        if row >= layout.dimensions(self.resolution).1 {
+            return None;
+        }
+
+        let start = location
+            .stride()
+            .checked_mul(row)?
+            .checked_add(location.offset())?;
+        let end = start.checked_add(layout.row_size(self.resolution))?;
+        self.buffer.get(start..end)
+    }
+
+    /// Iterate over the rows of plane `plane`, without any padding. Stops early at the first row
+    /// that is not inside the buffer.
+    pub fn plane_rows(&self, plane: usize) -> impl Iterator<Item = &[u8]> + '_ {
+        let rows = self
+            .source_frame_format
+            .descriptor()
+            .planes()
+            .get(plane)
+            .map_or(0, |layout| layout.dimensions(self.resolution).1);
+        (0..rows).map_while(move |row| self.plane_row(plane, row))
+    }
+
+    /// Checks that every plane of [`FrameFormat::descriptor`] has a [`FramePlane`], that all of
+    /// their rows are inside the buffer, and that the buffer ends where the planes do. The last
+    /// row may come with or without its padding, as drivers differ in that.
+    ///
+    /// # Errors
+    /// A description of the first plane that does not fit.
+    pub fn check_planes(&self) -> Result<(), String> {
+        let layouts = self.source_frame_format.descriptor().planes();
+        if self.planes.len() != layouts.len() {
+            return Err(format!(
+                "expected {} planes, got {}",
+                layouts.len(),
+                self.planes.len()
+            ));
+        }
+
+        // Where the planes end, without and with the padding of their last row.
+        let (mut end, mut padded_end) = (0, 0);
+        for (idx, (layout, location)) in layouts.iter().zip(&self.planes).enumerate() {
+            let row_size = layout.row_size(self.resolution);
+            let rows = layout.dimensions(self.resolution).1;
+            if location.stride() < row_size {
+                return Err(format!(
+                    "plane {idx} has a stride of {}, but its rows are {row_size} bytes",
+                    location.stride()
+                ));
+            }
+
+            let plane_padded_end = location
+                .stride()
+                .checked_mul(rows)
+                .and_then(|size| size.checked_add(location.offset()))
+                .ok_or_else(|| format!("plane {idx} is larger than memory"))?;
+            let plane_end = match rows {
+                0 => location.offset(),
+                _ => plane_padded_end - (location.stride() - row_size),
+            };
+            if plane_end > self.buffer.len() {
+                return Err(format!(
+                    "plane {idx} ends at byte {plane_end}, but the buffer is {} bytes",
+                    self.buffer.len()
+                ));
+            }
+            end = end.max(plane_end);
+            padded_end = padded_end.max(plane_padded_end);
+        }
+
+        if !layouts.is_empty() && self.buffer.len() != end && self.buffer.len() != padded_end {
+            return Err(format!(
+                "the planes end at byte {end} ({padded_end} with padding), but the buffer is {} \
+                 bytes",
+                self.buffer.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for FrameBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.picture() == other.picture()
+    }
+}
+
+impl Eq for FrameBuffer {}
+
+impl PartialOrd for FrameBuffer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.picture().partial_cmp(&other.picture())
+    }
+}
+
+impl Hash for FrameBuffer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.picture().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_leave_out_their_padding() {
+        let data: Vec<u8> = (0..16).collect();
+        let frame =
+            FrameBuffer::with_strides(Resolution::new(2, 2), &data, FrameFormat::Yuyv422, &[8]);
+        assert_eq!(frame.planes(), [FramePlane::new(0, 8)]);
+        assert_eq!(frame.plane_row(0, 1), Some(&data[8..12]));
+        assert_eq!(frame.plane_row(0, 2), None);
+        assert_eq!(frame.plane_rows(0).count(), 2);
+    }
+
+    #[test]
+    fn planes_must_fill_the_buffer_exactly() {
+        let resolution = Resolution::new(4, 4);
+        assert!(FrameBuffer::new(resolution, &[0; 24], FrameFormat::I420)
+            .check_planes()
+            .is_ok());
+        assert!(FrameBuffer::new(resolution, &[0; 23], FrameFormat::I420)
+            .check_planes()
+            .is_err());
+        assert!(FrameBuffer::new(resolution, &[0; 25], FrameFormat::I420)
+            .check_planes()
+            .is_err());
+
+        // With or without the padding of the last row.
+        let padded = |length: usize| {
+            FrameBuffer::with_strides(
+                Resolution::new(2, 2),
+                &vec![0; length],
+                FrameFormat::Yuyv422,
+                &[8],
+            )
+            .check_planes()
+        };
+        assert!(padded(12).is_ok());
+        assert!(padded(16).is_ok());
+        assert!(padded(14).is_err());
+        assert!(padded(17).is_err());
+    }
+
+    #[test]
+    fn planes_must_match_the_format() {
+        let frame = FrameBuffer::with_planes(
+            Resolution::new(2, 2),
+            &[0; 8],
+            FrameFormat::Yuyv422,
+            vec![FramePlane::new(0, 2)],
+        );
+        assert!(frame.check_planes().is_err());
+
+        let frame = FrameBuffer::with_planes(
+            Resolution::new(2, 2),
+            &[0; 8],
+            FrameFormat::Yuyv422,
+            Vec::new(),
+        );
+        assert!(frame.check_planes().is_err());
+    }
+
+    #[test]
+    fn huge_layouts_do_not_overflow() {
+        let frame = FrameBuffer::with_planes(
+            Resolution::new(2, 2),
+            &[0; 8],
+            FrameFormat::Yuyv422,
+            vec![FramePlane::new(usize::MAX - 2, usize::MAX / 2)],
+        );
+        assert_eq!(frame.plane_row(0, 0), None);
+        assert_eq!(frame.plane_row(0, 1), None);
+        assert!(frame.check_planes().is_err());
+
+        let frame = FrameBuffer::with_strides(
+            Resolution::new(2, 2),
+            &[0; 8],
+            FrameFormat::Nv12,
+            &[usize::MAX / 2],
+        );
+        assert!(frame.check_planes().is_err());
+    }
+
+    #[test]
+    fn new_frames_are_timestamped_now() {
+        let before = Instant::now();
+        let frame = FrameBuffer::new(Resolution::new(1, 1), &[0], FrameFormat::Luma8);
+        assert!(frame.timestamp() >= before && frame.timestamp() <= Instant::now());
+        assert_eq!(frame.sequence(), 0);
+        assert_eq!(frame.driver_timestamp(), None);
+    }
+
+    #[test]
+    fn owned_buffers_are_not_copied() {
+        let data = vec![0; 4];
+        let memory = data.as_ptr();
+        let frame = FrameBuffer::from_vec(Resolution::new(2, 2), data, FrameFormat::Luma8);
+        assert_eq!(frame.buffer().as_ptr(), memory);
+
+        let bytes = Bytes::from_static(&[0; 4]);
+        let frame =
+            FrameBuffer::from_bytes(Resolution::new(2, 2), bytes.clone(), FrameFormat::Luma8);
+        assert_eq!(frame.buffer().as_ptr(), bytes.as_ptr());
+        assert_eq!(frame.planes(), [FramePlane::new(0, 2)]);
+    }
+
+    #[test]
+    fn frames_are_equal_by_their_picture_only() {
+        let frame = FrameBuffer::new(Resolution::new(2, 1), &[1, 2], FrameFormat::Luma8);
+        let mut recaptured = frame.clone();
+        recaptured.set_sequence(7);
+        recaptured.set_timestamp(frame.timestamp() + Duration::from_millis(5));
+        recaptured.set_driver_timestamp(Some(Duration::from_secs(1)));
+        assert_eq!(frame, recaptured);
+        assert_eq!(frame.partial_cmp(&recaptured), Some(Ordering::Equal));
+        assert_eq!(
+            std::collections::HashSet::from([frame.clone(), recaptured]).len(),
+            1
+        );
+
+        let other = FrameBuffer::new(Resolution::new(2, 1), &[1, 3], FrameFormat::Luma8);
+        assert_ne!(frame, other);
+    }
+
+    #[test]
+    fn compressed_frames_have_no_planes() {
+        let frame = FrameBuffer::new(Resolution::new(640, 480), &[0xFF; 7], FrameFormat::MJpeg);
+        assert!(frame.planes().is_empty());
+        assert!(frame.check_planes().is_ok());
+    }
 }
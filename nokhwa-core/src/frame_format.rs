@@ -14,6 +14,7 @@
  * limitations under the License.
  */
 
+use crate::types::Resolution;
 use std::fmt::{Display, Formatter};
 
 /// Describes a frame format (i.e. how the bytes themselves are encoded). Often called `FourCC`.
@@ -50,12 +51,24 @@ pub enum FrameFormat {
     Nv12,
     Nv21,
     I420,
+    /// Semi-planar 4:2:0 like [`FrameFormat::Nv12`], with 16 bit little endian samples that hold
+    /// 10 bits of data in their most significant bits.
+    P010,
+    /// Semi-planar 4:2:0 like [`FrameFormat::Nv12`], with 16 bit little endian samples.
+    P016,
 
     // 16:1:1
     Yvu9,
 
     // Grayscale Formats
     Luma8,
+    /// 10 bit samples in the least significant bits of 16 bit little endian words.
+    Luma10,
+    /// 12 bit samples in the least significant bits of 16 bit little endian words.
+    Luma12,
+    /// MIPI CSI-2 packed 10 bit samples: every 4 pixels are stored in 5 bytes, the first 4 holding
+    /// the 8 most significant bits of each pixel and the last one their 2 least significant bits.
+    Luma10Packed,
     Luma16,
 
     // Depth
@@ -72,89 +85,450 @@ pub enum FrameFormat {
     ARgb8888,
 
     // Bayer Formats
-    Bayer8,
-    Bayer16,
+    Bayer8(BayerPattern),
+    Bayer16(BayerPattern),
 
     // Custom
     Custom([u8; 8]),
 }
 
-// FIXME: Fix these frame format lists! Maybe move to using a macro..?
-impl FrameFormat {
-    pub const ALL: &'static [FrameFormat] = &[
-        FrameFormat::H263,
-        FrameFormat::H264,
-        FrameFormat::H265,
-        FrameFormat::Av1,
-        FrameFormat::Avc1,
-        FrameFormat::Mpeg1,
-        FrameFormat::Mpeg2,
-        FrameFormat::Mpeg4,
-        FrameFormat::MJpeg,
-        FrameFormat::XVid,
-        FrameFormat::VP8,
-        FrameFormat::VP9,
-        FrameFormat::Yuyv422,
-        FrameFormat::Uyvy422,
-        FrameFormat::Nv12,
-        FrameFormat::Nv21,
-        FrameFormat::Yv12,
-        FrameFormat::Luma8,
-        FrameFormat::Luma16,
-        FrameFormat::Rgb332,
-        FrameFormat::RgbA8888,
-    ];
+/// The color filter array arrangement of a Bayer frame, named after the top-left 2x2 block of
+/// the sensor, read left to right, top to bottom.
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
 
-    pub const COMPRESSED: &'static [FrameFormat] = &[
-        FrameFormat::H263,
-        FrameFormat::H264,
-        FrameFormat::H265,
-        FrameFormat::Av1,
-        FrameFormat::Avc1,
-        FrameFormat::Mpeg1,
-        FrameFormat::Mpeg2,
-        FrameFormat::Mpeg4,
-        FrameFormat::MJpeg,
-        FrameFormat::XVid,
-        FrameFormat::VP8,
-        FrameFormat::VP9,
-    ];
+/// A single site of a [`BayerPattern`].
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub enum BayerSite {
+    Red,
+    /// A green site on a row shared with red sites.
+    GreenRed,
+    /// A green site on a row shared with blue sites.
+    GreenBlue,
+    Blue,
+}
 
-    pub const CHROMA: &'static [FrameFormat] = &[
-        FrameFormat::Yuyv422,
-        FrameFormat::Uyvy422,
-        FrameFormat::Nv12,
-        FrameFormat::Nv21,
-        FrameFormat::Yv12,
+impl BayerPattern {
+    pub const ALL: &'static [BayerPattern] = &[
+        BayerPattern::Rggb,
+        BayerPattern::Bggr,
+        BayerPattern::Grbg,
+        BayerPattern::Gbrg,
     ];
 
-    pub const LUMA: &'static [FrameFormat] = &[FrameFormat::Luma8, FrameFormat::Luma16];
-
-    pub const RGB: &'static [FrameFormat] = &[FrameFormat::Rgb332, FrameFormat::RgbA8888];
-
-    pub const COLOR_FORMATS: &'static [FrameFormat] = &[
-        FrameFormat::H265,
-        FrameFormat::H264,
-        FrameFormat::H263,
-        FrameFormat::Av1,
-        FrameFormat::Avc1,
-        FrameFormat::Mpeg1,
-        FrameFormat::Mpeg2,
-        FrameFormat::Mpeg4,
-        FrameFormat::MJpeg,
-        FrameFormat::XVid,
-        FrameFormat::VP8,
-        FrameFormat::VP9,
-        FrameFormat::Yuyv422,
-        FrameFormat::Uyvy422,
-        FrameFormat::Nv12,
-        FrameFormat::Nv21,
-        FrameFormat::Yv12,
-        FrameFormat::Rgb332,
-        FrameFormat::RgbA8888,
-    ];
+    /// Gets the [`BayerSite`] at the sensor coordinates `x`, `y`.
+    #[must_use]
+    pub fn site_at(self, x: usize, y: usize) -> BayerSite {
+        let rows = match self {
+            BayerPattern::Rggb => [
+                [BayerSite::Red, BayerSite::GreenRed],
+                [BayerSite::GreenBlue, BayerSite::Blue],
+            ],
+            BayerPattern::Bggr => [
+                [BayerSite::Blue, BayerSite::GreenBlue],
+                [BayerSite::GreenRed, BayerSite::Red],
+            ],
+            BayerPattern::Grbg => [
+                [BayerSite::GreenRed, BayerSite::Red],
+                [BayerSite::Blue, BayerSite::GreenBlue],
+            ],
+            BayerPattern::Gbrg => [
+                [BayerSite::GreenBlue, BayerSite::Blue],
+                [BayerSite::Red, BayerSite::GreenRed],
+            ],
+        };
+        rows[y % 2][x % 2]
+    }
+}
+
+impl Display for BayerPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// The broad family a [`FrameFormat`] belongs to.
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrameFormatCategory {
+    /// Compressed video or image formats, which do not have a fixed layout.
+    Compressed,
+    /// YCbCr formats.
+    Chroma,
+    /// Grayscale formats.
+    Luma,
+    /// Depth map formats.
+    Depth,
+    /// RGB formats.
+    Rgb,
+    /// Raw Bayer sensor data.
+    Bayer,
+    /// [`FrameFormat::Custom`], of which nothing is known.
+    Custom,
+}
+
+/// Memory layout of a single plane of an uncompressed frame.
+///
+/// A plane is made of rows of blocks. A block holds [`block_width`](PlaneLayout::block_width)
+/// consecutive samples in [`bits_per_block`](PlaneLayout::bits_per_block) bits, and rows are
+/// padded to a whole block. Most formats store one sample per block, packed ones like
+/// [`FrameFormat::Yuyv422`] or [`FrameFormat::Luma10Packed`] store more.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct PlaneLayout {
+    horizontal_subsampling: u32,
+    vertical_subsampling: u32,
+    block_width: u32,
+    bits_per_block: u32,
+}
+
+impl PlaneLayout {
+    const fn full(bits_per_pixel: u32) -> Self {
+        Self::subsampled(1, 1, bits_per_pixel)
+    }
+
+    const fn subsampled(horizontal: u32, vertical: u32, bits_per_pixel: u32) -> Self {
+        Self {
+            horizontal_subsampling: horizontal,
+            vertical_subsampling: vertical,
+            block_width: 1,
+            bits_per_block: bits_per_pixel,
+        }
+    }
+
+    const fn packed(block_width: u32, bits_per_block: u32) -> Self {
+        Self {
+            horizontal_subsampling: 1,
+            vertical_subsampling: 1,
+            block_width,
+            bits_per_block,
+        }
+    }
+
+    /// How many pixels of the frame share a sample of this plane horizontally.
+    #[must_use]
+    pub const fn horizontal_subsampling(self) -> u32 {
+        self.horizontal_subsampling
+    }
+
+    /// How many pixels of the frame share a sample of this plane vertically.
+    #[must_use]
+    pub const fn vertical_subsampling(self) -> u32 {
+        self.vertical_subsampling
+    }
+
+    /// How many samples are stored together in a block.
+    #[must_use]
+    pub const fn block_width(self) -> u32 {
+        self.block_width
+    }
+
+    /// Size of a block in bits.
+    #[must_use]
+    pub const fn bits_per_block(self) -> u32 {
+        self.bits_per_block
+    }
+
+    /// Average bits per sample of this plane, including padding.
+    #[must_use]
+    pub const fn bits_per_pixel(self) -> u32 {
+        self.bits_per_block / self.block_width
+    }
+
+    /// Width and height in samples of this plane for a frame of `resolution`. Odd sizes round up.
+    #[must_use]
+    pub const fn dimensions(self, resolution: Resolution) -> (usize, usize) {
+        (
+            resolution.width().div_ceil(self.horizontal_subsampling) as usize,
+            resolution.height().div_ceil(self.vertical_subsampling) as usize,
+        )
+    }
+
+    /// Size in bytes of a single row of this plane for a frame of `resolution`.
+    #[must_use]
+    pub const fn row_size(self, resolution: Resolution) -> usize {
+        let (width, _) = self.dimensions(resolution);
+        width.div_ceil(self.block_width as usize) * self.bits_per_block as usize / 8
+    }
+
+    /// Size in bytes of this plane for a frame of `resolution`.
+    #[must_use]
+    pub const fn size(self, resolution: Resolution) -> usize {
+        self.row_size(resolution) * self.dimensions(resolution).1
+    }
+}
+
+/// Describes the memory layout of a [`FrameFormat`], see [`FrameFormat::descriptor`].
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct FrameFormatDescriptor {
+    category: FrameFormatCategory,
+    planes: &'static [PlaneLayout],
+    chroma_subsampling: (u32, u32),
+}
+
+impl FrameFormatDescriptor {
+    const COMPRESSED: Self = Self::new(FrameFormatCategory::Compressed, &[], (1, 1));
+    const CUSTOM: Self = Self::new(FrameFormatCategory::Custom, &[], (1, 1));
+
+    const fn new(
+        category: FrameFormatCategory,
+        planes: &'static [PlaneLayout],
+        chroma_subsampling: (u32, u32),
+    ) -> Self {
+        Self {
+            category,
+            planes,
+            chroma_subsampling,
+        }
+    }
+
+    const fn packed(category: FrameFormatCategory, planes: &'static [PlaneLayout]) -> Self {
+        Self::new(category, planes, (1, 1))
+    }
+
+    /// The [`FrameFormatCategory`] of the format.
+    #[must_use]
+    pub const fn category(&self) -> FrameFormatCategory {
+        self.category
+    }
+
+    /// Whether the format is compressed. Compressed formats have no planes and no fixed size.
+    #[must_use]
+    pub const fn is_compressed(&self) -> bool {
+        matches!(self.category, FrameFormatCategory::Compressed)
+    }
+
+    /// The planes of the format, in memory order. Empty for compressed and custom formats.
+    #[must_use]
+    pub const fn planes(&self) -> &'static [PlaneLayout] {
+        self.planes
+    }
+
+    /// How many planes the format has.
+    #[must_use]
+    pub const fn plane_count(&self) -> usize {
+        self.planes.len()
+    }
+
+    /// Bits per pixel of each plane, in memory order.
+    pub fn bits_per_pixel(&self) -> impl Iterator<Item = u32> {
+        self.planes.iter().map(|plane| plane.bits_per_pixel())
+    }
+
+    /// The (horizontal, vertical) chroma subsampling factors, e.g. `(2, 1)` for 4:2:2 and `(2, 2)`
+    /// for 4:2:0. `(1, 1)` for formats without subsampled chroma.
+    #[must_use]
+    pub const fn chroma_subsampling(&self) -> (u32, u32) {
+        self.chroma_subsampling
+    }
+
+    /// Expected size in bytes of a frame of `resolution`, or [`None`] if the format has no fixed
+    /// size.
+    #[must_use]
+    pub const fn frame_size(&self, resolution: Resolution) -> Option<usize> {
+        if self.planes.is_empty() {
+            return None;
+        }
+
+        let mut size = 0;
+        let mut idx = 0;
+        while idx < self.planes.len() {
+            size += self.planes[idx].size(resolution);
+            idx += 1;
+        }
+        Some(size)
+    }
+}
+
+const PACKED_8: &[PlaneLayout] = &[PlaneLayout::full(8)];
+const PACKED_16: &[PlaneLayout] = &[PlaneLayout::full(16)];
+const PACKED_24: &[PlaneLayout] = &[PlaneLayout::full(24)];
+const PACKED_32: &[PlaneLayout] = &[PlaneLayout::full(32)];
+const PACKED_422: &[PlaneLayout] = &[PlaneLayout::packed(2, 32)];
+const PACKED_10_MIPI: &[PlaneLayout] = &[PlaneLayout::packed(4, 40)];
+const PLANAR_420: &[PlaneLayout] = &[
+    PlaneLayout::full(8),
+    PlaneLayout::subsampled(2, 2, 8),
+    PlaneLayout::subsampled(2, 2, 8),
+];
+const PLANAR_410: &[PlaneLayout] = &[
+    PlaneLayout::full(8),
+    PlaneLayout::subsampled(4, 4, 8),
+    PlaneLayout::subsampled(4, 4, 8),
+];
+const SEMI_PLANAR_420: &[PlaneLayout] = &[PlaneLayout::full(8), PlaneLayout::subsampled(2, 2, 16)];
+const SEMI_PLANAR_420_16: &[PlaneLayout] =
+    &[PlaneLayout::full(16), PlaneLayout::subsampled(2, 2, 32)];
+
+/// Generates [`FrameFormat::ALL`] and [`FrameFormat::descriptor`] from a single table, so that
+/// the two can never disagree.
+macro_rules! frame_format_table {
+    ($( $variant:ident $(($pattern:ident))? => $descriptor:expr, )*) => {
+        impl FrameFormat {
+            /// Every [`FrameFormat`] except [`FrameFormat::Custom`], in declaration order.
+            pub const ALL: &'static [FrameFormat] = &[
+                $( FrameFormat::$variant $((BayerPattern::$pattern))?, )*
+            ];
+
+            /// Describes the memory layout of this format.
+            #[must_use]
+            pub const fn descriptor(self) -> FrameFormatDescriptor {
+                match self {
+                    $( FrameFormat::$variant $((BayerPattern::$pattern))? => $descriptor, )*
+                    FrameFormat::Custom(_) => FrameFormatDescriptor::CUSTOM,
+                }
+            }
+        }
+    };
+}
+
+frame_format_table! {
+    H265 => FrameFormatDescriptor::COMPRESSED,
+    H264 => FrameFormatDescriptor::COMPRESSED,
+    Avc1 => FrameFormatDescriptor::COMPRESSED,
+    H263 => FrameFormatDescriptor::COMPRESSED,
+    Av1 => FrameFormatDescriptor::COMPRESSED,
+    Mpeg1 => FrameFormatDescriptor::COMPRESSED,
+    Mpeg2 => FrameFormatDescriptor::COMPRESSED,
+    Mpeg4 => FrameFormatDescriptor::COMPRESSED,
+    MJpeg => FrameFormatDescriptor::COMPRESSED,
+    XVid => FrameFormatDescriptor::COMPRESSED,
+    VP8 => FrameFormatDescriptor::COMPRESSED,
+    VP9 => FrameFormatDescriptor::COMPRESSED,
+    Ayuv444 => FrameFormatDescriptor::packed(FrameFormatCategory::Chroma, PACKED_32),
+    Yuyv422 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, PACKED_422, (2, 1)),
+    Uyvy422 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, PACKED_422, (2, 1)),
+    Yvyu422 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, PACKED_422, (2, 1)),
+    Yv12 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, PLANAR_420, (2, 2)),
+    Nv12 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, SEMI_PLANAR_420, (2, 2)),
+    Nv21 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, SEMI_PLANAR_420, (2, 2)),
+    I420 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, PLANAR_420, (2, 2)),
+    P010 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, SEMI_PLANAR_420_16, (2, 2)),
+    P016 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, SEMI_PLANAR_420_16, (2, 2)),
+    Yvu9 => FrameFormatDescriptor::new(FrameFormatCategory::Chroma, PLANAR_410, (4, 4)),
+    Luma8 => FrameFormatDescriptor::packed(FrameFormatCategory::Luma, PACKED_8),
+    Luma10 => FrameFormatDescriptor::packed(FrameFormatCategory::Luma, PACKED_16),
+    Luma12 => FrameFormatDescriptor::packed(FrameFormatCategory::Luma, PACKED_16),
+    Luma10Packed => FrameFormatDescriptor::packed(FrameFormatCategory::Luma, PACKED_10_MIPI),
+    Luma16 => FrameFormatDescriptor::packed(FrameFormatCategory::Luma, PACKED_16),
+    Depth16 => FrameFormatDescriptor::packed(FrameFormatCategory::Depth, PACKED_16),
+    Rgb332 => FrameFormatDescriptor::packed(FrameFormatCategory::Rgb, PACKED_8),
+    Rgb555 => FrameFormatDescriptor::packed(FrameFormatCategory::Rgb, PACKED_16),
+    Rgb565 => FrameFormatDescriptor::packed(FrameFormatCategory::Rgb, PACKED_16),
+    Rgb888 => FrameFormatDescriptor::packed(FrameFormatCategory::Rgb, PACKED_24),
+    RgbA8888 => FrameFormatDescriptor::packed(FrameFormatCategory::Rgb, PACKED_32),
+    ARgb8888 => FrameFormatDescriptor::packed(FrameFormatCategory::Rgb, PACKED_32),
+    Bayer8(Rggb) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_8),
+    Bayer8(Bggr) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_8),
+    Bayer8(Grbg) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_8),
+    Bayer8(Gbrg) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_8),
+    Bayer16(Rggb) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_16),
+    Bayer16(Bggr) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_16),
+    Bayer16(Grbg) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_16),
+    Bayer16(Gbrg) => FrameFormatDescriptor::packed(FrameFormatCategory::Bayer, PACKED_16),
+}
+
+/// Number of formats in [`FrameFormat::ALL`] that belong to any of `categories`.
+const fn count_in(categories: &[FrameFormatCategory]) -> usize {
+    let mut count = 0;
+    let mut idx = 0;
+    while idx < FrameFormat::ALL.len() {
+        if is_in(FrameFormat::ALL[idx], categories) {
+            count += 1;
+        }
+        idx += 1;
+    }
+    count
+}
+
+/// The formats in [`FrameFormat::ALL`] that belong to any of `categories`. `N` must be
+/// [`count_in(categories)`](count_in).
+const fn filter_in<const N: usize>(categories: &[FrameFormatCategory]) -> [FrameFormat; N] {
+    let mut formats = [FrameFormat::Custom([0; 8]); N];
+    let mut found = 0;
+    let mut idx = 0;
+    while idx < FrameFormat::ALL.len() {
+        if is_in(FrameFormat::ALL[idx], categories) {
+            formats[found] = FrameFormat::ALL[idx];
+            found += 1;
+        }
+        idx += 1;
+    }
+    formats
+}
+
+const fn is_in(frame_format: FrameFormat, categories: &[FrameFormatCategory]) -> bool {
+    let category = frame_format.descriptor().category as u8;
+    let mut idx = 0;
+    while idx < categories.len() {
+        if categories[idx] as u8 == category {
+            return true;
+        }
+        idx += 1;
+    }
+    false
+}
 
-    pub const GRAYSCALE: &'static [FrameFormat] = &[FrameFormat::Luma8, FrameFormat::Luma16];
+macro_rules! frame_format_category {
+    ($(#[$meta:meta])* $name:ident => [$($category:ident),*]) => {
+        $(#[$meta])*
+        pub const $name: &'static [FrameFormat] = {
+            const CATEGORIES: &[FrameFormatCategory] = &[$(FrameFormatCategory::$category),*];
+            &filter_in::<{ count_in(CATEGORIES) }>(CATEGORIES)
+        };
+    };
+}
+
+impl FrameFormat {
+    frame_format_category!(
+        /// Every compressed format.
+        COMPRESSED => [Compressed]
+    );
+    frame_format_category!(
+        /// Every YCbCr format.
+        CHROMA => [Chroma]
+    );
+    frame_format_category!(
+        /// Every grayscale format.
+        LUMA => [Luma]
+    );
+    frame_format_category!(
+        /// Every depth format.
+        DEPTH => [Depth]
+    );
+    frame_format_category!(
+        /// Every RGB format.
+        RGB => [Rgb]
+    );
+    frame_format_category!(
+        /// Every Bayer format.
+        BAYER => [Bayer]
+    );
+    frame_format_category!(
+        /// Every format that (may) carry color information.
+        COLOR_FORMATS => [Compressed, Chroma, Rgb, Bayer]
+    );
+    frame_format_category!(
+        /// Every grayscale format. Same as [`FrameFormat::LUMA`].
+        GRAYSCALE => [Luma]
+    );
+
+    /// Shorthand for [`self.descriptor().frame_size(resolution)`](FrameFormatDescriptor::frame_size).
+    #[must_use]
+    pub const fn frame_size(self, resolution: Resolution) -> Option<usize> {
+        self.descriptor().frame_size(resolution)
+    }
+
+    /// Shorthand for [`self.descriptor().is_compressed()`](FrameFormatDescriptor::is_compressed).
+    #[must_use]
+    pub const fn is_compressed(self) -> bool {
+        self.descriptor().is_compressed()
+    }
 }
 
 impl Display for FrameFormat {
@@ -190,3 +564,99 @@ macro_rules! define_back_and_fourth_frame_format {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_sizes_follow_the_planes() {
+        let size = |frame_format: FrameFormat, width, height| {
+            frame_format.frame_size(Resolution::new(width, height))
+        };
+        assert_eq!(size(FrameFormat::Yuyv422, 4, 2), Some(16));
+        assert_eq!(size(FrameFormat::I420, 4, 4), Some(24));
+        assert_eq!(size(FrameFormat::Yvu9, 4, 4), Some(18));
+        assert_eq!(size(FrameFormat::P010, 2, 2), Some(12));
+        assert_eq!(size(FrameFormat::Rgb888, 2, 2), Some(12));
+        assert_eq!(size(FrameFormat::MJpeg, 2, 2), None);
+        assert_eq!(size(FrameFormat::Custom(*b"ABCD\0\0\0\0"), 2, 2), None);
+    }
+
+    #[test]
+    fn odd_sizes_round_up_to_whole_samples_and_blocks() {
+        let size = |frame_format: FrameFormat, width, height| {
+            frame_format.frame_size(Resolution::new(width, height))
+        };
+        // 3x3 luma and 2x2 interleaved chroma.
+        assert_eq!(size(FrameFormat::Nv12, 3, 3), Some(17));
+        // Two 2 pixel blocks of 4 bytes.
+        assert_eq!(size(FrameFormat::Yuyv422, 3, 1), Some(8));
+        // Two 4 pixel blocks of 5 bytes.
+        assert_eq!(size(FrameFormat::Luma10Packed, 6, 1), Some(10));
+    }
+
+    #[test]
+    fn descriptors_describe_the_planes() {
+        let nv12 = FrameFormat::Nv12.descriptor();
+        assert_eq!(nv12.category(), FrameFormatCategory::Chroma);
+        assert_eq!(nv12.plane_count(), 2);
+        assert_eq!(nv12.bits_per_pixel().collect::<Vec<_>>(), [8, 16]);
+        assert_eq!(nv12.chroma_subsampling(), (2, 2));
+        assert!(!nv12.is_compressed());
+
+        let yuyv = FrameFormat::Yuyv422.descriptor();
+        assert_eq!(yuyv.bits_per_pixel().collect::<Vec<_>>(), [16]);
+        assert_eq!(yuyv.chroma_subsampling(), (2, 1));
+        assert_eq!(yuyv.planes()[0].block_width(), 2);
+
+        let mjpeg = FrameFormat::MJpeg.descriptor();
+        assert!(mjpeg.is_compressed());
+        assert_eq!(mjpeg.plane_count(), 0);
+    }
+
+    #[test]
+    fn every_format_is_in_exactly_one_category() {
+        let categories = [
+            FrameFormat::COMPRESSED,
+            FrameFormat::CHROMA,
+            FrameFormat::LUMA,
+            FrameFormat::DEPTH,
+            FrameFormat::RGB,
+            FrameFormat::BAYER,
+        ];
+        for frame_format in FrameFormat::ALL {
+            let found = categories
+                .iter()
+                .filter(|category| category.contains(frame_format))
+                .count();
+            assert_eq!(found, 1, "{frame_format}");
+        }
+        assert_eq!(
+            categories
+                .iter()
+                .map(|category| category.len())
+                .sum::<usize>(),
+            FrameFormat::ALL.len()
+        );
+
+        assert!(FrameFormat::CHROMA.contains(&FrameFormat::Ayuv444));
+        assert!(FrameFormat::CHROMA.contains(&FrameFormat::I420));
+        assert!(FrameFormat::DEPTH.contains(&FrameFormat::Depth16));
+        for pattern in BayerPattern::ALL {
+            assert!(FrameFormat::BAYER.contains(&FrameFormat::Bayer8(*pattern)));
+            assert!(FrameFormat::BAYER.contains(&FrameFormat::Bayer16(*pattern)));
+        }
+        assert!(!FrameFormat::COLOR_FORMATS.contains(&FrameFormat::Luma8));
+    }
+
+    #[test]
+    fn bayer_sites_repeat_every_two_pixels() {
+        let pattern = BayerPattern::Grbg;
+        assert_eq!(pattern.site_at(0, 0), BayerSite::GreenRed);
+        assert_eq!(pattern.site_at(1, 0), BayerSite::Red);
+        assert_eq!(pattern.site_at(0, 1), BayerSite::Blue);
+        assert_eq!(pattern.site_at(1, 1), BayerSite::GreenBlue);
+        assert_eq!(pattern.site_at(3, 2), BayerSite::Red);
+    }
+}
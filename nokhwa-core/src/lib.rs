@@ -3,7 +3,8 @@
 #![allow(clippy::cast_precision_loss)]
 #![allow(clippy::cast_sign_loss)]
 #![allow(clippy::cast_possible_truncation)]
-#![cfg_attr(feature = "test-fail-warning", deny(warnings))]
+#![allow(async_fn_in_trait)]
+#![cfg_attr(feature = "test-fail-warnings", deny(warnings))]
 #![cfg_attr(feature = "docs-features", feature(doc_cfg))]
 /*
  * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
@@ -22,17 +23,24 @@
  */
 
 //! Core type definitions for `nokhwa`
+pub mod buffer_pool;
 pub mod camera;
 pub mod decoder;
+pub mod encoder;
 pub mod error;
 pub mod format_request;
 pub mod frame_buffer;
 pub mod frame_format;
 pub mod properties;
-pub mod query;
 pub mod ranges;
+pub mod session;
 pub mod traits;
 pub mod types;
 pub mod utils;
+pub mod y4m;
 pub mod stream;
-mod platform;
+pub mod supervisor;
+#[cfg(feature = "testing")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "testing")))]
+pub mod testing;
+pub mod platform;
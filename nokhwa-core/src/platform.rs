@@ -1,6 +1,9 @@
-use crate::camera::{AsyncCamera, Camera};
+#[cfg(feature = "async")]
+use crate::camera::AsyncCamera;
+use crate::camera::Camera;
 use crate::error::NokhwaResult;
 use crate::types::{CameraIndex, CameraInformation};
+use std::fmt::{Display, Formatter};
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum Backends {
@@ -11,17 +14,38 @@ pub enum Backends {
     Custom(&'static str)
 }
 
+impl Display for Backends {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backends::Custom(name) => write!(f, "{name}"),
+            backend => write!(f, "{backend:?}"),
+        }
+    }
+}
+
 pub trait PlatformTrait {
     const PLATFORM: Backends;
     type Camera: Camera;
 
 
+    /// Blocks until the user has granted or refused access to the cameras.
+    ///
+    /// # Errors
+    /// If access was refused.
     fn block_on_permission(&mut self) -> NokhwaResult<()>;
 
     fn check_permission_given(&mut self) -> bool;
 
+    /// Lists the cameras of this platform.
+    ///
+    /// # Errors
+    /// If the cameras could not be listed.
     fn query(&mut self) -> NokhwaResult<Vec<CameraInformation>>;
 
+    /// Opens the camera at `index`.
+    ///
+    /// # Errors
+    /// If there is no such camera, or it could not be opened.
     fn open(&mut self, index: &CameraIndex) -> NokhwaResult<Self::Camera>;
 }
 
@@ -44,31 +44,48 @@ pub struct Properties {
 }
 
 impl Properties {
+    #[must_use]
     pub fn new(device_controls: HashMap<ControlId, ControlBody>) -> Self {
         Self {
             controls: device_controls,
         }
     }
 
+    #[must_use]
     pub fn empty() -> Self {
         Self::default()
     }
 
+    #[must_use]
     pub fn control_value(&self, control_id: &ControlId) -> Option<&ControlBody> {
         self.controls.get(control_id)
     }
 
+    /// Every control, by its [`ControlId`].
+    #[must_use]
+    pub fn controls(&self) -> &HashMap<ControlId, ControlBody> {
+        &self.controls
+    }
+
+    /// Adds or replaces the control `control_id`, returning the control it replaced.
+    pub fn insert_control(&mut self, control_id: ControlId, control: ControlBody) -> Option<ControlBody> {
+        self.controls.insert(control_id, control)
+    }
+
+    /// Sets the control `control_id` to `value`.
+    ///
+    /// # Errors
+    /// If there is no such control, or `value` is not valid for it.
     pub fn set_control_value(&mut self, control_id: &ControlId, value: ControlValue) -> NokhwaResult<()> {
-        // see if it exists
-        if let Some(control) = self.controls.get_mut(control_id) {
-            // FIXME: Remove this clone one day!
-            control.set_value(value.clone())?;
-        }
-        Err(NokhwaError::SetPropertyError {
-            property: control_id.to_string(),
-            value: value.to_string(),
-            error: "Not Found/Not Supported".to_string(),
-        })
+        let Some(control) = self.controls.get_mut(control_id) else {
+            return Err(NokhwaError::SetPropertyError {
+                property: control_id.to_string(),
+                value: value.to_string(),
+                error: "Not Found/Not Supported".to_string(),
+            });
+        };
+        control.set_value(value)?;
+        Ok(())
     }
 }
 
@@ -83,6 +100,7 @@ pub struct ControlBody {
 }
 
 impl ControlBody {
+    #[must_use]
     pub fn new(control_type: ControlType, control_flags: HashSet<ControlFlags>, control_value_descriptor: ControlValueDescriptor, value: Option<ControlValue>, default_value: Option<ControlValue>) -> Self {
         Self {
             control_type,
@@ -93,22 +111,27 @@ impl ControlBody {
         }
     }
 
+    #[must_use]
     pub fn control_type(&self) -> &ControlType {
         &self.control_type
     }
 
+    #[must_use]
     pub fn flags(&self) -> &HashSet<ControlFlags> {
         &self.flags
     }
 
+    #[must_use]
     pub fn descriptor(&self) -> &ControlValueDescriptor {
         &self.descriptor
     }
 
+    #[must_use]
     pub fn value(&self) -> &Option<ControlValue> {
         &self.value
     }
 
+    #[must_use]
     pub fn default_value(&self) -> &Option<ControlValue> {
         &self.default_value
     }
@@ -121,6 +144,10 @@ impl ControlBody {
         self.flags.remove(&flag)
     }
 
+    /// Sets the value of this control, returning the value it replaced.
+    ///
+    /// # Errors
+    /// If `value` is not valid for this control.
     pub fn set_value(&mut self, value: ControlValue) -> NokhwaResult<Option<ControlValue>> {
         if let ControlFlow::Break(()) =  self.descriptor.validate(&value) {
             return Err(NokhwaError::SetPropertyError {
@@ -130,12 +157,11 @@ impl ControlBody {
             })
         }
 
-        let old = core::mem::replace(&mut self.value, Some(value));
-        Ok(old)
+        Ok(self.value.replace(value))
     }
 
     pub fn clear_value(&mut self) -> Option<ControlValue> {
-        core::mem::replace(&mut self.value, None)
+        self.value.take()
     }
 
 
@@ -195,7 +221,9 @@ impl ControlValueDescriptor {
             }
             ControlValueDescriptor::Integer(int_range) => {
                 if let ControlValue::Integer(i) = value {
-                    int_range.validate(i)?;
+                    if int_range.validate(i).is_ok() {
+                        return ControlFlow::Continue(())
+                    }
                 }
             }
             ControlValueDescriptor::BitMask => {
@@ -205,7 +233,9 @@ impl ControlValueDescriptor {
             }
             ControlValueDescriptor::Float(float_range) => {
                 if let ControlValue::Float(i) = value {
-                    float_range.validate(i)?;
+                    if float_range.validate(i).is_ok() {
+                        return ControlFlow::Continue(())
+                    }
                 }
             }
             ControlValueDescriptor::String => {
@@ -224,11 +254,11 @@ impl ControlValueDescriptor {
                 }
             }
             ControlValueDescriptor::MultiChoice(choices) => {
-                if let &ControlValue::Array(values) = value {
+                if let ControlValue::Array(values) = value {
                     for v in values {
                         let mut contains = false;
                         for choice in choices {
-                            if choice.is_valid_value(v.as_ref()) {
+                            if choice.is_valid_value(&v.into()) {
                                 contains = true;
                                 break;
                             }
@@ -241,7 +271,7 @@ impl ControlValueDescriptor {
             }
             ControlValueDescriptor::Enum(choices) => {
                 for choice in choices {
-                    if choice.is_valid_value(&value) {
+                    if choice.is_valid_value(value) {
                         return ControlFlow::Continue(())
                     }
                 }
@@ -250,7 +280,7 @@ impl ControlValueDescriptor {
                 if let ControlValue::Map(setting_map) = &value {
                     for (setting_key, setting_value) in setting_map {
                         if let Some(descriptor) = map.get(setting_key) {
-                            if !descriptor.is_valid_value(setting_value.as_ref()) {
+                            if !descriptor.is_valid_value(&setting_value.into()) {
                                 return ControlFlow::Break(())
                             }
                         }
@@ -260,7 +290,7 @@ impl ControlValueDescriptor {
             ControlValueDescriptor::Menu(menu) => {
                 if let ControlValue::KeyValue(k, v) = &value {
                     if let Some(descriptor) = menu.get(k) {
-                        if descriptor.is_valid_value(v.as_ref()) {
+                        if descriptor.is_valid_value(&v.into()) {
                             return ControlFlow::Continue(())
                         }
                     }
@@ -283,6 +313,7 @@ pub enum ControlValuePrimitiveDescriptor {
 }
 
 impl ControlValuePrimitiveDescriptor {
+    #[must_use]
     pub fn is_valid_value(&self, other: &ControlValue) -> bool {
         match self {
             ControlValuePrimitiveDescriptor::Null => {
@@ -330,15 +361,15 @@ pub enum ControlValuePrimitive {
     Boolean(bool),
 }
 
-impl AsRef<ControlValue> for ControlValuePrimitive {
-    fn as_ref(&self) -> &ControlValue {
-        match self {
-            ControlValuePrimitive::Null => &ControlValue::Null,
-            ControlValuePrimitive::Integer(i) => &ControlValue::Integer(*i),
-            ControlValuePrimitive::BitMask(b) => &ControlValue::BitMask(*b),
-            ControlValuePrimitive::Float(f) => &ControlValue::Float(*f),
-            ControlValuePrimitive::String(s) => &ControlValue::String(s.clone()),
-            ControlValuePrimitive::Boolean(b) => &ControlValue::Boolean(*b),
+impl From<&ControlValuePrimitive> for ControlValue {
+    fn from(primitive: &ControlValuePrimitive) -> Self {
+        match primitive {
+            ControlValuePrimitive::Null => ControlValue::Null,
+            ControlValuePrimitive::Integer(i) => ControlValue::Integer(*i),
+            ControlValuePrimitive::BitMask(b) => ControlValue::BitMask(*b),
+            ControlValuePrimitive::Float(f) => ControlValue::Float(*f),
+            ControlValuePrimitive::String(s) => ControlValue::String(s.clone()),
+            ControlValuePrimitive::Boolean(b) => ControlValue::Boolean(*b),
         }
     }
 }
@@ -357,6 +388,7 @@ pub enum ControlValue {
 }
 
 impl ControlValue {
+    #[must_use]
     pub fn same_type(&self, other: &ControlValue) -> bool {
         match self {
             ControlValue::Null => {
@@ -412,3 +444,43 @@ impl From<ControlValuePrimitive> for ControlValue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exposure_time() -> Properties {
+        let control = ControlBody::new(
+            ControlType::Integer,
+            HashSet::new(),
+            ControlValueDescriptor::Integer(Range::new(50, Some(0), Some(100), None)),
+            Some(ControlValue::Integer(50)),
+            Some(ControlValue::Integer(50)),
+        );
+        Properties::new(HashMap::from([(ControlId::ExposureTime, control)]))
+    }
+
+    #[test]
+    fn setting_a_valid_value_succeeds() {
+        let mut properties = exposure_time();
+        properties
+            .set_control_value(&ControlId::ExposureTime, ControlValue::Integer(80))
+            .unwrap();
+        assert_eq!(
+            properties.control_value(&ControlId::ExposureTime).unwrap().value(),
+            &Some(ControlValue::Integer(80))
+        );
+    }
+
+    #[test]
+    fn setting_an_invalid_or_unknown_control_fails() {
+        let mut properties = exposure_time();
+        assert!(properties
+            .set_control_value(&ControlId::ExposureTime, ControlValue::Integer(120))
+            .is_err());
+        assert!(properties
+            .set_control_value(&ControlId::ExposureBias, ControlValue::Integer(80))
+            .is_err());
+        assert_eq!(properties, exposure_time());
+    }
+}
@@ -15,6 +15,9 @@ pub trait ValidatableRange {
     type Validation;
 
     /// Validates the value.
+    ///
+    /// # Errors
+    /// If `value` is not in the range.
     fn validate(&self, value: &Self::Validation) -> Result<(), RangeValidationFailure>;
 }
 
@@ -199,7 +202,7 @@ where
         if self.available.contains(value) {
             return Ok(());
         }
-        Err(RangeValidationFailure::default())
+        Err(RangeValidationFailure)
     }
 }
 
@@ -208,7 +211,7 @@ where
     T: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let default = default_to_string(&self.default);
+        let default = default_to_string(self.default.as_ref());
 
         write!(
             f,
@@ -232,10 +235,12 @@ where
     K: Clone + Debug + Hash + Eq,
     V: Clone + Debug,
 {
+    #[must_use]
     pub fn new(default: HashMap<K, V>) -> Self {
         Self { defaults: default }
     }
 
+    #[must_use]
     pub fn available_keys(&self) -> Keys<'_, K, V> {
         self.defaults.keys()
     }
@@ -266,6 +271,10 @@ impl<T> ArrayRange<T>
 where
     T: Clone + Debug + PartialEq,
 {
+    /// Creates an [`ArrayRange`] of the `appendable` options, starting out with `default`.
+    ///
+    /// # Errors
+    /// If an option of `default` is not `appendable`.
     pub fn new(appendable: Vec<T>, default: Vec<T>) -> Result<Self, NokhwaError> {
         for option in &default {
             if !appendable.contains(option) {
@@ -279,10 +288,12 @@ where
         })
     }
 
+    #[must_use]
     pub fn appendable_options(&self) -> &[T] {
         &self.appendable_options
     }
 
+    #[must_use]
     pub fn default_options(&self) -> &[T] {
         &self.default_options
     }
@@ -298,7 +309,7 @@ where
         if self.appendable_options.contains(value) {
             return Ok(());
         }
-        Err(RangeValidationFailure::default())
+        Err(RangeValidationFailure)
     }
 }
 
@@ -346,31 +357,21 @@ where
     T: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let default = default_to_string(&self.default);
+        let default = default_to_string(self.default.as_ref());
         write!(f, "Simple (Any Value): Default Value: {default}")
     }
 }
 
 fn bool_to_inclusive_char(inclusive: bool, upper: bool) -> char {
-    match inclusive {
-        true => {
-            if upper {
-                ']'
-            } else {
-                '['
-            }
-        }
-        false => {
-            if upper {
-                ')'
-            } else {
-                '('
-            }
-        }
+    match (inclusive, upper) {
+        (true, true) => ']',
+        (true, false) => '[',
+        (false, true) => ')',
+        (false, false) => '(',
     }
 }
 
-fn default_to_string<T>(default: &Option<T>) -> String
+fn default_to_string<T>(default: Option<&T>) -> String
 where
     T: Debug,
 {
@@ -403,7 +404,7 @@ where
         // 7 - 4 = 3
         // 3 % 3 = 0 Valid!
         if prepared_value % step != T::ZERO {
-            return Err(RangeValidationFailure::default());
+            return Err(RangeValidationFailure);
         }
     }
 
@@ -417,8 +418,8 @@ where
         } else {
             min < value
         };
-        if test {
-            return Err(RangeValidationFailure::default());
+        if !test {
+            return Err(RangeValidationFailure);
         }
     }
 
@@ -428,8 +429,8 @@ where
         } else {
             max > value
         };
-        if test {
-            return Err(RangeValidationFailure::default());
+        if !test {
+            return Err(RangeValidationFailure);
         }
     }
 
@@ -456,4 +457,36 @@ impl SimpleRangeItem for f32 {
 
 impl SimpleRangeItem for f64 {
     const ZERO: Self = 0_f64;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_accepts_values_within_its_bounds() {
+        let range = Range::new(5, Some(0), Some(10), None);
+        for value in [0, 3, 5, 10] {
+            assert_eq!(range.validate(&value), Ok(()), "{value}");
+        }
+        for value in [-1, 11] {
+            assert!(range.validate(&value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn range_excludes_exclusive_bounds() {
+        let range = Range::with_inclusive(5, Some(0), false, Some(10), false, None);
+        assert_eq!(range.validate(&1), Ok(()));
+        assert_eq!(range.validate(&9), Ok(()));
+        assert!(range.validate(&0).is_err());
+        assert!(range.validate(&10).is_err());
+    }
+
+    #[test]
+    fn range_checks_steps_from_its_minimum() {
+        let range = Range::new(4.0, Some(1.0), Some(10.0), Some(1.5));
+        assert_eq!(range.validate(&2.5), Ok(()));
+        assert!(range.validate(&3.0).is_err());
+    }
+}
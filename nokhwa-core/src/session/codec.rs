@@ -0,0 +1,660 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The binary encoding of session records. Integers are little endian, strings and byte arrays
+//! are prefixed with their length, enums with a tag. Maps and sets are written sorted, so equal
+//! values always encode the same.
+
+use crate::{
+    error::NokhwaError,
+    frame_buffer::{FrameBuffer, FramePlane},
+    frame_format::FrameFormat,
+    properties::{
+        ControlBody, ControlFlags, ControlId, ControlType, ControlValue, ControlValueDescriptor,
+        ControlValuePrimitive, ControlValuePrimitiveDescriptor, Properties,
+    },
+    ranges::Range,
+    stream::events::StopReason,
+    types::{CameraFormat, CameraIndex, CameraInformation, FrameRate, Resolution},
+};
+use bytes::Bytes;
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroI32,
+    time::Duration,
+};
+
+/// The [`ControlId`]s by their tag. Only ever appended to, tags are stored in sessions.
+const CONTROL_IDS: [ControlId; 18] = [
+    ControlId::FocusMode,
+    ControlId::FocusAutoType,
+    ControlId::FocusAutoRange,
+    ControlId::FocusAbsolute,
+    ControlId::FocusRelative,
+    ControlId::FocusStatus,
+    ControlId::ExposureMode,
+    ControlId::ExposureBias,
+    ControlId::ExposureTime,
+    ControlId::ExposureAutoPriority,
+    ControlId::ExposureIsoMode,
+    ControlId::ExposureIsoSensitivity,
+    ControlId::ExposureApertureAbsolute,
+    ControlId::ExposureApertureRelative,
+    ControlId::WhiteBalanceMode,
+    ControlId::WhiteBalanceTemperature,
+    ControlId::ZoomMode,
+    ControlId::LightingMode,
+];
+const PLATFORM_SPECIFIC_CONTROL: u8 = u8::MAX;
+
+const CONTROL_TYPES: [ControlType; 7] = [
+    ControlType::Button,
+    ControlType::Integer,
+    ControlType::Menu,
+    ControlType::IntegerMenu,
+    ControlType::BinaryMenu,
+    ControlType::Bitmask,
+    ControlType::String,
+];
+
+const CONTROL_FLAGS: [ControlFlags; 9] = [
+    ControlFlags::Disabled,
+    ControlFlags::Busy,
+    ControlFlags::ReadOnly,
+    ControlFlags::CascadingUpdates,
+    ControlFlags::Inactive,
+    ControlFlags::Slider,
+    ControlFlags::WriteOnly,
+    ControlFlags::ContinuousChange,
+    ControlFlags::ExecuteOnWrite,
+];
+
+fn tag_of<T: PartialEq>(table: &[T], value: &T) -> u8 {
+    table
+        .iter()
+        .position(|entry| entry == value)
+        .and_then(|tag| u8::try_from(tag).ok())
+        .unwrap_or(u8::MAX)
+}
+
+/// Builds the payload of a record.
+#[derive(Debug, Default)]
+pub(super) struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    pub(super) fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub(super) fn u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub(super) fn bool(&mut self, value: bool) {
+        self.u8(u8::from(value));
+    }
+
+    pub(super) fn u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn i32(&mut self, value: i32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn i64(&mut self, value: i64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn f64(&mut self, value: f64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    pub(super) fn bytes(&mut self, value: &[u8]) {
+        self.usize(value.len());
+        self.buffer.extend_from_slice(value);
+    }
+
+    pub(super) fn str(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    pub(super) fn option<T>(&mut self, value: Option<T>, mut encode: impl FnMut(&mut Self, T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            encode(self, value);
+        }
+    }
+
+    /// Nanoseconds, saturating at about 584 years.
+    pub(super) fn duration(&mut self, value: Duration) {
+        self.u64(u64::try_from(value.as_nanos()).unwrap_or(u64::MAX));
+    }
+
+    pub(super) fn resolution(&mut self, value: Resolution) {
+        self.u32(value.width());
+        self.u32(value.height());
+    }
+
+    /// By name, so that reordering [`FrameFormat`] does not change the meaning of sessions.
+    pub(super) fn frame_format(&mut self, value: FrameFormat) {
+        match value {
+            FrameFormat::Custom(fourcc) => {
+                self.u8(1);
+                self.buffer.extend_from_slice(&fourcc);
+            }
+            named => {
+                self.u8(0);
+                self.str(&named.to_string());
+            }
+        }
+    }
+
+    pub(super) fn camera_format(&mut self, value: CameraFormat) {
+        self.resolution(value.resolution());
+        self.frame_format(value.format());
+        self.i32(*value.frame_rate().numerator());
+        self.i32(*value.frame_rate().denominator());
+    }
+
+    pub(super) fn camera_information(&mut self, value: &CameraInformation) {
+        self.str(&value.human_name());
+        self.str(value.description());
+        self.str(&value.misc());
+        match value.index() {
+            CameraIndex::Index(index) => {
+                self.u8(0);
+                self.u32(*index);
+            }
+            CameraIndex::String(index) => {
+                self.u8(1);
+                self.str(index);
+            }
+        }
+    }
+
+    pub(super) fn frame_buffer(&mut self, value: &FrameBuffer) {
+        self.resolution(value.resolution());
+        self.frame_format(value.source_frame_format());
+        self.u64(value.sequence());
+        self.option(value.driver_timestamp(), Self::duration);
+        self.usize(value.planes().len());
+        for plane in value.planes() {
+            self.usize(plane.offset());
+            self.usize(plane.stride());
+        }
+        self.bytes(value.buffer());
+    }
+
+    pub(super) fn stop_reason(&mut self, value: &StopReason) {
+        match value {
+            StopReason::Requested => self.u8(0),
+            StopReason::DeviceDisconnected => self.u8(1),
+            StopReason::Error(why) => {
+                self.u8(2);
+                self.str(&why.to_string());
+            }
+        }
+    }
+
+    pub(super) fn properties(&mut self, value: &Properties) {
+        let mut controls = value.controls().iter().collect::<Vec<_>>();
+        controls.sort_by_key(|(control_id, _)| **control_id);
+        self.usize(controls.len());
+        for (control_id, control) in controls {
+            self.control_id(*control_id);
+            self.control_body(control);
+        }
+    }
+
+    fn control_id(&mut self, value: ControlId) {
+        match value {
+            ControlId::PlatformSpecific(id) => {
+                self.u8(PLATFORM_SPECIFIC_CONTROL);
+                self.u64(id);
+            }
+            known => self.u8(tag_of(&CONTROL_IDS, &known)),
+        }
+    }
+
+    fn control_body(&mut self, value: &ControlBody) {
+        self.u8(tag_of(&CONTROL_TYPES, value.control_type()));
+        let mut flags = value.flags().iter().copied().collect::<Vec<_>>();
+        flags.sort();
+        self.usize(flags.len());
+        for flag in flags {
+            self.u8(tag_of(&CONTROL_FLAGS, &flag));
+        }
+        self.descriptor(value.descriptor());
+        self.option(value.value().as_ref(), Self::value);
+        self.option(value.default_value().as_ref(), Self::value);
+    }
+
+    fn descriptor(&mut self, value: &ControlValueDescriptor) {
+        match value {
+            ControlValueDescriptor::Null => self.u8(0),
+            ControlValueDescriptor::Integer(range) => {
+                self.u8(1);
+                self.range(range, Self::i64);
+            }
+            ControlValueDescriptor::BitMask => self.u8(2),
+            ControlValueDescriptor::Float(range) => {
+                self.u8(3);
+                self.range(range, Self::f64);
+            }
+            ControlValueDescriptor::String => self.u8(4),
+            ControlValueDescriptor::Boolean => self.u8(5),
+            ControlValueDescriptor::Array(primitive) => {
+                self.u8(6);
+                self.primitive_descriptor(primitive);
+            }
+            ControlValueDescriptor::MultiChoice(choices) => {
+                self.u8(7);
+                self.list(choices, Self::primitive_descriptor);
+            }
+            ControlValueDescriptor::Enum(choices) => {
+                self.u8(8);
+                self.list(choices, Self::primitive_descriptor);
+            }
+            ControlValueDescriptor::Map(map) => {
+                self.u8(9);
+                self.map(map, Self::primitive_descriptor);
+            }
+            ControlValueDescriptor::Menu(menu) => {
+                self.u8(10);
+                self.map(menu, Self::primitive_descriptor);
+            }
+        }
+    }
+
+    fn primitive_descriptor(&mut self, value: &ControlValuePrimitiveDescriptor) {
+        match value {
+            ControlValuePrimitiveDescriptor::Null => self.u8(0),
+            ControlValuePrimitiveDescriptor::Integer(range) => {
+                self.u8(1);
+                self.range(range, Self::i64);
+            }
+            ControlValuePrimitiveDescriptor::BitMask => self.u8(2),
+            ControlValuePrimitiveDescriptor::Float(range) => {
+                self.u8(3);
+                self.range(range, Self::f64);
+            }
+            ControlValuePrimitiveDescriptor::String => self.u8(4),
+            ControlValuePrimitiveDescriptor::Boolean => self.u8(5),
+        }
+    }
+
+    fn range<T: Copy>(&mut self, value: &Range<T>, mut encode: impl FnMut(&mut Self, T)) {
+        encode(self, value.preferred());
+        self.option(value.minimum(), &mut encode);
+        self.bool(value.lower_inclusive());
+        self.option(value.maximum(), &mut encode);
+        self.bool(value.upper_inclusive());
+        self.option(value.step(), &mut encode);
+    }
+
+    fn value(&mut self, value: &ControlValue) {
+        match value {
+            ControlValue::Null => self.u8(0),
+            ControlValue::Integer(integer) => {
+                self.u8(1);
+                self.i64(*integer);
+            }
+            ControlValue::BitMask(mask) => {
+                self.u8(2);
+                self.i64(*mask);
+            }
+            ControlValue::Float(float) => {
+                self.u8(3);
+                self.f64(*float);
+            }
+            ControlValue::String(string) => {
+                self.u8(4);
+                self.str(string);
+            }
+            ControlValue::Boolean(boolean) => {
+                self.u8(5);
+                self.bool(*boolean);
+            }
+            ControlValue::Array(values) => {
+                self.u8(6);
+                self.list(values, Self::primitive);
+            }
+            ControlValue::KeyValue(key, value) => {
+                self.u8(7);
+                self.str(key);
+                self.primitive(value);
+            }
+            ControlValue::Map(map) => {
+                self.u8(8);
+                self.map(map, Self::primitive);
+            }
+        }
+    }
+
+    /// A [`ControlValuePrimitive`] shares its tags with [`ControlValue`].
+    fn primitive(&mut self, value: &ControlValuePrimitive) {
+        self.value(&ControlValue::from(value.clone()));
+    }
+
+    fn list<T>(&mut self, values: &[T], mut encode: impl FnMut(&mut Self, &T)) {
+        self.usize(values.len());
+        for value in values {
+            encode(self, value);
+        }
+    }
+
+    fn map<T>(&mut self, map: &HashMap<String, T>, mut encode: impl FnMut(&mut Self, &T)) {
+        let mut entries = map.iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        self.usize(entries.len());
+        for (key, value) in entries {
+            self.str(key);
+            encode(self, value);
+        }
+    }
+}
+
+/// Reads back what [`Encoder`] wrote. Every read returns `None` if the payload is too short or
+/// holds something that is not valid.
+#[derive(Debug)]
+pub(super) struct Decoder {
+    payload: Bytes,
+    position: usize,
+}
+
+impl Decoder {
+    pub(super) fn new(payload: Bytes) -> Self {
+        Self {
+            payload,
+            position: 0,
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Option<&[u8]> {
+        let end = self.position.checked_add(length)?;
+        let taken = self.payload.get(self.position..end)?;
+        self.position = end;
+        Some(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    pub(super) fn u8(&mut self) -> Option<u8> {
+        self.array::<1>().map(|[value]| value)
+    }
+
+    pub(super) fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    pub(super) fn u32(&mut self) -> Option<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub(super) fn i32(&mut self) -> Option<i32> {
+        self.array().map(i32::from_le_bytes)
+    }
+
+    pub(super) fn u64(&mut self) -> Option<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub(super) fn i64(&mut self) -> Option<i64> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    pub(super) fn f64(&mut self) -> Option<f64> {
+        self.array().map(f64::from_le_bytes)
+    }
+
+    pub(super) fn usize(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+
+    /// A byte array, shared with the payload rather than copied.
+    pub(super) fn bytes(&mut self) -> Option<Bytes> {
+        let length = self.usize()?;
+        let start = self.position;
+        self.take(length)?;
+        Some(self.payload.slice(start..start + length))
+    }
+
+    pub(super) fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+
+    /// The outer `None` means the read failed, the inner one that there is no value.
+    #[allow(clippy::option_option)]
+    pub(super) fn option<T>(
+        &mut self,
+        decode: impl FnOnce(&mut Self) -> Option<T>,
+    ) -> Option<Option<T>> {
+        if self.bool()? {
+            decode(self).map(Some)
+        } else {
+            Some(None)
+        }
+    }
+
+    pub(super) fn duration(&mut self) -> Option<Duration> {
+        self.u64().map(Duration::from_nanos)
+    }
+
+    pub(super) fn resolution(&mut self) -> Option<Resolution> {
+        Some(Resolution::new(self.u32()?, self.u32()?))
+    }
+
+    pub(super) fn frame_format(&mut self) -> Option<FrameFormat> {
+        match self.u8()? {
+            0 => {
+                let name = self.string()?;
+                FrameFormat::ALL
+                    .iter()
+                    .copied()
+                    .find(|frame_format| frame_format.to_string() == name)
+            }
+            1 => self.array().map(FrameFormat::Custom),
+            _ => None,
+        }
+    }
+
+    pub(super) fn camera_format(&mut self) -> Option<CameraFormat> {
+        let resolution = self.resolution()?;
+        let frame_format = self.frame_format()?;
+        let numerator = self.i32()?;
+        let denominator = NonZeroI32::new(self.i32()?)?;
+        Some(CameraFormat::new(
+            resolution,
+            frame_format,
+            FrameRate::new(numerator, denominator),
+        ))
+    }
+
+    pub(super) fn camera_information(&mut self) -> Option<CameraInformation> {
+        let human_name = self.string()?;
+        let description = self.string()?;
+        let misc = self.string()?;
+        let index = match self.u8()? {
+            0 => CameraIndex::Index(self.u32()?),
+            1 => CameraIndex::String(self.string()?),
+            _ => return None,
+        };
+        Some(CameraInformation::new(human_name, description, misc, index))
+    }
+
+    pub(super) fn frame_buffer(&mut self) -> Option<FrameBuffer> {
+        let resolution = self.resolution()?;
+        let frame_format = self.frame_format()?;
+        let sequence = self.u64()?;
+        let driver_timestamp = self.option(Self::duration)?;
+        let planes = (0..self.usize()?)
+            .map(|_| Some(FramePlane::new(self.usize()?, self.usize()?)))
+            .collect::<Option<Vec<_>>>()?;
+        let data = self.bytes()?;
+
+        let mut frame = FrameBuffer::from_bytes_with_planes(resolution, data, frame_format, planes);
+        frame.set_sequence(sequence);
+        frame.set_driver_timestamp(driver_timestamp);
+        Some(frame)
+    }
+
+    /// Errors come back as [`NokhwaError::GeneralError`] with the message of the original.
+    pub(super) fn stop_reason(&mut self) -> Option<StopReason> {
+        match self.u8()? {
+            0 => Some(StopReason::Requested),
+            1 => Some(StopReason::DeviceDisconnected),
+            2 => Some(StopReason::Error(NokhwaError::GeneralError(self.string()?))),
+            _ => None,
+        }
+    }
+
+    pub(super) fn properties(&mut self) -> Option<Properties> {
+        let controls = (0..self.usize()?)
+            .map(|_| Some((self.control_id()?, self.control_body()?)))
+            .collect::<Option<HashMap<_, _>>>()?;
+        Some(Properties::new(controls))
+    }
+
+    fn control_id(&mut self) -> Option<ControlId> {
+        match self.u8()? {
+            PLATFORM_SPECIFIC_CONTROL => Some(ControlId::PlatformSpecific(self.u64()?)),
+            tag => CONTROL_IDS.get(usize::from(tag)).copied(),
+        }
+    }
+
+    fn control_body(&mut self) -> Option<ControlBody> {
+        let control_type = *CONTROL_TYPES.get(usize::from(self.u8()?))?;
+        let flags = (0..self.usize()?)
+            .map(|_| CONTROL_FLAGS.get(usize::from(self.u8()?)).copied())
+            .collect::<Option<HashSet<_>>>()?;
+        let descriptor = self.descriptor()?;
+        let value = self.option(Self::value)?;
+        let default_value = self.option(Self::value)?;
+        Some(ControlBody::new(
+            control_type,
+            flags,
+            descriptor,
+            value,
+            default_value,
+        ))
+    }
+
+    fn descriptor(&mut self) -> Option<ControlValueDescriptor> {
+        Some(match self.u8()? {
+            0 => ControlValueDescriptor::Null,
+            1 => ControlValueDescriptor::Integer(self.range(Self::i64)?),
+            2 => ControlValueDescriptor::BitMask,
+            3 => ControlValueDescriptor::Float(self.range(Self::f64)?),
+            4 => ControlValueDescriptor::String,
+            5 => ControlValueDescriptor::Boolean,
+            6 => ControlValueDescriptor::Array(self.primitive_descriptor()?),
+            7 => ControlValueDescriptor::MultiChoice(self.list(Self::primitive_descriptor)?),
+            8 => ControlValueDescriptor::Enum(self.list(Self::primitive_descriptor)?),
+            9 => ControlValueDescriptor::Map(self.map(Self::primitive_descriptor)?),
+            10 => ControlValueDescriptor::Menu(self.map(Self::primitive_descriptor)?),
+            _ => return None,
+        })
+    }
+
+    fn primitive_descriptor(&mut self) -> Option<ControlValuePrimitiveDescriptor> {
+        Some(match self.u8()? {
+            0 => ControlValuePrimitiveDescriptor::Null,
+            1 => ControlValuePrimitiveDescriptor::Integer(self.range(Self::i64)?),
+            2 => ControlValuePrimitiveDescriptor::BitMask,
+            3 => ControlValuePrimitiveDescriptor::Float(self.range(Self::f64)?),
+            4 => ControlValuePrimitiveDescriptor::String,
+            5 => ControlValuePrimitiveDescriptor::Boolean,
+            _ => return None,
+        })
+    }
+
+    fn range<T: Copy>(
+        &mut self,
+        mut decode: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<Range<T>> {
+        let preferred = decode(self)?;
+        let minimum = self.option(&mut decode)?;
+        let lower_inclusive = self.bool()?;
+        let maximum = self.option(&mut decode)?;
+        let upper_inclusive = self.bool()?;
+        let step = self.option(&mut decode)?;
+        Some(Range::with_inclusive(
+            preferred,
+            minimum,
+            lower_inclusive,
+            maximum,
+            upper_inclusive,
+            step,
+        ))
+    }
+
+    fn value(&mut self) -> Option<ControlValue> {
+        Some(match self.u8()? {
+            0 => ControlValue::Null,
+            1 => ControlValue::Integer(self.i64()?),
+            2 => ControlValue::BitMask(self.i64()?),
+            3 => ControlValue::Float(self.f64()?),
+            4 => ControlValue::String(self.string()?),
+            5 => ControlValue::Boolean(self.bool()?),
+            6 => ControlValue::Array(self.list(Self::primitive)?),
+            7 => ControlValue::KeyValue(self.string()?, self.primitive()?),
+            8 => ControlValue::Map(self.map(Self::primitive)?),
+            _ => return None,
+        })
+    }
+
+    fn primitive(&mut self) -> Option<ControlValuePrimitive> {
+        Some(match self.value()? {
+            ControlValue::Null => ControlValuePrimitive::Null,
+            ControlValue::Integer(integer) => ControlValuePrimitive::Integer(integer),
+            ControlValue::BitMask(mask) => ControlValuePrimitive::BitMask(mask),
+            ControlValue::Float(float) => ControlValuePrimitive::Float(float),
+            ControlValue::String(string) => ControlValuePrimitive::String(string),
+            ControlValue::Boolean(boolean) => ControlValuePrimitive::Boolean(boolean),
+            _ => return None,
+        })
+    }
+
+    fn list<T>(&mut self, mut decode: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        (0..self.usize()?).map(|_| decode(self)).collect()
+    }
+
+    fn map<T>(
+        &mut self,
+        mut decode: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<HashMap<String, T>> {
+        (0..self.usize()?)
+            .map(|_| Some((self.string()?, decode(self)?)))
+            .collect()
+    }
+}
@@ -0,0 +1,705 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Recording camera sessions to disk and replaying them bit for bit.
+//!
+//! A session holds what a camera delivered: its [`CameraInformation`], the formats it
+//! enumerated, a snapshot of its [`Properties`] at the start and after every change, and every
+//! [`FrameBuffer`] of its streams with the time it arrived, its sequence number and its exact
+//! data, padding included. [`SessionRecorder`] wraps a [`Camera`](crate::camera::Camera) and
+//! records everything passing through it, [`ReplayCamera`] plays a recording back as a `Camera`
+//! with the same timing, so a problem seen with one device can be reproduced without it.
+//!
+//! # Format
+//! A session starts with the magic `NKHWSESS` and a little endian `u16` version, followed by
+//! records of a one byte tag, a little endian `u32` length and a payload of that length.
+//!
+//! | Tag | Record                            |
+//! |-----|-----------------------------------|
+//! | 1   | [`SessionRecord::Camera`]         |
+//! | 2   | [`SessionRecord::Formats`]        |
+//! | 3   | [`SessionRecord::Properties`]     |
+//! | 4   | [`SessionRecord::Started`]        |
+//! | 5   | [`SessionRecord::FormatChanged`]  |
+//! | 6   | [`SessionRecord::Frame`]          |
+//! | 7   | [`SessionRecord::Stopped`]        |
+//!
+//! Readers skip records with tags they do not know and bytes at the end of a payload they do
+//! not expect, so later versions can add both without breaking older readers.
+
+mod codec;
+mod recorder;
+mod replay;
+
+pub use recorder::SessionRecorder;
+pub use replay::ReplayCamera;
+
+use crate::{
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    properties::Properties,
+    stream::events::StopReason,
+    types::{CameraFormat, CameraInformation},
+};
+use bytes::Bytes;
+use codec::{Decoder, Encoder};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    time::Duration,
+};
+
+/// The first bytes of every session.
+pub const SESSION_MAGIC: [u8; 8] = *b"NKHWSESS";
+/// The version of the session format written by [`SessionWriter`].
+pub const SESSION_VERSION: u16 = 1;
+
+const TAG_CAMERA: u8 = 1;
+const TAG_FORMATS: u8 = 2;
+const TAG_PROPERTIES: u8 = 3;
+const TAG_STARTED: u8 = 4;
+const TAG_FORMAT_CHANGED: u8 = 5;
+const TAG_FRAME: u8 = 6;
+const TAG_STOPPED: u8 = 7;
+
+/// One entry of a session. Times are measured from the start of the recording.
+#[derive(Clone, Debug)]
+pub enum SessionRecord {
+    /// The recorded camera.
+    Camera(CameraInformation),
+    /// The formats the camera enumerated.
+    Formats(Vec<CameraFormat>),
+    /// Every control of the camera, at the start of the recording and after every change.
+    Properties {
+        at: Duration,
+        properties: Properties,
+    },
+    /// A stream started in this format.
+    Started { at: Duration, format: CameraFormat },
+    /// The stream renegotiated its format.
+    FormatChanged { at: Duration, format: CameraFormat },
+    /// A frame arrived, with its sequence number, driver timestamp and planes.
+    Frame { at: Duration, frame: FrameBuffer },
+    /// The stream stopped. Errors are kept as [`NokhwaError::GeneralError`] with their message.
+    Stopped { at: Duration, reason: StopReason },
+}
+
+impl SessionRecord {
+    /// When this record happened, `None` for the records describing the camera.
+    #[must_use]
+    pub fn at(&self) -> Option<Duration> {
+        match self {
+            SessionRecord::Camera(_) | SessionRecord::Formats(_) => None,
+            SessionRecord::Properties { at, .. }
+            | SessionRecord::Started { at, .. }
+            | SessionRecord::FormatChanged { at, .. }
+            | SessionRecord::Frame { at, .. }
+            | SessionRecord::Stopped { at, .. } => Some(*at),
+        }
+    }
+
+    fn encode(&self) -> (u8, Vec<u8>) {
+        let mut encoder = Encoder::default();
+        let tag = match self {
+            SessionRecord::Camera(info) => {
+                encoder.camera_information(info);
+                TAG_CAMERA
+            }
+            SessionRecord::Formats(formats) => {
+                encoder.usize(formats.len());
+                for format in formats {
+                    encoder.camera_format(*format);
+                }
+                TAG_FORMATS
+            }
+            SessionRecord::Properties { at, properties } => {
+                encoder.duration(*at);
+                encoder.properties(properties);
+                TAG_PROPERTIES
+            }
+            SessionRecord::Started { at, format } => {
+                encoder.duration(*at);
+                encoder.camera_format(*format);
+                TAG_STARTED
+            }
+            SessionRecord::FormatChanged { at, format } => {
+                encoder.duration(*at);
+                encoder.camera_format(*format);
+                TAG_FORMAT_CHANGED
+            }
+            SessionRecord::Frame { at, frame } => {
+                encoder.duration(*at);
+                encoder.frame_buffer(frame);
+                TAG_FRAME
+            }
+            SessionRecord::Stopped { at, reason } => {
+                encoder.duration(*at);
+                encoder.stop_reason(reason);
+                TAG_STOPPED
+            }
+        };
+        (tag, encoder.into_inner())
+    }
+
+    /// Decodes the payload of a record, `Ok(None)` if the tag is unknown.
+    fn decode(tag: u8, payload: Bytes) -> NokhwaResult<Option<Self>> {
+        let mut decoder = Decoder::new(payload);
+        let decoder = &mut decoder;
+        let record = match tag {
+            TAG_CAMERA => decoder.camera_information().map(SessionRecord::Camera),
+            TAG_FORMATS => decoder.usize().and_then(|count| {
+                (0..count)
+                    .map(|_| decoder.camera_format())
+                    .collect::<Option<Vec<_>>>()
+                    .map(SessionRecord::Formats)
+            }),
+            TAG_PROPERTIES => decoder.duration().and_then(|at| {
+                decoder
+                    .properties()
+                    .map(|properties| SessionRecord::Properties { at, properties })
+            }),
+            TAG_STARTED => decoder.duration().and_then(|at| {
+                decoder
+                    .camera_format()
+                    .map(|format| SessionRecord::Started { at, format })
+            }),
+            TAG_FORMAT_CHANGED => decoder.duration().and_then(|at| {
+                decoder
+                    .camera_format()
+                    .map(|format| SessionRecord::FormatChanged { at, format })
+            }),
+            TAG_FRAME => {
+                let record = decoder.duration().and_then(|at| {
+                    decoder
+                        .frame_buffer()
+                        .map(|frame| SessionRecord::Frame { at, frame })
+                });
+                // The planes come from the file, rows must not be read through them unchecked.
+                if let Some(SessionRecord::Frame { frame, .. }) = &record {
+                    frame
+                        .check_planes()
+                        .map_err(|error| NokhwaError::CorruptFrameError {
+                            src: frame.source_frame_format(),
+                            error: format!("session: {error}"),
+                        })?;
+                }
+                record
+            }
+            TAG_STOPPED => decoder.duration().and_then(|at| {
+                decoder
+                    .stop_reason()
+                    .map(|reason| SessionRecord::Stopped { at, reason })
+            }),
+            _ => return Ok(None),
+        };
+        record.map(Some).ok_or_else(|| {
+            NokhwaError::ReadFrameError(format!("session: record with tag {tag} is malformed"))
+        })
+    }
+}
+
+/// Writes a session to a [`Write`]r, e.g. a [`BufWriter`](std::io::BufWriter) around a
+/// [`File`](std::fs::File).
+#[derive(Debug)]
+pub struct SessionWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> SessionWriter<W> {
+    /// Writes the start of a session to `writer`.
+    ///
+    /// # Errors
+    /// If writing fails.
+    pub fn new(mut writer: W) -> NokhwaResult<Self> {
+        writer
+            .write_all(&SESSION_MAGIC)
+            .and_then(|()| writer.write_all(&SESSION_VERSION.to_le_bytes()))
+            .map_err(|why| write_error(&why))?;
+        Ok(Self { writer })
+    }
+
+    /// Appends `record` to the session.
+    ///
+    /// # Errors
+    /// If the record does not fit in a session, e.g. a frame of 4 GiB or more, or writing fails.
+    pub fn write_record(&mut self, record: &SessionRecord) -> NokhwaResult<()> {
+        let (tag, payload) = record.encode();
+        let length = u32::try_from(payload.len()).map_err(|_| {
+            NokhwaError::GeneralError(format!(
+                "Could not write session: record of {} bytes is too large",
+                payload.len()
+            ))
+        })?;
+
+        self.writer
+            .write_all(&[tag])
+            .and_then(|()| self.writer.write_all(&length.to_le_bytes()))
+            .and_then(|()| self.writer.write_all(&payload))
+            .map_err(|why| write_error(&why))
+    }
+
+    /// Flushes the underlying writer.
+    ///
+    /// # Errors
+    /// If flushing fails.
+    pub fn flush(&mut self) -> NokhwaResult<()> {
+        self.writer.flush().map_err(|why| write_error(&why))
+    }
+
+    /// Returns the underlying writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn write_error(why: &std::io::Error) -> NokhwaError {
+    NokhwaError::GeneralError(format!("Could not write session: {why}"))
+}
+
+/// Reads a session from a [`Read`]er, one [`SessionRecord`] at a time.
+///
+/// Also an [`Iterator`] over the records, which ends at the end of the session.
+#[derive(Debug)]
+pub struct SessionReader<R> {
+    reader: R,
+    version: u16,
+}
+
+impl<R: Read> SessionReader<R> {
+    /// Reads the start of a session from `reader`.
+    ///
+    /// # Errors
+    /// If `reader` does not hold a session, or one of a newer version than
+    /// [`SESSION_VERSION`].
+    pub fn new(mut reader: R) -> NokhwaResult<Self> {
+        let error = |error: String| NokhwaError::StructureError {
+            structure: "session header".to_string(),
+            error,
+        };
+
+        let mut header = [0; 10];
+        reader
+            .read_exact(&mut header)
+            .map_err(|why| error(why.to_string()))?;
+        if header[..8] != SESSION_MAGIC {
+            return Err(error("not a nokhwa session".to_string()));
+        }
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version > SESSION_VERSION {
+            return Err(error(format!(
+                "version {version} is newer than {SESSION_VERSION}"
+            )));
+        }
+
+        Ok(Self { reader, version })
+    }
+
+    /// The version of the session format.
+    #[must_use]
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Reads the next record, skipping those of unknown kinds. Returns `Ok(None)` at the end of
+    /// the session.
+    ///
+    /// # Errors
+    /// If reading fails, the session ends in the middle of a record, e.g. because the recording
+    /// was cut short, or a record is malformed. A frame whose planes do not fit its data is a
+    /// [`NokhwaError::CorruptFrameError`].
+    pub fn read_record(&mut self) -> NokhwaResult<Option<SessionRecord>> {
+        self.next_record(false)
+    }
+
+    /// [`SessionReader::read_record`], but skips frames without reading their payloads into
+    /// memory, for reading what was recorded without the cost of the frames.
+    pub(super) fn read_metadata_record(&mut self) -> NokhwaResult<Option<SessionRecord>> {
+        self.next_record(true)
+    }
+
+    fn next_record(&mut self, skip_frames: bool) -> NokhwaResult<Option<SessionRecord>> {
+        let error = |why: String| NokhwaError::ReadFrameError(format!("session: {why}"));
+
+        loop {
+            let mut header = [0; 5];
+            match self.reader.read(&mut header[..1]) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {}
+                Err(why) if why.kind() == ErrorKind::Interrupted => continue,
+                Err(why) => return Err(error(why.to_string())),
+            }
+            self.reader
+                .read_exact(&mut header[1..])
+                .map_err(|why| error(format!("record header: {why}")))?;
+
+            let tag = header[0];
+            let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+            let mut payload = (&mut self.reader).take(u64::from(length));
+            let mut data = Vec::new();
+            let skip = skip_frames && tag == TAG_FRAME;
+            let read = if skip {
+                io::copy(&mut payload, &mut io::sink())
+            } else {
+                payload.read_to_end(&mut data).map(|read| read as u64)
+            }
+            .map_err(|why| error(why.to_string()))?;
+            if read != u64::from(length) {
+                return Err(error(format!(
+                    "record with tag {tag} is cut short, {read} of {length} bytes"
+                )));
+            }
+            if skip {
+                continue;
+            }
+
+            if let Some(record) = SessionRecord::decode(tag, Bytes::from(data))? {
+                return Ok(Some(record));
+            }
+        }
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for SessionReader<R> {
+    type Item = NokhwaResult<SessionRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        frame_buffer::FramePlane,
+        frame_format::FrameFormat,
+        properties::{ControlBody, ControlId, ControlType, ControlValue, ControlValueDescriptor},
+        types::{CameraIndex, FrameRate, Resolution},
+    };
+    use std::{collections::HashSet, path::PathBuf};
+
+    fn format() -> CameraFormat {
+        CameraFormat::new(
+            Resolution::new(2, 1),
+            FrameFormat::Rgb888,
+            FrameRate::frame_rate(30),
+        )
+    }
+
+    fn frame(sequence: u64) -> FrameBuffer {
+        let mut frame = FrameBuffer::new(
+            Resolution::new(2, 1),
+            &[1, 2, 3, 4, 5, 6],
+            FrameFormat::Rgb888,
+        );
+        frame.set_sequence(sequence);
+        frame
+    }
+
+    fn write_session(records: &[SessionRecord]) -> Vec<u8> {
+        let mut writer = SessionWriter::new(Vec::new()).unwrap();
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    /// A session file that is removed again when dropped.
+    struct TempSession(PathBuf);
+
+    impl TempSession {
+        fn new(name: &str, session: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("nokhwa-{name}-{}.session", std::process::id()));
+            std::fs::write(&path, session).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempSession {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let session = write_session(&[
+            SessionRecord::Camera(CameraInformation::new(
+                "Camera".to_string(),
+                "A camera".to_string(),
+                "usb-1".to_string(),
+                CameraIndex::Index(3),
+            )),
+            SessionRecord::Started {
+                at: Duration::from_millis(5),
+                format: format(),
+            },
+            SessionRecord::Frame {
+                at: Duration::from_millis(10),
+                frame: frame(7),
+            },
+            SessionRecord::Stopped {
+                at: Duration::from_millis(20),
+                reason: StopReason::DeviceDisconnected,
+            },
+        ]);
+
+        let records = SessionReader::new(session.as_slice())
+            .unwrap()
+            .collect::<NokhwaResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(matches!(&records[0], SessionRecord::Camera(info) if info.misc() == "usb-1"));
+        assert!(matches!(&records[1], SessionRecord::Started { format: f, .. } if *f == format()));
+        match &records[2] {
+            SessionRecord::Frame { at, frame } => {
+                assert_eq!(*at, Duration::from_millis(10));
+                assert_eq!(frame.sequence(), 7);
+                assert_eq!(frame.buffer(), &[1, 2, 3, 4, 5, 6]);
+            }
+            other => panic!("expected a frame, got {other:?}"),
+        }
+        assert!(matches!(
+            &records[3],
+            SessionRecord::Stopped {
+                reason: StopReason::DeviceDisconnected,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn frames_with_planes_outside_their_data_are_corrupt() {
+        let frame = FrameBuffer::from_bytes_with_planes(
+            Resolution::new(2, 2),
+            Bytes::from_static(&[0; 12]),
+            FrameFormat::Rgb888,
+            vec![FramePlane::new(0, usize::MAX / 2)],
+        );
+        let session = write_session(&[SessionRecord::Frame {
+            at: Duration::ZERO,
+            frame,
+        }]);
+
+        let mut reader = SessionReader::new(session.as_slice()).unwrap();
+        assert!(matches!(
+            reader.read_record(),
+            Err(NokhwaError::CorruptFrameError { .. })
+        ));
+    }
+
+    #[test]
+    fn cut_short_sessions_fail_to_read() {
+        let mut session = write_session(&[SessionRecord::Frame {
+            at: Duration::ZERO,
+            frame: frame(0),
+        }]);
+        session.truncate(session.len() - 1);
+
+        let mut reader = SessionReader::new(session.as_slice()).unwrap();
+        assert!(matches!(
+            reader.read_record(),
+            Err(NokhwaError::ReadFrameError(_))
+        ));
+    }
+
+    #[test]
+    fn metadata_reads_skip_frames() {
+        let started = SessionRecord::Started {
+            at: Duration::ZERO,
+            format: format(),
+        };
+        let frame = SessionRecord::Frame {
+            at: Duration::from_millis(10),
+            frame: frame(0),
+        };
+        let mut session = write_session(&[started, frame.clone(), frame]);
+
+        let mut reader = SessionReader::new(session.as_slice()).unwrap();
+        assert!(matches!(
+            reader.read_metadata_record(),
+            Ok(Some(SessionRecord::Started { .. }))
+        ));
+        assert!(matches!(reader.read_metadata_record(), Ok(None)));
+
+        session.truncate(session.len() - 1);
+        let mut reader = SessionReader::new(session.as_slice()).unwrap();
+        reader.read_metadata_record().unwrap();
+        assert!(matches!(
+            reader.read_metadata_record(),
+            Err(NokhwaError::ReadFrameError(_))
+        ));
+    }
+
+    #[test]
+    fn replay_applies_property_snapshots_as_it_plays() {
+        use crate::camera::{Capture, Setting};
+        use crate::stream::BackpressurePolicy;
+
+        let exposure = ControlId::ExposureAutoPriority;
+        let mut changed = Properties::empty();
+        changed.insert_control(
+            exposure,
+            ControlBody::new(
+                ControlType::BinaryMenu,
+                HashSet::new(),
+                ControlValueDescriptor::Boolean,
+                Some(ControlValue::Boolean(true)),
+                None,
+            ),
+        );
+        let session = TempSession::new(
+            "replay-properties",
+            &write_session(&[
+                SessionRecord::Camera(CameraInformation::new(
+                    "Camera".to_string(),
+                    String::new(),
+                    String::new(),
+                    CameraIndex::Index(0),
+                )),
+                SessionRecord::Properties {
+                    at: Duration::ZERO,
+                    properties: Properties::empty(),
+                },
+                SessionRecord::Started {
+                    at: Duration::ZERO,
+                    format: format(),
+                },
+                SessionRecord::Frame {
+                    at: Duration::from_millis(1),
+                    frame: frame(0),
+                },
+                SessionRecord::Properties {
+                    at: Duration::from_millis(2),
+                    properties: changed.clone(),
+                },
+                SessionRecord::Frame {
+                    at: Duration::from_millis(3),
+                    frame: frame(1),
+                },
+                SessionRecord::Stopped {
+                    at: Duration::from_millis(4),
+                    reason: StopReason::Requested,
+                },
+            ]),
+        );
+
+        let mut camera = ReplayCamera::open(&session.0).unwrap();
+        camera.set_paced(false);
+        assert_eq!(camera.properties(), &Properties::empty());
+
+        let stream = camera
+            .open_stream_with_policy(BackpressurePolicy::Block(8))
+            .unwrap();
+        let sequences = stream
+            .frames()
+            .map(|frame| frame.unwrap().sequence())
+            .collect::<Vec<_>>();
+        assert_eq!(sequences, vec![0, 1]);
+        assert_eq!(camera.properties(), &changed);
+
+        camera
+            .set_property(&exposure, ControlValue::Boolean(false))
+            .unwrap();
+        assert_eq!(
+            camera
+                .properties()
+                .control_value(&exposure)
+                .unwrap()
+                .value(),
+            &Some(ControlValue::Boolean(false))
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn recorded_sessions_replay_the_same_frames() {
+        use crate::camera::Capture;
+        use crate::stream::BackpressurePolicy;
+        use crate::testing::MockCamera;
+
+        let mut camera = MockCamera::new(CameraIndex::Index(0), vec![format()]);
+        camera.set_frames((0..3).map(frame));
+        camera.set_paced(false);
+        let info = camera.info().clone();
+
+        let mut recorder = SessionRecorder::new(camera, info, Vec::new()).unwrap();
+        let captured = recorder
+            .open_stream_with_policy(BackpressurePolicy::Block(8))
+            .unwrap()
+            .frames()
+            .map(|frame| frame.unwrap().buffer().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(captured.len(), 3);
+        let (_, session) = recorder.finish().unwrap();
+
+        let session = TempSession::new("replay-round-trip", &session);
+        let mut replay = ReplayCamera::open(&session.0).unwrap();
+        replay.set_paced(false);
+        assert_eq!(replay.format(), Some(format()));
+        let replayed = replay
+            .open_stream_with_policy(BackpressurePolicy::Block(8))
+            .unwrap()
+            .frames()
+            .map(|frame| frame.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            replayed
+                .iter()
+                .map(FrameBuffer::sequence)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            replayed
+                .iter()
+                .map(|frame| frame.buffer().to_vec())
+                .collect::<Vec<_>>(),
+            captured
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn closing_a_recorder_with_a_full_stream_does_not_hang() {
+        use crate::camera::Capture;
+        use crate::stream::BackpressurePolicy;
+        use crate::testing::MockCamera;
+        use std::time::Instant;
+
+        let mut camera = MockCamera::new(CameraIndex::Index(0), vec![format()]);
+        camera.set_frames([frame(0)]);
+        camera.set_looping(true);
+        camera.set_paced(false);
+        let info = camera.info().clone();
+
+        let mut recorder = SessionRecorder::new(camera, info, std::io::sink()).unwrap();
+        let _stream = recorder
+            .open_stream_with_policy(BackpressurePolicy::Block(1))
+            .unwrap();
+        // Let the recording thread fill the stream and block on the next frame.
+        std::thread::sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        recorder.close_stream().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
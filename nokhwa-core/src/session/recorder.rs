@@ -0,0 +1,372 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{SessionRecord, SessionWriter};
+use crate::{
+    camera::{Camera, Capture, Setting},
+    error::{NokhwaError, NokhwaResult},
+    frame_format::FrameFormat,
+    properties::{ControlId, ControlValue, Properties},
+    stream::{
+        events::{Lifecycle, StopReason, StreamEvent},
+        frame_channel, BackpressurePolicy, FrameReceiver, FrameSender, Stream, StreamInnerTrait,
+    },
+    types::{CameraFormat, CameraInformation, FrameRate, Resolution},
+};
+use flume::Receiver;
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// How long the recorder waits for a camera frame before checking if it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How many camera frames the recorder queues while it writes. The camera stream blocks rather
+/// than dropping frames, so that every frame the camera delivered is recorded. Stopping closes
+/// the streams before waiting for the recording thread, so a full queue does not hold it up.
+const RECORD_QUEUE: usize = 4;
+
+/// A [`Camera`] that records everything passing through it to a session, see the
+/// [module documentation](super).
+///
+/// Streams opened through the recorder are relayed from the camera on a recording thread, which
+/// writes every event and frame before passing it on. Frames are timed by their
+/// [`FrameBuffer::timestamp`](crate::frame_buffer::FrameBuffer::timestamp). Properties are recorded when the recorder is created and after
+/// every successful [`Setting::set_property`].
+///
+/// Writing happens on the thread delivering frames, so `W` should be buffered, e.g. a
+/// [`BufWriter`](std::io::BufWriter). If writing fails, the stream stops with the error.
+pub struct SessionRecorder<C, W> {
+    camera: C,
+    origin: Instant,
+    writer: Arc<Mutex<SessionWriter<W>>>,
+    relay: Option<Relay>,
+}
+
+impl<C: Camera, W: Write + Send + 'static> SessionRecorder<C, W> {
+    /// Starts recording `camera`, described by `info`, to `writer`. The formats the camera
+    /// enumerates and its current properties are recorded right away.
+    ///
+    /// # Errors
+    /// If writing fails.
+    pub fn new(camera: C, info: CameraInformation, writer: W) -> NokhwaResult<Self> {
+        let mut writer = SessionWriter::new(writer)?;
+        writer.write_record(&SessionRecord::Camera(info))?;
+        writer.write_record(&SessionRecord::Formats(
+            camera.enumerate_formats().unwrap_or_default(),
+        ))?;
+        writer.write_record(&SessionRecord::Properties {
+            at: Duration::ZERO,
+            properties: camera.properties().clone(),
+        })?;
+
+        Ok(Self {
+            camera,
+            origin: Instant::now(),
+            writer: Arc::new(Mutex::new(writer)),
+            relay: None,
+        })
+    }
+
+    /// The recorded camera.
+    #[must_use]
+    pub fn camera(&self) -> &C {
+        &self.camera
+    }
+
+    /// How long the recording has been going.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    /// Stops recording, closing any stream that is still open, and hands back the camera and the
+    /// flushed writer.
+    ///
+    /// # Errors
+    /// If flushing fails, or a stream opened through the recorder is still being stopped on
+    /// another thread.
+    pub fn finish(mut self) -> NokhwaResult<(C, W)> {
+        if let Some(relay) = self.relay.take() {
+            relay.stop();
+        }
+        let _ = self.camera.close_stream();
+
+        let writer = Arc::try_unwrap(self.writer).map_err(|_| {
+            NokhwaError::GeneralError("a recorded stream is still stopping".to_string())
+        })?;
+        let mut writer = writer.into_inner().unwrap_or_else(PoisonError::into_inner);
+        writer.flush()?;
+        Ok((self.camera, writer.into_inner()))
+    }
+
+    fn writer(&self) -> MutexGuard<'_, SessionWriter<W>> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<C: Camera, W: Write + Send + 'static> Setting for SessionRecorder<C, W> {
+    fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError> {
+        self.camera.enumerate_formats()
+    }
+
+    fn enumerate_resolution_and_frame_rates(
+        &self,
+        frame_format: FrameFormat,
+    ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError> {
+        self.camera
+            .enumerate_resolution_and_frame_rates(frame_format)
+    }
+
+    fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError> {
+        self.camera.set_format(camera_format)
+    }
+
+    fn properties(&self) -> &Properties {
+        self.camera.properties()
+    }
+
+    /// Sets the property on the camera, then records all properties.
+    ///
+    /// # Errors
+    /// If the camera rejects the value, or recording the properties fails.
+    fn set_property(
+        &mut self,
+        property: &ControlId,
+        value: ControlValue,
+    ) -> Result<(), NokhwaError> {
+        self.camera.set_property(property, value)?;
+        let record = SessionRecord::Properties {
+            at: self.origin.elapsed(),
+            properties: self.camera.properties().clone(),
+        };
+        self.writer().write_record(&record)
+    }
+}
+
+impl<C: Camera, W: Write + Send + 'static> Capture for SessionRecorder<C, W> {
+    fn open_stream(&mut self) -> Result<Stream, NokhwaError> {
+        self.open_stream_with_policy(BackpressurePolicy::default())
+    }
+
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        if self
+            .relay
+            .as_ref()
+            .is_some_and(|relay| relay.running.load(Ordering::Acquire))
+        {
+            return Err(NokhwaError::OpenStreamError(
+                "a stream is already open".to_string(),
+            ));
+        }
+
+        let opened = Instant::now();
+        let camera_stream = self
+            .camera
+            .open_stream_with_policy(BackpressurePolicy::Block(RECORD_QUEUE))?;
+        let (sender, receiver) = frame_channel(policy);
+        let lifecycle = sender.lifecycle();
+        let running = Arc::new(AtomicBool::new(true));
+        let recording = Recording {
+            camera_stream,
+            frames: sender,
+            origin: self.origin,
+            last: opened,
+            writer: self.writer.clone(),
+            running: running.clone(),
+        };
+        let handle = std::thread::Builder::new()
+            .name("nokhwa-session-recorder".to_string())
+            .spawn(move || recording.run())
+            .map_err(|why| NokhwaError::OpenStreamError(why.to_string()))?;
+
+        let relay = Relay {
+            running,
+            stream: lifecycle,
+            handle: Arc::new(Mutex::new(Some(handle))),
+        };
+        self.relay = Some(relay.clone());
+        Ok(Stream::new(Box::new(RecordedStream { receiver, relay })))
+    }
+
+    fn close_stream(&mut self) -> Result<(), NokhwaError> {
+        if let Some(relay) = self.relay.take() {
+            relay.stop();
+        }
+        self.camera.close_stream()
+    }
+}
+
+impl<C: Camera, W: Write + Send + 'static> Camera for SessionRecorder<C, W> {}
+
+/// The handle of a running recording thread, shared by the recorder and its stream. Whichever
+/// stops it first joins it.
+#[derive(Clone)]
+struct Relay {
+    running: Arc<AtomicBool>,
+    /// Of the recorded stream, closed when stopping in case the recording thread is blocked
+    /// passing on a frame.
+    stream: Arc<Lifecycle>,
+    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl Relay {
+    /// Stops the recording thread and waits for it, `false` if it panicked.
+    fn stop(&self) -> bool {
+        self.running.store(false, Ordering::Release);
+        self.stream.close();
+        let handle = self
+            .handle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match handle {
+            Some(handle) => handle.join().is_ok(),
+            None => true,
+        }
+    }
+}
+
+/// The recording thread of one stream.
+struct Recording<W> {
+    camera_stream: Stream,
+    frames: FrameSender,
+    origin: Instant,
+    /// When the last record happened. Events are only seen between frames, so they are recorded
+    /// as happening right after whatever came before them.
+    last: Instant,
+    writer: Arc<Mutex<SessionWriter<W>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl<W: Write> Recording<W> {
+    fn run(mut self) {
+        let events = self.camera_stream.events();
+        // The camera reports why it stopped before its last frame is taken.
+        let mut stopped = None;
+
+        let reason = loop {
+            if !self.running.load(Ordering::Acquire) || self.frames.is_disconnected() {
+                break StopReason::Requested;
+            }
+            let frame = self.camera_stream.poll_frame_timeout(POLL_INTERVAL);
+            if let Err(why) = self.record_events(&events, &mut stopped) {
+                break StopReason::Error(why);
+            }
+
+            match frame {
+                Ok(frame) => {
+                    self.last = self.last.max(frame.timestamp());
+                    let record = SessionRecord::Frame {
+                        at: self.last.saturating_duration_since(self.origin),
+                        frame: frame.clone(),
+                    };
+                    if let Err(why) = self.write(&record) {
+                        break StopReason::Error(why);
+                    }
+                    if self.frames.forward(frame).is_err() {
+                        break StopReason::Requested;
+                    }
+                }
+                Err(NokhwaError::FrameTimeoutError(_)) => {}
+                Err(why) => break stopped.unwrap_or(StopReason::Error(why)),
+            }
+        };
+
+        let record = SessionRecord::Stopped {
+            at: self.origin.elapsed(),
+            reason: reason.clone(),
+        };
+        let reason = match self.write(&record).and_then(|()| self.flush()) {
+            Ok(()) => reason,
+            Err(why) => StopReason::Error(why),
+        };
+        self.frames.stop(reason);
+        self.running.store(false, Ordering::Release);
+    }
+
+    /// Records and passes on the events the camera reported since the last frame.
+    fn record_events(
+        &mut self,
+        events: &Receiver<StreamEvent>,
+        stopped: &mut Option<StopReason>,
+    ) -> NokhwaResult<()> {
+        let at = self.last.saturating_duration_since(self.origin);
+        for event in events.try_iter() {
+            match event {
+                StreamEvent::Started(format) => {
+                    self.write(&SessionRecord::Started { at, format })?;
+                    self.frames.emit(event);
+                }
+                StreamEvent::FormatChanged(format) => {
+                    self.write(&SessionRecord::FormatChanged { at, format })?;
+                    self.frames.emit(event);
+                }
+                StreamEvent::DeviceDisconnected => self.frames.emit(event),
+                StreamEvent::Stopped(reason) => *stopped = Some(reason),
+                // Reported by the relayed stream itself.
+                StreamEvent::FirstFrame | StreamEvent::Stalled(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, record: &SessionRecord) -> NokhwaResult<()> {
+        self.writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .write_record(record)
+    }
+
+    fn flush(&self) -> NokhwaResult<()> {
+        self.writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .flush()
+    }
+}
+
+/// The inner half of a stream opened through a [`SessionRecorder`].
+struct RecordedStream {
+    receiver: FrameReceiver,
+    relay: Relay,
+}
+
+impl StreamInnerTrait for RecordedStream {
+    fn receiver(&self) -> &FrameReceiver {
+        &self.receiver
+    }
+
+    fn stop(&mut self) -> NokhwaResult<()> {
+        if self.relay.stop() {
+            Ok(())
+        } else {
+            Err(NokhwaError::StreamShutdownError(
+                "session recorder panicked".to_string(),
+            ))
+        }
+    }
+}
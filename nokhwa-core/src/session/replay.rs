@@ -0,0 +1,366 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{SessionReader, SessionRecord};
+use crate::{
+    camera::{Camera, Capture, Setting},
+    error::{NokhwaError, NokhwaResult},
+    frame_format::FrameFormat,
+    properties::{ControlId, ControlValue, Properties},
+    stream::{
+        events::{StopReason, StreamEvent},
+        frame_channel,
+        producer::{sleep_until, Producer, ProducerStream},
+        BackpressurePolicy, FrameSender, Stream,
+    },
+    types::{CameraFormat, CameraInformation, FrameRate, Resolution},
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A [`Camera`] that plays back a session recorded by a
+/// [`SessionRecorder`](super::SessionRecorder).
+///
+/// The camera describes itself as the recorded one: it enumerates the recorded formats and
+/// starts out with the recorded properties. Its stream plays the first recorded stream, from
+/// [`StreamEvent::Started`] to [`StreamEvent::Stopped`] with the recorded reason. Frames keep
+/// their data, planes, sequence numbers and driver timestamps, and are timestamped as far apart
+/// as they arrived while recording. With the default [`BackpressurePolicy`], a consumer slower
+/// than the recording misses frames; [`BackpressurePolicy::Block`] delivers every frame by
+/// holding playback back until each one is taken.
+///
+/// Properties follow the recording: once the stream has played up to a recorded snapshot,
+/// [`Setting::properties`] returns it, so a consumer sees controls change at the point in the
+/// stream where they changed while recording.
+///
+/// The recording is played as it is: [`Setting::set_format`] only accepts recorded formats and
+/// [`Setting::set_property`] only changes the properties of this camera until the next recorded
+/// snapshot, neither changes the frames.
+#[derive(Debug)]
+pub struct ReplayCamera {
+    path: PathBuf,
+    info: CameraInformation,
+    formats: Vec<CameraFormat>,
+    stream_formats: Vec<CameraFormat>,
+    /// The properties set through [`Setting::set_property`], or of a session without snapshots.
+    properties: Properties,
+    /// Which snapshot `properties` was changed from, if they override it.
+    overridden: Option<usize>,
+    property_snapshots: Vec<(Duration, Properties)>,
+    /// How many snapshots the player has reached, shared with the player thread.
+    snapshots_reached: Arc<AtomicUsize>,
+    paced: bool,
+    player: Option<Producer>,
+}
+
+impl ReplayCamera {
+    /// Opens the session at `path`, reading it once to learn what was recorded, skipping over the
+    /// frames. A session that is cut short or damaged is played up to the first bad record, where
+    /// the stream stops with the error.
+    ///
+    /// # Errors
+    /// If the file cannot be read, is not a session, or holds no camera.
+    pub fn open(path: impl AsRef<Path>) -> NokhwaResult<Self> {
+        let path = path.as_ref();
+        let error = |why: String| NokhwaError::OpenDeviceError(path.display().to_string(), why);
+
+        let mut info = None;
+        let mut formats = Vec::new();
+        let mut stream_formats = Vec::new();
+        let mut property_snapshots = Vec::new();
+        let mut session = read_session(path)?;
+        loop {
+            // A recording that was cut short plays up to where it ends.
+            let record = match session.read_metadata_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(_) if info.is_some() => break,
+                Err(why) => return Err(error(why.to_string())),
+            };
+            match record {
+                SessionRecord::Camera(camera) => info = Some(camera),
+                SessionRecord::Formats(enumerated) => formats = enumerated,
+                SessionRecord::Properties { at, properties } => {
+                    property_snapshots.push((at, properties));
+                }
+                SessionRecord::Started { format, .. }
+                | SessionRecord::FormatChanged { format, .. } => {
+                    if !stream_formats.contains(&format) {
+                        stream_formats.push(format);
+                    }
+                }
+                SessionRecord::Frame { .. } | SessionRecord::Stopped { .. } => {}
+            }
+        }
+
+        let info = info.ok_or_else(|| error("session has no camera".to_string()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            info,
+            formats,
+            stream_formats,
+            properties: Properties::default(),
+            overridden: None,
+            property_snapshots,
+            snapshots_reached: Arc::new(AtomicUsize::new(1)),
+            paced: true,
+            player: None,
+        })
+    }
+
+    /// The [`CameraInformation`] of the recorded camera.
+    #[must_use]
+    pub fn info(&self) -> &CameraInformation {
+        &self.info
+    }
+
+    /// The session being played.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The format the recorded stream started in, `None` if the session holds no stream.
+    #[must_use]
+    pub fn format(&self) -> Option<CameraFormat> {
+        self.stream_formats.first().copied()
+    }
+
+    /// Every recorded snapshot of the properties, with when it was taken.
+    #[must_use]
+    pub fn property_snapshots(&self) -> &[(Duration, Properties)] {
+        &self.property_snapshots
+    }
+
+    /// The number of the snapshot in effect, counting from 1. The first one is in effect until
+    /// the stream plays past another.
+    fn current_snapshot(&self) -> usize {
+        self.snapshots_reached.load(Ordering::Acquire).max(1)
+    }
+
+    /// Whether frames are delivered with the recorded timing. Unpaced frames are delivered as
+    /// fast as the stream takes them, but still timestamped as recorded.
+    pub fn set_paced(&mut self, paced: bool) {
+        self.paced = paced;
+    }
+}
+
+impl Setting for ReplayCamera {
+    fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError> {
+        Ok(self.formats.clone())
+    }
+
+    fn enumerate_resolution_and_frame_rates(
+        &self,
+        frame_format: FrameFormat,
+    ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError> {
+        let mut resolutions: HashMap<Resolution, Vec<FrameRate>> = HashMap::new();
+        for format in self
+            .formats
+            .iter()
+            .filter(|format| format.format() == frame_format)
+        {
+            let frame_rates = resolutions.entry(format.resolution()).or_default();
+            if !frame_rates.contains(&format.frame_rate()) {
+                frame_rates.push(format.frame_rate());
+            }
+        }
+        Ok(resolutions)
+    }
+
+    /// Accepts the recorded formats, without changing what is played.
+    ///
+    /// # Errors
+    /// If `camera_format` was neither enumerated nor streamed by the recorded camera.
+    fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError> {
+        if self.formats.contains(&camera_format) || self.stream_formats.contains(&camera_format) {
+            Ok(())
+        } else {
+            Err(NokhwaError::SetPropertyError {
+                property: "format".to_string(),
+                value: camera_format.to_string(),
+                error: "not a recorded format".to_string(),
+            })
+        }
+    }
+
+    /// The snapshot the stream has played up to, unless changed with
+    /// [`Setting::set_property`] since.
+    fn properties(&self) -> &Properties {
+        let current = self.current_snapshot();
+        match self.property_snapshots.get(current - 1) {
+            Some((_, snapshot)) if self.overridden != Some(current) => snapshot,
+            _ => &self.properties,
+        }
+    }
+
+    /// Changes the properties of this camera, until the stream plays up to the next recorded
+    /// snapshot.
+    fn set_property(
+        &mut self,
+        property: &ControlId,
+        value: ControlValue,
+    ) -> Result<(), NokhwaError> {
+        let current = self.current_snapshot();
+        if self.overridden != Some(current) {
+            self.properties = self.properties().clone();
+        }
+        self.properties.set_control_value(property, value)?;
+        if !self.property_snapshots.is_empty() {
+            self.overridden = Some(current);
+        }
+        Ok(())
+    }
+}
+
+impl Capture for ReplayCamera {
+    fn open_stream(&mut self) -> Result<Stream, NokhwaError> {
+        self.open_stream_with_policy(BackpressurePolicy::default())
+    }
+
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        if self.player.as_ref().is_some_and(Producer::is_running) {
+            return Err(NokhwaError::OpenStreamError(
+                "a stream is already open".to_string(),
+            ));
+        }
+        if self.stream_formats.is_empty() {
+            return Err(NokhwaError::OpenStreamError(
+                "session holds no stream".to_string(),
+            ));
+        }
+
+        let (sender, receiver) = frame_channel(policy);
+        // The player counts the snapshots again as it plays them.
+        self.snapshots_reached.store(0, Ordering::Release);
+        let path = self.path.clone();
+        let paced = self.paced;
+        let snapshots_reached = self.snapshots_reached.clone();
+        let (player, handle) = Producer::spawn("nokhwa-session-replay", move |running| {
+            play(&path, paced, &sender, &snapshots_reached, &running);
+        })?;
+        self.player = Some(player.clone());
+        Ok(Stream::new(Box::new(ProducerStream::new(
+            receiver, player, handle,
+        ))))
+    }
+
+    fn close_stream(&mut self) -> Result<(), NokhwaError> {
+        if let Some(player) = self.player.take() {
+            player.stop();
+        }
+        Ok(())
+    }
+}
+
+impl Camera for ReplayCamera {}
+
+fn read_session(path: &Path) -> NokhwaResult<SessionReader<BufReader<File>>> {
+    let file = File::open(path)
+        .map_err(|why| NokhwaError::OpenDeviceError(path.display().to_string(), why.to_string()))?;
+    SessionReader::new(BufReader::new(file))
+}
+
+/// Plays the first stream of the session at `path`, counting the property snapshots it plays
+/// up to in `snapshots_reached`.
+fn play(
+    path: &Path,
+    paced: bool,
+    frames: &FrameSender,
+    snapshots_reached: &AtomicUsize,
+    running: &AtomicBool,
+) {
+    let records = match read_session(path) {
+        Ok(records) => records,
+        Err(why) => {
+            frames.stop(StopReason::Error(why));
+            return;
+        }
+    };
+
+    // When the stream started in the recording, and when it started playing.
+    let mut origin: Option<(Duration, Instant)> = None;
+    let mut snapshots = 0;
+    for record in records {
+        if !running.load(Ordering::Acquire) || frames.is_disconnected() {
+            break;
+        }
+        let record = match record {
+            Ok(record) => record,
+            Err(why) => {
+                frames.stop(StopReason::Error(why));
+                return;
+            }
+        };
+        let Some(at) = record.at() else {
+            continue;
+        };
+        if let SessionRecord::Properties { .. } = record {
+            snapshots += 1;
+        }
+        let (recorded, playing) = match (origin, &record) {
+            (Some(origin), _) => origin,
+            (None, SessionRecord::Started { .. }) => *origin.insert((at, Instant::now())),
+            // Snapshots taken before the stream started are in effect when it starts.
+            (None, SessionRecord::Properties { .. }) => {
+                snapshots_reached.store(snapshots, Ordering::Release);
+                continue;
+            }
+            (None, _) => continue,
+        };
+
+        let due = playing + at.saturating_sub(recorded);
+        if paced && !sleep_until(Some(due), running) {
+            break;
+        }
+        match record {
+            SessionRecord::Started { format, .. } => frames.emit(StreamEvent::Started(format)),
+            SessionRecord::FormatChanged { format, .. } => {
+                frames.emit(StreamEvent::FormatChanged(format));
+            }
+            SessionRecord::Frame { mut frame, .. } => {
+                frame.set_timestamp(due);
+                if frames.forward(frame).is_err() {
+                    break;
+                }
+            }
+            SessionRecord::Stopped { reason, .. } => {
+                if matches!(reason, StopReason::DeviceDisconnected) {
+                    frames.emit(StreamEvent::DeviceDisconnected);
+                }
+                frames.stop(reason);
+                return;
+            }
+            SessionRecord::Properties { .. } => {
+                snapshots_reached.store(snapshots, Ordering::Release);
+            }
+            SessionRecord::Camera(_) | SessionRecord::Formats(_) => {}
+        }
+    }
+    frames.stop(StopReason::Requested);
+}
@@ -0,0 +1,312 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! [`Iterator`] and [`futures::Stream`] adapters for [`Stream`].
+//!
+//! Items are [`NokhwaResult`]s like those of [`Stream::poll_frame`], so the adapters work with
+//! `?` and `TryStreamExt`. A disconnect is not an error: the adapters end cleanly once the backend
+//! is gone and every queued frame is taken. If the backend stopped because it failed, e.g. with
+//! [`NokhwaError::ReadFrameError`](crate::error::NokhwaError::ReadFrameError), that error is
+//! yielded once before the end.
+
+use crate::{
+    error::NokhwaResult,
+    frame_buffer::FrameBuffer,
+    stream::{FrameReceiver, Stream},
+};
+use flume::RecvError;
+
+/// Turns the result of a blocking receive into an adapter item. `ended` is set once the channel
+/// is disconnected, after which only `None` is returned.
+fn frame_item(
+    receiver: &FrameReceiver,
+    frame: Result<FrameBuffer, RecvError>,
+    ended: &mut bool,
+) -> Option<NokhwaResult<FrameBuffer>> {
+    if *ended {
+        return None;
+    }
+    match frame {
+        Ok(frame) => Some(Ok(receiver.delivered(frame))),
+        Err(RecvError::Disconnected) => end(receiver, ended),
+    }
+}
+
+/// Ends an adapter, returning the error the backend stopped with, if any.
+fn end(receiver: &FrameReceiver, ended: &mut bool) -> Option<NokhwaResult<FrameBuffer>> {
+    if std::mem::replace(ended, true) {
+        return None;
+    }
+    receiver.stop_error().map(Err)
+}
+
+/// Blocking [`Iterator`] over the frames of a borrowed [`Stream`], see [`Stream::frames`].
+#[derive(Clone)]
+pub struct Frames<'a> {
+    stream: &'a Stream,
+    ended: bool,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(stream: &'a Stream) -> Self {
+        Self {
+            stream,
+            ended: false,
+        }
+    }
+}
+
+impl Iterator for Frames<'_> {
+    type Item = NokhwaResult<FrameBuffer>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let receiver = self.stream.inner.receiver();
+        frame_item(receiver, receiver.recv(), &mut self.ended)
+    }
+}
+
+/// Blocking [`Iterator`] that owns a [`Stream`], created by [`Stream::into_iter`]. Dropping it
+/// stops the stream.
+pub struct IntoFrames {
+    stream: Stream,
+    ended: bool,
+}
+
+impl IntoFrames {
+    /// Gives back the [`Stream`].
+    #[must_use]
+    pub fn into_inner(self) -> Stream {
+        self.stream
+    }
+}
+
+impl Iterator for IntoFrames {
+    type Item = NokhwaResult<FrameBuffer>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let receiver = self.stream.inner.receiver();
+        frame_item(receiver, receiver.recv(), &mut self.ended)
+    }
+}
+
+impl IntoIterator for Stream {
+    type Item = NokhwaResult<FrameBuffer>;
+    type IntoIter = IntoFrames;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoFrames {
+            stream: self,
+            ended: false,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::asynchronous::{FrameStream, IntoFrameStream};
+
+#[cfg(feature = "async")]
+mod asynchronous {
+    use super::end;
+    use crate::{
+        error::NokhwaResult,
+        frame_buffer::FrameBuffer,
+        stream::{FrameReceiver, Stream},
+    };
+    use flume::{r#async::RecvStream, Receiver};
+    use futures::stream::{FusedStream, Stream as FuturesStream};
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    /// [`futures::Stream`] over the frames of a borrowed [`Stream`], see
+    /// [`Stream::frame_stream`].
+    #[cfg_attr(feature = "docs-features", doc(cfg(feature = "async")))]
+    pub struct FrameStream<'a> {
+        frames: RecvStream<'a, FrameBuffer>,
+        receiver: &'a FrameReceiver,
+        ended: bool,
+    }
+
+    impl<'a> FrameStream<'a> {
+        pub(crate) fn new(stream: &'a Stream) -> Self {
+            let receiver = stream.inner.receiver();
+            Self {
+                frames: receiver.stream(),
+                receiver,
+                ended: false,
+            }
+        }
+    }
+
+    impl FuturesStream for FrameStream<'_> {
+        type Item = NokhwaResult<FrameBuffer>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = &mut *self;
+            poll_frame(&mut this.frames, this.receiver, &mut this.ended, cx)
+        }
+    }
+
+    impl FusedStream for FrameStream<'_> {
+        fn is_terminated(&self) -> bool {
+            self.ended
+        }
+    }
+
+    /// [`futures::Stream`] that owns a [`Stream`], see [`Stream::into_frame_stream`]. Dropping it
+    /// stops the stream.
+    #[cfg_attr(feature = "docs-features", doc(cfg(feature = "async")))]
+    pub struct IntoFrameStream {
+        frames: RecvStream<'static, FrameBuffer>,
+        // Dropped after `frames`, it keeps the backend running.
+        stream: Stream,
+        ended: bool,
+    }
+
+    impl IntoFrameStream {
+        pub(crate) fn new(stream: Stream) -> Self {
+            Self {
+                frames: Receiver::clone(stream.inner.receiver()).into_stream(),
+                stream,
+                ended: false,
+            }
+        }
+
+        /// Gives back the [`Stream`]. Frames already taken from the queue but not yet yielded
+        /// are lost.
+        #[must_use]
+        pub fn into_inner(self) -> Stream {
+            self.stream
+        }
+    }
+
+    impl FuturesStream for IntoFrameStream {
+        type Item = NokhwaResult<FrameBuffer>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = &mut *self;
+            poll_frame(
+                &mut this.frames,
+                this.stream.inner.receiver(),
+                &mut this.ended,
+                cx,
+            )
+        }
+    }
+
+    impl FusedStream for IntoFrameStream {
+        fn is_terminated(&self) -> bool {
+            self.ended
+        }
+    }
+
+    /// The async counterpart of [`frame_item`](super::frame_item).
+    fn poll_frame(
+        frames: &mut RecvStream<'_, FrameBuffer>,
+        receiver: &FrameReceiver,
+        ended: &mut bool,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<NokhwaResult<FrameBuffer>>> {
+        if *ended {
+            return Poll::Ready(None);
+        }
+        Pin::new(frames).poll_next(cx).map(|frame| match frame {
+            Some(frame) => Some(Ok(receiver.delivered(frame))),
+            None => end(receiver, ended),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{NokhwaError, NokhwaResult},
+        frame_buffer::FrameBuffer,
+        frame_format::FrameFormat,
+        stream::{
+            events::StopReason, frame_channel, BackpressurePolicy, FrameReceiver, FrameSender,
+            Stream, StreamInnerTrait,
+        },
+        types::Resolution,
+    };
+
+    struct ChannelInner {
+        receiver: FrameReceiver,
+    }
+
+    impl StreamInnerTrait for ChannelInner {
+        fn receiver(&self) -> &FrameReceiver {
+            &self.receiver
+        }
+
+        fn stop(&mut self) -> NokhwaResult<()> {
+            Ok(())
+        }
+    }
+
+    /// A stream with two queued frames whose backend is gone, having stopped for `reason`.
+    fn ended_stream(reason: StopReason) -> Stream {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::Block(4));
+        send_two(&sender);
+        sender.stop(reason);
+        drop(sender);
+        Stream::new(Box::new(ChannelInner { receiver }))
+    }
+
+    fn send_two(sender: &FrameSender) {
+        for _ in 0..2 {
+            let frame = FrameBuffer::new(Resolution::new(1, 1), &[0, 0, 0], FrameFormat::Rgb888);
+            sender.send(frame).unwrap();
+        }
+    }
+
+    #[test]
+    fn frames_end_cleanly_after_a_requested_stop() {
+        let stream = ended_stream(StopReason::Requested);
+        let items = stream.frames().collect::<Vec<_>>();
+        assert_eq!(items.len(), 2);
+        assert!(items.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn frames_yield_the_backend_error_once() {
+        let failure = NokhwaError::ReadFrameError("device failed".to_string());
+        let mut frames = ended_stream(StopReason::Error(failure)).into_iter();
+        assert!(frames.next().unwrap().is_ok());
+        assert!(frames.next().unwrap().is_ok());
+        assert!(matches!(
+            frames.next(),
+            Some(Err(NokhwaError::ReadFrameError(why))) if why == "device failed"
+        ));
+        assert!(frames.next().is_none());
+        assert!(frames.next().is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn frame_streams_yield_the_backend_error_once() {
+        use futures::{executor::block_on, stream::FusedStream, StreamExt};
+
+        let failure = NokhwaError::ReadFrameError("device failed".to_string());
+        let mut frames = ended_stream(StopReason::Error(failure)).into_frame_stream();
+        let items = block_on((&mut frames).collect::<Vec<_>>());
+        assert_eq!(items.len(), 3);
+        assert!(items[2].is_err());
+        assert!(frames.is_terminated());
+        assert!(block_on(frames.next()).is_none());
+    }
+}
@@ -0,0 +1,223 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A runtime agnostic timer future, so that the async waits of [`Stream`](super::Stream) work on
+//! any executor.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError},
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+
+#[derive(Default)]
+struct DelayState {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+/// The deadlines of every pending [`Delay`], watched by a single thread started on first use.
+#[derive(Default)]
+struct Timer {
+    pending: Mutex<Pending>,
+    /// Notified when a deadline earlier than all others is added.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// By deadline, then by when they were added.
+    delays: BTreeMap<(Instant, u64), Arc<Mutex<DelayState>>>,
+    next_id: u64,
+    started: bool,
+}
+
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(Timer::default)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Timer {
+    /// Adds a delay firing at `deadline`, returning its key, or `None` if the timer thread could
+    /// not be started.
+    fn schedule(
+        &'static self,
+        deadline: Instant,
+        state: Arc<Mutex<DelayState>>,
+    ) -> Option<(Instant, u64)> {
+        let mut pending = lock(&self.pending);
+        if !pending.started {
+            pending.started = std::thread::Builder::new()
+                .name("nokhwa-delay".to_string())
+                .spawn(move || self.run())
+                .is_ok();
+            if !pending.started {
+                return None;
+            }
+        }
+
+        let key = (deadline, pending.next_id);
+        pending.next_id += 1;
+        let earliest = match pending.delays.first_key_value() {
+            Some((first, _)) => key < *first,
+            None => true,
+        };
+        pending.delays.insert(key, state);
+        if earliest {
+            self.changed.notify_one();
+        }
+        Some(key)
+    }
+
+    fn cancel(&self, key: (Instant, u64)) {
+        // Waking the thread is not needed, it only fires the delays that are still there.
+        lock(&self.pending).delays.remove(&key);
+    }
+
+    fn run(&self) {
+        let mut pending = lock(&self.pending);
+        loop {
+            let now = Instant::now();
+            while let Some(entry) = pending.delays.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                let waker = {
+                    let mut state = lock(entry.get());
+                    state.fired = true;
+                    state.waker.take()
+                };
+                entry.remove();
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+
+            pending = match pending.delays.first_key_value() {
+                Some(((deadline, _), _)) => {
+                    let timeout = deadline.saturating_duration_since(now);
+                    self.changed
+                        .wait_timeout(pending, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(pending)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+/// Completes at a deadline. All delays are watched by one helper thread, which sleeps until the
+/// earliest of them; a dropped delay is forgotten.
+pub(crate) struct Delay {
+    state: Arc<Mutex<DelayState>>,
+    key: Option<(Instant, u64)>,
+}
+
+impl Delay {
+    pub(crate) fn until(deadline: Instant) -> Self {
+        let state = Arc::new(Mutex::new(DelayState::default()));
+        let key = timer().schedule(deadline, state.clone());
+        // Without a timer thread the delay could never fire, so fire it right away instead.
+        if key.is_none() {
+            lock(&state).fired = true;
+        }
+        Self { state, key }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Checked under the same lock the timer fires it with, so a firing is never missed.
+        let mut state = lock(&self.state);
+        if state.fired {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            timer().cancel(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn fires_at_the_deadline() {
+        let start = Instant::now();
+        futures::executor::block_on(Delay::until(start + Duration::from_millis(50)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        futures::executor::block_on(Delay::until(start));
+    }
+
+    #[test]
+    fn fires_delays_in_deadline_order() {
+        let start = Instant::now();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let waiters: Vec<_> = [150, 50, 100]
+            .into_iter()
+            .map(|millis| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    futures::executor::block_on(Delay::until(
+                        start + Duration::from_millis(millis),
+                    ));
+                    sender.send(millis).unwrap();
+                })
+            })
+            .collect();
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [50, 100, 150]);
+    }
+
+    #[test]
+    fn forgets_dropped_delays() {
+        let delays: Vec<_> = (0..100)
+            .map(|_| Delay::until(Instant::now() + Duration::from_secs(30)))
+            .collect();
+        assert!(lock(&timer().pending).delays.len() >= 100);
+        let keys: Vec<_> = delays.iter().filter_map(|delay| delay.key).collect();
+        drop(delays);
+
+        let pending = lock(&timer().pending);
+        assert!(keys.iter().all(|key| !pending.delays.contains_key(key)));
+    }
+}
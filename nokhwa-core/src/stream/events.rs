@@ -0,0 +1,275 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Lifecycle events of a [`Stream`](super::Stream), delivered on a side-channel next to the
+//! frames, see [`Stream::events`](super::Stream::events).
+//!
+//! Backends report [`StreamEvent::Started`], [`StreamEvent::FormatChanged`],
+//! [`StreamEvent::DeviceDisconnected`] and why they stopped through
+//! [`FrameSender`](super::FrameSender). [`StreamEvent::FirstFrame`] is reported by the channel
+//! itself and [`StreamEvent::Stalled`] by the watchdog set up with
+//! [`Stream::set_stall_threshold`](super::Stream::set_stall_threshold).
+
+use crate::{error::NokhwaError, types::CameraFormat};
+use flume::{Receiver, Sender, TrySendError};
+use std::{
+    fmt::{Display, Formatter},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// How many events are kept for a consumer that does not keep up. Newer events are dropped,
+/// except [`StreamEvent::Stopped`], which evicts the oldest one instead.
+const EVENT_CAPACITY: usize = 64;
+
+/// A lifecycle transition of a [`Stream`](super::Stream).
+#[derive(Clone, Debug)]
+pub enum StreamEvent {
+    /// The backend started streaming in this format.
+    Started(CameraFormat),
+    /// The backend delivered its first frame.
+    FirstFrame,
+    /// No frame has arrived for this long. Reported once per stall.
+    Stalled(Duration),
+    /// The backend renegotiated the format, e.g. the resolution, mid-stream.
+    FormatChanged(CameraFormat),
+    /// The device was unplugged or otherwise went away.
+    DeviceDisconnected,
+    /// The stream has stopped and will deliver no more frames. Always the last event.
+    Stopped(StopReason),
+}
+
+/// Why a [`Stream`](super::Stream) stopped, see [`StreamEvent::Stopped`].
+#[derive(Clone, Debug)]
+pub enum StopReason {
+    /// The stream was stopped or dropped by its owner.
+    Requested,
+    /// The device went away.
+    DeviceDisconnected,
+    /// The backend failed.
+    Error(NokhwaError),
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Requested => write!(f, "requested"),
+            StopReason::DeviceDisconnected => write!(f, "device disconnected"),
+            StopReason::Error(why) => write!(f, "{why}"),
+        }
+    }
+}
+
+/// Lifecycle state shared by both halves of a [`frame_channel`](super::frame_channel).
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    events: Sender<StreamEvent>,
+    /// Makes room for [`StreamEvent::Stopped`] when the consumer lags behind.
+    evictor: Receiver<StreamEvent>,
+    created: Instant,
+    last_frame: Mutex<Option<Instant>>,
+    /// Set when the owner starts stopping the stream, before the backend is stopped.
+    closing: AtomicBool,
+    stopped: AtomicBool,
+    reason: Mutex<Option<StopReason>>,
+}
+
+impl Lifecycle {
+    pub(crate) fn new() -> (Arc<Self>, Receiver<StreamEvent>) {
+        let (events, receiver) = flume::bounded(EVENT_CAPACITY);
+        let lifecycle = Lifecycle {
+            events,
+            evictor: receiver.clone(),
+            created: Instant::now(),
+            last_frame: Mutex::new(None),
+            closing: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            reason: Mutex::new(None),
+        };
+        (Arc::new(lifecycle), receiver)
+    }
+
+    /// Reports `event`, unless the stream has already stopped.
+    pub(crate) fn emit(&self, event: StreamEvent) {
+        if !self.stopped.load(Ordering::Acquire) {
+            let _ = self.events.try_send(event);
+        }
+    }
+
+    /// Records a frame from the backend, reporting [`StreamEvent::FirstFrame`] for the first one.
+    pub(crate) fn frame_arrived(&self) {
+        let first = self
+            .last_frame
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(Instant::now())
+            .is_none();
+        if first {
+            self.emit(StreamEvent::FirstFrame);
+        }
+    }
+
+    /// When the last frame arrived, or the channel was created if there was none yet.
+    fn last_activity(&self) -> Instant {
+        self.last_frame
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .unwrap_or(self.created)
+    }
+
+    /// Reports [`StreamEvent::Stopped`], once. If the queue is full, the oldest event is dropped
+    /// to make room for it.
+    pub(crate) fn stop(&self, reason: StopReason) {
+        if !self.stopped.swap(true, Ordering::AcqRel) {
+            *self.reason.lock().unwrap_or_else(PoisonError::into_inner) = Some(reason.clone());
+            let mut event = StreamEvent::Stopped(reason);
+            // The evictor keeps the channel connected, so sending only ever fails when it is full.
+            while let Err(TrySendError::Full(unsent)) = self.events.try_send(event) {
+                let _ = self.evictor.try_recv();
+                event = unsent;
+            }
+        }
+    }
+
+    /// Refuses every further frame, so that a backend blocked on a full queue gives up and can be
+    /// joined while the stream is stopping.
+    pub(crate) fn close(&self) {
+        self.closing.store(true, Ordering::Release);
+    }
+
+    /// Whether the stream is stopping or has stopped.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::Acquire) || self.is_stopped()
+    }
+
+    /// The error the backend stopped with, if it stopped because of one.
+    pub(crate) fn error(&self) -> Option<NokhwaError> {
+        match &*self.reason.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(StopReason::Error(why)) => Some(why.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+}
+
+/// Reports [`StreamEvent::Stalled`] when no frame has arrived for `threshold`. The helper
+/// thread exits when the stream stops, or is woken and joined when the watchdog is dropped.
+#[derive(Debug)]
+pub(crate) struct StallWatchdog {
+    cancelled: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StallWatchdog {
+    pub(crate) fn new(lifecycle: Arc<Lifecycle>, threshold: Duration) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let cancelled = cancelled.clone();
+            std::thread::Builder::new()
+                .name("nokhwa-stall-watchdog".to_string())
+                .spawn(move || {
+                    // The frame that last reported a stall, so that every stall is reported once.
+                    let mut reported = None;
+                    while !cancelled.load(Ordering::Acquire) && !lifecycle.is_stopped() {
+                        let last_activity = lifecycle.last_activity();
+                        let idle = last_activity.elapsed();
+                        if idle < threshold {
+                            std::thread::park_timeout(threshold.saturating_sub(idle));
+                        } else {
+                            if reported != Some(last_activity) {
+                                reported = Some(last_activity);
+                                lifecycle.emit(StreamEvent::Stalled(idle));
+                            }
+                            std::thread::park_timeout(threshold);
+                        }
+                    }
+                })
+                .ok()
+        };
+
+        Self { cancelled, thread }
+    }
+}
+
+impl Drop for StallWatchdog {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopped_is_the_last_event_even_when_the_consumer_lags() {
+        let (lifecycle, events) = Lifecycle::new();
+        for _ in 0..EVENT_CAPACITY * 2 {
+            lifecycle.emit(StreamEvent::FirstFrame);
+        }
+        lifecycle.stop(StopReason::DeviceDisconnected);
+        lifecycle.emit(StreamEvent::FirstFrame);
+
+        let events = events.drain().collect::<Vec<_>>();
+        assert_eq!(events.len(), EVENT_CAPACITY);
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Stopped(StopReason::DeviceDisconnected))
+        ));
+    }
+
+    #[test]
+    fn only_the_first_stop_is_reported() {
+        let (lifecycle, events) = Lifecycle::new();
+        lifecycle.stop(StopReason::Error(NokhwaError::ReadFrameError(
+            "gone".to_string(),
+        )));
+        lifecycle.stop(StopReason::Requested);
+
+        assert!(matches!(
+            lifecycle.error(),
+            Some(NokhwaError::ReadFrameError(_))
+        ));
+        assert_eq!(events.drain().count(), 1);
+    }
+
+    #[test]
+    fn stall_watchdog_reports_a_stall_once_and_joins_on_drop() {
+        let (lifecycle, events) = Lifecycle::new();
+        let watchdog = StallWatchdog::new(lifecycle, Duration::from_millis(20));
+
+        let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(event, StreamEvent::Stalled(_)));
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(events.is_empty());
+
+        let started = Instant::now();
+        drop(watchdog);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
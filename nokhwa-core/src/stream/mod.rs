@@ -0,0 +1,894 @@
+use crate::error::{NokhwaError, NokhwaResult};
+use crate::frame_buffer::FrameBuffer;
+use events::{Lifecycle, StallWatchdog, StopReason, StreamEvent};
+use flume::{Receiver, RecvTimeoutError, SendTimeoutError, Sender, TryRecvError, TrySendError};
+use stats::{StatsCollector, StreamStats};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// What happens when a backend produces frames faster than the [`Stream`] consumes them.
+#[derive(Copy, Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub enum BackpressurePolicy {
+    /// Queue up to this many frames, then block the backend until the consumer catches up. No
+    /// frames are dropped, but the driver may drop frames itself while the backend is blocked.
+    Block(usize),
+    /// Queue up to this many frames, then drop the oldest queued frame to make room.
+    DropOldest(usize),
+    /// Queue up to this many frames, then drop new frames until there is room again.
+    DropNewest(usize),
+    /// Keep only the most recent frame, overwriting it with every new one.
+    LatestOnly,
+}
+
+impl BackpressurePolicy {
+    /// How many frames can be queued at once.
+    #[must_use]
+    pub fn capacity(self) -> usize {
+        match self {
+            BackpressurePolicy::Block(capacity)
+            | BackpressurePolicy::DropOldest(capacity)
+            | BackpressurePolicy::DropNewest(capacity) => capacity.max(1),
+            BackpressurePolicy::LatestOnly => 1,
+        }
+    }
+}
+
+impl Default for BackpressurePolicy {
+    /// Queues up to 4 frames, dropping the oldest. A slow consumer sees recent frames, without the
+    /// backend ever blocking.
+    fn default() -> Self {
+        BackpressurePolicy::DropOldest(4)
+    }
+}
+
+/// How long a blocked [`FrameSender::send`] waits before checking if the [`Stream`] is gone or
+/// stopping.
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Creates the channel a backend uses to hand frames to a [`Stream`], queueing frames according
+/// to `policy`.
+#[must_use]
+pub fn frame_channel(policy: BackpressurePolicy) -> (FrameSender, FrameReceiver) {
+    let (sender, receiver) = flume::bounded(policy.capacity());
+    let alive = Arc::new(());
+    let dropped = Arc::new(AtomicU64::new(0));
+    let (lifecycle, events) = Lifecycle::new();
+    let stats = Arc::new(StatsCollector::new());
+    let evictor = match policy {
+        BackpressurePolicy::DropOldest(_) | BackpressurePolicy::LatestOnly => {
+            Some(receiver.clone())
+        }
+        BackpressurePolicy::Block(_) | BackpressurePolicy::DropNewest(_) => None,
+    };
+
+    (
+        FrameSender {
+            sender,
+            evictor,
+            policy,
+            next_sequence: Arc::new(AtomicU64::new(0)),
+            dropped: dropped.clone(),
+            receiver_alive: Arc::downgrade(&alive),
+            lifecycle: lifecycle.clone(),
+            stats: stats.clone(),
+        },
+        FrameReceiver {
+            receiver,
+            policy,
+            dropped,
+            events,
+            lifecycle,
+            stats,
+            _alive: alive,
+        },
+    )
+}
+
+/// The sending half of a [`frame_channel`]. Stamps every frame with a sequence number as it
+/// enters the stream, so that frames dropped by the driver or by the [`BackpressurePolicy`] show
+/// up as gaps.
+#[derive(Clone, Debug)]
+pub struct FrameSender {
+    sender: Sender<FrameBuffer>,
+    /// Used to evict the oldest frame, for the policies that do so.
+    evictor: Option<Receiver<FrameBuffer>>,
+    policy: BackpressurePolicy,
+    next_sequence: Arc<AtomicU64>,
+    dropped: Arc<AtomicU64>,
+    receiver_alive: Weak<()>,
+    lifecycle: Arc<Lifecycle>,
+    stats: Arc<StatsCollector>,
+}
+
+impl FrameSender {
+    /// Stamps `frame` with the next sequence number and queues it following the
+    /// [`BackpressurePolicy`] of the channel. Sequence numbers of dropped frames are not reused.
+    ///
+    /// # Errors
+    /// If the [`Stream`] has been dropped or is stopping.
+    pub fn send(&self, mut frame: FrameBuffer) -> NokhwaResult<()> {
+        if self.is_disconnected() {
+            return Err(disconnected());
+        }
+        frame.set_sequence(self.next_sequence.fetch_add(1, Ordering::Relaxed));
+        self.enqueue(frame)
+    }
+
+    /// Queues a `frame` that already has a sequence number, e.g. one relayed from another
+    /// [`Stream`], keeping that number so gaps upstream still show up downstream. Gaps are
+    /// counted in [`StreamStats::backend_dropped`].
+    ///
+    /// # Errors
+    /// If the [`Stream`] has been dropped or is stopping.
+    pub fn forward(&self, frame: FrameBuffer) -> NokhwaResult<()> {
+        if self.is_disconnected() {
+            return Err(disconnected());
+        }
+        let expected = self
+            .next_sequence
+            .fetch_max(frame.sequence() + 1, Ordering::Relaxed);
+        self.stats
+            .frames_lost(frame.sequence().saturating_sub(expected));
+        self.enqueue(frame)
+    }
+
+    fn enqueue(&self, mut frame: FrameBuffer) -> NokhwaResult<()> {
+        self.lifecycle.frame_arrived();
+        self.stats.frame_arrived(&frame);
+
+        match self.policy {
+            BackpressurePolicy::Block(_) => loop {
+                match self.sender.send_timeout(frame, BLOCK_POLL_INTERVAL) {
+                    Ok(()) => return Ok(()),
+                    Err(SendTimeoutError::Timeout(unsent)) if !self.is_disconnected() => {
+                        frame = unsent;
+                    }
+                    Err(_) => return Err(disconnected()),
+                }
+            },
+            BackpressurePolicy::DropNewest(_) => match self.sender.try_send(frame) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+                Err(TrySendError::Disconnected(_)) => Err(disconnected()),
+            },
+            BackpressurePolicy::DropOldest(_) | BackpressurePolicy::LatestOnly => loop {
+                match self.sender.try_send(frame) {
+                    Ok(()) => return Ok(()),
+                    Err(TrySendError::Full(unsent)) => {
+                        // The consumer may have taken a frame in the meantime, then there is
+                        // nothing to evict and the next attempt succeeds.
+                        if let Some(Ok(_)) = self.evictor.as_ref().map(Receiver::try_recv) {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        frame = unsent;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return Err(disconnected()),
+                }
+            },
+        }
+    }
+
+    /// Records that the driver dropped `count` frames before they reached the backend, e.g. from
+    /// a jump in the V4L2 buffer sequence. They are counted in [`StreamStats::backend_dropped`].
+    pub fn skip(&self, count: u64) {
+        self.next_sequence.fetch_add(count, Ordering::Relaxed);
+        self.stats.frames_lost(count);
+    }
+
+    /// The sequence number the next frame will get.
+    #[must_use]
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence.load(Ordering::Relaxed)
+    }
+
+    /// The [`BackpressurePolicy`] of this channel.
+    #[must_use]
+    pub fn policy(&self) -> BackpressurePolicy {
+        self.policy
+    }
+
+    /// Reports a lifecycle `event` on the [`Stream::events`] side-channel, e.g.
+    /// [`StreamEvent::Started`] once the device is streaming.
+    pub fn emit(&self, event: StreamEvent) {
+        if let StreamEvent::Started(format) | StreamEvent::FormatChanged(format) = event {
+            self.stats.format_changed(format);
+        }
+        self.lifecycle.emit(event);
+    }
+
+    /// Reports that the backend stopped on its own for `reason`. Only the first report of either
+    /// half of the channel is kept, no events are reported after it.
+    pub fn stop(&self, reason: StopReason) {
+        self.lifecycle.stop(reason);
+    }
+
+    /// Refuses every further frame, so a thread blocked sending to a full
+    /// [`BackpressurePolicy::Block`] queue returns.
+    pub(crate) fn close(&self) {
+        self.lifecycle.close();
+    }
+
+    /// The lifecycle of the channel, to close it from somewhere that must not keep the channel
+    /// connected by holding a sender.
+    pub(crate) fn lifecycle(&self) -> Arc<Lifecycle> {
+        self.lifecycle.clone()
+    }
+
+    /// Whether the receiving [`Stream`] has been dropped, or is stopping or stopped. Backends
+    /// should stop sending frames once it is.
+    #[must_use]
+    pub fn is_disconnected(&self) -> bool {
+        self.receiver_alive.strong_count() == 0 || self.lifecycle.is_closing()
+    }
+}
+
+/// The receiving half of a [`frame_channel`], held by the [`StreamInnerTrait`] of a [`Stream`].
+/// Dereferences to the underlying [`Receiver`].
+#[derive(Clone, Debug)]
+pub struct FrameReceiver {
+    receiver: Receiver<FrameBuffer>,
+    policy: BackpressurePolicy,
+    dropped: Arc<AtomicU64>,
+    events: Receiver<StreamEvent>,
+    lifecycle: Arc<Lifecycle>,
+    stats: Arc<StatsCollector>,
+    _alive: Arc<()>,
+}
+
+impl FrameReceiver {
+    /// The [`BackpressurePolicy`] of this channel.
+    #[must_use]
+    pub fn policy(&self) -> BackpressurePolicy {
+        self.policy
+    }
+
+    /// How many frames the [`BackpressurePolicy`] has dropped so far.
+    #[must_use]
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The lifecycle events of this channel, see [`Stream::events`].
+    #[must_use]
+    pub fn events(&self) -> &Receiver<StreamEvent> {
+        &self.events
+    }
+
+    /// The error to return once the channel is disconnected: the error the backend stopped with,
+    /// if any.
+    pub(crate) fn closed_error(&self) -> NokhwaError {
+        self.stop_error().unwrap_or_else(disconnected)
+    }
+
+    /// The error the backend stopped with, if it stopped because it failed.
+    pub(crate) fn stop_error(&self) -> Option<NokhwaError> {
+        self.lifecycle.error()
+    }
+
+    /// Refuses every further frame, so a backend blocked sending to a full
+    /// [`BackpressurePolicy::Block`] queue returns and can be joined.
+    pub(crate) fn close(&self) {
+        self.lifecycle.close();
+    }
+
+    /// Records that the consumer took `frame`, for [`Stream::stats`].
+    pub(crate) fn delivered(&self, frame: FrameBuffer) -> FrameBuffer {
+        self.stats.frame_delivered(&frame);
+        frame
+    }
+}
+
+impl Deref for FrameReceiver {
+    type Target = Receiver<FrameBuffer>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+fn disconnected() -> NokhwaError {
+    NokhwaError::ReadFrameError("stream is disconnected!".to_string())
+}
+
+pub mod adapters;
+#[cfg(feature = "async")]
+mod delay;
+pub mod events;
+pub mod producer;
+pub mod stats;
+pub mod tee;
+
+/// The backend half of a [`Stream`]. It is moved to whichever thread the [`Stream`] is used
+/// on, so it has to be [`Send`].
+pub trait StreamInnerTrait: Send {
+    /// The receiving end of the channel the backend sends its frames through.
+    fn receiver(&self) -> &FrameReceiver;
+    /// Stops the backend and releases the device. [`Stream`] calls this exactly once, from
+    /// [`Stream::stop_stream`] or when it is dropped, so it does not have to be idempotent.
+    ///
+    /// # Errors
+    /// If the backend fails to stop, which [`Stream`] reports as [`StopReason::Error`].
+    fn stop(&mut self) -> NokhwaResult<()>;
+}
+
+pub struct Stream {
+    inner: Box<dyn StreamInnerTrait>,
+    watchdog: Option<StallWatchdog>,
+    stopped: bool,
+}
+
+impl Stream {
+    /// Creates a [`Stream`] that takes its frames from `inner`.
+    #[must_use]
+    pub fn new(inner: Box<dyn StreamInnerTrait>) -> Self {
+        Self {
+            inner,
+            watchdog: None,
+            stopped: false,
+        }
+    }
+
+    // pub unsafe fn erase_lifetime(self) -> Stream<'static> {
+    //     Self {
+    //         inner: self.inner,
+    //         phantom_data: Default::default(),
+    //     }
+    // }
+
+    /// Fails if the backend has gone away and every frame it queued has been taken.
+    ///
+    /// # Errors
+    /// The error the backend stopped with, or [`NokhwaError::ReadFrameError`] if it stopped
+    /// without one.
+    pub fn check_disconnected(&self) -> NokhwaResult<()> {
+        let receiver = self.inner.receiver();
+        if receiver.is_disconnected() && receiver.is_empty() {
+            return Err(receiver.closed_error());
+        }
+        Ok(())
+    }
+
+    /// Waits for the next frame.
+    ///
+    /// # Errors
+    /// The error of [`Stream::check_disconnected`] if the stream is disconnected.
+    pub fn poll_frame(&self) -> NokhwaResult<FrameBuffer> {
+        self.check_disconnected()?;
+
+        self.inner
+            .receiver()
+            .recv()
+            .map(|frame| self.inner.receiver().delivered(frame))
+            .map_err(|_| self.inner.receiver().closed_error())
+    }
+
+    /// Takes the next frame if one is queued, without waiting.
+    ///
+    /// # Errors
+    /// The error of [`Stream::check_disconnected`] if the stream is disconnected.
+    pub fn try_poll_frame(&self) -> NokhwaResult<Option<FrameBuffer>> {
+        self.check_disconnected()?;
+
+        if self.inner.receiver().is_empty() {
+            return Ok(None);
+        }
+
+        let possible_frame = self.inner.receiver().try_recv();
+
+        match possible_frame {
+            Ok(f) => Ok(Some(self.inner.receiver().delivered(f))),
+            Err(why) => match why {
+                TryRecvError::Empty => Ok(None),
+                TryRecvError::Disconnected => Err(self.inner.receiver().closed_error()),
+            },
+        }
+    }
+
+    /// Waits at most `timeout` for a frame.
+    ///
+    /// # Errors
+    /// [`NokhwaError::FrameTimeoutError`] if no frame arrived in time, or the error of
+    /// [`Stream::check_disconnected`] if the stream is disconnected.
+    pub fn poll_frame_timeout(&self, timeout: Duration) -> NokhwaResult<FrameBuffer> {
+        self.check_disconnected()?;
+
+        self.inner
+            .receiver()
+            .recv_timeout(timeout)
+            .map(|frame| self.inner.receiver().delivered(frame))
+            .map_err(|why| match why {
+                RecvTimeoutError::Timeout => NokhwaError::FrameTimeoutError(timeout),
+                RecvTimeoutError::Disconnected => self.inner.receiver().closed_error(),
+            })
+    }
+
+    /// Waits for a frame until `deadline`. A deadline in the past only takes an already queued
+    /// frame.
+    ///
+    /// # Errors
+    /// [`NokhwaError::FrameTimeoutError`] if no frame arrived in time, or the error of
+    /// [`Stream::check_disconnected`] if the stream is disconnected.
+    pub fn poll_frame_deadline(&self, deadline: Instant) -> NokhwaResult<FrameBuffer> {
+        let started = Instant::now();
+        self.check_disconnected()?;
+
+        self.inner
+            .receiver()
+            .recv_deadline(deadline)
+            .map(|frame| self.inner.receiver().delivered(frame))
+            .map_err(|why| match why {
+                RecvTimeoutError::Timeout => {
+                    NokhwaError::FrameTimeoutError(deadline.saturating_duration_since(started))
+                }
+                RecvTimeoutError::Disconnected => self.inner.receiver().closed_error(),
+            })
+    }
+
+    /// [`Stream::poll_frame`], awaiting the next frame.
+    ///
+    /// # Errors
+    /// The error of [`Stream::check_disconnected`] if the stream is disconnected.
+    #[cfg(feature = "async")]
+    pub async fn await_frame(&self) -> NokhwaResult<FrameBuffer> {
+        use futures::TryFutureExt;
+
+        self.check_disconnected()?;
+
+        self.inner
+            .receiver()
+            .recv_async()
+            .map_err(|_| self.inner.receiver().closed_error())
+            .await
+            .map(|frame| self.inner.receiver().delivered(frame))
+    }
+
+    /// Asynchronous [`Stream::poll_frame_timeout`]. Works on any executor.
+    ///
+    /// # Errors
+    /// See [`Stream::poll_frame_timeout`].
+    #[cfg(feature = "async")]
+    pub async fn await_frame_timeout(&self, timeout: Duration) -> NokhwaResult<FrameBuffer> {
+        let started = Instant::now();
+        self.await_frame_until(started, started + timeout).await
+    }
+
+    /// Asynchronous [`Stream::poll_frame_deadline`]. Works on any executor.
+    ///
+    /// # Errors
+    /// See [`Stream::poll_frame_deadline`].
+    #[cfg(feature = "async")]
+    pub async fn await_frame_deadline(&self, deadline: Instant) -> NokhwaResult<FrameBuffer> {
+        self.await_frame_until(Instant::now(), deadline).await
+    }
+
+    #[cfg(feature = "async")]
+    async fn await_frame_until(
+        &self,
+        started: Instant,
+        deadline: Instant,
+    ) -> NokhwaResult<FrameBuffer> {
+        use futures::future::{select, Either};
+
+        self.check_disconnected()?;
+        // Like `recv_deadline`, take a queued frame even if the deadline has passed.
+        if let Some(frame) = self.try_poll_frame()? {
+            return Ok(frame);
+        }
+
+        match select(
+            self.inner.receiver().recv_async(),
+            delay::Delay::until(deadline),
+        )
+        .await
+        {
+            Either::Left((frame, _)) => frame
+                .map(|frame| self.inner.receiver().delivered(frame))
+                .map_err(|_| self.inner.receiver().closed_error()),
+            Either::Right(((), _)) => Err(NokhwaError::FrameTimeoutError(
+                deadline.saturating_duration_since(started),
+            )),
+        }
+    }
+
+    /// A blocking [`Iterator`] over the frames of this stream, see [`adapters`].
+    #[must_use]
+    pub fn frames(&self) -> adapters::Frames<'_> {
+        adapters::Frames::new(self)
+    }
+
+    /// A [`futures::Stream`] over the frames of this stream, see [`adapters`].
+    #[cfg(feature = "async")]
+    #[must_use]
+    pub fn frame_stream(&self) -> adapters::FrameStream<'_> {
+        adapters::FrameStream::new(self)
+    }
+
+    /// Turns this stream into a [`futures::Stream`] of its frames, see [`adapters`].
+    #[cfg(feature = "async")]
+    #[must_use]
+    pub fn into_frame_stream(self) -> adapters::IntoFrameStream {
+        adapters::IntoFrameStream::new(self)
+    }
+
+    /// The [`BackpressurePolicy`] this stream queues frames with.
+    #[must_use]
+    pub fn backpressure_policy(&self) -> BackpressurePolicy {
+        self.inner.receiver().policy()
+    }
+
+    /// How many frames the [`BackpressurePolicy`] of this stream has dropped so far.
+    #[must_use]
+    pub fn dropped_frames(&self) -> u64 {
+        self.inner.receiver().dropped_frames()
+    }
+
+    /// Turns this stream into a [`StreamTee`](tee::StreamTee), which hands every frame to any
+    /// number of subscriber streams. The device keeps streaming until the tee is dropped.
+    #[must_use]
+    pub fn tee(self) -> tee::StreamTee {
+        tee::StreamTee::new(self)
+    }
+
+    /// Measured frame rate, jitter, latency, throughput and drop counters of this stream.
+    #[must_use]
+    pub fn stats(&self) -> StreamStats {
+        let receiver = self.inner.receiver();
+        receiver.stats.snapshot(receiver.dropped_frames())
+    }
+
+    /// Sets the window [`Stream::stats`] measures rates and latencies over, by default
+    /// [`DEFAULT_STATS_WINDOW`](stats::DEFAULT_STATS_WINDOW).
+    pub fn set_stats_window(&self, window: Duration) {
+        self.inner.receiver().stats.set_window(window);
+    }
+
+    /// The lifecycle [`StreamEvent`]s of this stream. Every clone of the receiver takes events
+    /// from the same queue, so there should be one consumer. The queue is bounded, events that
+    /// do not fit are dropped.
+    #[must_use]
+    pub fn events(&self) -> Receiver<StreamEvent> {
+        self.inner.receiver().events().clone()
+    }
+
+    /// Reports [`StreamEvent::Stalled`] whenever no frame arrives for `threshold`, or stops
+    /// doing so if `None`.
+    pub fn set_stall_threshold(&mut self, threshold: Option<Duration>) {
+        self.watchdog = threshold.map(|threshold| {
+            StallWatchdog::new(self.inner.receiver().lifecycle.clone(), threshold)
+        });
+    }
+
+    /// Stops the backend and closes the stream.
+    ///
+    /// # Errors
+    /// If the backend fails to stop.
+    pub fn stop_stream(mut self) -> NokhwaResult<()> {
+        self.stop_inner()
+    }
+
+    /// Stops the backend and reports [`StreamEvent::Stopped`], unless that already happened.
+    fn stop_inner(&mut self) -> NokhwaResult<()> {
+        if self.stopped {
+            return Ok(());
+        }
+        self.stopped = true;
+        self.watchdog = None;
+        // Closed first, so a backend blocked on a full `Block` queue can be joined.
+        self.inner.receiver().close();
+        let result = self.inner.stop();
+        self.inner.receiver().lifecycle.stop(match &result {
+            Ok(()) => StopReason::Requested,
+            Err(why) => StopReason::Error(why.clone()),
+        });
+        result
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let _ = self.stop_inner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame_format::FrameFormat, types::Resolution};
+    use std::thread::JoinHandle;
+
+    fn frame() -> FrameBuffer {
+        FrameBuffer::new(Resolution::new(1, 1), &[0, 0, 0], FrameFormat::Rgb888)
+    }
+
+    /// A backend that sends frames from its own thread until the stream refuses them, and joins
+    /// that thread when stopped.
+    struct ThreadedInner {
+        receiver: FrameReceiver,
+        thread: Option<JoinHandle<u64>>,
+    }
+
+    impl ThreadedInner {
+        fn stream(policy: BackpressurePolicy) -> Stream {
+            let (sender, receiver) = frame_channel(policy);
+            let thread = std::thread::spawn(move || {
+                let mut sent = 0;
+                while sender.send(frame()).is_ok() {
+                    sent += 1;
+                }
+                sent
+            });
+            Stream::new(Box::new(ThreadedInner {
+                receiver,
+                thread: Some(thread),
+            }))
+        }
+    }
+
+    impl StreamInnerTrait for ThreadedInner {
+        fn receiver(&self) -> &FrameReceiver {
+            &self.receiver
+        }
+
+        fn stop(&mut self) -> NokhwaResult<()> {
+            if let Some(thread) = self.thread.take() {
+                thread.join().expect("producer panicked");
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stopping_a_full_block_stream_does_not_hang() {
+        let stream = ThreadedInner::stream(BackpressurePolicy::Block(1));
+        // Let the producer fill the queue and block on the next frame.
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(stream.inner.receiver().len(), 1);
+
+        let started = Instant::now();
+        stream.stop_stream().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn block_policy_drops_nothing() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::Block(2));
+        let consumer = std::thread::spawn(move || {
+            (0..100)
+                .map(|_| receiver.recv().unwrap().sequence())
+                .collect::<Vec<_>>()
+        });
+        for _ in 0..100 {
+            sender.send(frame()).unwrap();
+        }
+        assert_eq!(consumer.join().unwrap(), (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest_frames() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::DropOldest(3));
+        for _ in 0..10 {
+            sender.send(frame()).unwrap();
+        }
+        assert_eq!(receiver.dropped_frames(), 7);
+        let kept = receiver
+            .drain()
+            .map(|frame| frame.sequence())
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![7, 8, 9]);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_oldest_frames() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::DropNewest(3));
+        for _ in 0..10 {
+            sender.send(frame()).unwrap();
+        }
+        assert_eq!(receiver.dropped_frames(), 7);
+        let kept = receiver
+            .drain()
+            .map(|frame| frame.sequence())
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn latest_only_keeps_one_frame() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::LatestOnly);
+        for _ in 0..5 {
+            sender.send(frame()).unwrap();
+        }
+        assert_eq!(receiver.dropped_frames(), 4);
+        assert_eq!(receiver.try_recv().unwrap().sequence(), 4);
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn sending_stamps_a_sequence_and_keeps_the_timestamps() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::DropOldest(8));
+        let mut sent = frame();
+        let captured = sent.timestamp() + Duration::from_millis(5);
+        sent.set_sequence(42);
+        sent.set_timestamp(captured);
+        sent.set_driver_timestamp(Some(Duration::from_micros(1234)));
+        sender.send(sent).unwrap();
+        sender.send(frame()).unwrap();
+
+        let first = receiver.try_recv().unwrap();
+        assert_eq!(first.sequence(), 0);
+        assert_eq!(first.timestamp(), captured);
+        assert_eq!(first.driver_timestamp(), Some(Duration::from_micros(1234)));
+        assert_eq!(receiver.try_recv().unwrap().sequence(), 1);
+        assert_eq!(sender.next_sequence(), 2);
+    }
+
+    #[test]
+    fn skipped_and_forwarded_gaps_count_as_backend_drops() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::DropOldest(8));
+        sender.send(frame()).unwrap();
+        sender.skip(3);
+        sender.send(frame()).unwrap();
+        assert_eq!(
+            receiver.drain().map(|frame| frame.sequence()).last(),
+            Some(4)
+        );
+        assert_eq!(receiver.stats.snapshot(0).backend_dropped(), 3);
+
+        let (relay, relayed) = frame_channel(BackpressurePolicy::DropOldest(8));
+        for sequence in [0, 1, 5, 6] {
+            let mut frame = frame();
+            frame.set_sequence(sequence);
+            relay.forward(frame).unwrap();
+        }
+        let stats = relayed.stats.snapshot(relayed.dropped_frames());
+        assert_eq!(stats.backend_dropped(), 3);
+        assert_eq!(stats.channel_dropped(), 0);
+        assert_eq!(stats.frames_received(), 4);
+    }
+
+    #[test]
+    fn sending_to_a_dropped_stream_fails() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::Block(1));
+        drop(receiver);
+        assert!(sender.is_disconnected());
+        assert!(sender.send(frame()).is_err());
+    }
+
+    /// A backend whose frames are sent by the test itself.
+    struct FinishedInner(FrameReceiver);
+
+    impl StreamInnerTrait for FinishedInner {
+        fn receiver(&self) -> &FrameReceiver {
+            &self.0
+        }
+
+        fn stop(&mut self) -> NokhwaResult<()> {
+            Ok(())
+        }
+    }
+
+    /// A backend that counts how often it is stopped.
+    struct CountingInner(FrameReceiver, Arc<AtomicU64>);
+
+    impl StreamInnerTrait for CountingInner {
+        fn receiver(&self) -> &FrameReceiver {
+            &self.0
+        }
+
+        fn stop(&mut self) -> NokhwaResult<()> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_backend_is_stopped_once() {
+        let stops = Arc::new(AtomicU64::new(0));
+        let (_sender, receiver) = frame_channel(BackpressurePolicy::Block(1));
+        Stream::new(Box::new(CountingInner(receiver, stops.clone())))
+            .stop_stream()
+            .unwrap();
+        assert_eq!(stops.load(Ordering::SeqCst), 1);
+
+        let (_sender, receiver) = frame_channel(BackpressurePolicy::Block(1));
+        drop(Stream::new(Box::new(CountingInner(
+            receiver,
+            stops.clone(),
+        ))));
+        assert_eq!(stops.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn frames_queued_before_the_backend_went_away_are_delivered() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::Block(4));
+        sender.send(frame()).unwrap();
+        sender.send(frame()).unwrap();
+        drop(sender);
+        let stream = Stream::new(Box::new(FinishedInner(receiver)));
+
+        assert!(stream.check_disconnected().is_ok());
+        assert_eq!(stream.poll_frame().unwrap().sequence(), 0);
+        assert_eq!(stream.try_poll_frame().unwrap().unwrap().sequence(), 1);
+        assert!(matches!(
+            stream.check_disconnected(),
+            Err(NokhwaError::ReadFrameError(_))
+        ));
+        assert!(stream.poll_frame().is_err());
+    }
+
+    #[test]
+    fn a_failed_backend_reports_its_error_once_drained() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::Block(4));
+        sender.send(frame()).unwrap();
+        sender.stop(StopReason::Error(NokhwaError::ReadFrameError(
+            "unplugged".to_string(),
+        )));
+        drop(sender);
+        let stream = Stream::new(Box::new(FinishedInner(receiver)));
+
+        assert!(stream.poll_frame().is_ok());
+        for result in [stream.check_disconnected(), stream.poll_frame().map(drop)] {
+            assert!(matches!(
+                result,
+                Err(NokhwaError::ReadFrameError(why)) if why == "unplugged"
+            ));
+        }
+        assert!(matches!(
+            stream.try_poll_frame(),
+            Err(NokhwaError::ReadFrameError(why)) if why == "unplugged"
+        ));
+    }
+
+    #[test]
+    fn timed_waits_give_up_at_their_deadline() {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::Block(4));
+        let stream = Stream::new(Box::new(FinishedInner(receiver)));
+        let timeout = Duration::from_millis(20);
+
+        let started = Instant::now();
+        assert!(matches!(
+            stream.poll_frame_timeout(timeout),
+            Err(NokhwaError::FrameTimeoutError(waited)) if waited == timeout
+        ));
+        assert!(started.elapsed() >= timeout);
+
+        // A deadline in the past still takes a queued frame.
+        sender.send(frame()).unwrap();
+        assert!(stream.poll_frame_deadline(started).is_ok());
+        assert!(matches!(
+            stream.poll_frame_deadline(started),
+            Err(NokhwaError::FrameTimeoutError(_))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_timed_waits_give_up_at_their_deadline() {
+        use futures::executor::block_on;
+
+        let (sender, receiver) = frame_channel(BackpressurePolicy::Block(4));
+        let stream = Stream::new(Box::new(FinishedInner(receiver)));
+        let timeout = Duration::from_millis(20);
+
+        let started = Instant::now();
+        assert!(matches!(
+            block_on(stream.await_frame_timeout(timeout)),
+            Err(NokhwaError::FrameTimeoutError(waited)) if waited == timeout
+        ));
+        assert!(started.elapsed() >= timeout);
+
+        sender.send(frame()).unwrap();
+        assert!(block_on(stream.await_frame_deadline(started)).is_ok());
+
+        let sending = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            sender.send(frame())
+        });
+        assert!(block_on(stream.await_frame_timeout(Duration::from_secs(2))).is_ok());
+        sending.join().unwrap().unwrap();
+    }
+}
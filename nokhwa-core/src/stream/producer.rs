@@ -0,0 +1,253 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Building blocks for cameras that produce their frames on a thread of their own, such as
+//! software, playback and network cameras.
+//!
+//! A [`Producer`] runs the thread and stops it, a [`Pacer`] times its frames at a frame rate,
+//! and a [`ProducerStream`] is the inner half of the [`Stream`](super::Stream) it feeds.
+
+use crate::{
+    error::{NokhwaError, NokhwaResult},
+    stream::{FrameReceiver, StreamInnerTrait},
+    types::FrameRate,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{JoinHandle, Thread},
+    time::{Duration, Instant},
+};
+
+/// The time between frames at `frame_rate`, if it is positive.
+#[must_use]
+pub fn frame_interval(frame_rate: FrameRate) -> Option<Duration> {
+    let fps = frame_rate.approximate_float()?;
+    (fps.is_finite() && fps > 0.0).then(|| Duration::from_secs_f32(1.0 / fps))
+}
+
+/// Parks until `deadline`, or until stopped if there is none. Returns `false` if `running` was
+/// cleared, which [`Producer::stop`] wakes the thread for.
+pub fn sleep_until(deadline: Option<Instant>, running: &AtomicBool) -> bool {
+    loop {
+        if !running.load(Ordering::Acquire) {
+            return false;
+        }
+        match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return true;
+                }
+                std::thread::park_timeout(deadline - now);
+            }
+            None => std::thread::park(),
+        }
+    }
+}
+
+/// When the next frame of a paced stream is due.
+#[derive(Copy, Clone, Debug)]
+pub struct Pacer {
+    next_frame: Instant,
+}
+
+impl Pacer {
+    /// Creates a pacer whose first frame is due right away.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// When the next frame is due.
+    #[must_use]
+    pub fn next_frame(&self) -> Instant {
+        self.next_frame
+    }
+
+    /// Parks until the next frame is due, see [`sleep_until`].
+    pub fn wait(&self, running: &AtomicBool) -> bool {
+        sleep_until(Some(self.next_frame), running)
+    }
+
+    /// Schedules the next frame `interval` after the one that was due, for a frame produced at
+    /// `at`.
+    ///
+    /// Running late, e.g. producing a frame takes longer than the frame rate allows, skips ahead
+    /// to `at` rather than delivering a burst of frames to catch up.
+    pub fn schedule(&mut self, at: Instant, interval: Duration) {
+        self.next_frame = (self.next_frame + interval).max(at);
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The handle of a running producer thread, shared by a camera and its stream.
+#[derive(Clone, Debug)]
+pub struct Producer {
+    running: Arc<AtomicBool>,
+    thread: Thread,
+}
+
+impl Producer {
+    /// Runs `produce` on a thread called `name`, handing it the flag that is cleared when the
+    /// producer is stopped. The flag is also cleared once `produce` returns.
+    ///
+    /// # Errors
+    /// If the thread cannot be spawned.
+    pub fn spawn(
+        name: &str,
+        produce: impl FnOnce(Arc<AtomicBool>) + Send + 'static,
+    ) -> NokhwaResult<(Self, JoinHandle<()>)> {
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = {
+            let running = running.clone();
+            std::thread::Builder::new()
+                .name(name.to_string())
+                .spawn(move || {
+                    produce(running.clone());
+                    running.store(false, Ordering::Release);
+                })
+                .map_err(|why| NokhwaError::OpenStreamError(why.to_string()))?
+        };
+        let producer = Self {
+            running,
+            thread: handle.thread().clone(),
+        };
+        Ok((producer, handle))
+    }
+
+    /// Whether the thread is still producing frames.
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Tells the thread to stop, waking it if it is parked. Does not wait for it.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+        self.thread.unpark();
+    }
+}
+
+/// The inner half of a stream fed by a [`Producer`]. Stopping it stops the thread and waits for
+/// it.
+pub struct ProducerStream {
+    receiver: FrameReceiver,
+    producer: Producer,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ProducerStream {
+    #[must_use]
+    pub fn new(receiver: FrameReceiver, producer: Producer, handle: JoinHandle<()>) -> Self {
+        Self {
+            receiver,
+            producer,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl StreamInnerTrait for ProducerStream {
+    fn receiver(&self) -> &FrameReceiver {
+        &self.receiver
+    }
+
+    fn stop(&mut self) -> NokhwaResult<()> {
+        self.producer.stop();
+        if let Some(handle) = self.handle.take() {
+            let name = handle.thread().name().unwrap_or("producer").to_string();
+            handle
+                .join()
+                .map_err(|_| NokhwaError::StreamShutdownError(format!("{name} thread panicked")))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::{frame_channel, BackpressurePolicy};
+
+    #[test]
+    fn frame_interval_of_positive_rates() {
+        let interval = frame_interval(FrameRate::frame_rate(25)).unwrap();
+        assert!(interval.abs_diff(Duration::from_millis(40)) < Duration::from_micros(1));
+        assert_eq!(frame_interval(FrameRate::frame_rate(0)), None);
+    }
+
+    #[test]
+    fn pacer_skips_ahead_when_late() {
+        let interval = Duration::from_millis(10);
+        let mut pacer = Pacer::new();
+        let start = pacer.next_frame();
+
+        pacer.schedule(start, interval);
+        assert_eq!(pacer.next_frame(), start + interval);
+
+        // A little late: frames stay on the grid.
+        pacer.schedule(start + interval + Duration::from_millis(2), interval);
+        assert_eq!(pacer.next_frame(), start + 2 * interval);
+
+        // Frames late: the next one is due right away, rather than all the missed ones.
+        let late = start + Duration::from_millis(50);
+        pacer.schedule(late, interval);
+        assert_eq!(pacer.next_frame(), late);
+        pacer.schedule(late, interval);
+        assert_eq!(pacer.next_frame(), late + interval);
+    }
+
+    #[test]
+    fn stopping_wakes_a_sleeping_producer() {
+        let (producer, handle) = Producer::spawn("nokhwa-test", |running| {
+            assert!(!sleep_until(None, &running));
+        })
+        .unwrap();
+        assert!(producer.is_running());
+
+        producer.stop();
+        handle.join().unwrap();
+        assert!(!producer.is_running());
+    }
+
+    #[test]
+    fn finishing_clears_running() {
+        let (producer, handle) = Producer::spawn("nokhwa-test", |_| {}).unwrap();
+        handle.join().unwrap();
+        assert!(!producer.is_running());
+    }
+
+    #[test]
+    fn stopping_the_stream_reports_a_panic() {
+        let (_, receiver) = frame_channel(BackpressurePolicy::default());
+        let (producer, handle) = Producer::spawn("nokhwa-test", |_| panic!("oops")).unwrap();
+        let mut stream = ProducerStream::new(receiver, producer, handle);
+        assert!(matches!(
+            stream.stop(),
+            Err(NokhwaError::StreamShutdownError(why)) if why == "nokhwa-test thread panicked"
+        ));
+    }
+}
@@ -0,0 +1,395 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Live statistics of a [`Stream`](super::Stream), see [`Stream::stats`](super::Stream::stats).
+//!
+//! Frame rate, jitter and throughput are measured as frames arrive from the backend, so they
+//! show what the device achieves regardless of how fast the consumer is. Latency is measured
+//! from the capture [`timestamp`](FrameBuffer::timestamp) of a frame to when the consumer takes
+//! it from the stream.
+
+use crate::{frame_buffer::FrameBuffer, types::CameraFormat};
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The window rates and latencies are measured over, unless changed with
+/// [`Stream::set_stats_window`](super::Stream::set_stats_window).
+pub const DEFAULT_STATS_WINDOW: Duration = Duration::from_secs(2);
+
+/// A snapshot of the statistics of a [`Stream`](super::Stream).
+///
+/// Rates and latencies cover the frames of the last [`window`](StreamStats::window), and are
+/// `None` until there are enough frames to measure them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StreamStats {
+    window: Duration,
+    frames_received: u64,
+    frames_delivered: u64,
+    fps: Option<f64>,
+    jitter: Option<Duration>,
+    mean_latency: Option<Duration>,
+    max_latency: Option<Duration>,
+    bytes_per_second: Option<f64>,
+    backend_dropped: u64,
+    channel_dropped: u64,
+    format: Option<CameraFormat>,
+}
+
+impl StreamStats {
+    /// The window rates and latencies are measured over.
+    #[must_use]
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// How many frames arrived from the backend since the stream opened.
+    #[must_use]
+    pub fn frames_received(&self) -> u64 {
+        self.frames_received
+    }
+
+    /// How many frames the consumer took from the stream since it opened.
+    #[must_use]
+    pub fn frames_delivered(&self) -> u64 {
+        self.frames_delivered
+    }
+
+    /// The measured frame rate, in frames per second.
+    #[must_use]
+    pub fn fps(&self) -> Option<f64> {
+        self.fps
+    }
+
+    /// The standard deviation of the time between frames.
+    #[must_use]
+    pub fn jitter(&self) -> Option<Duration> {
+        self.jitter
+    }
+
+    /// The mean time from capturing a frame to the consumer taking it.
+    #[must_use]
+    pub fn mean_latency(&self) -> Option<Duration> {
+        self.mean_latency
+    }
+
+    /// The longest time from capturing a frame to the consumer taking it.
+    #[must_use]
+    pub fn max_latency(&self) -> Option<Duration> {
+        self.max_latency
+    }
+
+    /// The measured throughput of frame data, in bytes per second.
+    #[must_use]
+    pub fn bytes_per_second(&self) -> Option<f64> {
+        self.bytes_per_second
+    }
+
+    /// How many frames were lost before reaching the stream: those the backend reported with
+    /// [`FrameSender::skip`](super::FrameSender::skip), and gaps in the
+    /// [`sequence`](FrameBuffer::sequence) numbers of frames relayed with
+    /// [`FrameSender::forward`](super::FrameSender::forward).
+    #[must_use]
+    pub fn backend_dropped(&self) -> u64 {
+        self.backend_dropped
+    }
+
+    /// How many frames the [`BackpressurePolicy`](super::BackpressurePolicy) of the stream
+    /// dropped.
+    #[must_use]
+    pub fn channel_dropped(&self) -> u64 {
+        self.channel_dropped
+    }
+
+    /// All frames that were dropped, by the backend or the channel.
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.backend_dropped + self.channel_dropped
+    }
+
+    /// The format the backend last reported with [`StreamEvent::Started`] or
+    /// [`StreamEvent::FormatChanged`].
+    ///
+    /// [`StreamEvent::Started`]: super::events::StreamEvent::Started
+    /// [`StreamEvent::FormatChanged`]: super::events::StreamEvent::FormatChanged
+    #[must_use]
+    pub fn format(&self) -> Option<CameraFormat> {
+        self.format
+    }
+
+    /// The frame rate of the negotiated [`format`](StreamStats::format), to compare
+    /// [`fps`](StreamStats::fps) against.
+    #[must_use]
+    pub fn negotiated_fps(&self) -> Option<f64> {
+        self.format
+            .and_then(|format| format.frame_rate().approximate_float())
+            .map(f64::from)
+    }
+}
+
+#[derive(Debug)]
+struct Samples {
+    window: Duration,
+    /// When frames arrived, and their size in bytes.
+    arrivals: VecDeque<(Instant, usize)>,
+    /// When frames were delivered, and their latency.
+    deliveries: VecDeque<(Instant, Duration)>,
+    frames_received: u64,
+    frames_delivered: u64,
+    backend_dropped: u64,
+    format: Option<CameraFormat>,
+}
+
+impl Samples {
+    fn prune(&mut self, now: Instant) {
+        let Some(cutoff) = now.checked_sub(self.window) else {
+            return;
+        };
+        while self.arrivals.front().is_some_and(|(at, _)| *at < cutoff) {
+            self.arrivals.pop_front();
+        }
+        while self.deliveries.front().is_some_and(|(at, _)| *at < cutoff) {
+            self.deliveries.pop_front();
+        }
+    }
+}
+
+/// Collects the samples behind [`StreamStats`], shared by both halves of a
+/// [`frame_channel`](super::frame_channel).
+#[derive(Debug)]
+pub(crate) struct StatsCollector {
+    samples: Mutex<Samples>,
+}
+
+impl StatsCollector {
+    pub(crate) fn new() -> Self {
+        Self {
+            samples: Mutex::new(Samples {
+                window: DEFAULT_STATS_WINDOW,
+                arrivals: VecDeque::new(),
+                deliveries: VecDeque::new(),
+                frames_received: 0,
+                frames_delivered: 0,
+                backend_dropped: 0,
+                format: None,
+            }),
+        }
+    }
+
+    fn samples(&self) -> std::sync::MutexGuard<'_, Samples> {
+        self.samples.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records a `frame` arriving from the backend.
+    pub(crate) fn frame_arrived(&self, frame: &FrameBuffer) {
+        let mut samples = self.samples();
+        // Taken under the lock, so samples are queued in the order of their times.
+        let now = Instant::now();
+        samples.frames_received += 1;
+        samples.arrivals.push_back((now, frame.buffer().len()));
+        samples.prune(now);
+    }
+
+    /// Records `count` frames lost before reaching the stream.
+    pub(crate) fn frames_lost(&self, count: u64) {
+        self.samples().backend_dropped += count;
+    }
+
+    /// Records the consumer taking `frame` from the stream.
+    pub(crate) fn frame_delivered(&self, frame: &FrameBuffer) {
+        let mut samples = self.samples();
+        let now = Instant::now();
+        samples.frames_delivered += 1;
+        samples
+            .deliveries
+            .push_back((now, now.saturating_duration_since(frame.timestamp())));
+        samples.prune(now);
+    }
+
+    pub(crate) fn format_changed(&self, format: CameraFormat) {
+        self.samples().format = Some(format);
+    }
+
+    pub(crate) fn set_window(&self, window: Duration) {
+        self.samples().window = window;
+    }
+
+    pub(crate) fn snapshot(&self, channel_dropped: u64) -> StreamStats {
+        let mut samples = self.samples();
+        samples.prune(Instant::now());
+
+        // Rates are taken over the span between the first and last frame in the window, so the
+        // first frame only marks the start of the span.
+        let span = match (samples.arrivals.front(), samples.arrivals.back()) {
+            (Some((first, _)), Some((last, _))) if last > first => Some(*last - *first),
+            _ => None,
+        };
+        let intervals = samples.arrivals.len().saturating_sub(1);
+        let fps = span.map(|span| intervals as f64 / span.as_secs_f64());
+        let jitter = span.map(|span| {
+            let mean = span.as_secs_f64() / intervals as f64;
+            let variance = samples
+                .arrivals
+                .iter()
+                .zip(samples.arrivals.iter().skip(1))
+                .map(|((previous, _), (current, _))| {
+                    ((*current - *previous).as_secs_f64() - mean).powi(2)
+                })
+                .sum::<f64>()
+                / intervals as f64;
+            Duration::from_secs_f64(variance.sqrt())
+        });
+        let bytes_per_second = span.map(|span| {
+            let bytes = samples.arrivals.iter().skip(1).map(|(_, bytes)| *bytes);
+            bytes.sum::<usize>() as f64 / span.as_secs_f64()
+        });
+
+        let latencies = samples.deliveries.iter().map(|(_, latency)| *latency);
+        let mean_latency = u32::try_from(samples.deliveries.len())
+            .ok()
+            .filter(|count| *count > 0)
+            .map(|count| latencies.clone().sum::<Duration>() / count);
+
+        StreamStats {
+            window: samples.window,
+            frames_received: samples.frames_received,
+            frames_delivered: samples.frames_delivered,
+            fps,
+            jitter,
+            mean_latency,
+            max_latency: latencies.max(),
+            bytes_per_second,
+            backend_dropped: samples.backend_dropped,
+            channel_dropped,
+            format: samples.format,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        frame_format::FrameFormat,
+        types::{FrameRate, Resolution},
+    };
+
+    /// A collector with frames of `bytes` arriving `intervals` apart, starting now.
+    fn arrivals(intervals: &[u64], bytes: usize) -> StatsCollector {
+        let collector = StatsCollector::new();
+        {
+            let mut samples = collector.samples();
+            let mut at = Instant::now();
+            samples.arrivals.push_back((at, bytes));
+            for interval in intervals {
+                at += Duration::from_millis(*interval);
+                samples.arrivals.push_back((at, bytes));
+            }
+        }
+        collector
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn nothing_is_measured_without_frames() {
+        let stats = StatsCollector::new().snapshot(0);
+        assert_eq!(stats.window(), DEFAULT_STATS_WINDOW);
+        assert_eq!(stats.frames_received(), 0);
+        assert_eq!(stats.fps(), None);
+        assert_eq!(stats.jitter(), None);
+        assert_eq!(stats.mean_latency(), None);
+        assert_eq!(stats.bytes_per_second(), None);
+        assert_eq!(stats.format(), None);
+    }
+
+    #[test]
+    fn steady_frames_have_no_jitter() {
+        let stats = arrivals(&[100, 100, 100], 10).snapshot(0);
+        assert_close(stats.fps().unwrap(), 10.0);
+        assert_eq!(stats.jitter(), Some(Duration::ZERO));
+        assert_close(stats.bytes_per_second().unwrap(), 100.0);
+    }
+
+    #[test]
+    fn jitter_is_the_deviation_between_frames() {
+        let stats = arrivals(&[100, 300], 10).snapshot(0);
+        assert_close(stats.fps().unwrap(), 5.0);
+        let jitter = stats.jitter().unwrap();
+        assert!(jitter.abs_diff(Duration::from_millis(100)) < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn latency_is_measured_on_delivery() {
+        let collector = StatsCollector::new();
+        {
+            let mut samples = collector.samples();
+            let now = Instant::now();
+            samples
+                .deliveries
+                .push_back((now, Duration::from_millis(10)));
+            samples
+                .deliveries
+                .push_back((now, Duration::from_millis(30)));
+        }
+        let stats = collector.snapshot(0);
+        assert_eq!(stats.mean_latency(), Some(Duration::from_millis(20)));
+        assert_eq!(stats.max_latency(), Some(Duration::from_millis(30)));
+
+        collector.frame_delivered(&FrameBuffer::new(
+            Resolution::new(1, 1),
+            &[0],
+            FrameFormat::Luma8,
+        ));
+        assert_eq!(collector.snapshot(0).frames_delivered(), 1);
+    }
+
+    #[test]
+    fn frames_leave_the_window() {
+        let collector = StatsCollector::new();
+        let frame = FrameBuffer::new(Resolution::new(1, 1), &[0], FrameFormat::Luma8);
+        collector.frame_arrived(&frame);
+        collector.frame_arrived(&frame);
+        collector.set_window(Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let stats = collector.snapshot(0);
+        assert_eq!(stats.window(), Duration::from_millis(10));
+        assert_eq!(stats.frames_received(), 2);
+        assert_eq!(stats.fps(), None);
+    }
+
+    #[test]
+    fn drops_and_format_are_reported() {
+        let collector = StatsCollector::new();
+        collector.frames_lost(3);
+        collector.format_changed(CameraFormat::new(
+            Resolution::new(640, 480),
+            FrameFormat::Yuyv422,
+            FrameRate::frame_rate(30),
+        ));
+
+        let stats = collector.snapshot(2);
+        assert_eq!(stats.backend_dropped(), 3);
+        assert_eq!(stats.channel_dropped(), 2);
+        assert_eq!(stats.dropped(), 5);
+        assert_close(stats.negotiated_fps().unwrap(), 30.0);
+    }
+}
@@ -0,0 +1,289 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Fan-out of a single [`Stream`] to several independent consumers.
+//!
+//! A [`Capture`](crate::camera::Capture) only ever has one stream open. [`Stream::tee`] moves
+//! that stream onto a relay thread, which hands every frame to each subscriber. Subscribers are
+//! ordinary [`Stream`]s with their own [`BackpressurePolicy`], so a slow recorder does not hold
+//! back a preview that only wants the latest frame.
+
+use crate::{
+    error::NokhwaResult,
+    stream::{
+        events::StreamEvent, frame_channel, BackpressurePolicy, FrameReceiver, FrameSender, Stream,
+        StreamInnerTrait, BLOCK_POLL_INTERVAL,
+    },
+};
+use flume::{Receiver, RecvTimeoutError};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+};
+
+type Subscribers = Arc<Mutex<Vec<FrameSender>>>;
+
+/// Hands every frame of a [`Stream`] to any number of subscriber [`Stream`]s, created with
+/// [`StreamTee::subscribe`].
+///
+/// Subscribers can attach and detach at any time without touching the device: dropping a
+/// subscriber detaches it. Frames keep the sequence numbers of the source stream, so a subscriber
+/// sees gaps both for frames the source dropped and for frames its own policy dropped.
+///
+/// The [`events`](Stream::events) of the source stream are passed on to every subscriber, except
+/// [`StreamEvent::FirstFrame`], which each subscriber reports for itself.
+///
+/// Frames are handed to subscribers one after the other, so a subscriber with
+/// [`BackpressurePolicy::Block`] holds back every other subscriber while it is full.
+///
+/// Dropping the tee stops the source stream and disconnects all subscribers.
+#[derive(Debug)]
+pub struct StreamTee {
+    subscribers: Subscribers,
+    running: Arc<AtomicBool>,
+    relay: Option<JoinHandle<()>>,
+}
+
+impl StreamTee {
+    pub(crate) fn new(source: Stream) -> Self {
+        let subscribers = Subscribers::default();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let relay = {
+            let subscribers = subscribers.clone();
+            let running = running.clone();
+            std::thread::Builder::new()
+                .name("nokhwa-stream-tee".to_string())
+                .spawn(move || relay(&source, &subscribers, &running))
+                .ok()
+        };
+        if relay.is_none() {
+            running.store(false, Ordering::Release);
+        }
+
+        Self {
+            subscribers,
+            running,
+            relay,
+        }
+    }
+
+    /// Attaches a new subscriber, which receives every frame from now on, queued according to
+    /// `policy`. Drop the returned [`Stream`] to detach it.
+    ///
+    /// If the source stream has already ended, the returned stream is disconnected.
+    #[must_use]
+    pub fn subscribe(&self, policy: BackpressurePolicy) -> Stream {
+        let (sender, receiver) = frame_channel(policy);
+        {
+            let mut subscribers = self
+                .subscribers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            // Checked under the lock, so the relay cannot miss this sender when it shuts down.
+            if self.is_running() {
+                subscribers.push(sender);
+            }
+        }
+        Stream::new(Box::new(TeeSubscriber { receiver }))
+    }
+
+    /// How many subscribers are attached.
+    #[must_use]
+    pub fn subscriber_count(&self) -> usize {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        subscribers.retain(|subscriber| !subscriber.is_disconnected());
+        subscribers.len()
+    }
+
+    /// Whether the source stream is still delivering frames.
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for StreamTee {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        // The relay may be blocked on a full subscriber, which only returns once it is closed.
+        for subscriber in self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            subscriber.close();
+        }
+        if let Some(relay) = self.relay.take() {
+            let _ = relay.join();
+        }
+    }
+}
+
+/// Moves frames and events from `source` to the `subscribers` until the tee is dropped or
+/// `source` ends.
+fn relay(source: &Stream, subscribers: &Mutex<Vec<FrameSender>>, running: &AtomicBool) {
+    let events = source.events();
+    while running.load(Ordering::Acquire) {
+        match source.inner.receiver().recv_timeout(BLOCK_POLL_INTERVAL) {
+            Ok(frame) => {
+                let frame = source.inner.receiver().delivered(frame);
+                // Send from a snapshot, so a blocking subscriber does not hold up `subscribe`.
+                let targets = subscribers
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone();
+                for target in &targets {
+                    // A failed send means the subscriber is gone, it is pruned below.
+                    let _ = target.forward(frame.clone());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let mut attached = subscribers.lock().unwrap_or_else(PoisonError::into_inner);
+        attached.retain(|subscriber| !subscriber.is_disconnected());
+        pass_on_events(&events, &attached);
+    }
+
+    running.store(false, Ordering::Release);
+    let mut attached = subscribers.lock().unwrap_or_else(PoisonError::into_inner);
+    // Events the source reported right before it ended, e.g. why it stopped.
+    pass_on_events(&events, &attached);
+    // Dropping the senders disconnects every subscriber.
+    attached.clear();
+}
+
+fn pass_on_events(events: &Receiver<StreamEvent>, subscribers: &[FrameSender]) {
+    for event in events.try_iter() {
+        for subscriber in subscribers {
+            match &event {
+                StreamEvent::FirstFrame => {}
+                StreamEvent::Stopped(reason) => subscriber.stop(reason.clone()),
+                event => subscriber.emit(event.clone()),
+            }
+        }
+    }
+}
+
+/// The inner half of a subscriber [`Stream`]. Stopping it only detaches the subscriber.
+struct TeeSubscriber {
+    receiver: FrameReceiver,
+}
+
+impl StreamInnerTrait for TeeSubscriber {
+    fn receiver(&self) -> &FrameReceiver {
+        &self.receiver
+    }
+
+    fn stop(&mut self) -> NokhwaResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame_buffer::FrameBuffer, frame_format::FrameFormat, types::Resolution};
+    use std::time::{Duration, Instant};
+
+    struct ChannelInner {
+        receiver: FrameReceiver,
+    }
+
+    impl StreamInnerTrait for ChannelInner {
+        fn receiver(&self) -> &FrameReceiver {
+            &self.receiver
+        }
+
+        fn stop(&mut self) -> NokhwaResult<()> {
+            Ok(())
+        }
+    }
+
+    fn source() -> (FrameSender, Stream) {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::DropOldest(8));
+        (sender, Stream::new(Box::new(ChannelInner { receiver })))
+    }
+
+    fn frame() -> FrameBuffer {
+        FrameBuffer::new(Resolution::new(1, 1), &[0, 0, 0], FrameFormat::Rgb888)
+    }
+
+    #[test]
+    fn every_subscriber_gets_every_frame() {
+        let (sender, stream) = source();
+        let tee = stream.tee();
+        let first = tee.subscribe(BackpressurePolicy::Block(4));
+        let second = tee.subscribe(BackpressurePolicy::Block(4));
+        assert_eq!(tee.subscriber_count(), 2);
+
+        for _ in 0..3 {
+            sender.send(frame()).unwrap();
+        }
+        for subscriber in [&first, &second] {
+            let sequences = (0..3)
+                .map(|_| {
+                    subscriber
+                        .poll_frame_timeout(Duration::from_secs(2))
+                        .unwrap()
+                        .sequence()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(sequences, vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn dropping_a_subscriber_detaches_it() {
+        let (_sender, stream) = source();
+        let tee = stream.tee();
+        let subscriber = tee.subscribe(BackpressurePolicy::LatestOnly);
+        assert_eq!(tee.subscriber_count(), 1);
+        drop(subscriber);
+        assert_eq!(tee.subscriber_count(), 0);
+    }
+
+    #[test]
+    fn dropping_a_tee_with_a_stalled_subscriber_does_not_hang() {
+        let (sender, stream) = source();
+        let tee = stream.tee();
+        let stalled = tee.subscribe(BackpressurePolicy::Block(1));
+        let preview = tee.subscribe(BackpressurePolicy::LatestOnly);
+
+        for _ in 0..4 {
+            sender.send(frame()).unwrap();
+        }
+        // Let the relay fill the stalled subscriber and block on its next frame.
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(stalled.inner.receiver().len(), 1);
+
+        let started = Instant::now();
+        drop(tee);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(sender.is_disconnected());
+        let _ = preview.inner.receiver().drain();
+        assert!(preview.check_disconnected().is_err());
+    }
+}
@@ -0,0 +1,697 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Keeps a camera streaming across disconnects.
+//!
+//! [`Supervisor::open`] opens a camera on a supervisor thread and hands out a [`Stream`] that
+//! outlives the device. When the device stream disconnects, the supervisor finds the device again
+//! by its identity, reapplies the last [`CameraFormat`] and every property set through the
+//! [`Supervisor`], and resumes delivering frames on the same [`Stream`]. Reconnect attempts are
+//! spaced out by a [`BackoffPolicy`].
+//!
+//! The consumer stream reports [`StreamEvent::DeviceDisconnected`] when the device is lost and
+//! [`StreamEvent::Started`] each time it is (re)connected.
+
+use crate::{
+    camera::{Capture, Setting},
+    error::{NokhwaError, NokhwaResult},
+    platform::PlatformTrait,
+    properties::{ControlId, ControlValue},
+    stream::{
+        events::{StopReason, StreamEvent},
+        frame_channel, BackpressurePolicy, FrameReceiver, FrameSender, Stream, StreamInnerTrait,
+    },
+    types::{CameraFormat, CameraIndex, CameraInformation},
+};
+use flume::{Receiver, RecvTimeoutError, Sender};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// How long the supervisor waits for a device frame before checking for commands.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait between reconnect attempts.
+///
+/// The first retry happens after [`initial`](BackoffPolicy::initial), every further retry waits
+/// [`factor`](BackoffPolicy::factor) times longer, up to [`max`](BackoffPolicy::max).
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct BackoffPolicy {
+    initial: Duration,
+    max: Duration,
+    factor: u32,
+    max_attempts: Option<u32>,
+}
+
+impl BackoffPolicy {
+    /// Doubles the delay after every failed attempt, from `initial` up to `max`, retrying forever.
+    #[must_use]
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        BackoffPolicy {
+            initial,
+            max,
+            factor: 2,
+            max_attempts: None,
+        }
+    }
+
+    /// Waits `delay` between attempts, retrying forever.
+    #[must_use]
+    pub fn fixed(delay: Duration) -> Self {
+        BackoffPolicy {
+            initial: delay,
+            max: delay,
+            factor: 1,
+            max_attempts: None,
+        }
+    }
+
+    /// The delay before the first retry.
+    #[must_use]
+    pub fn initial(&self) -> Duration {
+        self.initial
+    }
+
+    /// The longest delay between retries.
+    #[must_use]
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// How much longer each retry waits than the one before.
+    #[must_use]
+    pub fn factor(&self) -> u32 {
+        self.factor
+    }
+
+    /// Sets how much longer each retry waits than the one before.
+    pub fn set_factor(&mut self, factor: u32) {
+        self.factor = factor;
+    }
+
+    /// How many failed attempts in a row the supervisor makes before giving up, `None` for no
+    /// limit.
+    #[must_use]
+    pub fn max_attempts(&self) -> Option<u32> {
+        self.max_attempts
+    }
+
+    /// Sets how many failed attempts in a row the supervisor makes before giving up.
+    pub fn set_max_attempts(&mut self, max_attempts: Option<u32>) {
+        self.max_attempts = max_attempts;
+    }
+
+    /// The delay after `failures` failed attempts in a row.
+    #[must_use]
+    pub fn delay(&self, failures: u32) -> Duration {
+        let scale = self
+            .factor
+            .checked_pow(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial
+            .checked_mul(scale)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+}
+
+impl Default for BackoffPolicy {
+    /// Retries forever, from 100 milliseconds up to 5 seconds apart.
+    fn default() -> Self {
+        BackoffPolicy::exponential(Duration::from_millis(100), Duration::from_secs(5))
+    }
+}
+
+enum Command {
+    SetFormat(CameraFormat, Sender<NokhwaResult<()>>),
+    SetProperty(ControlId, ControlValue, Sender<NokhwaResult<()>>),
+    Stop,
+}
+
+#[derive(Debug)]
+struct SharedState {
+    connected: AtomicBool,
+    reconnects: AtomicU64,
+    format: Mutex<CameraFormat>,
+}
+
+/// Control handle of a supervised camera, see the [module documentation](self).
+///
+/// The supervisor runs until its [`Stream`] is stopped or dropped. Dropping the [`Supervisor`]
+/// only gives up control over the format and properties.
+#[derive(Clone, Debug)]
+pub struct Supervisor {
+    commands: Sender<Command>,
+    state: Arc<SharedState>,
+    device: CameraInformation,
+}
+
+impl Supervisor {
+    /// Opens `device` through `platform` in `format` on a supervisor thread, and returns the
+    /// supervisor with the [`Stream`] it delivers frames on, queued following `policy`.
+    ///
+    /// The device is found again after a disconnect by its human name, description and misc
+    /// string, preferring its old index if several devices match. Backends should keep a stable
+    /// identifier, like a bus path or serial number, in one of these.
+    ///
+    /// # Errors
+    /// If the device could not be opened the first time. Later failures are retried following
+    /// `backoff`.
+    pub fn open<P>(
+        platform: P,
+        device: CameraInformation,
+        format: CameraFormat,
+        policy: BackpressurePolicy,
+        backoff: BackoffPolicy,
+    ) -> NokhwaResult<(Self, Stream)>
+    where
+        P: PlatformTrait + Send + 'static,
+    {
+        let (sender, receiver) = frame_channel(policy);
+        let (commands, command_receiver) = flume::unbounded();
+        let (opened, opened_receiver) = flume::bounded(1);
+        let state = Arc::new(SharedState {
+            connected: AtomicBool::new(false),
+            reconnects: AtomicU64::new(0),
+            format: Mutex::new(format),
+        });
+
+        let worker = Worker {
+            platform,
+            device: device.clone(),
+            index: device.index().clone(),
+            properties: Vec::new(),
+            backoff,
+            state: state.clone(),
+            commands: command_receiver,
+            frames: sender,
+            last_sequence: None,
+        };
+        let thread = std::thread::Builder::new()
+            .name("nokhwa-supervisor".to_string())
+            .spawn(move || worker.run(&opened))
+            .map_err(|why| NokhwaError::OpenStreamError(why.to_string()))?;
+
+        match opened_receiver.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(why)) => {
+                let _ = thread.join();
+                return Err(why);
+            }
+            Err(_) => {
+                let _ = thread.join();
+                return Err(NokhwaError::OpenStreamError(
+                    "supervisor exited while opening the device".to_string(),
+                ));
+            }
+        }
+
+        let stream = Stream::new(Box::new(SupervisedStream {
+            receiver,
+            commands: commands.clone(),
+            thread: Some(thread),
+        }));
+        Ok((
+            Supervisor {
+                commands,
+                state,
+                device,
+            },
+            stream,
+        ))
+    }
+
+    /// The device this supervisor keeps open, as it was first opened.
+    #[must_use]
+    pub fn device(&self) -> &CameraInformation {
+        &self.device
+    }
+
+    /// Whether the device is currently streaming.
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.state.connected.load(Ordering::Acquire)
+    }
+
+    /// How many times the device has been reconnected.
+    #[must_use]
+    pub fn reconnects(&self) -> u64 {
+        self.state.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// The format the device is (re)opened in.
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        *self
+            .state
+            .format
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Restarts the stream in `format`, which is then reapplied on every reconnect. While the
+    /// device is disconnected, the format is only recorded.
+    ///
+    /// # Errors
+    /// If the device rejects the format, in which case the old format stays, or the supervisor
+    /// has stopped.
+    pub fn set_format(&self, format: CameraFormat) -> NokhwaResult<()> {
+        self.request(|reply| Command::SetFormat(format, reply))
+    }
+
+    /// Sets a property, which is then reapplied on every reconnect. While the device is
+    /// disconnected, the value is only recorded.
+    ///
+    /// # Errors
+    /// If the device rejects the value, in which case it is not recorded, or the supervisor has
+    /// stopped.
+    pub fn set_property(&self, property: ControlId, value: ControlValue) -> NokhwaResult<()> {
+        self.request(|reply| Command::SetProperty(property, value, reply))
+    }
+
+    fn request(
+        &self,
+        command: impl FnOnce(Sender<NokhwaResult<()>>) -> Command,
+    ) -> NokhwaResult<()> {
+        let stopped = || NokhwaError::GeneralError("supervisor has stopped".to_string());
+        let (reply, response) = flume::bounded(1);
+        self.commands.send(command(reply)).map_err(|_| stopped())?;
+        response.recv().map_err(|_| stopped())?
+    }
+}
+
+impl std::fmt::Debug for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::SetFormat(format, _) => write!(f, "SetFormat({format})"),
+            Command::SetProperty(property, value, _) => {
+                write!(f, "SetProperty({property}, {value})")
+            }
+            Command::Stop => write!(f, "Stop"),
+        }
+    }
+}
+
+/// The inner half of the [`Stream`] a [`Supervisor`] delivers frames on.
+struct SupervisedStream {
+    receiver: FrameReceiver,
+    commands: Sender<Command>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StreamInnerTrait for SupervisedStream {
+    fn receiver(&self) -> &FrameReceiver {
+        &self.receiver
+    }
+
+    fn stop(&mut self) -> NokhwaResult<()> {
+        // The worker may be blocked passing on a frame, closing the channel makes it give up.
+        self.receiver.close();
+        let _ = self.commands.send(Command::Stop);
+        if let Some(thread) = self.thread.take() {
+            thread
+                .join()
+                .map_err(|_| NokhwaError::StreamShutdownError("supervisor panicked".to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// An open device and its stream.
+struct Connection<C> {
+    // Declared first, so the stream is dropped before the camera it came from.
+    stream: Stream,
+    camera: C,
+}
+
+/// The supervisor thread.
+struct Worker<P: PlatformTrait> {
+    platform: P,
+    device: CameraInformation,
+    /// Where the device was last seen.
+    index: CameraIndex,
+    /// Properties set through the supervisor, in the order they were set.
+    properties: Vec<(ControlId, ControlValue)>,
+    backoff: BackoffPolicy,
+    state: Arc<SharedState>,
+    commands: Receiver<Command>,
+    frames: FrameSender,
+    /// Sequence number of the last frame of the device stream, to carry its gaps over.
+    last_sequence: Option<u64>,
+}
+
+impl<P: PlatformTrait> Worker<P> {
+    fn run(mut self, opened: &Sender<NokhwaResult<()>>) {
+        let mut connection = match self.connect() {
+            Ok(connection) => {
+                let _ = opened.send(Ok(()));
+                Some(connection)
+            }
+            Err(why) => {
+                let _ = opened.send(Err(why));
+                return;
+            }
+        };
+        let mut failures = 0;
+        let mut retry_at = Instant::now();
+
+        loop {
+            // While connected, commands are checked between frames. While disconnected, they are
+            // waited for until the next reconnect attempt is due.
+            let command = if connection.is_some() {
+                self.commands.try_recv().ok()
+            } else {
+                match self.commands.recv_deadline(retry_at) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            };
+            match command {
+                Some(Command::Stop) => break,
+                Some(Command::SetFormat(format, reply)) => {
+                    let was_connected = connection.is_some();
+                    let _ = reply.send(self.set_format(format, &mut connection));
+                    if was_connected && connection.is_none() {
+                        failures = 0;
+                        retry_at = Instant::now() + self.backoff.initial();
+                    }
+                    continue;
+                }
+                Some(Command::SetProperty(property, value, reply)) => {
+                    let _ = reply.send(self.set_property(property, value, connection.as_mut()));
+                    continue;
+                }
+                None => {}
+            }
+            if self.frames.is_disconnected() {
+                break;
+            }
+
+            if let Some(active) = connection.take() {
+                connection = self.pump(active);
+                if connection.is_none() {
+                    failures = 0;
+                    retry_at = Instant::now() + self.backoff.initial();
+                }
+                continue;
+            }
+
+            match self.connect() {
+                Ok(reconnected) => {
+                    self.state.reconnects.fetch_add(1, Ordering::Relaxed);
+                    connection = Some(reconnected);
+                }
+                Err(why) => {
+                    failures += 1;
+                    if self
+                        .backoff
+                        .max_attempts()
+                        .is_some_and(|max_attempts| failures >= max_attempts)
+                    {
+                        self.frames.stop(StopReason::Error(why));
+                        break;
+                    }
+                    retry_at = Instant::now() + self.backoff.delay(failures);
+                }
+            }
+        }
+
+        if let Some(connection) = connection {
+            Self::close(connection);
+        }
+        self.state.connected.store(false, Ordering::Release);
+    }
+
+    /// Finds the device by its identity, preferring where it was last seen.
+    fn find_device(&mut self) -> NokhwaResult<CameraIndex> {
+        let device = &self.device;
+        self.platform
+            .query()?
+            .into_iter()
+            .filter(|info| {
+                info.human_name() == device.human_name()
+                    && info.description() == device.description()
+                    && info.misc() == device.misc()
+            })
+            .min_by_key(|info| info.index() != &self.index)
+            .map(|info| info.index().clone())
+            .ok_or_else(|| {
+                NokhwaError::OpenDeviceError(
+                    device.human_name(),
+                    "device is not connected".to_string(),
+                )
+            })
+    }
+
+    /// Opens the device and reapplies the format and properties.
+    fn connect(&mut self) -> NokhwaResult<Connection<P::Camera>> {
+        let index = self.find_device()?;
+        let mut camera = self.platform.open(&index)?;
+        let format = *self
+            .state
+            .format
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        camera.set_format(format)?;
+        for (property, value) in &self.properties {
+            camera.set_property(property, value.clone())?;
+        }
+        let stream = camera.open_stream()?;
+
+        self.index = index;
+        self.last_sequence = None;
+        self.state.connected.store(true, Ordering::Release);
+        self.frames.emit(StreamEvent::Started(format));
+        Ok(Connection { stream, camera })
+    }
+
+    fn close(connection: Connection<P::Camera>) {
+        let Connection { stream, mut camera } = connection;
+        drop(stream);
+        let _ = camera.close_stream();
+    }
+
+    /// Passes on the next frame of the device, or gives up the connection if it is lost.
+    fn pump(&mut self, connection: Connection<P::Camera>) -> Option<Connection<P::Camera>> {
+        match connection.stream.poll_frame_timeout(POLL_INTERVAL) {
+            Ok(frame) => {
+                if let Some(last) = self.last_sequence {
+                    self.frames.skip(frame.sequence().saturating_sub(last + 1));
+                }
+                self.last_sequence = Some(frame.sequence());
+                // A failed send means the consumer is gone, which ends the supervisor.
+                let _ = self.frames.send(frame);
+                Some(connection)
+            }
+            Err(NokhwaError::FrameTimeoutError(_)) => Some(connection),
+            Err(_) => {
+                Self::close(connection);
+                self.state.connected.store(false, Ordering::Release);
+                self.frames.emit(StreamEvent::DeviceDisconnected);
+                None
+            }
+        }
+    }
+
+    fn set_format(
+        &mut self,
+        format: CameraFormat,
+        connection: &mut Option<Connection<P::Camera>>,
+    ) -> NokhwaResult<()> {
+        let record = |state: &SharedState| {
+            *state.format.lock().unwrap_or_else(PoisonError::into_inner) = format;
+        };
+        let Some(Connection { stream, mut camera }) = connection.take() else {
+            record(&self.state);
+            return Ok(());
+        };
+
+        drop(stream);
+        let _ = camera.close_stream();
+        let result = camera.set_format(format);
+        if result.is_ok() {
+            record(&self.state);
+        }
+
+        match camera.open_stream() {
+            Ok(stream) => {
+                self.last_sequence = None;
+                if result.is_ok() {
+                    self.frames.emit(StreamEvent::FormatChanged(format));
+                }
+                *connection = Some(Connection { stream, camera });
+                result
+            }
+            Err(why) => {
+                // Reconnecting applies the recorded format.
+                drop(camera);
+                self.state.connected.store(false, Ordering::Release);
+                self.frames.emit(StreamEvent::DeviceDisconnected);
+                result.and(Err(why))
+            }
+        }
+    }
+
+    fn set_property(
+        &mut self,
+        property: ControlId,
+        value: ControlValue,
+        connection: Option<&mut Connection<P::Camera>>,
+    ) -> NokhwaResult<()> {
+        if let Some(connection) = connection {
+            connection.camera.set_property(&property, value.clone())?;
+        }
+        self.properties
+            .retain(|(recorded, _)| *recorded != property);
+        self.properties.push((property, value));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{
+        frame_buffer::FrameBuffer,
+        frame_format::FrameFormat,
+        testing::{MockCamera, MockPlatform},
+        types::{FrameRate, Resolution},
+    };
+
+    fn format(width: u32) -> CameraFormat {
+        CameraFormat::new(
+            Resolution::new(width, 1),
+            FrameFormat::Rgb888,
+            FrameRate::frame_rate(100),
+        )
+    }
+
+    fn camera() -> MockCamera {
+        let mut camera = MockCamera::new(CameraIndex::Index(0), vec![format(1), format(2)]);
+        camera.set_frames([FrameBuffer::new(
+            Resolution::new(1, 1),
+            &[0, 0, 0],
+            FrameFormat::Rgb888,
+        )]);
+        camera.set_looping(true);
+        camera
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_max() {
+        let backoff =
+            BackoffPolicy::exponential(Duration::from_millis(100), Duration::from_millis(500));
+        let delays = (1..=5).map(|failures| backoff.delay(failures));
+        assert_eq!(
+            delays.collect::<Vec<_>>(),
+            [100, 200, 400, 500, 500].map(Duration::from_millis)
+        );
+        assert_eq!(
+            BackoffPolicy::fixed(Duration::from_millis(50)).delay(10),
+            Duration::from_millis(50)
+        );
+    }
+
+    #[test]
+    fn reconnects_and_reapplies_the_format() {
+        let camera = camera();
+        let platform = MockPlatform::new(vec![camera.clone()]);
+        let (supervisor, stream) = Supervisor::open(
+            platform,
+            camera.info().clone(),
+            format(1),
+            BackpressurePolicy::LatestOnly,
+            BackoffPolicy::fixed(Duration::from_millis(10)),
+        )
+        .unwrap();
+        let events = stream.events();
+
+        supervisor.set_format(format(2)).unwrap();
+        assert_eq!(camera.format(), format(2));
+        stream.poll_frame_timeout(Duration::from_secs(2)).unwrap();
+
+        camera.disconnect();
+        wait_for(|| !supervisor.is_connected());
+        camera.set_format(format(1)).unwrap();
+        camera.reconnect();
+        wait_for(|| supervisor.is_connected());
+
+        assert_eq!(supervisor.reconnects(), 1);
+        assert_eq!(camera.format(), format(2));
+        stream.poll_frame_timeout(Duration::from_secs(2)).unwrap();
+        assert!(events
+            .try_iter()
+            .any(|event| matches!(event, StreamEvent::DeviceDisconnected)));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let camera = camera();
+        let platform = MockPlatform::new(vec![camera.clone()]);
+        let mut backoff = BackoffPolicy::fixed(Duration::from_millis(5));
+        backoff.set_max_attempts(Some(2));
+        let (_supervisor, stream) = Supervisor::open(
+            platform,
+            camera.info().clone(),
+            format(1),
+            BackpressurePolicy::LatestOnly,
+            backoff,
+        )
+        .unwrap();
+
+        camera.disconnect();
+        // Ends once the supervisor gives up, with the error of the last attempt.
+        assert!(matches!(
+            stream.frames().last(),
+            Some(Err(NokhwaError::OpenDeviceError(..)))
+        ));
+    }
+
+    #[test]
+    fn stopping_a_full_block_stream_does_not_hang() {
+        let mut camera = camera();
+        camera.set_paced(false);
+        let platform = MockPlatform::new(vec![camera.clone()]);
+        let (_supervisor, stream) = Supervisor::open(
+            platform,
+            camera.info().clone(),
+            format(1),
+            BackpressurePolicy::Block(1),
+            BackoffPolicy::default(),
+        )
+        .unwrap();
+        // Let the worker fill the queue and block on the next frame.
+        std::thread::sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        stream.stop_stream().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,552 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A scriptable [`Camera`] for testing code built on the `Camera` trait without a device.
+//!
+//! A [`MockCamera`] enumerates the formats and controls it is given, fails the calls it is told
+//! to fail, and streams a script of [`MockEvent`]s: fixed frames, pauses, format changes, read
+//! errors and disconnects. [`MockPlatform`] lists mock cameras through [`PlatformTrait`].
+//!
+//! A stream that fails with [`MockEvent::Error`] hands that error to the consumer through
+//! [`Stream::poll_frame`] once the frames before it are taken, and reports it with
+//! [`StreamEvent::Stopped`].
+
+use crate::{
+    camera::{Camera, Capture, Setting},
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    platform::{Backends, PlatformTrait},
+    properties::{ControlBody, ControlId, ControlValue, Properties},
+    stream::{
+        events::{StopReason, StreamEvent},
+        frame_channel,
+        producer::{frame_interval, sleep_until, Pacer, Producer, ProducerStream},
+        BackpressurePolicy, FrameSender, Stream,
+    },
+    types::{CameraFormat, CameraIndex, CameraInformation, FrameRate, Resolution},
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// One step of the stream script of a [`MockCamera`].
+#[derive(Clone, Debug)]
+pub enum MockEvent {
+    /// Delivers this frame, stamped with a fresh sequence number and timestamp.
+    Frame(FrameBuffer),
+    /// Pauses the stream. A long wait at the end of a script keeps the stream open.
+    Wait(Duration),
+    /// Renegotiates the format, reported as [`StreamEvent::FormatChanged`].
+    ChangeFormat(CameraFormat),
+    /// Ends the stream with this error, e.g. a [`NokhwaError::ReadFrameError`].
+    Error(NokhwaError),
+    /// Unplugs the device: ends the stream, and the camera cannot be opened again until
+    /// [`MockCamera::reconnect`].
+    Disconnect,
+}
+
+/// State of the simulated device, shared by every clone of a [`MockCamera`].
+#[derive(Debug)]
+struct MockDevice {
+    format: Mutex<CameraFormat>,
+    connected: AtomicBool,
+    stream: Mutex<Option<Producer>>,
+}
+
+/// A [`Camera`] whose behavior is scripted by the test using it. Clones share the simulated
+/// device, but each has its own controls.
+///
+/// The script plays once, paced at the frame rate of the current format; whether a slow test
+/// misses frames depends on the [`BackpressurePolicy`] of the stream.
+#[derive(Clone, Debug)]
+pub struct MockCamera {
+    info: CameraInformation,
+    formats: Vec<CameraFormat>,
+    rejected_formats: Vec<(CameraFormat, NokhwaError)>,
+    properties: Properties,
+    rejected_properties: HashMap<ControlId, NokhwaError>,
+    open_error: Option<NokhwaError>,
+    script: Vec<MockEvent>,
+    looping: bool,
+    paced: bool,
+    disconnect_after: Option<u64>,
+    device: Arc<MockDevice>,
+}
+
+impl MockCamera {
+    /// Creates a camera that enumerates `formats` and starts out in the first of them, or the
+    /// default [`CameraFormat`] if there are none. It has no controls and an empty script.
+    #[must_use]
+    pub fn new(index: CameraIndex, formats: Vec<CameraFormat>) -> Self {
+        let format = formats.first().copied().unwrap_or_default();
+        let info = CameraInformation::new(
+            format!("Mock Camera {index}"),
+            "Scripted mock camera".to_string(),
+            format!("mock:{index}"),
+            index,
+        );
+
+        Self {
+            info,
+            formats,
+            rejected_formats: Vec::new(),
+            properties: Properties::empty(),
+            rejected_properties: HashMap::new(),
+            open_error: None,
+            script: Vec::new(),
+            looping: false,
+            paced: true,
+            disconnect_after: None,
+            device: Arc::new(MockDevice {
+                format: Mutex::new(format),
+                connected: AtomicBool::new(true),
+                stream: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// The [`CameraInformation`] of this camera.
+    #[must_use]
+    pub fn info(&self) -> &CameraInformation {
+        &self.info
+    }
+
+    /// The current format, as last set by [`Setting::set_format`] or
+    /// [`MockEvent::ChangeFormat`].
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        *self
+            .device
+            .format
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Makes [`Setting::set_format`] fail with `error` for exactly `format`.
+    pub fn reject_format(&mut self, format: CameraFormat, error: NokhwaError) {
+        self.rejected_formats.push((format, error));
+    }
+
+    /// Replaces all controls.
+    pub fn set_properties(&mut self, properties: Properties) {
+        self.properties = properties;
+    }
+
+    /// Adds or replaces the control `id`, which [`Setting::set_property`] validates values
+    /// against.
+    pub fn add_control(&mut self, id: ControlId, body: ControlBody) {
+        self.properties.insert_control(id, body);
+    }
+
+    /// Makes [`Setting::set_property`] fail with `error` for the control `id`.
+    pub fn reject_property(&mut self, id: ControlId, error: NokhwaError) {
+        self.rejected_properties.insert(id, error);
+    }
+
+    /// Makes opening a stream fail with `error`, or succeed again with `None`.
+    pub fn set_open_error(&mut self, error: Option<NokhwaError>) {
+        self.open_error = error;
+    }
+
+    /// Replaces the script with `frames`, delivered in order.
+    pub fn set_frames(&mut self, frames: impl IntoIterator<Item = FrameBuffer>) {
+        self.script = frames.into_iter().map(MockEvent::Frame).collect();
+    }
+
+    /// Replaces the script.
+    pub fn set_script(&mut self, script: Vec<MockEvent>) {
+        self.script = script;
+    }
+
+    /// Adds a step to the end of the script.
+    pub fn push_event(&mut self, event: MockEvent) {
+        self.script.push(event);
+    }
+
+    /// Whether the script starts over after its last step instead of ending the stream.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Whether frames are paced at the frame rate of the current format. Unpaced frames are
+    /// delivered as fast as the stream takes them.
+    pub fn set_paced(&mut self, paced: bool) {
+        self.paced = paced;
+    }
+
+    /// Unplugs the device after the stream has delivered `frames` frames, or never with `None`.
+    pub fn disconnect_after(&mut self, frames: Option<u64>) {
+        self.disconnect_after = frames;
+    }
+
+    /// Whether the simulated device is plugged in.
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.device.connected.load(Ordering::Acquire)
+    }
+
+    /// Unplugs the device, ending its stream as [`MockEvent::Disconnect`] does.
+    pub fn disconnect(&self) {
+        self.device.connected.store(false, Ordering::Release);
+        if let Some(player) = self.player().take() {
+            player.stop();
+        }
+    }
+
+    /// Plugs the device back in after a disconnect.
+    pub fn reconnect(&self) {
+        self.device.connected.store(true, Ordering::Release);
+    }
+
+    fn player(&self) -> std::sync::MutexGuard<'_, Option<Producer>> {
+        self.device
+            .stream
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Setting for MockCamera {
+    fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError> {
+        Ok(self.formats.clone())
+    }
+
+    fn enumerate_resolution_and_frame_rates(
+        &self,
+        frame_format: FrameFormat,
+    ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError> {
+        let mut resolutions: HashMap<Resolution, Vec<FrameRate>> = HashMap::new();
+        for format in self
+            .formats
+            .iter()
+            .filter(|format| format.format() == frame_format)
+        {
+            let frame_rates = resolutions.entry(format.resolution()).or_default();
+            if !frame_rates.contains(&format.frame_rate()) {
+                frame_rates.push(format.frame_rate());
+            }
+        }
+        Ok(resolutions)
+    }
+
+    fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError> {
+        if let Some((_, error)) = self
+            .rejected_formats
+            .iter()
+            .find(|(rejected, _)| *rejected == camera_format)
+        {
+            return Err(error.clone());
+        }
+        if !self.formats.contains(&camera_format) {
+            return Err(NokhwaError::SetPropertyError {
+                property: "format".to_string(),
+                value: camera_format.to_string(),
+                error: "not an enumerated format".to_string(),
+            });
+        }
+
+        *self
+            .device
+            .format
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = camera_format;
+        Ok(())
+    }
+
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn set_property(
+        &mut self,
+        property: &ControlId,
+        value: ControlValue,
+    ) -> Result<(), NokhwaError> {
+        if let Some(error) = self.rejected_properties.get(property) {
+            return Err(error.clone());
+        }
+        self.properties.set_control_value(property, value)
+    }
+}
+
+impl Capture for MockCamera {
+    fn open_stream(&mut self) -> Result<Stream, NokhwaError> {
+        self.open_stream_with_policy(BackpressurePolicy::default())
+    }
+
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        if let Some(error) = &self.open_error {
+            return Err(error.clone());
+        }
+        if !self.is_connected() {
+            return Err(NokhwaError::OpenStreamError(
+                "device is disconnected".to_string(),
+            ));
+        }
+
+        let mut current = self.player();
+        if current.as_ref().is_some_and(Producer::is_running) {
+            return Err(NokhwaError::OpenStreamError(
+                "a stream is already open".to_string(),
+            ));
+        }
+
+        let (sender, receiver) = frame_channel(policy);
+        let script = Script {
+            events: self.script.clone(),
+            looping: self.looping,
+            paced: self.paced,
+            disconnect_after: self.disconnect_after,
+        };
+        let device = self.device.clone();
+        let (player, handle) = Producer::spawn("nokhwa-mock", move |running| {
+            play(&script, &sender, &device, &running);
+        })?;
+        *current = Some(player.clone());
+        Ok(Stream::new(Box::new(ProducerStream::new(
+            receiver, player, handle,
+        ))))
+    }
+
+    fn close_stream(&mut self) -> Result<(), NokhwaError> {
+        if let Some(player) = self.player().take() {
+            player.stop();
+        }
+        Ok(())
+    }
+}
+
+impl Camera for MockCamera {}
+
+/// A [`PlatformTrait`] that lists [`MockCamera`]s.
+///
+/// [`PlatformTrait::open`] hands out clones, which share the simulated device with the camera
+/// given to the platform. Disconnected cameras are not listed and cannot be opened.
+#[derive(Clone, Debug, Default)]
+pub struct MockPlatform {
+    cameras: Vec<MockCamera>,
+    permission: Option<bool>,
+}
+
+impl MockPlatform {
+    /// Creates a platform listing `cameras`. Permission is granted.
+    #[must_use]
+    pub fn new(cameras: Vec<MockCamera>) -> Self {
+        Self {
+            cameras,
+            permission: Some(true),
+        }
+    }
+
+    /// The camera with `index`, e.g. to disconnect it.
+    #[must_use]
+    pub fn camera(&self, index: &CameraIndex) -> Option<&MockCamera> {
+        self.cameras
+            .iter()
+            .find(|camera| camera.info().index() == index)
+    }
+
+    /// Whether the user grants access to the cameras, or `None` if they have not decided yet.
+    pub fn set_permission(&mut self, permission: Option<bool>) {
+        self.permission = permission;
+    }
+}
+
+impl PlatformTrait for MockPlatform {
+    const PLATFORM: Backends = Backends::Custom("mock");
+    type Camera = MockCamera;
+
+    fn block_on_permission(&mut self) -> NokhwaResult<()> {
+        match self.permission {
+            Some(true) => Ok(()),
+            _ => Err(NokhwaError::PermissionDenied),
+        }
+    }
+
+    fn check_permission_given(&mut self) -> bool {
+        self.permission == Some(true)
+    }
+
+    fn query(&mut self) -> NokhwaResult<Vec<CameraInformation>> {
+        Ok(self
+            .cameras
+            .iter()
+            .filter(|camera| camera.is_connected())
+            .map(|camera| camera.info().clone())
+            .collect())
+    }
+
+    fn open(&mut self, index: &CameraIndex) -> NokhwaResult<Self::Camera> {
+        if !self.check_permission_given() {
+            return Err(NokhwaError::PermissionDenied);
+        }
+        let error =
+            |error: &str| NokhwaError::OpenDeviceError(index.to_string(), error.to_string());
+        match self.camera(index) {
+            Some(camera) if camera.is_connected() => Ok(camera.clone()),
+            Some(_) => Err(error("device is disconnected")),
+            None => Err(error("no such mock camera")),
+        }
+    }
+}
+
+/// What a stream plays, copied from the camera when it opens.
+struct Script {
+    events: Vec<MockEvent>,
+    looping: bool,
+    paced: bool,
+    disconnect_after: Option<u64>,
+}
+
+fn play(script: &Script, frames: &FrameSender, device: &MockDevice, running: &AtomicBool) {
+    let format = || *device.format.lock().unwrap_or_else(PoisonError::into_inner);
+    frames.emit(StreamEvent::Started(format()));
+
+    let disconnect = || {
+        device.connected.store(false, Ordering::Release);
+        frames.emit(StreamEvent::DeviceDisconnected);
+        frames.stop(StopReason::DeviceDisconnected);
+    };
+    // Unplugged through the camera, or by the script after enough frames.
+    let unplugged = |delivered| {
+        !device.connected.load(Ordering::Acquire)
+            || script
+                .disconnect_after
+                .is_some_and(|after| delivered >= after)
+    };
+
+    let mut delivered = 0;
+    let mut pacer = Pacer::new();
+    'script: loop {
+        for event in &script.events {
+            if !running.load(Ordering::Acquire) || frames.is_disconnected() {
+                break 'script;
+            }
+            if unplugged(delivered) {
+                disconnect();
+                return;
+            }
+
+            match event {
+                MockEvent::Frame(frame) => {
+                    if script.paced && !pacer.wait(running) {
+                        break 'script;
+                    }
+                    let sent_at = Instant::now();
+                    let mut frame = frame.clone();
+                    frame.set_timestamp(sent_at);
+                    if frames.send(frame).is_err() {
+                        break 'script;
+                    }
+                    delivered += 1;
+
+                    let interval = frame_interval(format().frame_rate()).unwrap_or_default();
+                    pacer.schedule(sent_at, interval);
+                }
+                MockEvent::Wait(duration) => {
+                    // A wait too long to represent lasts until the stream is stopped.
+                    if !sleep_until(Instant::now().checked_add(*duration), running) {
+                        break 'script;
+                    }
+                }
+                MockEvent::ChangeFormat(new_format) => {
+                    *device.format.lock().unwrap_or_else(PoisonError::into_inner) = *new_format;
+                    frames.emit(StreamEvent::FormatChanged(*new_format));
+                }
+                MockEvent::Error(why) => {
+                    frames.stop(StopReason::Error(why.clone()));
+                    return;
+                }
+                MockEvent::Disconnect => {
+                    disconnect();
+                    return;
+                }
+            }
+        }
+
+        if !script.looping || script.events.is_empty() {
+            break;
+        }
+    }
+
+    if unplugged(delivered) {
+        disconnect();
+    } else {
+        frames.stop(StopReason::Requested);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(frames: u64) -> MockCamera {
+        let format = CameraFormat::new(
+            Resolution::new(2, 1),
+            FrameFormat::Rgb888,
+            FrameRate::frame_rate(30),
+        );
+        let mut camera = MockCamera::new(CameraIndex::Index(0), vec![format]);
+        camera.set_frames((0..frames).map(|_| {
+            FrameBuffer::new(
+                Resolution::new(2, 1),
+                &[1, 2, 3, 4, 5, 6],
+                FrameFormat::Rgb888,
+            )
+        }));
+        camera.set_paced(false);
+        camera
+    }
+
+    #[test]
+    fn a_queue_holding_the_script_receives_every_frame() {
+        let mut camera = camera(5);
+        let stream = camera
+            .open_stream_with_policy(BackpressurePolicy::DropNewest(5))
+            .unwrap();
+        // The whole script is queued without the test taking anything.
+        let events = stream.events();
+        while !matches!(events.recv(), Ok(StreamEvent::Stopped(_))) {}
+
+        let sequences = stream
+            .frames()
+            .map(|frame| frame.unwrap().sequence())
+            .collect::<Vec<_>>();
+        assert_eq!(sequences, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn dropping_a_blocked_stream_ends_the_script() {
+        let mut camera = camera(5);
+        let stream = camera
+            .open_stream_with_policy(BackpressurePolicy::Block(1))
+            .unwrap();
+        stream.poll_frame().unwrap();
+        // The player is blocked on the full queue with frames left to send.
+        drop(stream);
+
+        assert!(camera.open_stream().is_ok());
+    }
+}
@@ -17,7 +17,7 @@ use num_traits::FromPrimitive;
 
 /// Describes the index of the camera.
 /// - Index: A numbered index
-/// - String: A string, used for `IPCameras` or on the Browser as DeviceIDs.
+/// - String: A string, used for `IPCameras` or on the Browser as `DeviceIDs`.
 #[derive(Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub enum CameraIndex {
@@ -43,7 +43,7 @@ impl CameraIndex {
     pub fn as_string(&self) -> String {
         match self {
             CameraIndex::Index(i) => i.to_string(),
-            CameraIndex::String(s) => s.to_string(),
+            CameraIndex::String(s) => s.clone(),
         }
     }
 
@@ -116,28 +116,28 @@ impl Resolution {
     /// Get the width of Resolution
     #[must_use]
     #[inline]
-    pub fn width(self) -> u32 {
+    pub const fn width(self) -> u32 {
         self.width_x
     }
 
     /// Get the height of Resolution
     #[must_use]
     #[inline]
-    pub fn height(self) -> u32 {
+    pub const fn height(self) -> u32 {
         self.height_y
     }
 
     /// Get the x (width) of Resolution
     #[must_use]
     #[inline]
-    pub fn x(self) -> u32 {
+    pub const fn x(self) -> u32 {
         self.width_x
     }
 
     /// Get the y (height) of Resolution
     #[must_use]
     #[inline]
-    pub fn y(self) -> u32 {
+    pub const fn y(self) -> u32 {
         self.height_y
     }
 
@@ -229,30 +229,37 @@ pub struct FrameRate {
 }
 
 impl FrameRate {
+    #[must_use]
     pub const fn new(numerator: i32, denominator: NonZeroI32) -> Self {
         Self {
             rational: Rational32::new_raw(numerator, denominator.get()),
         }
     }
 
+    #[must_use]
+    #[allow(clippy::self_named_constructors)]
     pub const fn frame_rate(fps: i32) -> Self {
         Self {
             rational: Rational32::new_raw(fps, 1),
         }
     }
 
+    #[must_use]
     pub fn numerator(&self) -> &i32 {
         self.rational.numer()
     }
 
+    #[must_use]
     pub fn denominator(&self) -> &i32 {
         self.rational.denom()
     }
 
+    #[must_use]
     pub fn as_raw(&self) -> &Rational32 {
         &self.rational
     }
 
+    #[must_use]
     pub fn approximate_float(&self) -> Option<f32> {
         let numerator_float = f32::from_i32(*self.numerator())?;
         let denominator_float = f32::from_i32(*self.denominator())?;
@@ -0,0 +1,549 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Reading and writing YUV4MPEG2 (Y4M) files, the simplest container for raw YUV video,
+//! understood by `ffmpeg`, `x264` and `libaom`.
+//!
+//! A Y4M file is a one line text header followed by frames, each a `FRAME` line and the planes
+//! of the picture one after another. [`Y4mReader`] turns frames into [`FrameBuffer`]s as follows:
+//!
+//! | Colorspace                        | [`FrameFormat`]          |
+//! |-----------------------------------|--------------------------|
+//! | `420jpeg`, `420paldv`, `420mpeg2` | [`FrameFormat::I420`]    |
+//! | `422`                             | [`FrameFormat::Yuyv422`] |
+//! | `mono`                            | [`FrameFormat::Luma8`]   |
+//!
+//! [`Y4mWriter`] takes the other way, and also converts frames of related formats:
+//!
+//! | [`FrameFormat`]                                  | Colorspace |
+//! |--------------------------------------------------|------------|
+//! | `I420`, `Yv12`, `Nv12`, `Nv21`                   | `420jpeg`  |
+//! | `Yuyv422`, `Uyvy422`, `Yvyu422`                  | `422`      |
+//! | `Luma8`                                          | `mono`     |
+//!
+//! The chroma siting of the 4:2:0 colorspaces and the interlacing and aspect ratio of the file
+//! are not taken into account when reading. Written files are progressive with square pixels,
+//! and YUV files are marked as limited range, like the frames of the
+//! [`encoder`](crate::encoder).
+
+use crate::{
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    types::{CameraFormat, FrameRate, Resolution},
+};
+use std::{
+    io::{BufRead, ErrorKind, Read, Write},
+    num::NonZeroI32,
+};
+
+const SIGNATURE: &[u8] = b"YUV4MPEG2";
+const FRAME_SIGNATURE: &[u8] = b"FRAME";
+/// Header lines longer than this are rejected rather than read into memory.
+const MAX_LINE: u64 = 64 * 1024;
+/// Frames larger than this, e.g. of a corrupt header, are rejected rather than allocated.
+const MAX_FRAME: usize = 256 * 1024 * 1024;
+
+/// The pixel layout of a Y4M file, from the `C` header parameter.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub enum Y4mColorspace {
+    /// 4:2:0 with chroma centered between luma samples, the default.
+    #[default]
+    C420Jpeg,
+    /// 4:2:0 with chroma sited like PAL DV.
+    C420Paldv,
+    /// 4:2:0 with chroma sited like MPEG-2.
+    C420Mpeg2,
+    /// 4:2:2.
+    C422,
+    /// Luma only.
+    Mono,
+}
+
+impl Y4mColorspace {
+    /// The value of the `C` header parameter.
+    #[must_use]
+    pub fn tag(self) -> &'static str {
+        match self {
+            Y4mColorspace::C420Jpeg => "420jpeg",
+            Y4mColorspace::C420Paldv => "420paldv",
+            Y4mColorspace::C420Mpeg2 => "420mpeg2",
+            Y4mColorspace::C422 => "422",
+            Y4mColorspace::Mono => "mono",
+        }
+    }
+
+    /// The colorspace of a `C` header parameter, if it is supported.
+    #[must_use]
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "420" | "420jpeg" => Some(Y4mColorspace::C420Jpeg),
+            "420paldv" => Some(Y4mColorspace::C420Paldv),
+            "420mpeg2" => Some(Y4mColorspace::C420Mpeg2),
+            "422" => Some(Y4mColorspace::C422),
+            "mono" => Some(Y4mColorspace::Mono),
+            _ => None,
+        }
+    }
+
+    /// The [`FrameFormat`] of frames read in this colorspace.
+    #[must_use]
+    pub fn frame_format(self) -> FrameFormat {
+        match self {
+            Y4mColorspace::C420Jpeg | Y4mColorspace::C420Paldv | Y4mColorspace::C420Mpeg2 => {
+                FrameFormat::I420
+            }
+            Y4mColorspace::C422 => FrameFormat::Yuyv422,
+            Y4mColorspace::Mono => FrameFormat::Luma8,
+        }
+    }
+
+    /// The colorspace frames of `frame_format` are written in, if [`Y4mWriter`] can write them.
+    #[must_use]
+    pub fn for_frame_format(frame_format: FrameFormat) -> Option<Self> {
+        match frame_format {
+            FrameFormat::I420 | FrameFormat::Yv12 | FrameFormat::Nv12 | FrameFormat::Nv21 => {
+                Some(Y4mColorspace::C420Jpeg)
+            }
+            FrameFormat::Yuyv422 | FrameFormat::Uyvy422 | FrameFormat::Yvyu422 => {
+                Some(Y4mColorspace::C422)
+            }
+            FrameFormat::Luma8 => Some(Y4mColorspace::Mono),
+            _ => None,
+        }
+    }
+
+    /// The width and height of each chroma plane of a `resolution` picture.
+    fn chroma_dimensions(self, resolution: Resolution) -> (usize, usize) {
+        let (width, height) = (resolution.width() as usize, resolution.height() as usize);
+        match self {
+            Y4mColorspace::C420Jpeg | Y4mColorspace::C420Paldv | Y4mColorspace::C420Mpeg2 => {
+                (width.div_ceil(2), height.div_ceil(2))
+            }
+            Y4mColorspace::C422 => (width.div_ceil(2), height),
+            Y4mColorspace::Mono => (0, 0),
+        }
+    }
+
+    /// The size in bytes of a `resolution` picture, if it fits in memory.
+    fn frame_size(self, resolution: Resolution) -> Option<usize> {
+        let (chroma_width, chroma_height) = self.chroma_dimensions(resolution);
+        let luma = (resolution.width() as usize).checked_mul(resolution.height() as usize)?;
+        chroma_width
+            .checked_mul(chroma_height)?
+            .checked_mul(2)?
+            .checked_add(luma)
+    }
+}
+
+/// Reads the frames of a Y4M file as [`FrameBuffer`]s.
+///
+/// Also an [`Iterator`] over the frames, which ends at the end of the file.
+#[derive(Debug)]
+pub struct Y4mReader<R> {
+    reader: R,
+    format: CameraFormat,
+    colorspace: Y4mColorspace,
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    /// Reads the header of the file.
+    ///
+    /// # Errors
+    /// If the header cannot be read, is malformed, or uses an unsupported colorspace.
+    pub fn new(mut reader: R) -> NokhwaResult<Self> {
+        let header = read_line(&mut reader)
+            .map_err(|why| header_error(&why.to_string()))?
+            .ok_or_else(|| header_error("the file is empty"))?;
+
+        let mut params = header.split(|byte| *byte == b' ');
+        if params.next() != Some(SIGNATURE) {
+            return Err(header_error("not a YUV4MPEG2 file"));
+        }
+
+        let (mut width, mut height, mut frame_rate) = (None, None, None);
+        let mut colorspace = Y4mColorspace::default();
+        for param in params.filter(|param| !param.is_empty()) {
+            let value = std::str::from_utf8(&param[1..]);
+            match (param[0], value) {
+                (b'W', Ok(value)) => width = value.parse::<u32>().ok(),
+                (b'H', Ok(value)) => height = value.parse::<u32>().ok(),
+                (b'F', Ok(value)) => frame_rate = parse_frame_rate(value),
+                (b'C', Ok(value)) => {
+                    colorspace = Y4mColorspace::from_tag(value)
+                        .ok_or_else(|| header_error(&format!("unsupported colorspace {value}")))?;
+                }
+                // Interlacing, aspect ratio and extensions do not change how frames are read.
+                _ => {}
+            }
+        }
+
+        let resolution = match (width, height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => {
+                Resolution::new(width, height)
+            }
+            _ => return Err(header_error("missing or invalid frame size")),
+        };
+        let frame_rate = frame_rate.ok_or_else(|| header_error("missing or invalid frame rate"))?;
+
+        Ok(Self {
+            reader,
+            format: CameraFormat::new(resolution, colorspace.frame_format(), frame_rate),
+            colorspace,
+        })
+    }
+
+    /// The resolution, frame rate and [`FrameFormat`] of the frames.
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    /// The colorspace of the file.
+    #[must_use]
+    pub fn colorspace(&self) -> Y4mColorspace {
+        self.colorspace
+    }
+
+    /// Reads the next frame, or `None` at the end of the file.
+    ///
+    /// # Errors
+    /// If the frame cannot be read or is truncated, or is too large to read.
+    pub fn read_frame(&mut self) -> NokhwaResult<Option<FrameBuffer>> {
+        let read_error = |why: String| NokhwaError::ReadFrameError(format!("Y4M: {why}"));
+
+        let Some(line) = read_line(&mut self.reader).map_err(|why| read_error(why.to_string()))?
+        else {
+            return Ok(None);
+        };
+        if !line.starts_with(FRAME_SIGNATURE) {
+            return Err(read_error("missing FRAME marker".to_string()));
+        }
+
+        let resolution = self.format.resolution();
+        let size = self
+            .colorspace
+            .frame_size(resolution)
+            .filter(|size| *size <= MAX_FRAME)
+            .ok_or_else(|| NokhwaError::CorruptFrameError {
+                src: self.format.format(),
+                error: format!("a {resolution} frame is too large to read"),
+            })?;
+        let mut picture = vec![0; size];
+        self.reader
+            .read_exact(&mut picture)
+            .map_err(|why| match why.kind() {
+                ErrorKind::UnexpectedEof => read_error("truncated frame".to_string()),
+                _ => read_error(why.to_string()),
+            })?;
+
+        let data = match self.colorspace {
+            Y4mColorspace::C422 => planar_422_to_yuyv(resolution, &picture),
+            _ => picture,
+        };
+        Ok(Some(FrameBuffer::from_vec(
+            resolution,
+            data,
+            self.format.format(),
+        )))
+    }
+
+    /// Gives back the underlying reader.
+    #[must_use]
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: BufRead> Iterator for Y4mReader<R> {
+    type Item = NokhwaResult<FrameBuffer>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// Writes [`FrameBuffer`]s to a Y4M file.
+///
+/// The header is written by [`Y4mWriter::new`], with the resolution and frame rate of the
+/// [`CameraFormat`] and the colorspace of its [`FrameFormat`]. Every frame is written with a
+/// single write, wrap files in a [`BufWriter`](std::io::BufWriter) if that is too often.
+#[derive(Debug)]
+pub struct Y4mWriter<W> {
+    writer: W,
+    format: CameraFormat,
+    /// The frame being written, kept to reuse its allocation.
+    frame: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the header of a file holding frames of `format`.
+    ///
+    /// # Errors
+    /// If frames of `format` cannot be written, see the [module documentation](self), its
+    /// resolution is empty or its frame rate is not positive, or the header cannot be written.
+    pub fn new(mut writer: W, format: CameraFormat) -> NokhwaResult<Self> {
+        let colorspace = Y4mColorspace::for_frame_format(format.format()).ok_or_else(|| {
+            header_error(&format!("{} frames cannot be written", format.format()))
+        })?;
+        if format.width() == 0 || format.height() == 0 {
+            return Err(header_error("resolution is empty"));
+        }
+        let frame_rate = format.frame_rate();
+        let (numerator, denominator) = (*frame_rate.numerator(), *frame_rate.denominator());
+        if numerator <= 0 || denominator <= 0 {
+            return Err(header_error("frame rate is not positive"));
+        }
+
+        let range = match colorspace {
+            Y4mColorspace::Mono => "",
+            _ => " XCOLORRANGE=LIMITED",
+        };
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{numerator}:{denominator} Ip A1:1 C{}{range}",
+            format.width(),
+            format.height(),
+            colorspace.tag(),
+        )
+        .map_err(|why| header_error(&why.to_string()))?;
+
+        Ok(Self {
+            writer,
+            format,
+            frame: Vec::new(),
+        })
+    }
+
+    /// The format of the frames that are written.
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    /// Writes `frame`, converting it to the colorspace of the file.
+    ///
+    /// # Errors
+    /// If `frame` does not have the resolution and [`FrameFormat`] of the file, its planes do not
+    /// fit in its buffer, or it cannot be written.
+    pub fn write_frame(&mut self, frame: &FrameBuffer) -> NokhwaResult<()> {
+        let error = |error: String| NokhwaError::ProcessFrameError {
+            src: frame.source_frame_format(),
+            destination: "Y4M".to_string(),
+            error,
+        };
+
+        if frame.resolution() != self.format.resolution()
+            || frame.source_frame_format() != self.format.format()
+        {
+            return Err(error(format!(
+                "expected a {} {} frame, got {} {}",
+                self.format.resolution(),
+                self.format.format(),
+                frame.resolution(),
+                frame.source_frame_format(),
+            )));
+        }
+        frame.check_planes().map_err(error)?;
+
+        self.frame.clear();
+        self.frame.extend_from_slice(FRAME_SIGNATURE);
+        self.frame.push(b'\n');
+        let width = frame.resolution().width() as usize;
+        match frame.source_frame_format() {
+            FrameFormat::Yv12 => {
+                // Stored with the Cr plane first.
+                for plane in [0, 2, 1] {
+                    frame
+                        .plane_rows(plane)
+                        .for_each(|row| self.frame.extend_from_slice(row));
+                }
+            }
+            frame_format @ (FrameFormat::Nv12 | FrameFormat::Nv21) => {
+                frame
+                    .plane_rows(0)
+                    .for_each(|row| self.frame.extend_from_slice(row));
+                let cb = usize::from(frame_format == FrameFormat::Nv21);
+                for offset in [cb, 1 - cb] {
+                    for row in frame.plane_rows(1) {
+                        self.frame
+                            .extend(row.iter().skip(offset).step_by(2).copied());
+                    }
+                }
+            }
+            frame_format @ (FrameFormat::Yuyv422 | FrameFormat::Uyvy422 | FrameFormat::Yvyu422) => {
+                let (luma, cb, cr) = match frame_format {
+                    FrameFormat::Uyvy422 => (1, 0, 2),
+                    FrameFormat::Yvyu422 => (0, 3, 1),
+                    _ => (0, 1, 3),
+                };
+                for row in frame.plane_rows(0) {
+                    // An odd width leaves a luma sample too many in the last pair.
+                    self.frame.extend(
+                        row.chunks_exact(4)
+                            .flat_map(|pair| [pair[luma], pair[luma + 2]])
+                            .take(width),
+                    );
+                }
+                for sample in [cb, cr] {
+                    for row in frame.plane_rows(0) {
+                        self.frame
+                            .extend(row.chunks_exact(4).map(|pair| pair[sample]));
+                    }
+                }
+            }
+            _ => {
+                for plane in 0..frame.planes().len() {
+                    frame
+                        .plane_rows(plane)
+                        .for_each(|row| self.frame.extend_from_slice(row));
+                }
+            }
+        }
+
+        self.writer
+            .write_all(&self.frame)
+            .map_err(|why| error(why.to_string()))
+    }
+
+    /// Flushes the underlying writer.
+    ///
+    /// # Errors
+    /// If the writer cannot be flushed.
+    pub fn flush(&mut self) -> NokhwaResult<()> {
+        self.writer
+            .flush()
+            .map_err(|why| NokhwaError::ProcessFrameError {
+                src: self.format.format(),
+                destination: "Y4M".to_string(),
+                error: why.to_string(),
+            })
+    }
+
+    /// Gives back the underlying writer.
+    #[must_use]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn header_error(error: &str) -> NokhwaError {
+    NokhwaError::StructureError {
+        structure: "Y4M header".to_string(),
+        error: error.to_string(),
+    }
+}
+
+/// Reads a line without its newline, or `None` at the end of the input.
+fn read_line(reader: &mut impl BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    if reader.take(MAX_LINE).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "line is truncated or too long",
+        ));
+    }
+    Ok(Some(line))
+}
+
+/// Parses a `F` header parameter, e.g. `30000:1001`.
+fn parse_frame_rate(value: &str) -> Option<FrameRate> {
+    let (numerator, denominator) = value.split_once(':')?;
+    let numerator = numerator.parse::<i32>().ok().filter(|fps| *fps > 0)?;
+    let denominator = NonZeroI32::new(denominator.parse().ok()?).filter(|den| den.get() > 0)?;
+    Some(FrameRate::new(numerator, denominator))
+}
+
+/// Interleaves the planes of a planar 4:2:2 picture into YUYV.
+fn planar_422_to_yuyv(resolution: Resolution, picture: &[u8]) -> Vec<u8> {
+    let (width, height) = (resolution.width() as usize, resolution.height() as usize);
+    let chroma_width = width.div_ceil(2);
+    let (luma, chroma) = picture.split_at(width * height);
+    let (cb, cr) = chroma.split_at(chroma_width * height);
+
+    let mut packed = Vec::with_capacity(chroma_width * 4 * height);
+    for row in 0..height {
+        let luma = &luma[row * width..(row + 1) * width];
+        for x in 0..chroma_width {
+            let chroma = row * chroma_width + x;
+            // An odd last column repeats its luma sample.
+            let (y0, y1) = (luma[2 * x], luma[(2 * x + 1).min(width - 1)]);
+            packed.extend_from_slice(&[y0, cb[chroma], y1, cr[chroma]]);
+        }
+    }
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn reader(header: &str) -> Y4mReader<Cursor<Vec<u8>>> {
+        Y4mReader::new(Cursor::new(format!("{header}\nFRAME\n").into_bytes())).unwrap()
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let format = CameraFormat::new(
+            Resolution::new(2, 2),
+            FrameFormat::I420,
+            FrameRate::frame_rate(25),
+        );
+        let frame = FrameBuffer::new(
+            Resolution::new(2, 2),
+            &[1, 2, 3, 4, 5, 6],
+            FrameFormat::I420,
+        );
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let file = writer.into_inner();
+
+        let mut reader = Y4mReader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.format(), format);
+        assert_eq!(
+            reader.read_frame().unwrap().unwrap().buffer(),
+            frame.buffer()
+        );
+        assert!(reader.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_frames_too_large_to_read() {
+        for size in ["W65535 H65535", "W4294967295 H4294967295"] {
+            let mut reader = reader(&format!("YUV4MPEG2 {size} F30:1 C420jpeg"));
+            assert!(matches!(
+                reader.read_frame(),
+                Err(NokhwaError::CorruptFrameError {
+                    src: FrameFormat::I420,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn reports_truncated_frames() {
+        let mut reader = reader("YUV4MPEG2 W4 H4 F30:1 Cmono");
+        assert!(matches!(
+            reader.read_frame(),
+            Err(NokhwaError::ReadFrameError(why)) if why == "Y4M: truncated frame"
+        ));
+    }
+}
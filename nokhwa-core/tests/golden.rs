@@ -0,0 +1,640 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Golden-image checks for the decoders, encoder and Y4M support.
+//!
+//! Every check encodes a known pattern (100% BT.601 color bars) into the source format by hand,
+//! decodes it and compares the result against the pattern the bars were generated from.
+#![cfg(all(feature = "decoding-yuv", feature = "decoding-bayer"))]
+
+#[cfg(feature = "decoding-mozjpeg")]
+use image::codecs::jpeg::JpegEncoder;
+#[cfg(feature = "decoding-mozjpeg")]
+use image::ExtendedColorType;
+use image::{Luma, Rgb, Rgba};
+use nokhwa_core::decoder::bayer::{BayerDecoder, DemosaicMethod};
+use nokhwa_core::decoder::luma::DeepLumaDecoder;
+#[cfg(feature = "decoding-mozjpeg")]
+use nokhwa_core::decoder::mjpeg::{MJpegDecoder, MJpegFrameStatus};
+use nokhwa_core::decoder::yuv::{P010Decoder, PlanarYuvDecoder, Yuv422Decoder};
+use nokhwa_core::decoder::{Decoder, StaticDecoder};
+use nokhwa_core::encoder::{encode_rgb888, rgb888_to_yuv444};
+#[cfg(feature = "decoding-mozjpeg")]
+use nokhwa_core::error::NokhwaError;
+use nokhwa_core::frame_buffer::{FrameBuffer, FramePlane};
+use nokhwa_core::frame_format::{BayerPattern, BayerSite, FrameFormat};
+use nokhwa_core::types::{CameraFormat, FrameRate, Resolution};
+use nokhwa_core::y4m::{Y4mColorspace, Y4mReader, Y4mWriter};
+use std::num::NonZeroI32;
+
+/// 100% color bars as (RGB, BT.601 limited range YCbCr).
+const BARS: [([u8; 3], [u8; 3]); 8] = [
+    ([255, 255, 255], [235, 128, 128]),
+    ([255, 255, 0], [210, 16, 146]),
+    ([0, 255, 255], [170, 166, 16]),
+    ([0, 255, 0], [145, 54, 34]),
+    ([255, 0, 255], [106, 202, 222]),
+    ([255, 0, 0], [81, 90, 240]),
+    ([0, 0, 255], [41, 240, 110]),
+    ([0, 0, 0], [16, 128, 128]),
+];
+
+/// Rounding in the integer YCbCr transform may be off by this much per channel.
+const TOLERANCE: u8 = 2;
+
+/// Bar index of column `x` in a frame `width` pixels wide.
+fn bar_at(x: usize, width: usize) -> usize {
+    x * BARS.len() / width
+}
+
+fn assert_close(name: &str, decoded: &[u8], expected: &[u8], tolerance: u8) {
+    assert_eq!(decoded.len(), expected.len(), "{name}: length mismatch");
+    for (idx, (d, e)) in decoded.iter().zip(expected).enumerate() {
+        assert!(
+            d.abs_diff(*e) <= tolerance,
+            "{name}: byte {idx} decoded as {d}, expected {e}"
+        );
+    }
+}
+
+/// Golden RGB, RGBA and luma images of the bar pattern.
+fn golden(resolution: Resolution) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let width = resolution.width() as usize;
+    let (mut rgb, mut rgba, mut luma) = (vec![], vec![], vec![]);
+    for _ in 0..resolution.height() {
+        for x in 0..width {
+            let (color, yuv) = BARS[bar_at(x, width)];
+            rgb.extend_from_slice(&color);
+            rgba.extend_from_slice(&color);
+            rgba.push(255);
+            luma.push(yuv[0]);
+        }
+    }
+    (rgb, rgba, luma)
+}
+
+fn encode_packed_422(resolution: Resolution, frame_format: FrameFormat) -> Vec<u8> {
+    let width = resolution.width() as usize;
+    let mut frame = vec![];
+    for _ in 0..resolution.height() {
+        for x in (0..width).step_by(2) {
+            let [y0, u, v] = BARS[bar_at(x, width)].1;
+            let y1 = BARS[bar_at((x + 1).min(width - 1), width)].1[0];
+            let macropixel = match frame_format {
+                FrameFormat::Yuyv422 => [y0, u, y1, v],
+                FrameFormat::Uyvy422 => [u, y0, v, y1],
+                FrameFormat::Yvyu422 => [y0, v, y1, u],
+                _ => unreachable!(),
+            };
+            frame.extend_from_slice(&macropixel);
+        }
+    }
+    frame
+}
+
+#[test]
+fn packed_422_decodes_to_the_bars() {
+    for resolution in [Resolution::new(16, 2), Resolution::new(15, 3)] {
+        let (rgb, rgba, luma) = golden(resolution);
+        for frame_format in [
+            FrameFormat::Yuyv422,
+            FrameFormat::Uyvy422,
+            FrameFormat::Yvyu422,
+        ] {
+            let name = format!("{frame_format} {resolution}");
+            let buffer = FrameBuffer::new(
+                resolution,
+                &encode_packed_422(resolution, frame_format),
+                frame_format,
+            );
+
+            let decoded = Yuv422Decoder::<Rgb<u8>>::decode_static(&buffer).unwrap();
+            assert_close(&format!("{name} -> RGB"), &decoded, &rgb, TOLERANCE);
+
+            let mut decoder = Yuv422Decoder::<Rgba<u8>>::new();
+            let mut output =
+                vec![0; Yuv422Decoder::<Rgba<u8>>::predicted_size_of_frame(&buffer).unwrap()];
+            decoder.decode_buffer(&buffer, &mut output).unwrap();
+            assert_close(&format!("{name} -> RGBA"), &output, &rgba, TOLERANCE);
+
+            let decoded = Yuv422Decoder::<Luma<u8>>::new().decode(&buffer).unwrap();
+            assert_close(&format!("{name} -> Luma"), &decoded, &luma, 0);
+        }
+    }
+
+    let truncated = FrameBuffer::new(Resolution::new(16, 2), &[0; 10], FrameFormat::Yuyv422);
+    assert!(Yuv422Decoder::<Rgb<u8>>::decode_static(&truncated).is_err());
+    let mut too_small = [0; 4];
+    let buffer = FrameBuffer::new(
+        Resolution::new(16, 2),
+        &encode_packed_422(Resolution::new(16, 2), FrameFormat::Yuyv422),
+        FrameFormat::Yuyv422,
+    );
+    assert!(Yuv422Decoder::<Rgb<u8>>::decode_static_to_buffer(&buffer, &mut too_small).is_err());
+}
+
+fn encode_planar(resolution: Resolution, frame_format: FrameFormat) -> Vec<u8> {
+    let (width, height) = (resolution.width() as usize, resolution.height() as usize);
+    let subsampling = if frame_format == FrameFormat::Yvu9 {
+        4
+    } else {
+        2
+    };
+    let (chroma_width, chroma_height) = (width.div_ceil(subsampling), height.div_ceil(subsampling));
+
+    let mut luma = vec![];
+    for _ in 0..height {
+        luma.extend((0..width).map(|x| BARS[bar_at(x, width)].1[0]));
+    }
+    let (mut cb, mut cr) = (vec![], vec![]);
+    for _ in 0..chroma_height {
+        for x in 0..chroma_width {
+            let [_, u, v] = BARS[bar_at(x * subsampling, width)].1;
+            cb.push(u);
+            cr.push(v);
+        }
+    }
+
+    let chroma = match frame_format {
+        FrameFormat::Nv12 => cb.iter().zip(&cr).flat_map(|(u, v)| [*u, *v]).collect(),
+        FrameFormat::Nv21 => cb.iter().zip(&cr).flat_map(|(u, v)| [*v, *u]).collect(),
+        FrameFormat::I420 => [cb, cr].concat(),
+        FrameFormat::Yv12 | FrameFormat::Yvu9 => [cr, cb].concat(),
+        _ => unreachable!(),
+    };
+    [luma, chroma].concat()
+}
+
+#[test]
+fn planar_yuv_decodes_to_the_bars() {
+    for frame_format in [
+        FrameFormat::Nv12,
+        FrameFormat::Nv21,
+        FrameFormat::I420,
+        FrameFormat::Yv12,
+        FrameFormat::Yvu9,
+    ] {
+        let resolutions = if frame_format == FrameFormat::Yvu9 {
+            [Resolution::new(32, 8), Resolution::new(31, 7)]
+        } else {
+            [Resolution::new(16, 4), Resolution::new(15, 5)]
+        };
+        for resolution in resolutions {
+            let (rgb, rgba, luma) = golden(resolution);
+            let name = format!("{frame_format} {resolution}");
+            let frame = encode_planar(resolution, frame_format);
+            let buffer = FrameBuffer::new(resolution, &frame, frame_format);
+
+            let decoded = PlanarYuvDecoder::<Rgb<u8>>::decode_static(&buffer).unwrap();
+            assert_close(&format!("{name} -> RGB"), &decoded, &rgb, TOLERANCE);
+
+            let mut output =
+                vec![0; PlanarYuvDecoder::<Rgba<u8>>::predicted_size_of_frame(&buffer).unwrap()];
+            PlanarYuvDecoder::<Rgba<u8>>::new()
+                .decode_buffer(&buffer, &mut output)
+                .unwrap();
+            assert_close(&format!("{name} -> RGBA"), &output, &rgba, TOLERANCE);
+
+            let decoded = PlanarYuvDecoder::<Luma<u8>>::new().decode(&buffer).unwrap();
+            assert_close(&format!("{name} -> Luma"), &decoded, &luma, 0);
+
+            let truncated = FrameBuffer::new(resolution, &frame[1..], frame_format);
+            assert!(PlanarYuvDecoder::<Rgb<u8>>::decode_static(&truncated).is_err());
+        }
+    }
+}
+
+fn assert_close_16(name: &str, decoded: &[u16], expected: &[u16], tolerance: u16) {
+    assert_eq!(decoded.len(), expected.len(), "{name}: length mismatch");
+    for (idx, (d, e)) in decoded.iter().zip(expected).enumerate() {
+        assert!(
+            d.abs_diff(*e) <= tolerance,
+            "{name}: sample {idx} decoded as {d}, expected {e}"
+        );
+    }
+}
+
+#[test]
+fn p010_decodes_to_the_bars() {
+    for resolution in [Resolution::new(16, 4), Resolution::new(15, 5)] {
+        let (rgb, _, luma) = golden(resolution);
+        let rgb: Vec<u16> = rgb.iter().map(|c| u16::from(*c) * 257).collect();
+        let luma: Vec<u16> = luma.iter().map(|y| u16::from(*y) << 8).collect();
+
+        // P010 stores its 10 bits MSB aligned, so an 8 bit sample shifted into the top byte is
+        // valid for both formats.
+        let frame: Vec<u8> = encode_planar(resolution, FrameFormat::Nv12)
+            .into_iter()
+            .flat_map(|sample| (u16::from(sample) << 8).to_le_bytes())
+            .collect();
+
+        for frame_format in [FrameFormat::P010, FrameFormat::P016] {
+            let name = format!("{frame_format} {resolution}");
+            let buffer = FrameBuffer::new(resolution, &frame, frame_format);
+
+            let decoded = P010Decoder::<Rgb<u16>>::decode_static(&buffer).unwrap();
+            assert_close_16(
+                &format!("{name} -> RGB16"),
+                &decoded,
+                &rgb,
+                u16::from(TOLERANCE) * 257,
+            );
+
+            let decoded = P010Decoder::<Luma<u16>>::new().decode(&buffer).unwrap();
+            assert_close_16(&format!("{name} -> Luma16"), &decoded, &luma, 0);
+
+            let truncated = FrameBuffer::new(resolution, &frame[2..], frame_format);
+            assert!(P010Decoder::<Rgb<u16>>::decode_static(&truncated).is_err());
+        }
+    }
+}
+
+#[test]
+fn deep_luma_scales_to_16_bits() {
+    // A ramp over the whole sample range, with a width that does not fill the last packed group.
+    let resolution = Resolution::new(1023, 2);
+    let ramp: Vec<u16> = (0..2).flat_map(|_| 0..1023).collect();
+    let expected: Vec<u16> = ramp.iter().map(|v| (v << 6) | (v >> 4)).collect();
+
+    let luma10: Vec<u8> = ramp.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let buffer = FrameBuffer::new(resolution, &luma10, FrameFormat::Luma10);
+    let decoded = DeepLumaDecoder::<Luma<u16>>::decode_static(&buffer).unwrap();
+    assert_close_16("Luma10 -> Luma16", &decoded, &expected, 0);
+
+    let luma12: Vec<u8> = ramp.iter().flat_map(|v| (v << 2).to_le_bytes()).collect();
+    let buffer = FrameBuffer::new(resolution, &luma12, FrameFormat::Luma12);
+    let decoded = DeepLumaDecoder::<Luma<u16>>::decode_static(&buffer).unwrap();
+    assert_close_16("Luma12 -> Luma16", &decoded, &expected, 64);
+
+    let mut packed = vec![];
+    for row in ramp.chunks(1023) {
+        for group in row.chunks(4) {
+            let mut bytes = [0_u8; 5];
+            for (idx, v) in group.iter().enumerate() {
+                bytes[idx] = (v >> 2) as u8;
+                bytes[4] |= ((v & 0b11) as u8) << (idx * 2);
+            }
+            packed.extend_from_slice(&bytes);
+        }
+    }
+    let buffer = FrameBuffer::new(resolution, &packed, FrameFormat::Luma10Packed);
+    let decoded = DeepLumaDecoder::<Luma<u16>>::decode_static(&buffer).unwrap();
+    assert_close_16("Luma10Packed -> Luma16", &decoded, &expected, 0);
+
+    let decoded = DeepLumaDecoder::<Rgb<u16>>::new().decode(&buffer).unwrap();
+    let expected_rgb: Vec<u16> = expected.iter().flat_map(|v| [*v; 3]).collect();
+    assert_close_16("Luma10Packed -> RGB16", &decoded, &expected_rgb, 0);
+
+    let truncated = FrameBuffer::new(resolution, &packed[1..], FrameFormat::Luma10Packed);
+    assert!(DeepLumaDecoder::<Luma<u16>>::decode_static(&truncated).is_err());
+}
+
+#[cfg(feature = "decoding-mozjpeg")]
+#[test]
+fn mjpeg_decodes_and_recovers_truncated_frames() {
+    // Bars are 8 pixels wide so that JPEG blocks never straddle two of them.
+    let resolution = Resolution::new(64, 16);
+    let (rgb, _, _) = golden(resolution);
+    let mut jpeg = vec![];
+    JpegEncoder::new_with_quality(&mut jpeg, 100)
+        .encode(&rgb, 64, 16, ExtendedColorType::Rgb8)
+        .unwrap();
+
+    let mut decoder = MJpegDecoder::<Rgb<u8>>::new();
+    let buffer = FrameBuffer::new(resolution, &jpeg, FrameFormat::MJpeg);
+    let decoded = decoder.decode(&buffer).unwrap();
+    assert_close("MJpeg -> RGB", &decoded, &rgb, 8);
+    assert_eq!(decoder.last_frame_status(), MJpegFrameStatus::Complete);
+
+    // Zero padding after the end of image marker is not a truncation.
+    let padded = [jpeg.as_slice(), &[0; 32]].concat();
+    let buffer = FrameBuffer::new(resolution, &padded, FrameFormat::MJpeg);
+    decoder.decode(&buffer).unwrap();
+    assert_eq!(decoder.last_frame_status(), MJpegFrameStatus::Complete);
+
+    // Cut the frame half way through the entropy coded data, like a dropped USB transfer would.
+    let start_of_scan = jpeg.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+    let truncated = &jpeg[..start_of_scan + (jpeg.len() - start_of_scan) / 2];
+    let buffer = FrameBuffer::new(resolution, truncated, FrameFormat::MJpeg);
+    decoder.decode(&buffer).unwrap();
+    assert_eq!(decoder.last_frame_status(), MJpegFrameStatus::Recovered);
+    assert_eq!(decoder.recovered_frames(), 1);
+
+    let garbage = FrameBuffer::new(resolution, &[0xAB; 64], FrameFormat::MJpeg);
+    assert!(matches!(
+        decoder.decode(&garbage),
+        Err(NokhwaError::CorruptFrameError { .. })
+    ));
+}
+
+fn encode_bayer(resolution: Resolution, pattern: BayerPattern, wide: bool) -> Vec<u8> {
+    let width = resolution.width() as usize;
+    let mut frame = vec![];
+    for y in 0..resolution.height() as usize {
+        for x in 0..width {
+            let [r, g, b] = BARS[bar_at(x, width)].0;
+            let sample = match pattern.site_at(x, y) {
+                BayerSite::Red => r,
+                BayerSite::GreenRed | BayerSite::GreenBlue => g,
+                BayerSite::Blue => b,
+            };
+            if wide {
+                frame.extend_from_slice(&(u16::from(sample) * 257).to_le_bytes());
+            } else {
+                frame.push(sample);
+            }
+        }
+    }
+    frame
+}
+
+#[test]
+fn bayer_demosaics_the_bars() {
+    // Bars are 8 pixels wide. Both methods reach at most 2 pixels out, so only the columns at
+    // least that far from the next bar are guaranteed to be exact.
+    let resolution = Resolution::new(64, 6);
+    let (rgb, _, _) = golden(resolution);
+    let interior = |idx: &usize| (2..6).contains(&(idx / 3 % 8));
+
+    for pattern in BayerPattern::ALL.iter().copied() {
+        for (frame_format, wide) in [
+            (FrameFormat::Bayer8(pattern), false),
+            (FrameFormat::Bayer16(pattern), true),
+        ] {
+            let frame = encode_bayer(resolution, pattern, wide);
+            let buffer = FrameBuffer::new(resolution, &frame, frame_format);
+
+            for method in [DemosaicMethod::Bilinear, DemosaicMethod::MalvarHeCutler] {
+                let name = format!("{frame_format} {method:?}");
+
+                let decoded = BayerDecoder::<Rgb<u8>>::new(method)
+                    .decode(&buffer)
+                    .unwrap();
+                let (decoded, expected): (Vec<u8>, Vec<u8>) = decoded
+                    .iter()
+                    .zip(&rgb)
+                    .enumerate()
+                    .filter(|(idx, _)| interior(idx))
+                    .map(|(_, (d, e))| (*d, *e))
+                    .unzip();
+                assert_close(&format!("{name} -> RGB"), &decoded, &expected, 0);
+
+                let decoded = BayerDecoder::<Rgb<u16>>::new(method)
+                    .decode(&buffer)
+                    .unwrap();
+                let mismatch = decoded
+                    .iter()
+                    .zip(&rgb)
+                    .enumerate()
+                    .filter(|(idx, _)| interior(idx))
+                    .find(|(_, (d, e))| **d != u16::from(**e) * 257);
+                assert!(mismatch.is_none(), "{name} -> RGB16: {mismatch:?}");
+            }
+
+            let truncated = FrameBuffer::new(resolution, &frame[1..], frame_format);
+            assert!(BayerDecoder::<Rgb<u8>>::decode_static(&truncated).is_err());
+        }
+    }
+}
+
+/// Lays `frame` out again with `padding` bytes after every row of every plane, and `padding`
+/// bytes between planes.
+fn pad_rows(
+    frame: &[u8],
+    resolution: Resolution,
+    frame_format: FrameFormat,
+    padding: usize,
+) -> (Vec<u8>, Vec<FramePlane>) {
+    let tight = FrameBuffer::new(resolution, frame, frame_format);
+    let (mut padded, mut planes) = (vec![], vec![]);
+    for (idx, layout) in frame_format.descriptor().planes().iter().enumerate() {
+        padded.resize(padded.len() + padding, 0xEE);
+        planes.push(FramePlane::new(
+            padded.len(),
+            layout.row_size(resolution) + padding,
+        ));
+        for row in tight.plane_rows(idx) {
+            padded.extend_from_slice(row);
+            padded.resize(padded.len() + padding, 0xEE);
+        }
+    }
+    (padded, planes)
+}
+
+fn decode_rgb(buffer: &FrameBuffer) -> Vec<u8> {
+    match buffer.source_frame_format() {
+        FrameFormat::Yuyv422 => Yuv422Decoder::<Rgb<u8>>::decode_static(buffer)
+            .unwrap()
+            .into_raw(),
+        FrameFormat::P010 => P010Decoder::<Rgb<u16>>::decode_static(buffer)
+            .unwrap()
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect(),
+        FrameFormat::Luma10Packed => DeepLumaDecoder::<Rgb<u16>>::decode_static(buffer)
+            .unwrap()
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect(),
+        FrameFormat::Bayer8(_) => BayerDecoder::<Rgb<u8>>::decode_static(buffer)
+            .unwrap()
+            .into_raw(),
+        _ => PlanarYuvDecoder::<Rgb<u8>>::decode_static(buffer)
+            .unwrap()
+            .into_raw(),
+    }
+}
+
+#[test]
+fn padded_rows_decode_like_tight_ones() {
+    let resolution = Resolution::new(15, 5);
+    let p010: Vec<u8> = encode_planar(resolution, FrameFormat::Nv12)
+        .into_iter()
+        .flat_map(|sample| (u16::from(sample) << 8).to_le_bytes())
+        .collect();
+    let packed = (0..resolution.height() * 20)
+        .map(|v| (v * 7) as u8)
+        .collect::<Vec<_>>();
+    let frames = [
+        (
+            FrameFormat::Yuyv422,
+            encode_packed_422(resolution, FrameFormat::Yuyv422),
+        ),
+        (
+            FrameFormat::Nv12,
+            encode_planar(resolution, FrameFormat::Nv12),
+        ),
+        (
+            FrameFormat::I420,
+            encode_planar(resolution, FrameFormat::I420),
+        ),
+        (
+            FrameFormat::Yvu9,
+            encode_planar(resolution, FrameFormat::Yvu9),
+        ),
+        (FrameFormat::P010, p010),
+        (FrameFormat::Luma10Packed, packed),
+        (
+            FrameFormat::Bayer8(BayerPattern::Rggb),
+            encode_bayer(resolution, BayerPattern::Rggb, false),
+        ),
+    ];
+
+    for (frame_format, frame) in frames {
+        let expected = decode_rgb(&FrameBuffer::new(resolution, &frame, frame_format));
+        let (padded, planes) = pad_rows(&frame, resolution, frame_format, 9);
+        let buffer = FrameBuffer::with_planes(resolution, &padded, frame_format, planes.clone());
+        assert_eq!(decode_rgb(&buffer), expected, "{frame_format}: padded rows");
+
+        let truncated = FrameBuffer::with_planes(
+            resolution,
+            &padded[..padded.len() - 10],
+            frame_format,
+            planes,
+        );
+        assert!(truncated.check_planes().is_err());
+    }
+
+    // V4L2 single-planar NV12 with `bytesperline` 32: both planes share the stride.
+    let resolution = Resolution::new(16, 4);
+    let frame = encode_planar(resolution, FrameFormat::Nv12);
+    let mut padded = vec![];
+    for row in frame.chunks(16) {
+        padded.extend_from_slice(row);
+        padded.extend_from_slice(&[0xEE; 16]);
+    }
+    let buffer = FrameBuffer::with_strides(resolution, &padded, FrameFormat::Nv12, &[32]);
+    let expected = decode_rgb(&FrameBuffer::new(resolution, &frame, FrameFormat::Nv12));
+    assert_eq!(decode_rgb(&buffer), expected, "Nv12: bytesperline");
+}
+
+#[test]
+fn encoder_produces_the_bars() {
+    for (rgb, yuv) in BARS {
+        assert_close(
+            &format!("{rgb:?} -> YCbCr"),
+            &rgb888_to_yuv444(rgb[0], rgb[1], rgb[2]),
+            &yuv,
+            1,
+        );
+    }
+
+    // Bars are 2 pixels wide, so every chroma sample covers a single bar.
+    let resolution = Resolution::new(16, 2);
+    let (rgb, rgba, luma) = golden(resolution);
+    let mut frames = vec![(FrameFormat::RgbA8888, rgba), (FrameFormat::Luma8, luma)];
+    for frame_format in [
+        FrameFormat::Yuyv422,
+        FrameFormat::Uyvy422,
+        FrameFormat::Yvyu422,
+    ] {
+        frames.push((frame_format, encode_packed_422(resolution, frame_format)));
+    }
+    for frame_format in [
+        FrameFormat::Nv12,
+        FrameFormat::Nv21,
+        FrameFormat::I420,
+        FrameFormat::Yv12,
+    ] {
+        frames.push((frame_format, encode_planar(resolution, frame_format)));
+    }
+    for pattern in BayerPattern::ALL {
+        frames.push((
+            FrameFormat::Bayer8(*pattern),
+            encode_bayer(resolution, *pattern, false),
+        ));
+        frames.push((
+            FrameFormat::Bayer16(*pattern),
+            encode_bayer(resolution, *pattern, true),
+        ));
+    }
+
+    for (frame_format, expected) in frames {
+        let encoded = encode_rgb888(resolution, &rgb, frame_format).unwrap();
+        assert!(encoded.check_planes().is_ok());
+        assert_close(
+            &format!("RGB -> {frame_format}"),
+            encoded.buffer(),
+            &expected,
+            1,
+        );
+    }
+
+    assert!(encode_rgb888(resolution, &rgb[1..], FrameFormat::Nv12).is_err());
+    assert!(encode_rgb888(resolution, &rgb, FrameFormat::MJpeg).is_err());
+}
+
+#[test]
+fn y4m_round_trips_the_bars() {
+    // Odd sizes, so that the last chroma samples cover a single column or row.
+    let resolution = Resolution::new(15, 3);
+    let (rgb, _, _) = golden(resolution);
+    let frame_rate = FrameRate::new(30000, NonZeroI32::new(1001).unwrap());
+    let frames = [
+        (FrameFormat::I420, FrameFormat::I420),
+        (FrameFormat::Yv12, FrameFormat::I420),
+        (FrameFormat::Nv12, FrameFormat::I420),
+        (FrameFormat::Nv21, FrameFormat::I420),
+        (FrameFormat::Yuyv422, FrameFormat::Yuyv422),
+        (FrameFormat::Uyvy422, FrameFormat::Yuyv422),
+        (FrameFormat::Yvyu422, FrameFormat::Yuyv422),
+        (FrameFormat::Luma8, FrameFormat::Luma8),
+    ];
+
+    for (frame_format, read_format) in frames {
+        let format = CameraFormat::new(resolution, frame_format, frame_rate);
+        let frame = encode_rgb888(resolution, &rgb, frame_format).unwrap();
+        let (padded, planes) = pad_rows(frame.buffer(), resolution, frame_format, 3);
+        let padded = FrameBuffer::with_planes(resolution, &padded, frame_format, planes);
+
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame(&padded).unwrap();
+        let file = writer.into_inner();
+
+        let mut reader = Y4mReader::new(file.as_slice()).unwrap();
+        assert_eq!(
+            reader.format(),
+            CameraFormat::new(resolution, read_format, frame_rate)
+        );
+        assert_eq!(
+            Some(reader.colorspace()),
+            Y4mColorspace::for_frame_format(frame_format)
+        );
+        let expected = encode_rgb888(resolution, &rgb, read_format).unwrap();
+        for _ in 0..2 {
+            let read = reader.read_frame().unwrap().unwrap();
+            assert_eq!(read.buffer(), expected.buffer(), "{frame_format} -> Y4M");
+        }
+        assert!(reader.read_frame().unwrap().is_none());
+    }
+
+    let format = CameraFormat::new(resolution, FrameFormat::I420, frame_rate);
+    let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+    let wrong = encode_rgb888(resolution, &rgb, FrameFormat::Nv12).unwrap();
+    assert!(writer.write_frame(&wrong).is_err());
+    let mjpeg = CameraFormat::new(resolution, FrameFormat::MJpeg, frame_rate);
+    assert!(Y4mWriter::new(Vec::new(), mjpeg).is_err());
+
+    let mut truncated = Y4mWriter::new(Vec::new(), format).unwrap();
+    truncated
+        .write_frame(&encode_rgb888(resolution, &rgb, FrameFormat::I420).unwrap())
+        .unwrap();
+    let mut file = truncated.into_inner();
+    file.pop();
+    let mut reader = Y4mReader::new(file.as_slice()).unwrap();
+    assert!(reader.read_frame().is_err());
+}
@@ -0,0 +1,97 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::reader::{Chunked, Multipart};
+use crate::backends::capture::network::{
+    connect, read_head, Authenticator, Buffered, Head, Interrupt, Url,
+};
+use std::{io::Read, io::Write, time::Duration};
+
+/// A `200 OK` response carrying multipart data.
+pub(super) type Parts = Multipart<Box<dyn Read + Send>>;
+
+/// Requests `url` and returns its multipart body, answering an authentication challenge once.
+pub(super) fn get(
+    url: &Url,
+    authenticator: &mut Authenticator,
+    timeout: Duration,
+    interrupt: &Interrupt,
+) -> Result<Parts, String> {
+    let mut challenged = false;
+    loop {
+        let socket = connect(url, timeout, interrupt)?;
+
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: nokhwa/{}\r\n\
+             Accept: multipart/x-mixed-replace\r\nConnection: close\r\n",
+            url.target(),
+            url.authority(),
+            env!("CARGO_PKG_VERSION"),
+        );
+        if let Some(authorization) = authenticator.authorization("GET", url.target()) {
+            request.push_str("Authorization: ");
+            request.push_str(&authorization);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        (&socket)
+            .write_all(request.as_bytes())
+            .map_err(|why| why.to_string())?;
+
+        let mut reader = Buffered::new(socket);
+        let head: Head = read_head(&mut reader, "HTTP").map_err(|why| why.to_string())?;
+        match head.status {
+            200 => {}
+            401 if !challenged && authenticator.challenged(&head.headers) => {
+                challenged = true;
+                continue;
+            }
+            401 if !authenticator.has_credentials() => {
+                return Err("401 Unauthorized, the camera needs a user name and password".into())
+            }
+            status => return Err(format!("{status} {}", head.reason)),
+        }
+
+        let content_type = head.header("content-type").unwrap_or_default();
+        let boundary = multipart_boundary(content_type)
+            .ok_or_else(|| format!("expected a multipart response, got {content_type:?}"))?;
+        let chunked = head
+            .header("transfer-encoding")
+            .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+        let body: Box<dyn Read + Send> = if chunked {
+            Box::new(Chunked::new(reader))
+        } else {
+            Box::new(reader)
+        };
+        return Ok(Multipart::new(body, &boundary));
+    }
+}
+
+/// The boundary of a `multipart/...; boundary=...` content type.
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.to_ascii_lowercase().starts_with("multipart/") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|boundary| !boundary.is_empty())
+    })
+}
@@ -0,0 +1,649 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A camera that reads MJPEG streamed over HTTP, as served by most IP cameras, without `OpenCV`.
+//!
+//! The camera requests an `http://` URL and reads the JPEG images of the
+//! `multipart/x-mixed-replace` response, with or without a `Content-Length` for each part and
+//! with or without chunked transfer encoding. Cameras asking for basic or digest authentication
+//! are answered with the user name and password of the URL, or those given to
+//! [`HttpCamera::with_credentials`].
+
+mod client;
+mod reader;
+
+use crate::backends::capture::{
+    jpeg::scan_jpeg,
+    network::{reconnect, Authenticator, Credentials, Interrupt, NetworkStream, Player, Url},
+};
+use client::Parts;
+use nokhwa_core::{
+    camera::{Camera, Capture, Setting},
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    platform::{Backends, PlatformTrait},
+    properties::{ControlId, ControlValue, Properties},
+    stream::{
+        events::{StopReason, StreamEvent},
+        frame_channel, BackpressurePolicy, FrameSender, Stream,
+    },
+    supervisor::BackoffPolicy,
+    types::{CameraFormat, CameraIndex, CameraInformation, FrameRate, Resolution},
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The frame rate reported for streams, which do not announce one.
+const DEFAULT_FRAME_RATE: FrameRate = FrameRate::frame_rate(30);
+
+/// How long connecting, and waiting for data once connected, may take by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A camera streaming MJPEG over HTTP, see the [module documentation](self).
+///
+/// The camera is asked for its first image when it is opened, to learn its resolution. Its only
+/// format is that resolution in [`FrameFormat::MJpeg`], at a nominal 30 frames per second: the
+/// camera decides how fast frames arrive. If the camera changes its resolution mid-stream, the
+/// stream reports [`StreamEvent::FormatChanged`].
+///
+/// When the connection is lost, the stream reports [`StreamEvent::DeviceDisconnected`] and
+/// reconnects following its [`BackoffPolicy`], reporting [`StreamEvent::Started`] once it is
+/// back. If the policy gives up, the stream stops with the last error.
+pub struct HttpCamera {
+    info: CameraInformation,
+    url: Url,
+    /// Answers the challenge the camera made when it was opened, so streams authenticate right
+    /// away.
+    authenticator: Authenticator,
+    format: CameraFormat,
+    timeout: Duration,
+    backoff: BackoffPolicy,
+    properties: Properties,
+    player: Option<Player>,
+}
+
+impl HttpCamera {
+    /// Opens the camera at `url`, with the URL as its [`CameraIndex`]. A user name and password
+    /// in the URL are used to authenticate.
+    ///
+    /// # Errors
+    /// If the URL is not an `http://` URL, or the camera does not answer with an MJPEG stream.
+    pub fn new(url: &str) -> NokhwaResult<Self> {
+        Self::with_index(CameraIndex::String(url.to_string()), url)
+    }
+
+    /// Opens the camera at `url` as the camera at `index`.
+    ///
+    /// # Errors
+    /// If the URL is not an `http://` URL, or the camera does not answer with an MJPEG stream.
+    pub fn with_index(index: CameraIndex, url: &str) -> NokhwaResult<Self> {
+        let parsed = Url::parse(url, "http", 80)
+            .map_err(|why| NokhwaError::OpenDeviceError(url.to_string(), why))?;
+        let credentials = parsed.credentials().cloned();
+        Self::open(index, url, parsed, credentials)
+    }
+
+    /// Opens the camera at `url`, authenticating as `username` with `password` rather than with
+    /// what the URL holds.
+    ///
+    /// # Errors
+    /// If the URL is not an `http://` URL, or the camera does not answer with an MJPEG stream.
+    pub fn with_credentials(url: &str, username: &str, password: &str) -> NokhwaResult<Self> {
+        let parsed = Url::parse(url, "http", 80)
+            .map_err(|why| NokhwaError::OpenDeviceError(url.to_string(), why))?;
+        let credentials = Credentials::new(username.to_string(), password.to_string());
+        Self::open(
+            CameraIndex::String(url.to_string()),
+            url,
+            parsed,
+            Some(credentials),
+        )
+    }
+
+    fn open(
+        index: CameraIndex,
+        url: &str,
+        parsed: Url,
+        credentials: Option<Credentials>,
+    ) -> NokhwaResult<Self> {
+        let error = |why: String| NokhwaError::OpenDeviceError(url.to_string(), why);
+
+        let mut authenticator = Authenticator::new(credentials);
+        let mut parts = client::get(
+            &parsed,
+            &mut authenticator,
+            DEFAULT_TIMEOUT,
+            &Interrupt::default(),
+        )
+        .map_err(error)?;
+        let resolution = loop {
+            let part = parts
+                .next_part()
+                .map_err(|why| error(why.to_string()))?
+                .ok_or_else(|| error("camera sent no image".to_string()))?;
+            if let Some(resolution) = scan_jpeg(&part).and_then(|jpeg| jpeg.resolution) {
+                break resolution;
+            }
+        };
+
+        Ok(Self {
+            info: CameraInformation::new(
+                url.to_string(),
+                "MJPEG over HTTP".to_string(),
+                url.to_string(),
+                index,
+            ),
+            url: parsed,
+            authenticator,
+            format: CameraFormat::new(resolution, FrameFormat::MJpeg, DEFAULT_FRAME_RATE),
+            timeout: DEFAULT_TIMEOUT,
+            backoff: BackoffPolicy::default(),
+            properties: Properties::new(HashMap::new()),
+            player: None,
+        })
+    }
+
+    /// The [`CameraInformation`] of this camera.
+    #[must_use]
+    pub fn info(&self) -> &CameraInformation {
+        &self.info
+    }
+
+    /// The format of the first image the camera sent.
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    /// How long connecting, and waiting for data once connected, may take before the connection
+    /// is considered lost. Applies to streams opened afterwards.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// How a lost connection is retried. Applies to streams opened afterwards.
+    pub fn set_backoff(&mut self, backoff: BackoffPolicy) {
+        self.backoff = backoff;
+    }
+}
+
+impl Setting for HttpCamera {
+    fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError> {
+        Ok(vec![self.format])
+    }
+
+    fn enumerate_resolution_and_frame_rates(
+        &self,
+        frame_format: FrameFormat,
+    ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError> {
+        if frame_format != FrameFormat::MJpeg {
+            return Ok(HashMap::new());
+        }
+        Ok(HashMap::from([(
+            self.format.resolution(),
+            vec![self.format.frame_rate()],
+        )]))
+    }
+
+    /// Accepts the format of the camera at any frame rate, which the camera ignores.
+    ///
+    /// # Errors
+    /// If the resolution or [`FrameFormat`] are not those of the camera.
+    fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError> {
+        if camera_format.resolution() == self.format.resolution()
+            && camera_format.format() == self.format.format()
+        {
+            Ok(())
+        } else {
+            Err(NokhwaError::SetPropertyError {
+                property: "format".to_string(),
+                value: camera_format.to_string(),
+                error: "network cameras stream in the format they are configured with".to_string(),
+            })
+        }
+    }
+
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn set_property(
+        &mut self,
+        property: &ControlId,
+        value: ControlValue,
+    ) -> Result<(), NokhwaError> {
+        Err(NokhwaError::SetPropertyError {
+            property: property.to_string(),
+            value: value.to_string(),
+            error: "network cameras have no controls".to_string(),
+        })
+    }
+}
+
+impl Capture for HttpCamera {
+    fn open_stream(&mut self) -> Result<Stream, NokhwaError> {
+        self.open_stream_with_policy(BackpressurePolicy::default())
+    }
+
+    /// Connects to the camera and starts receiving frames.
+    ///
+    /// # Errors
+    /// If a stream is already open, or the camera cannot be reached. Connections lost later are
+    /// retried on the stream.
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        if self.player.as_ref().is_some_and(Player::is_running) {
+            return Err(NokhwaError::OpenStreamError(
+                "a stream is already open".to_string(),
+            ));
+        }
+
+        let mut connection = Connection {
+            url: self.url.clone(),
+            authenticator: self.authenticator.clone(),
+            timeout: self.timeout,
+            interrupt: Arc::new(Interrupt::default()),
+        };
+        let parts = connection.open().map_err(NokhwaError::OpenStreamError)?;
+
+        let (sender, receiver) = frame_channel(policy);
+        let (format, backoff) = (self.format, self.backoff);
+        let interrupt = connection.interrupt.clone();
+        let (player, handle) = Player::spawn("nokhwa-http", interrupt, move |running| {
+            let receiving = Receiving {
+                frames: sender,
+                connection,
+                format,
+                backoff,
+                running,
+            };
+            receiving.run(parts);
+        })?;
+
+        self.player = Some(player.clone());
+        Ok(Stream::new(Box::new(NetworkStream::new(
+            receiver, player, handle,
+        ))))
+    }
+
+    fn close_stream(&mut self) -> Result<(), NokhwaError> {
+        if let Some(player) = self.player.take() {
+            player.stop();
+        }
+        Ok(())
+    }
+}
+
+impl Camera for HttpCamera {}
+
+impl Drop for HttpCamera {
+    fn drop(&mut self) {
+        let _ = self.close_stream();
+    }
+}
+
+/// A [`PlatformTrait`] that lists network cameras as [`HttpCamera`]s.
+///
+/// The cameras are at indices `0..` in the order their URLs were given. Querying does not
+/// contact them. A [`CameraIndex::String`] opens the camera at that URL, whether it is listed or
+/// not.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct HttpPlatform {
+    urls: Vec<String>,
+}
+
+impl HttpPlatform {
+    /// Creates a platform listing the cameras at `urls`.
+    #[must_use]
+    pub fn new(urls: Vec<String>) -> Self {
+        Self { urls }
+    }
+
+    /// Adds the camera at `url` to the end of the list.
+    pub fn add(&mut self, url: impl Into<String>) {
+        self.urls.push(url.into());
+    }
+}
+
+impl PlatformTrait for HttpPlatform {
+    const PLATFORM: Backends = Backends::Custom("http");
+    type Camera = HttpCamera;
+
+    fn block_on_permission(&mut self) -> NokhwaResult<()> {
+        Ok(())
+    }
+
+    fn check_permission_given(&mut self) -> bool {
+        true
+    }
+
+    fn query(&mut self) -> NokhwaResult<Vec<CameraInformation>> {
+        Ok(self
+            .urls
+            .iter()
+            .zip(0..)
+            .map(|(url, index)| {
+                CameraInformation::new(
+                    url.clone(),
+                    "MJPEG over HTTP".to_string(),
+                    url.clone(),
+                    CameraIndex::Index(index),
+                )
+            })
+            .collect())
+    }
+
+    fn open(&mut self, index: &CameraIndex) -> NokhwaResult<Self::Camera> {
+        match index {
+            CameraIndex::Index(number) => {
+                let url = usize::try_from(*number)
+                    .ok()
+                    .and_then(|number| self.urls.get(number))
+                    .ok_or_else(|| {
+                        NokhwaError::OpenDeviceError(
+                            index.to_string(),
+                            "no such camera".to_string(),
+                        )
+                    })?;
+                HttpCamera::with_index(index.clone(), url)
+            }
+            CameraIndex::String(url) => HttpCamera::new(url),
+        }
+    }
+}
+
+/// What is needed to connect to the camera again.
+struct Connection {
+    url: Url,
+    authenticator: Authenticator,
+    timeout: Duration,
+    interrupt: Arc<Interrupt>,
+}
+
+impl Connection {
+    fn open(&mut self) -> Result<Parts, String> {
+        client::get(
+            &self.url,
+            &mut self.authenticator,
+            self.timeout,
+            &self.interrupt,
+        )
+    }
+}
+
+/// The receiving thread of one stream.
+struct Receiving {
+    frames: FrameSender,
+    connection: Connection,
+    format: CameraFormat,
+    backoff: BackoffPolicy,
+    running: Arc<AtomicBool>,
+}
+
+impl Receiving {
+    fn run(mut self, mut parts: Parts) {
+        self.frames.emit(StreamEvent::Started(self.format));
+        loop {
+            self.receive(&mut parts);
+            if !self.is_running() {
+                break;
+            }
+            self.frames.emit(StreamEvent::DeviceDisconnected);
+            match self.reconnect() {
+                Ok(reconnected) => parts = reconnected,
+                Err(reason) => {
+                    self.frames.stop(reason);
+                    return;
+                }
+            }
+            self.frames.emit(StreamEvent::Started(self.format));
+        }
+        self.frames.stop(StopReason::Requested);
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire) && !self.frames.is_disconnected()
+    }
+
+    /// Delivers frames until the connection is lost or the stream is stopped.
+    fn receive(&mut self, parts: &mut Parts) {
+        while self.is_running() {
+            // The camera ending the stream is treated like losing the connection.
+            let Ok(Some(mut data)) = parts.next_part() else {
+                return;
+            };
+            // Parts that are not a whole JPEG image, e.g. cut short by the camera, are skipped.
+            let Some(jpeg) = scan_jpeg(&data) else {
+                continue;
+            };
+            data.truncate(jpeg.length);
+
+            let resolution = jpeg.resolution.unwrap_or(self.format.resolution());
+            if resolution != self.format.resolution() {
+                self.format =
+                    CameraFormat::new(resolution, FrameFormat::MJpeg, self.format.frame_rate());
+                self.frames.emit(StreamEvent::FormatChanged(self.format));
+            }
+            if self
+                .frames
+                .send(FrameBuffer::from_vec(resolution, data, FrameFormat::MJpeg))
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Connects again following the backoff policy, or returns why the stream stops.
+    fn reconnect(&mut self) -> Result<Parts, StopReason> {
+        let (running, frames) = (&self.running, &self.frames);
+        let connection = &mut self.connection;
+        reconnect(
+            &self.backoff,
+            || running.load(Ordering::Acquire) && !frames.is_disconnected(),
+            || connection.open(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        time::Instant,
+    };
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// The smallest JPEG [`scan_jpeg`] accepts: a start of frame segment between the start and
+    /// end of image.
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 0x08];
+        jpeg.extend(height.to_be_bytes());
+        jpeg.extend(width.to_be_bytes());
+        jpeg.extend([0x01, 0x01, 0x11, 0x00, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    /// Writes `data` as a part, with a `Content-Length` or delimited by the next boundary only.
+    fn write_part(socket: &mut TcpStream, data: &[u8], with_length: bool) -> std::io::Result<()> {
+        let mut part = b"--frame\r\nContent-Type: image/jpeg\r\n".to_vec();
+        if with_length {
+            part.extend(format!("Content-Length: {}\r\n", data.len()).bytes());
+        }
+        part.extend(b"\r\n");
+        part.extend(data);
+        part.extend(b"\r\n");
+        socket.write_all(&part)
+    }
+
+    /// Streams images of `width` by `height` until the camera hangs up, alternating between
+    /// parts with and without a `Content-Length`.
+    fn stream_frames(socket: &mut TcpStream, width: u16, height: u16) {
+        let image = jpeg(width, height);
+        for number in 0.. {
+            if write_part(socket, &image, number % 2 == 0).is_err() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// A stand-in MJPEG camera, serving each connection with `serve` given how many came before
+    /// it. Returns the URL of the stream.
+    fn camera_server(serve: impl Fn(usize, &mut TcpStream) + Send + Sync + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let serve = Arc::new(serve);
+        std::thread::spawn(move || {
+            for (number, socket) in listener.incoming().enumerate() {
+                let Ok(mut socket) = socket else {
+                    return;
+                };
+                let serve = serve.clone();
+                std::thread::spawn(move || {
+                    let mut head = Vec::new();
+                    let mut byte = [0];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        match socket.read(&mut byte) {
+                            Ok(1) => head.push(byte[0]),
+                            _ => return,
+                        }
+                    }
+                    let response = b"HTTP/1.1 200 OK\r\n\
+                        Content-Type: multipart/x-mixed-replace; boundary=frame\r\n\r\n";
+                    if socket.write_all(response).is_ok() {
+                        serve(number, &mut socket);
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    fn wait_for(stream: &Stream, expected: fn(&StreamEvent) -> bool) {
+        let events = stream.events();
+        loop {
+            let event = events.recv_timeout(WAIT).expect("event was not reported");
+            if expected(&event) {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn receives_frames_from_a_multipart_stream() {
+        let url = camera_server(|_, socket| stream_frames(socket, 16, 8));
+        let mut camera = HttpCamera::new(&url).unwrap();
+        assert_eq!(camera.format().resolution(), Resolution::new(16, 8));
+
+        let stream = camera.open_stream().unwrap();
+        for _ in 0..4 {
+            let frame = stream.poll_frame_timeout(WAIT).unwrap();
+            assert_eq!(frame.resolution(), Resolution::new(16, 8));
+            assert_eq!(frame.buffer(), jpeg(16, 8).as_slice());
+        }
+        stream.stop_stream().unwrap();
+    }
+
+    #[test]
+    fn reconnects_when_the_camera_hangs_up() {
+        let url = camera_server(|number, socket| match number {
+            // Opening the camera, then a stream that ends after two images.
+            0 => stream_frames(socket, 16, 8),
+            1 => {
+                let _ = write_part(socket, &jpeg(16, 8), true);
+                let _ = write_part(socket, &jpeg(16, 8), true);
+            }
+            _ => stream_frames(socket, 32, 8),
+        });
+        let mut camera = HttpCamera::new(&url).unwrap();
+        camera.set_backoff(BackoffPolicy::fixed(Duration::from_millis(10)));
+        let stream = camera.open_stream().unwrap();
+
+        wait_for(&stream, |event| {
+            matches!(event, StreamEvent::DeviceDisconnected)
+        });
+        wait_for(&stream, |event| matches!(event, StreamEvent::Started(_)));
+        let frame = loop {
+            let frame = stream.poll_frame_timeout(WAIT).unwrap();
+            if frame.resolution() != Resolution::new(16, 8) {
+                break frame;
+            }
+        };
+        assert_eq!(frame.resolution(), Resolution::new(32, 8));
+        stream.stop_stream().unwrap();
+    }
+
+    #[test]
+    fn drops_the_connection_on_an_oversized_part() {
+        let url = camera_server(|number, socket| match number {
+            0 => stream_frames(socket, 16, 8),
+            // An image, then a part too large to read, after which the camera stalls.
+            1 => {
+                let _ = write_part(socket, &jpeg(16, 8), true);
+                let _ = socket.write_all(b"--frame\r\nContent-Length: 1000000000\r\n\r\n");
+                std::thread::sleep(WAIT);
+            }
+            _ => stream_frames(socket, 32, 8),
+        });
+        let mut camera = HttpCamera::new(&url).unwrap();
+        camera.set_backoff(BackoffPolicy::fixed(Duration::from_millis(10)));
+        let stream = camera.open_stream().unwrap();
+
+        let frame = stream.poll_frame_timeout(WAIT).unwrap();
+        assert_eq!(frame.resolution(), Resolution::new(16, 8));
+        // Without waiting for the stalled camera to time out.
+        let start = Instant::now();
+        wait_for(&stream, |event| {
+            matches!(event, StreamEvent::DeviceDisconnected)
+        });
+        assert!(start.elapsed() < Duration::from_secs(2));
+        let frame = stream.poll_frame_timeout(WAIT).unwrap();
+        assert_eq!(frame.resolution(), Resolution::new(32, 8));
+        stream.stop_stream().unwrap();
+    }
+
+    #[test]
+    fn stopping_interrupts_a_stalled_connection() {
+        let url = camera_server(|number, socket| {
+            if number == 0 {
+                stream_frames(socket, 16, 8);
+            } else {
+                let _ = write_part(socket, &jpeg(16, 8), true);
+                std::thread::sleep(WAIT * 2);
+            }
+        });
+        let mut camera = HttpCamera::new(&url).unwrap();
+        let stream = camera.open_stream().unwrap();
+        stream.poll_frame_timeout(WAIT).unwrap();
+
+        let start = Instant::now();
+        stream.stop_stream().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
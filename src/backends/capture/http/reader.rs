@@ -0,0 +1,131 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::backends::capture::network::Buffered;
+use std::io::{Error, ErrorKind, Read, Result};
+
+/// The body of a response sent with `Transfer-Encoding: chunked`.
+pub(super) struct Chunked<R> {
+    reader: Buffered<R>,
+    /// What is left of the current chunk, `None` after the last one.
+    remaining: Option<usize>,
+}
+
+impl<R: Read> Chunked<R> {
+    pub(super) fn new(reader: Buffered<R>) -> Self {
+        Self {
+            reader,
+            remaining: Some(0),
+        }
+    }
+}
+
+impl<R: Read> Read for Chunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let remaining = match self.remaining {
+            None => return Ok(0),
+            Some(0) => {
+                let line = self.reader.read_line()?;
+                // Chunk extensions after a `;` are ignored.
+                let size = line.split(';').next().unwrap_or_default().trim();
+                let size = usize::from_str_radix(size, 16).map_err(|_| {
+                    Error::new(ErrorKind::InvalidData, format!("bad chunk size {size:?}"))
+                })?;
+                if size == 0 {
+                    self.remaining = None;
+                    return Ok(0);
+                }
+                size
+            }
+            Some(remaining) => remaining,
+        };
+
+        let length = buf.len().min(remaining);
+        let read = self.reader.read(&mut buf[..length])?;
+        if read == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining = Some(remaining - read);
+        if remaining == read {
+            // Every chunk ends with a line ending.
+            self.reader.read_line()?;
+        }
+        Ok(read)
+    }
+}
+
+/// Reads the parts of a `multipart/x-mixed-replace` body.
+pub(super) struct Multipart<R> {
+    reader: Buffered<R>,
+    /// What a line starting a part may start with: `--` and the boundary, and for a boundary
+    /// that starts with `--` the boundary itself, since many cameras announce it with the `--`
+    /// they send it with.
+    delimiters: Vec<Vec<u8>>,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Reads the parts of `reader`, separated by `boundary`.
+    pub(super) fn new(reader: R, boundary: &str) -> Self {
+        let mut delimiters = vec![format!("--{boundary}").into_bytes()];
+        if boundary.starts_with("--") {
+            delimiters.push(boundary.as_bytes().to_vec());
+        }
+        Self {
+            reader: Buffered::new(reader),
+            delimiters,
+        }
+    }
+
+    /// Reads the data of the next part, or `None` after the last one.
+    pub(super) fn next_part(&mut self) -> Result<Option<Vec<u8>>> {
+        // Anything before the delimiter, like a preamble or the end of a part that was longer
+        // than its `Content-Length`, is skipped.
+        loop {
+            let line = self.reader.read_line_bytes()?;
+            let matched = self
+                .delimiters
+                .iter()
+                .position(|delimiter| line.starts_with(delimiter));
+            if let Some(matched) = matched {
+                if line[self.delimiters[matched].len()..].starts_with(b"--") {
+                    return Ok(None);
+                }
+                // Once seen, only the delimiter the camera actually sends is looked for.
+                let delimiter = self.delimiters.swap_remove(matched);
+                self.delimiters = vec![delimiter];
+                break;
+            }
+        }
+
+        let mut content_length = None;
+        loop {
+            let line = self.reader.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        match content_length {
+            Some(length) => self.reader.read_vec(length).map(Some),
+            None => self.reader.read_until_line(&self.delimiters[0]).map(Some),
+        }
+    }
+}
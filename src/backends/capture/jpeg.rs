@@ -0,0 +1,93 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Finding JPEG images in a byte stream, for the backends that receive MJPEG as files or over
+//! the network.
+
+use nokhwa_core::types::Resolution;
+
+/// What scanning the segments of a JPEG image found.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct JpegInfo {
+    /// The length of the image, up to and including its end of image marker.
+    pub(super) length: usize,
+    /// The size from the start of frame segment.
+    pub(super) resolution: Option<Resolution>,
+}
+
+/// Scans the JPEG image at the start of `data`, or `None` if it does not start with one or is
+/// truncated.
+///
+/// Segments are skipped by their length, so thumbnails embedded in EXIF data are not mistaken
+/// for the end of the image.
+pub(super) fn scan_jpeg(data: &[u8]) -> Option<JpegInfo> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut resolution = None;
+    let mut position = 2;
+    loop {
+        // Markers may be preceded by any number of fill bytes.
+        while *data.get(position)? == 0xFF && *data.get(position + 1)? == 0xFF {
+            position += 1;
+        }
+        if *data.get(position)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(position + 1)?;
+        position += 2;
+
+        match marker {
+            0xD9 => {
+                return Some(JpegInfo {
+                    length: position,
+                    resolution,
+                })
+            }
+            // Markers without a segment.
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+
+        let length = usize::from(u16::from_be_bytes([
+            *data.get(position)?,
+            *data.get(position + 1)?,
+        ]));
+        let segment = data.get(position..position + length)?;
+        // Start of frame, except DHT, JPG and DAC which share the range.
+        if matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let height = u16::from_be_bytes([*segment.get(3)?, *segment.get(4)?]);
+            let width = u16::from_be_bytes([*segment.get(5)?, *segment.get(6)?]);
+            resolution = Some(Resolution::new(u32::from(width), u32::from(height)));
+        }
+        position += length;
+
+        if marker == 0xDA {
+            // Entropy coded data runs until a marker that is neither stuffing nor a restart.
+            loop {
+                if *data.get(position)? == 0xFF {
+                    match *data.get(position + 1)? {
+                        0x00 | 0xD0..=0xD7 => position += 2,
+                        _ => break,
+                    }
+                } else {
+                    position += 1;
+                }
+            }
+        }
+    }
+}
@@ -170,3 +170,31 @@ mod v4l2_backend;
 #[cfg(feature = "input-opencv")]
 #[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-opencv")))]
 pub use opencv_backend::OpenCvCaptureDevice;
+#[cfg(feature = "input-synthetic")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-synthetic")))]
+pub mod synthetic;
+#[cfg(feature = "input-synthetic")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-synthetic")))]
+pub use synthetic::{SyntheticCamera, SyntheticPattern, SyntheticPlatform};
+#[cfg(feature = "input-playback")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-playback")))]
+pub mod playback;
+#[cfg(feature = "input-playback")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-playback")))]
+pub use playback::{PlaybackCamera, PlaybackPlatform};
+#[cfg(feature = "input-http")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-http")))]
+pub mod http;
+#[cfg(feature = "input-http")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-http")))]
+pub use http::{HttpCamera, HttpPlatform};
+#[cfg(feature = "input-rtsp")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-rtsp")))]
+pub mod rtsp;
+#[cfg(feature = "input-rtsp")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-rtsp")))]
+pub use rtsp::{RtspCamera, RtspPlatform, RtspTransport};
+#[cfg(any(feature = "input-playback", feature = "input-http"))]
+mod jpeg;
+#[cfg(any(feature = "input-http", feature = "input-rtsp"))]
+mod network;
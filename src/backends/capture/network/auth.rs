@@ -0,0 +1,216 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::Credentials;
+use base64::Engine;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+
+/// How the server asked to be authenticated.
+#[derive(Clone, Debug)]
+enum Challenge {
+    Basic,
+    Digest {
+        realm: String,
+        nonce: String,
+        opaque: Option<String>,
+        /// Whether the server offered `qop=auth`. Without it, the RFC 2069 response is sent.
+        qop_auth: bool,
+        /// `MD5-sess` rather than `MD5`.
+        session: bool,
+    },
+}
+
+impl Challenge {
+    /// Reads a `WWW-Authenticate` header, `None` if the scheme is not supported.
+    fn parse(header: &str) -> Option<Self> {
+        let (scheme, params) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            return Some(Challenge::Basic);
+        }
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+
+        let params = auth_params(params);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        let session = match param("algorithm") {
+            None => false,
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("md5") => false,
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("md5-sess") => true,
+            // SHA-256 and friends.
+            Some(_) => return None,
+        };
+        Some(Challenge::Digest {
+            realm: param("realm").unwrap_or_default(),
+            nonce: param("nonce")?,
+            opaque: param("opaque"),
+            qop_auth: param("qop").is_some_and(|qop| {
+                qop.split(',')
+                    .any(|qop| qop.trim().eq_ignore_ascii_case("auth"))
+            }),
+            session,
+        })
+    }
+}
+
+/// Splits `key=value, key="quoted, value"` into its pairs.
+fn auth_params(params: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = params.trim_start();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let after = after.trim_start();
+        let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((index, char)) = chars.next() {
+                match char {
+                    '\\' => value.extend(chars.next().map(|(_, escaped)| escaped)),
+                    '"' => {
+                        end = index + 1;
+                        break;
+                    }
+                    _ => value.push(char),
+                }
+            }
+            (value, &quoted[end..])
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (after[..end].trim().to_string(), &after[end..])
+        };
+        pairs.push((key, value));
+        rest = after.trim_start().trim_start_matches(',').trim_start();
+    }
+    pairs
+}
+
+fn md5_hex(data: &str) -> String {
+    format!("{:x}", md5::compute(data))
+}
+
+/// Answers authentication challenges, remembering the last one so that reconnects authenticate
+/// right away.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Authenticator {
+    credentials: Option<Credentials>,
+    challenge: Option<Challenge>,
+    /// How many requests have been sent with the current digest nonce.
+    nonce_count: u32,
+}
+
+impl Authenticator {
+    pub(crate) fn new(credentials: Option<Credentials>) -> Self {
+        Self {
+            credentials,
+            challenge: None,
+            nonce_count: 0,
+        }
+    }
+
+    /// Takes up the challenges of a `401 Unauthorized` response, preferring digest over basic.
+    /// Returns `false` if there are no credentials or no supported challenge.
+    pub(crate) fn challenged(&mut self, headers: &[(String, String)]) -> bool {
+        let challenge = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("www-authenticate"))
+            .filter_map(|(_, value)| Challenge::parse(value))
+            .max_by_key(|challenge| matches!(challenge, Challenge::Digest { .. }));
+        self.nonce_count = 0;
+        self.challenge = challenge;
+        self.credentials.is_some() && self.challenge.is_some()
+    }
+
+    /// Whether there is a user name and password to answer challenges with.
+    pub(crate) fn has_credentials(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// The `Authorization` header for a `method` request of `uri`, if a challenge has been taken
+    /// up.
+    pub(crate) fn authorization(&mut self, method: &str, uri: &str) -> Option<String> {
+        let Credentials { username, password } = self.credentials.as_ref()?;
+        match self.challenge.as_ref()? {
+            Challenge::Basic => {
+                let encoded = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{username}:{password}"));
+                Some(format!("Basic {encoded}"))
+            }
+            Challenge::Digest {
+                realm,
+                nonce,
+                opaque,
+                qop_auth,
+                session,
+            } => {
+                self.nonce_count += 1;
+                let nonce_count = format!("{:08x}", self.nonce_count);
+                let cnonce = cnonce();
+
+                let mut ha1 = md5_hex(&format!("{username}:{realm}:{password}"));
+                if *session {
+                    ha1 = md5_hex(&format!("{ha1}:{nonce}:{cnonce}"));
+                }
+                let ha2 = md5_hex(&format!("{method}:{uri}"));
+                let response = if *qop_auth {
+                    md5_hex(&format!("{ha1}:{nonce}:{nonce_count}:{cnonce}:auth:{ha2}"))
+                } else {
+                    md5_hex(&format!("{ha1}:{nonce}:{ha2}"))
+                };
+
+                let algorithm = if *session { "MD5-sess" } else { "MD5" };
+                let mut fields = vec![
+                    format!("username=\"{username}\""),
+                    format!("realm=\"{realm}\""),
+                    format!("nonce=\"{nonce}\""),
+                    format!("uri=\"{uri}\""),
+                    format!("algorithm={algorithm}"),
+                    format!("response=\"{response}\""),
+                ];
+                if let Some(opaque) = opaque {
+                    fields.push(format!("opaque=\"{opaque}\""));
+                }
+                if *qop_auth {
+                    fields.push("qop=auth".to_string());
+                    fields.push(format!("nc={nonce_count}"));
+                    fields.push(format!("cnonce=\"{cnonce}\""));
+                }
+                Some(format!("Digest {}", fields.join(", ")))
+            }
+        }
+    }
+}
+
+/// A client nonce, unpredictable enough to keep a digest from being replayed.
+fn cnonce() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    format!("{:016x}", hasher.finish())
+}
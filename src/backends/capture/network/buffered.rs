@@ -0,0 +1,164 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::io::{Error, ErrorKind, Read, Result};
+
+/// The longest header line that is accepted.
+const MAX_LINE: usize = 8 * 1024;
+/// The largest block of data that is read at once, e.g. a part of a multipart body.
+const MAX_PART: usize = 64 * 1024 * 1024;
+
+fn invalid(why: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, why.into())
+}
+
+/// A reader that buffers ahead, for reading lines and delimited data out of responses.
+pub(crate) struct Buffered<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// Where the unread data in `buffer` starts.
+    start: usize,
+}
+
+impl<R: Read> Buffered<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            start: 0,
+        }
+    }
+
+    fn unread(&self) -> &[u8] {
+        &self.buffer[self.start..]
+    }
+
+    /// Reads more data into the buffer. Fails at the end of the data.
+    fn fill(&mut self) -> Result<()> {
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+        let length = self.buffer.len();
+        self.buffer.resize(length + 16 * 1024, 0);
+        let read = loop {
+            match self.reader.read(&mut self.buffer[length..]) {
+                Err(why) if why.kind() == ErrorKind::Interrupted => {}
+                result => break result,
+            }
+        };
+        self.buffer
+            .truncate(length + read.as_ref().map_or(0, |read| *read));
+        match read {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(()),
+            Err(why) => Err(why),
+        }
+    }
+
+    /// Takes `length` bytes out of the buffer.
+    fn take(&mut self, length: usize) -> Vec<u8> {
+        let taken = self.unread()[..length].to_vec();
+        self.start += length;
+        taken
+    }
+
+    /// The next byte, without reading it.
+    #[cfg(feature = "input-rtsp")]
+    pub(crate) fn peek(&mut self) -> Result<u8> {
+        while self.unread().is_empty() {
+            self.fill()?;
+        }
+        Ok(self.unread()[0])
+    }
+
+    /// Reads a line of text, without its line ending.
+    pub(crate) fn read_line(&mut self) -> Result<String> {
+        String::from_utf8(self.read_line_bytes()?).map_err(|_| invalid("line is not UTF-8"))
+    }
+
+    /// Reads a line, without its line ending.
+    pub(crate) fn read_line_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut searched = 0;
+        loop {
+            if let Some(end) = self.unread()[searched..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+            {
+                let mut line = self.take(searched + end + 1);
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+            searched = self.unread().len();
+            if searched > MAX_LINE {
+                return Err(invalid("line is too long"));
+            }
+            self.fill()?;
+        }
+    }
+
+    /// Reads exactly `length` bytes.
+    pub(crate) fn read_vec(&mut self, length: usize) -> Result<Vec<u8>> {
+        if length > MAX_PART {
+            return Err(invalid(format!("part of {length} bytes is too large")));
+        }
+        while self.unread().len() < length {
+            self.fill()?;
+        }
+        Ok(self.take(length))
+    }
+
+    /// Reads up to the next line starting with `delimiter`, which is left unread. The line
+    /// ending before the delimiter is not part of the data.
+    #[cfg(feature = "input-http")]
+    pub(crate) fn read_until_line(&mut self, delimiter: &[u8]) -> Result<Vec<u8>> {
+        let mut searched = 0;
+        loop {
+            let unread = self.unread();
+            let found = unread[searched..]
+                .windows(delimiter.len() + 1)
+                .position(|window| window[0] == b'\n' && &window[1..] == delimiter);
+            if let Some(found) = found {
+                let mut data = self.take(searched + found + 1);
+                data.pop();
+                if data.last() == Some(&b'\r') {
+                    data.pop();
+                }
+                return Ok(data);
+            }
+            searched = unread.len().saturating_sub(delimiter.len());
+            if searched > MAX_PART {
+                return Err(invalid("part is too large"));
+            }
+            self.fill()?;
+        }
+    }
+}
+
+impl<R: Read> Read for Buffered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.unread().is_empty() {
+            return self.reader.read(buf);
+        }
+        let length = buf.len().min(self.unread().len());
+        buf[..length].copy_from_slice(&self.unread()[..length]);
+        self.start += length;
+        Ok(length)
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! What the backends talking to network cameras share: URLs, authentication, reading
+//! text-based protocols like HTTP and RTSP, and running and reconnecting the receiving thread.
+
+mod auth;
+mod buffered;
+mod player;
+mod url;
+
+pub(crate) use auth::Authenticator;
+pub(crate) use buffered::Buffered;
+pub(crate) use player::{reconnect, NetworkStream, Player};
+pub(crate) use url::{Credentials, Url};
+
+use std::{
+    io::Read,
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+/// The connection a stream is reading from, so that stopping the stream can interrupt a read
+/// that is blocked on the network.
+#[derive(Debug, Default)]
+pub(crate) struct Interrupt {
+    socket: Mutex<Option<TcpStream>>,
+    interrupted: AtomicBool,
+}
+
+impl Interrupt {
+    /// Closes the current connection, and any connection made afterwards.
+    pub(crate) fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Release);
+        if let Some(socket) = self
+            .socket
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn register(&self, socket: &TcpStream) -> Result<(), String> {
+        let mut current = self.socket.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.interrupted.load(Ordering::Acquire) {
+            return Err("stream was stopped".to_string());
+        }
+        *current = socket.try_clone().ok();
+        Ok(())
+    }
+}
+
+/// Connects to the host of `url`, with `timeout` for connecting and for every read and write
+/// afterwards. The connection is closed when `interrupt` is.
+pub(crate) fn connect(
+    url: &Url,
+    timeout: Duration,
+    interrupt: &Interrupt,
+) -> Result<TcpStream, String> {
+    let host = url.host().trim_start_matches('[').trim_end_matches(']');
+    let addresses = (host, url.port())
+        .to_socket_addrs()
+        .map_err(|why| format!("{}: {why}", url.host()))?;
+
+    let mut last_error = format!("{}: no addresses", url.host());
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(socket) => {
+                socket
+                    .set_read_timeout(Some(timeout))
+                    .and_then(|()| socket.set_write_timeout(Some(timeout)))
+                    .map_err(|why| why.to_string())?;
+                let _ = socket.set_nodelay(true);
+                interrupt.register(&socket)?;
+                return Ok(socket);
+            }
+            Err(why) => last_error = format!("{address}: {why}"),
+        }
+    }
+    Err(last_error)
+}
+
+/// The status line and headers of a response.
+pub(crate) struct Head {
+    pub(crate) status: u16,
+    pub(crate) reason: String,
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// The value of the first header called `name`.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads the status line and headers of a response of `protocol`, e.g. `HTTP`.
+pub(crate) fn read_head<R: Read>(
+    reader: &mut Buffered<R>,
+    protocol: &str,
+) -> std::io::Result<Head> {
+    let bad = |why: String| std::io::Error::new(std::io::ErrorKind::InvalidData, why);
+
+    let status_line = reader.read_line()?;
+    let mut fields = status_line.splitn(3, ' ');
+    let version = fields.next().unwrap_or_default();
+    if !version
+        .strip_prefix(protocol)
+        .is_some_and(|version| version.starts_with('/'))
+    {
+        return Err(bad(format!("not an {protocol} response: {status_line:?}")));
+    }
+    let status = fields
+        .next()
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| bad(format!("bad status line {status_line:?}")))?;
+    let reason = fields.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let line = reader.read_line()?;
+        if line.is_empty() {
+            return Ok(Head {
+                status,
+                reason,
+                headers,
+            });
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+}
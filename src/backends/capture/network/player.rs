@@ -0,0 +1,125 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::Interrupt;
+use nokhwa_core::{
+    error::{NokhwaError, NokhwaResult},
+    stream::{
+        events::StopReason,
+        producer::{Producer, ProducerStream},
+        FrameReceiver, StreamInnerTrait,
+    },
+    supervisor::BackoffPolicy,
+};
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    thread::JoinHandle,
+    time::Instant,
+};
+
+/// The handle of a running receiving thread, shared by a camera and its stream.
+#[derive(Clone, Debug)]
+pub(crate) struct Player {
+    producer: Producer,
+    interrupt: Arc<Interrupt>,
+}
+
+impl Player {
+    /// Runs `receive` on a thread called `name`, see [`Producer::spawn`]. Stopping also closes
+    /// the connection of `interrupt` and wakes the thread if it is waiting to reconnect.
+    pub(crate) fn spawn(
+        name: &str,
+        interrupt: Arc<Interrupt>,
+        receive: impl FnOnce(Arc<AtomicBool>) + Send + 'static,
+    ) -> NokhwaResult<(Self, JoinHandle<()>)> {
+        let (producer, handle) = Producer::spawn(name, receive)?;
+        Ok((
+            Self {
+                producer,
+                interrupt,
+            },
+            handle,
+        ))
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.producer.is_running()
+    }
+
+    pub(crate) fn stop(&self) {
+        self.producer.stop();
+        self.interrupt.interrupt();
+    }
+}
+
+/// Opens the connection again following `backoff`, or returns why the stream stops. Waiting
+/// between attempts is cut short by unparking the thread, after which `is_running` is checked.
+pub(crate) fn reconnect<T>(
+    backoff: &BackoffPolicy,
+    is_running: impl Fn() -> bool,
+    mut open: impl FnMut() -> Result<T, String>,
+) -> Result<T, StopReason> {
+    let mut failures = 0;
+    let mut retry_at = Instant::now() + backoff.initial();
+    loop {
+        while Instant::now() < retry_at {
+            if !is_running() {
+                return Err(StopReason::Requested);
+            }
+            std::thread::park_timeout(retry_at.saturating_duration_since(Instant::now()));
+        }
+        if !is_running() {
+            return Err(StopReason::Requested);
+        }
+
+        let why = match open() {
+            Ok(connection) => return Ok(connection),
+            Err(why) => why,
+        };
+        failures += 1;
+        if backoff
+            .max_attempts()
+            .is_some_and(|max_attempts| failures >= max_attempts)
+        {
+            return Err(StopReason::Error(NokhwaError::ReadFrameError(why)));
+        }
+        retry_at = Instant::now() + backoff.delay(failures);
+    }
+}
+
+/// The inner half of a network camera stream. Stopping it closes the connection.
+pub(crate) struct NetworkStream {
+    player: Player,
+    inner: ProducerStream,
+}
+
+impl NetworkStream {
+    pub(crate) fn new(receiver: FrameReceiver, player: Player, handle: JoinHandle<()>) -> Self {
+        let inner = ProducerStream::new(receiver, player.producer.clone(), handle);
+        Self { player, inner }
+    }
+}
+
+impl StreamInnerTrait for NetworkStream {
+    fn receiver(&self) -> &FrameReceiver {
+        self.inner.receiver()
+    }
+
+    fn stop(&mut self) -> NokhwaResult<()> {
+        self.player.stop();
+        self.inner.stop()
+    }
+}
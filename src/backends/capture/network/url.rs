@@ -0,0 +1,153 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::{Display, Formatter};
+
+/// The parts of a URL needed to connect to a camera and make requests.
+///
+/// Displayed without its credentials, as it is sent in requests.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct Url {
+    scheme: &'static str,
+    /// The host as written in the URL, with brackets around IPv6 addresses.
+    host: String,
+    port: u16,
+    default_port: u16,
+    /// The path and query.
+    target: String,
+    credentials: Option<Credentials>,
+}
+
+impl Url {
+    /// Parses `url`, which must be a `scheme://` URL, connecting to `default_port` if it has no
+    /// port.
+    pub(crate) fn parse(
+        url: &str,
+        scheme: &'static str,
+        default_port: u16,
+    ) -> Result<Self, String> {
+        let rest = match url.split_once("://") {
+            Some((found, rest)) if found.eq_ignore_ascii_case(scheme) => rest,
+            Some((found, _)) => return Err(format!("{found}:// URLs are not supported")),
+            None => return Err(format!("not a {scheme}:// URL")),
+        };
+        // Fragments are not sent.
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(start) if rest[start..].starts_with('?') => {
+                (&rest[..start], format!("/{}", &rest[start..]))
+            }
+            Some(start) => (&rest[..start], rest[start..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+
+        let (credentials, host_port) = match authority.rsplit_once('@') {
+            Some((user_info, host_port)) => {
+                let (username, password) = user_info.split_once(':').unwrap_or((user_info, ""));
+                let credentials =
+                    Credentials::new(percent_decode(username)?, percent_decode(password)?);
+                (Some(credentials), host_port)
+            }
+            None => (None, authority),
+        };
+        // The port is after the last `:`, unless that is inside the brackets of an IPv6 address.
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| format!("bad port {port:?}"))?,
+            ),
+            _ => (host_port, default_port),
+        };
+        if host.is_empty() {
+            return Err("URL has no host".to_string());
+        }
+
+        Ok(Self {
+            scheme,
+            host: host.to_string(),
+            port,
+            default_port,
+            target,
+            credentials,
+        })
+    }
+
+    /// The host as written in the URL, with brackets around IPv6 addresses.
+    pub(crate) fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The path and query.
+    #[cfg(feature = "input-http")]
+    pub(crate) fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub(crate) fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+
+    /// The host, and the port unless it is the default one, as sent in the `Host` header.
+    pub(crate) fn authority(&self) -> String {
+        if self.port == self.default_port {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl Display for Url {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}{}", self.scheme, self.authority(), self.target)
+    }
+}
+
+fn percent_decode(text: &str) -> Result<String, String> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next().unwrap_or(b'!'), bytes.next().unwrap_or(b'!')];
+            let value = std::str::from_utf8(&hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("bad percent escape in {text:?}"))?;
+            decoded.push(value);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("{text:?} is not UTF-8"))
+}
+
+/// A user name and password to authenticate with.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct Credentials {
+    pub(super) username: String,
+    pub(super) password: String,
+}
+
+impl Credentials {
+    pub(crate) fn new(username: String, password: String) -> Self {
+        Self { username, password }
+    }
+}
@@ -29,7 +29,7 @@ use wgpu::{
 #[cfg_attr(feature = "docs-features", doc(cfg(feature = "input-ipcam")))]
 #[deprecated(
     since = "0.10.0",
-    note = "please use `Camera` with `CameraIndex::String` and `input-opencv` enabled."
+    note = "please use `HttpCamera` with `input-http` enabled for MJPEG over HTTP, `RtspCamera` with `input-rtsp` enabled for RTSP, or `Camera` with `CameraIndex::String` and `input-opencv` enabled."
 )]
 pub struct NetworkCamera {
     ip: String,
@@ -0,0 +1,437 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{FrameSource, DEFAULT_FRAME_RATE};
+use nokhwa_core::{
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    types::{CameraFormat, FrameRate, Resolution},
+};
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    num::NonZeroI32,
+    path::Path,
+};
+
+/// How deep the lists of a file may nest. Real files nest no deeper than `strl` in `hdrl`, or
+/// `rec ` in `movi`; deeper nesting is taken as a corrupt file rather than walked until the stack
+/// runs out.
+const MAX_DEPTH: usize = 8;
+
+/// A chunk of a RIFF file.
+#[derive(Copy, Clone, Debug)]
+struct Chunk {
+    id: [u8; 4],
+    /// Where the data of the chunk starts.
+    offset: u64,
+    size: u64,
+}
+
+/// How the pixels of the video stream are stored.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Pixels {
+    /// Frames are stored as they are delivered.
+    Direct(FrameFormat),
+    /// Uncompressed 24 bit BGR with rows padded to 4 bytes, bottom row first unless `top_down`.
+    Bgr { top_down: bool },
+}
+
+/// What has been learned about the video stream while walking the file.
+#[derive(Debug, Default)]
+struct Header {
+    /// The number of streams seen so far, and the number of the video stream once it is found.
+    streams: u8,
+    video_stream: Option<u8>,
+    /// From `avih`, the fallback if the stream has no rate.
+    micro_seconds_per_frame: u32,
+    /// From the `strh` of the video stream.
+    rate: Option<(u32, u32)>,
+    /// From the `strf` of the video stream.
+    picture: Option<(Resolution, Pixels)>,
+    /// The data chunks of the video stream, in order.
+    frames: Vec<(u64, u64)>,
+}
+
+/// An AVI file, with frames read from the first video stream as they are needed.
+///
+/// Large files split into `AVIX` parts by `OpenDML` are read as one. The `idx1` index is not
+/// used, frames are found by walking the `movi` lists.
+pub(super) struct AviSource {
+    file: File,
+    frames: Vec<(u64, u64)>,
+    next: usize,
+    format: CameraFormat,
+    pixels: Pixels,
+}
+
+impl AviSource {
+    pub(super) fn open(path: &Path) -> NokhwaResult<Self> {
+        let error = |why: String| NokhwaError::OpenDeviceError(path.display().to_string(), why);
+
+        let mut file = File::open(path).map_err(|why| error(why.to_string()))?;
+        let length = file.metadata().map_err(|why| error(why.to_string()))?.len();
+
+        let mut header = Header::default();
+        for riff in chunks(&mut file, 0, length).map_err(|why| error(why.to_string()))? {
+            if &riff.id != b"RIFF" {
+                continue;
+            }
+            let form = read_fourcc(&mut file, riff.offset).map_err(|why| error(why.to_string()))?;
+            if &form == b"AVI " || &form == b"AVIX" {
+                walk(
+                    &mut file,
+                    riff.offset + 4,
+                    riff.offset + riff.size,
+                    &mut header,
+                    0,
+                )
+                .map_err(|why| error(why.to_string()))?;
+            }
+        }
+
+        let (resolution, pixels) = header
+            .picture
+            .ok_or_else(|| error("no supported video stream".to_string()))?;
+        let frame_rate = match header.rate {
+            Some((scale, rate)) => frame_rate(rate, scale),
+            None => frame_rate(1_000_000, header.micro_seconds_per_frame),
+        }
+        .unwrap_or(DEFAULT_FRAME_RATE);
+        let frame_format = match pixels {
+            Pixels::Direct(frame_format) => frame_format,
+            Pixels::Bgr { .. } => FrameFormat::Rgb888,
+        };
+
+        Ok(Self {
+            file,
+            frames: header.frames,
+            next: 0,
+            format: CameraFormat::new(resolution, frame_format, frame_rate),
+            pixels,
+        })
+    }
+}
+
+impl FrameSource for AviSource {
+    fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    fn next_frame(&mut self) -> NokhwaResult<Option<FrameBuffer>> {
+        let error = |why: String| NokhwaError::ReadFrameError(format!("AVI: {why}"));
+
+        let Some(&(offset, size)) = self.frames.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+
+        let size = usize::try_from(size).map_err(|why| error(why.to_string()))?;
+        let mut data = vec![0; size];
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.read_exact(&mut data))
+            .map_err(|why| error(why.to_string()))?;
+
+        let resolution = self.format.resolution();
+        let data = match self.pixels {
+            Pixels::Direct(_) => data,
+            Pixels::Bgr { top_down } => bgr_to_rgb(resolution, &data, top_down)
+                .ok_or_else(|| error(format!("frame of {size} bytes is too short")))?,
+        };
+        Ok(Some(FrameBuffer::from_vec(
+            resolution,
+            data,
+            self.format.format(),
+        )))
+    }
+}
+
+/// Lists the chunks between `start` and `end`, stopping at the first one that does not fit. A
+/// list that does not fit, e.g. in a recording that was cut short, is cut to what is there.
+fn chunks(file: &mut File, start: u64, end: u64) -> std::io::Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    let mut position = start;
+    while position + 8 <= end {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+
+        let id = [header[0], header[1], header[2], header[3]];
+        let size = u64::from(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ]));
+        let offset = position + 8;
+        if offset + size > end {
+            if matches!(&id, b"RIFF" | b"LIST") {
+                chunks.push(Chunk {
+                    id,
+                    offset,
+                    size: end - offset,
+                });
+            }
+            break;
+        }
+        chunks.push(Chunk { id, offset, size });
+        // Chunks are padded to an even size.
+        position = offset + size + size % 2;
+    }
+    Ok(chunks)
+}
+
+fn read_fourcc(file: &mut File, offset: u64) -> std::io::Result<[u8; 4]> {
+    let mut fourcc = [0; 4];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut fourcc)?;
+    Ok(fourcc)
+}
+
+/// Reads up to `limit` bytes of the data of `chunk`.
+fn read_chunk(file: &mut File, chunk: Chunk, limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(chunk.offset))?;
+    file.take(chunk.size.min(limit)).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Walks the chunks between `start` and `end`, `depth` lists deep, descending into the lists
+/// that matter.
+fn walk(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    header: &mut Header,
+    depth: usize,
+) -> std::io::Result<()> {
+    for chunk in chunks(file, start, end)? {
+        match &chunk.id {
+            b"LIST" if chunk.size >= 4 => {
+                let list = read_fourcc(file, chunk.offset)?;
+                if !matches!(&list, b"hdrl" | b"strl" | b"movi" | b"rec ") {
+                    continue;
+                }
+                if depth >= MAX_DEPTH {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "lists are nested too deeply",
+                    ));
+                }
+                walk(
+                    file,
+                    chunk.offset + 4,
+                    chunk.offset + chunk.size,
+                    header,
+                    depth + 1,
+                )?;
+            }
+            b"avih" => {
+                let avih = read_chunk(file, chunk, 4)?;
+                header.micro_seconds_per_frame = u32_at(&avih, 0).unwrap_or_default();
+            }
+            b"strh" => {
+                let strh = read_chunk(file, chunk, 28)?;
+                if header.video_stream.is_none() && strh.starts_with(b"vids") {
+                    header.video_stream = Some(header.streams);
+                    header.rate = u32_at(&strh, 20).zip(u32_at(&strh, 24));
+                }
+                header.streams = header.streams.saturating_add(1);
+            }
+            // The format of the stream whose header came right before.
+            b"strf"
+                if header.picture.is_none()
+                    && header.video_stream.is_some()
+                    && header.video_stream == header.streams.checked_sub(1) =>
+            {
+                header.picture = bitmap_info(&read_chunk(file, chunk, 40)?);
+            }
+            [tens @ b'0'..=b'9', ones @ b'0'..=b'9', b'd', b'b' | b'c'] if chunk.size > 0 => {
+                let stream = (tens - b'0') * 10 + (ones - b'0');
+                if header.video_stream == Some(stream) {
+                    header.frames.push((chunk.offset, chunk.size));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The size and pixel layout from a `BITMAPINFOHEADER`, if it is supported.
+fn bitmap_info(strf: &[u8]) -> Option<(Resolution, Pixels)> {
+    let width = i32::from_le_bytes(strf.get(4..8)?.try_into().ok()?);
+    let height = i32::from_le_bytes(strf.get(8..12)?.try_into().ok()?);
+    let bit_count = u16::from_le_bytes(strf.get(14..16)?.try_into().ok()?);
+    let compression: [u8; 4] = strf.get(16..20)?.try_into().ok()?;
+
+    let pixels = match &compression {
+        [0, 0, 0, 0] if bit_count == 24 => Pixels::Bgr {
+            top_down: height < 0,
+        },
+        b"MJPG" | b"mjpg" => Pixels::Direct(FrameFormat::MJpeg),
+        b"YUY2" | b"YUYV" => Pixels::Direct(FrameFormat::Yuyv422),
+        b"UYVY" => Pixels::Direct(FrameFormat::Uyvy422),
+        b"YVYU" => Pixels::Direct(FrameFormat::Yvyu422),
+        b"NV12" => Pixels::Direct(FrameFormat::Nv12),
+        b"NV21" => Pixels::Direct(FrameFormat::Nv21),
+        b"I420" | b"IYUV" => Pixels::Direct(FrameFormat::I420),
+        b"YV12" => Pixels::Direct(FrameFormat::Yv12),
+        b"Y800" | b"GREY" | b"Y8  " => Pixels::Direct(FrameFormat::Luma8),
+        _ => return None,
+    };
+    let resolution = Resolution::new(width.unsigned_abs(), height.unsigned_abs());
+    (resolution.width() > 0 && resolution.height() > 0).then_some((resolution, pixels))
+}
+
+/// `rate / scale` frames per second, if both are positive and fit.
+fn frame_rate(rate: u32, scale: u32) -> Option<FrameRate> {
+    let rate = i32::try_from(rate).ok().filter(|rate| *rate > 0)?;
+    let scale = NonZeroI32::new(i32::try_from(scale).ok()?).filter(|scale| scale.get() > 0)?;
+    Some(FrameRate::new(rate, scale))
+}
+
+/// Turns a Windows bitmap into tightly packed RGB, top row first.
+fn bgr_to_rgb(resolution: Resolution, data: &[u8], top_down: bool) -> Option<Vec<u8>> {
+    let (width, height) = (resolution.width() as usize, resolution.height() as usize);
+    let stride = (width * 3).next_multiple_of(4);
+    if data.len() < stride * (height - 1) + width * 3 {
+        return None;
+    }
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for row in 0..height {
+        let row = if top_down { row } else { height - 1 - row };
+        let bgr = &data[row * stride..row * stride + width * 3];
+        rgb.extend(
+            bgr.chunks_exact(3)
+                .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]]),
+        );
+    }
+    Some(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// An AVI file that is removed again when dropped.
+    struct TempAvi(PathBuf);
+
+    impl TempAvi {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("nokhwa-{name}-{}.avi", std::process::id()));
+            std::fs::write(&path, data).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempAvi {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let size = u32::try_from(data.len()).unwrap();
+        let mut chunk = [id, &size.to_le_bytes(), data].concat();
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn list(id: &[u8], kind: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(id, &[kind, &chunks.concat()].concat())
+    }
+
+    /// The `hdrl` list of a 2x2 MJPEG stream at 25 frames per second.
+    fn hdrl() -> Vec<u8> {
+        let mut stream_header = [0; 56];
+        stream_header[..4].copy_from_slice(b"vids");
+        stream_header[20..24].copy_from_slice(&1_u32.to_le_bytes());
+        stream_header[24..28].copy_from_slice(&25_u32.to_le_bytes());
+        let mut stream_format = [0; 40];
+        stream_format[..4].copy_from_slice(&40_u32.to_le_bytes());
+        stream_format[4..8].copy_from_slice(&2_i32.to_le_bytes());
+        stream_format[8..12].copy_from_slice(&2_i32.to_le_bytes());
+        stream_format[16..20].copy_from_slice(b"MJPG");
+
+        list(
+            b"LIST",
+            b"hdrl",
+            &[
+                chunk(b"avih", &[0; 56]),
+                list(
+                    b"LIST",
+                    b"strl",
+                    &[
+                        chunk(b"strh", &stream_header),
+                        chunk(b"strf", &stream_format),
+                    ],
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn reads_the_frames_of_the_video_stream() {
+        let movi = list(
+            b"LIST",
+            b"movi",
+            &[
+                chunk(b"00dc", &[1, 2, 3]),
+                chunk(b"01wb", &[0; 4]),
+                chunk(b"00dc", &[4]),
+            ],
+        );
+        let avi = TempAvi::new("avi-frames", &list(b"RIFF", b"AVI ", &[hdrl(), movi]));
+
+        let mut source = AviSource::open(&avi.0).unwrap();
+        assert_eq!(
+            source.format(),
+            CameraFormat::new(
+                Resolution::new(2, 2),
+                FrameFormat::MJpeg,
+                FrameRate::frame_rate(25)
+            )
+        );
+        assert_eq!(source.next_frame().unwrap().unwrap().buffer(), &[1, 2, 3]);
+        assert_eq!(source.next_frame().unwrap().unwrap().buffer(), &[4]);
+        assert!(source.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_lists_nested_too_deeply() {
+        let mut nested = list(b"LIST", b"movi", &[chunk(b"00dc", &[1])]);
+        for _ in 0..=MAX_DEPTH {
+            nested = list(b"LIST", b"rec ", &[nested]);
+        }
+        let avi = TempAvi::new("avi-nested", &list(b"RIFF", b"AVI ", &[hdrl(), nested]));
+
+        assert!(matches!(
+            AviSource::open(&avi.0),
+            Err(NokhwaError::OpenDeviceError(_, why)) if why == "lists are nested too deeply"
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{FrameSource, DEFAULT_FRAME_RATE};
+use crate::backends::capture::jpeg::scan_jpeg;
+use nokhwa_core::{
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    types::{CameraFormat, Resolution},
+};
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "pnm", "ppm", "pgm", "pbm"];
+
+/// A directory of images, one frame each, in the order of their file names.
+///
+/// JPEG images are delivered as they are, as [`FrameFormat::MJpeg`]. Other images are decoded
+/// to [`FrameFormat::Rgb888`]. All images must have the same size and be either JPEG or not.
+pub(super) struct ImageSequence {
+    images: Vec<PathBuf>,
+    next: usize,
+    format: CameraFormat,
+}
+
+impl ImageSequence {
+    pub(super) fn open(directory: &Path) -> NokhwaResult<Self> {
+        let error =
+            |why: String| NokhwaError::OpenDeviceError(directory.display().to_string(), why);
+
+        let mut images = std::fs::read_dir(directory)
+            .map_err(|why| error(why.to_string()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && is_image(path))
+            .collect::<Vec<_>>();
+        images.sort();
+
+        let first = images
+            .first()
+            .ok_or_else(|| error("no images in directory".to_string()))?;
+        let frame = load(first).map_err(|why| error(why.to_string()))?;
+        let format = CameraFormat::new(
+            frame.resolution(),
+            frame.source_frame_format(),
+            DEFAULT_FRAME_RATE,
+        );
+
+        Ok(Self {
+            images,
+            next: 0,
+            format,
+        })
+    }
+}
+
+impl FrameSource for ImageSequence {
+    fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    fn next_frame(&mut self) -> NokhwaResult<Option<FrameBuffer>> {
+        let Some(path) = self.images.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+
+        let frame = load(path)?;
+        if frame.resolution() != self.format.resolution()
+            || frame.source_frame_format() != self.format.format()
+        {
+            return Err(NokhwaError::ReadFrameError(format!(
+                "{}: image is {} {}, expected {} {}",
+                path.display(),
+                frame.resolution(),
+                frame.source_frame_format(),
+                self.format.resolution(),
+                self.format.format(),
+            )));
+        }
+        Ok(Some(frame))
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+fn load(path: &Path) -> NokhwaResult<FrameBuffer> {
+    let error = |why: String| NokhwaError::ReadFrameError(format!("{}: {why}", path.display()));
+
+    let data = std::fs::read(path).map_err(|why| error(why.to_string()))?;
+    if let Some(jpeg) = scan_jpeg(&data) {
+        let resolution = jpeg
+            .resolution
+            .ok_or_else(|| error("JPEG image has no frame header".to_string()))?;
+        return Ok(FrameBuffer::from_vec(resolution, data, FrameFormat::MJpeg));
+    }
+
+    let image = image::load_from_memory(&data)
+        .map_err(|why| error(why.to_string()))?
+        .into_rgb8();
+    let resolution = Resolution::new(image.width(), image.height());
+    Ok(FrameBuffer::from_vec(
+        resolution,
+        image.into_raw(),
+        FrameFormat::Rgb888,
+    ))
+}
@@ -0,0 +1,89 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{FrameSource, DEFAULT_FRAME_RATE};
+use crate::backends::capture::jpeg::scan_jpeg;
+use nokhwa_core::{
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    types::{CameraFormat, Resolution},
+};
+use std::{ops::Range, path::Path};
+
+/// A raw MJPEG stream, JPEG images one after another, read into memory.
+pub(super) struct MjpegSource {
+    data: Vec<u8>,
+    frames: Vec<Range<usize>>,
+    next: usize,
+    resolution: Resolution,
+}
+
+impl MjpegSource {
+    pub(super) fn open(path: &Path) -> NokhwaResult<Self> {
+        let error = |why: String| NokhwaError::OpenDeviceError(path.display().to_string(), why);
+
+        let data = std::fs::read(path).map_err(|why| error(why.to_string()))?;
+        let mut frames = Vec::new();
+        let mut resolution = None;
+        let mut position = 0;
+        while position < data.len() {
+            // Garbage between images, e.g. multipart boundaries, is skipped.
+            let Some(start) = find_start(&data[position..]).map(|start| position + start) else {
+                break;
+            };
+            match scan_jpeg(&data[start..]) {
+                Some(jpeg) => {
+                    resolution = resolution.or(jpeg.resolution);
+                    frames.push(start..start + jpeg.length);
+                    position = start + jpeg.length;
+                }
+                None => position = start + 2,
+            }
+        }
+
+        let resolution = resolution.ok_or_else(|| error("no JPEG images found".to_string()))?;
+        Ok(Self {
+            data,
+            frames,
+            next: 0,
+            resolution,
+        })
+    }
+}
+
+impl FrameSource for MjpegSource {
+    fn format(&self) -> CameraFormat {
+        CameraFormat::new(self.resolution, FrameFormat::MJpeg, DEFAULT_FRAME_RATE)
+    }
+
+    fn next_frame(&mut self) -> NokhwaResult<Option<FrameBuffer>> {
+        let Some(frame) = self.frames.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+        Ok(Some(FrameBuffer::new(
+            self.resolution,
+            &self.data[frame.clone()],
+            FrameFormat::MJpeg,
+        )))
+    }
+}
+
+/// The offset of the next start of image marker.
+fn find_start(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|window| window == [0xFF, 0xD8])
+}
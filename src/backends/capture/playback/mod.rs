@@ -0,0 +1,621 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A camera that plays back recorded video, for reproducing problems and testing without a
+//! device.
+//!
+//! The following are understood, recognized by their contents rather than their extension:
+//! - YUV4MPEG2 (`.y4m`) files, see [`nokhwa_core::y4m`].
+//! - AVI files holding MJPEG or raw YUV video.
+//! - Raw MJPEG streams, JPEG images one after another, as written by many IP cameras.
+//! - Directories of JPEG, PNG or PNM images, played in the order of their file names.
+
+mod avi;
+mod images;
+mod mjpeg;
+
+use nokhwa_core::{
+    camera::{Camera, Capture, Setting},
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    platform::{Backends, PlatformTrait},
+    properties::{ControlId, ControlValue, Properties},
+    stream::{
+        events::{StopReason, StreamEvent},
+        frame_channel,
+        producer::{frame_interval, Pacer, Producer, ProducerStream},
+        BackpressurePolicy, FrameSender, Stream,
+    },
+    types::{CameraFormat, CameraIndex, CameraInformation, FrameRate, Resolution},
+    y4m::Y4mReader,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// The frame rate of recordings that do not store one, raw MJPEG and image sequences.
+const DEFAULT_FRAME_RATE: FrameRate = FrameRate::frame_rate(30);
+
+/// Where frames of a recording come from, read one after another from the start.
+trait FrameSource: Send {
+    /// The resolution, [`FrameFormat`] and recorded frame rate of the frames.
+    fn format(&self) -> CameraFormat;
+
+    /// Reads the next frame, or `None` at the end of the recording.
+    fn next_frame(&mut self) -> NokhwaResult<Option<FrameBuffer>>;
+}
+
+impl<R: std::io::BufRead + Send> FrameSource for Y4mReader<R> {
+    fn format(&self) -> CameraFormat {
+        Y4mReader::format(self)
+    }
+
+    fn next_frame(&mut self) -> NokhwaResult<Option<FrameBuffer>> {
+        self.read_frame()
+    }
+}
+
+/// Opens the recording at `path` at its first frame.
+fn open_source(path: &Path) -> NokhwaResult<Box<dyn FrameSource>> {
+    let error = |why: String| NokhwaError::OpenDeviceError(path.display().to_string(), why);
+
+    if path.is_dir() {
+        return Ok(Box::new(images::ImageSequence::open(path)?));
+    }
+
+    let mut magic = [0; 12];
+    let mut file = File::open(path).map_err(|why| error(why.to_string()))?;
+    let read = file
+        .read(&mut magic)
+        .map_err(|why| error(why.to_string()))?;
+    let magic = &magic[..read];
+
+    if magic.starts_with(b"YUV4MPEG2") {
+        // Reopened rather than rewound, the header has to be read again anyway.
+        let file = File::open(path).map_err(|why| error(why.to_string()))?;
+        let reader = Y4mReader::new(BufReader::new(file)).map_err(|why| error(why.to_string()))?;
+        Ok(Box::new(reader))
+    } else if magic.starts_with(b"RIFF") && magic.get(8..12) == Some(b"AVI ") {
+        Ok(Box::new(avi::AviSource::open(path)?))
+    } else if magic.starts_with(&[0xFF, 0xD8]) {
+        Ok(Box::new(mjpeg::MjpegSource::open(path)?))
+    } else {
+        Err(error("not a Y4M, AVI or MJPEG file".to_string()))
+    }
+}
+
+/// How a recording is played, shared between the camera and its stream so that changes apply to
+/// a running stream.
+#[derive(Copy, Clone, Debug)]
+struct Playback {
+    format: CameraFormat,
+    paced: bool,
+    looping: bool,
+}
+
+/// A camera that plays back a recording, see the [module documentation](self) for what can be
+/// played.
+///
+/// The only format is the one of the recording, but its frame rate can be changed with
+/// [`Setting::set_format`] to play faster or slower. By default frames are paced at that frame
+/// rate and the stream stops with [`StopReason::Requested`] at the end of the recording, see
+/// [`PlaybackCamera::set_paced`] and [`PlaybackCamera::set_looping`].
+///
+/// Every stream starts at the beginning of the recording. Frames are timestamped when they are
+/// delivered, not with the time they were recorded at.
+pub struct PlaybackCamera {
+    info: CameraInformation,
+    path: PathBuf,
+    recorded: CameraFormat,
+    playback: Arc<Mutex<Playback>>,
+    properties: Properties,
+    player: Option<Producer>,
+}
+
+impl PlaybackCamera {
+    /// Opens the recording at `path`, with the path as its [`CameraIndex`].
+    ///
+    /// # Errors
+    /// If the recording cannot be read or is not understood.
+    pub fn new(path: impl AsRef<Path>) -> NokhwaResult<Self> {
+        let path = path.as_ref();
+        Self::with_index(CameraIndex::String(path.display().to_string()), path)
+    }
+
+    /// Opens the recording at `path` as the camera at `index`.
+    ///
+    /// # Errors
+    /// If the recording cannot be read or is not understood.
+    pub fn with_index(index: CameraIndex, path: impl AsRef<Path>) -> NokhwaResult<Self> {
+        let path = path.as_ref();
+        let recorded = open_source(path)?.format();
+        let playback = Playback {
+            format: recorded,
+            paced: true,
+            looping: false,
+        };
+
+        Ok(Self {
+            info: camera_information(index, path),
+            path: path.to_path_buf(),
+            recorded,
+            playback: Arc::new(Mutex::new(playback)),
+            properties: Properties::new(HashMap::new()),
+            player: None,
+        })
+    }
+
+    /// The [`CameraInformation`] of this camera.
+    #[must_use]
+    pub fn info(&self) -> &CameraInformation {
+        &self.info
+    }
+
+    /// The recording that is played.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The format of the recording, with the frame rate it was recorded at.
+    #[must_use]
+    pub fn recorded_format(&self) -> CameraFormat {
+        self.recorded
+    }
+
+    /// The format frames are played at.
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        self.playback().format
+    }
+
+    /// Whether frames are paced at the frame rate of the current format. Unpaced frames are
+    /// delivered as fast as the stream takes them.
+    pub fn set_paced(&mut self, paced: bool) {
+        self.playback().paced = paced;
+    }
+
+    /// Whether the recording starts over at its end instead of ending the stream.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.playback().looping = looping;
+    }
+
+    fn playback(&self) -> std::sync::MutexGuard<'_, Playback> {
+        self.playback.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Setting for PlaybackCamera {
+    fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError> {
+        Ok(vec![self.recorded])
+    }
+
+    fn enumerate_resolution_and_frame_rates(
+        &self,
+        frame_format: FrameFormat,
+    ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError> {
+        if frame_format != self.recorded.format() {
+            return Ok(HashMap::new());
+        }
+        Ok(HashMap::from([(
+            self.recorded.resolution(),
+            vec![self.recorded.frame_rate()],
+        )]))
+    }
+
+    fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError> {
+        let error = |error: &str| NokhwaError::SetPropertyError {
+            property: "format".to_string(),
+            value: camera_format.to_string(),
+            error: error.to_string(),
+        };
+
+        if camera_format.resolution() != self.recorded.resolution()
+            || camera_format.format() != self.recorded.format()
+        {
+            return Err(error(
+                "recordings play at their recorded resolution and format",
+            ));
+        }
+        if frame_interval(camera_format.frame_rate()).is_none() {
+            return Err(error("frame rate is not positive"));
+        }
+
+        self.playback().format = camera_format;
+        Ok(())
+    }
+
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn set_property(
+        &mut self,
+        property: &ControlId,
+        value: ControlValue,
+    ) -> Result<(), NokhwaError> {
+        Err(NokhwaError::SetPropertyError {
+            property: property.to_string(),
+            value: value.to_string(),
+            error: "recordings have no controls".to_string(),
+        })
+    }
+}
+
+impl Capture for PlaybackCamera {
+    fn open_stream(&mut self) -> Result<Stream, NokhwaError> {
+        self.open_stream_with_policy(BackpressurePolicy::default())
+    }
+
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        if self.player.as_ref().is_some_and(Producer::is_running) {
+            return Err(NokhwaError::OpenStreamError(
+                "a stream is already open".to_string(),
+            ));
+        }
+
+        let source =
+            open_source(&self.path).map_err(|why| NokhwaError::OpenStreamError(why.to_string()))?;
+        let (sender, receiver) = frame_channel(policy);
+        let path = self.path.clone();
+        let playback = self.playback.clone();
+        let (player, handle) = Producer::spawn("nokhwa-playback", move |running| {
+            play(&sender, source, &path, &playback, &running);
+        })?;
+        self.player = Some(player.clone());
+        Ok(Stream::new(Box::new(ProducerStream::new(
+            receiver, player, handle,
+        ))))
+    }
+
+    fn close_stream(&mut self) -> Result<(), NokhwaError> {
+        if let Some(player) = self.player.take() {
+            player.stop();
+        }
+        Ok(())
+    }
+}
+
+impl Camera for PlaybackCamera {}
+
+impl Drop for PlaybackCamera {
+    fn drop(&mut self) {
+        let _ = self.close_stream();
+    }
+}
+
+/// A [`PlatformTrait`] that lists recordings as [`PlaybackCamera`]s.
+///
+/// The recordings are at indices `0..` in the order they were given. A
+/// [`CameraIndex::String`] opens the recording at that path, whether it is listed or not.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct PlaybackPlatform {
+    paths: Vec<PathBuf>,
+}
+
+impl PlaybackPlatform {
+    /// Creates a platform listing the recordings at `paths`.
+    #[must_use]
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self { paths }
+    }
+
+    /// Adds the recording at `path` to the end of the list.
+    pub fn add(&mut self, path: impl Into<PathBuf>) {
+        self.paths.push(path.into());
+    }
+}
+
+impl PlatformTrait for PlaybackPlatform {
+    const PLATFORM: Backends = Backends::Custom("playback");
+    type Camera = PlaybackCamera;
+
+    fn block_on_permission(&mut self) -> NokhwaResult<()> {
+        Ok(())
+    }
+
+    fn check_permission_given(&mut self) -> bool {
+        true
+    }
+
+    fn query(&mut self) -> NokhwaResult<Vec<CameraInformation>> {
+        Ok(self
+            .paths
+            .iter()
+            .zip(0..)
+            .filter(|(path, _)| path.exists())
+            .map(|(path, index)| camera_information(CameraIndex::Index(index), path))
+            .collect())
+    }
+
+    fn open(&mut self, index: &CameraIndex) -> NokhwaResult<Self::Camera> {
+        match index {
+            CameraIndex::Index(number) => {
+                let path = usize::try_from(*number)
+                    .ok()
+                    .and_then(|number| self.paths.get(number))
+                    .ok_or_else(|| {
+                        NokhwaError::OpenDeviceError(
+                            index.to_string(),
+                            "no such recording".to_string(),
+                        )
+                    })?;
+                PlaybackCamera::with_index(index.clone(), path)
+            }
+            CameraIndex::String(path) => PlaybackCamera::new(path),
+        }
+    }
+}
+
+fn camera_information(index: CameraIndex, path: &Path) -> CameraInformation {
+    let name = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+    CameraInformation::new(
+        name,
+        "Playback of a recording".to_string(),
+        path.display().to_string(),
+        index,
+    )
+}
+
+/// Delivers the frames of `source` until stopped, the stream is gone or the recording ends.
+/// Looping reopens the recording at `path`.
+fn play(
+    frames: &FrameSender,
+    mut source: Box<dyn FrameSource>,
+    path: &Path,
+    playback: &Mutex<Playback>,
+    running: &AtomicBool,
+) {
+    let current = || *playback.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let mut format = current().format;
+    frames.emit(StreamEvent::Started(format));
+
+    // Whether this pass through the recording delivered anything, so that looping an empty
+    // recording does not spin.
+    let mut delivered = false;
+    let mut pacer = Pacer::new();
+    while running.load(Ordering::Acquire) && !frames.is_disconnected() {
+        if current().paced && !pacer.wait(running) {
+            break;
+        }
+        let playback = current();
+        let now = Instant::now();
+
+        if playback.format != format {
+            format = playback.format;
+            frames.emit(StreamEvent::FormatChanged(format));
+        }
+        match source.next_frame() {
+            Ok(Some(frame)) => {
+                if frames.send(frame).is_err() {
+                    break;
+                }
+                delivered = true;
+            }
+            Ok(None) if playback.looping && delivered => {
+                match open_source(path) {
+                    Ok(reopened) => source = reopened,
+                    Err(why) => {
+                        frames.stop(StopReason::Error(why));
+                        return;
+                    }
+                }
+                delivered = false;
+                continue;
+            }
+            Ok(None) => break,
+            Err(why) => {
+                frames.stop(StopReason::Error(why));
+                return;
+            }
+        }
+
+        let interval = frame_interval(format.frame_rate()).unwrap_or(Duration::from_secs(1));
+        pacer.schedule(now, interval);
+    }
+
+    frames.stop(StopReason::Requested);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nokhwa_core::y4m::Y4mWriter;
+
+    /// A recording, file or directory, that is removed again when dropped.
+    struct TempRecording(PathBuf);
+
+    impl TempRecording {
+        fn path(name: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("nokhwa-{name}-{}", std::process::id()))
+        }
+
+        fn file(name: &str, data: &[u8]) -> Self {
+            let path = Self::path(name);
+            std::fs::write(&path, data).unwrap();
+            Self(path)
+        }
+
+        fn directory(name: &str, files: &[(&str, &[u8])]) -> Self {
+            let path = Self::path(name);
+            std::fs::create_dir_all(&path).unwrap();
+            for (file, data) in files {
+                std::fs::write(path.join(file), data).unwrap();
+            }
+            Self(path)
+        }
+    }
+
+    impl Drop for TempRecording {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// A Y4M recording of 2x2 I420 frames at 25 frames per second, each filled with its index.
+    fn y4m(name: &str, frames: u8) -> TempRecording {
+        let format = CameraFormat::new(
+            Resolution::new(2, 2),
+            FrameFormat::I420,
+            FrameRate::frame_rate(25),
+        );
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        for idx in 0..frames {
+            let frame = FrameBuffer::new(Resolution::new(2, 2), &[idx; 6], FrameFormat::I420);
+            writer.write_frame(&frame).unwrap();
+        }
+        TempRecording::file(name, &writer.into_inner())
+    }
+
+    /// A JPEG image of a 4x2 frame, without any image data.
+    const JPEG: &[u8] = &[
+        0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x02, 0x00, 0x04, 0x01, 0x01, 0x11, 0x00,
+        0xFF, 0xD9,
+    ];
+
+    fn unpaced(path: &Path) -> PlaybackCamera {
+        let mut camera = PlaybackCamera::new(path).unwrap();
+        camera.set_paced(false);
+        camera
+    }
+
+    #[test]
+    fn plays_a_recording_to_its_end() {
+        let recording = y4m("playback-end.y4m", 3);
+        let mut camera = unpaced(&recording.0);
+        assert_eq!(
+            camera.recorded_format().frame_rate(),
+            FrameRate::frame_rate(25)
+        );
+
+        let stream = camera
+            .open_stream_with_policy(BackpressurePolicy::DropNewest(8))
+            .unwrap();
+        for idx in 0..3 {
+            assert_eq!(stream.poll_frame().unwrap().buffer(), &[idx; 6]);
+        }
+        assert!(stream.poll_frame().is_err());
+        assert!(matches!(
+            stream.events().try_recv(),
+            Ok(StreamEvent::Started(format)) if format == camera.recorded_format()
+        ));
+    }
+
+    #[test]
+    fn loops_when_asked() {
+        let recording = y4m("playback-loop.y4m", 2);
+        let mut camera = unpaced(&recording.0);
+        camera.set_looping(true);
+
+        let stream = camera
+            .open_stream_with_policy(BackpressurePolicy::DropNewest(8))
+            .unwrap();
+        let played = (0..5)
+            .map(|_| stream.poll_frame().unwrap().buffer()[0])
+            .collect::<Vec<_>>();
+        assert_eq!(played, [0, 1, 0, 1, 0]);
+        stream.stop_stream().unwrap();
+    }
+
+    #[test]
+    fn only_the_frame_rate_can_be_changed() {
+        let recording = y4m("playback-format.y4m", 1);
+        let camera = PlaybackCamera::new(&recording.0).unwrap();
+        let recorded = camera.recorded_format();
+
+        let faster = CameraFormat::new(
+            recorded.resolution(),
+            recorded.format(),
+            FrameRate::frame_rate(50),
+        );
+        camera.set_format(faster).unwrap();
+        assert_eq!(camera.format(), faster);
+        assert_eq!(camera.recorded_format(), recorded);
+
+        let larger = CameraFormat::new(
+            Resolution::new(4, 4),
+            recorded.format(),
+            recorded.frame_rate(),
+        );
+        assert!(camera.set_format(larger).is_err());
+    }
+
+    #[test]
+    fn plays_raw_mjpeg_skipping_what_is_between_images() {
+        let data = [JPEG, b"--boundary\r\n\r\n", JPEG].concat();
+        let recording = TempRecording::file("playback-raw.mjpeg", &data);
+        let mut source = open_source(&recording.0).unwrap();
+
+        assert_eq!(
+            source.format(),
+            CameraFormat::new(
+                Resolution::new(4, 2),
+                FrameFormat::MJpeg,
+                DEFAULT_FRAME_RATE
+            )
+        );
+        assert_eq!(source.next_frame().unwrap().unwrap().buffer(), JPEG);
+        assert_eq!(source.next_frame().unwrap().unwrap().buffer(), JPEG);
+        assert!(source.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn plays_images_in_the_order_of_their_names() {
+        let first: &[u8] = b"P5\n2 1\n255\n\x00\x00";
+        let second: &[u8] = b"P5\n2 1\n255\n\xFF\xFF";
+        let recording = TempRecording::directory(
+            "playback-images",
+            &[
+                ("b.pgm", second),
+                ("a.pgm", first),
+                ("notes.txt", b"skipped"),
+            ],
+        );
+        let mut source = open_source(&recording.0).unwrap();
+
+        assert_eq!(source.format().format(), FrameFormat::Rgb888);
+        assert_eq!(source.format().resolution(), Resolution::new(2, 1));
+        assert_eq!(source.next_frame().unwrap().unwrap().buffer(), &[0; 6]);
+        assert_eq!(source.next_frame().unwrap().unwrap().buffer(), &[0xFF; 6]);
+        assert!(source.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_what_it_does_not_understand() {
+        let recording = TempRecording::file("playback-unknown.bin", b"not a recording");
+        assert!(matches!(
+            PlaybackCamera::new(&recording.0),
+            Err(NokhwaError::OpenDeviceError(..))
+        ));
+    }
+}
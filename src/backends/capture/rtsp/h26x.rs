@@ -0,0 +1,553 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The RTP payload formats of H.264 (RFC 6184) and H.265 (RFC 7798), and reading the picture
+//! size out of their sequence parameter sets.
+
+use super::{rtp::Depacketizer, sdp::Codec};
+use nokhwa_core::types::Resolution;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// The largest access unit put back together, as large as a part read over HTTP may be. Larger
+/// ones are dropped rather than buffered for as long as the camera keeps sending.
+const MAX_ACCESS_UNIT: usize = 64 * 1024 * 1024;
+
+/// Reassembles access units in Annex B form, every NAL unit behind a start code.
+///
+/// Keyframes the camera sends without parameter sets get the last ones seen, from the session
+/// description or the stream, so that decoding can start at any keyframe.
+pub(super) struct H26xDepacketizer {
+    codec: Codec,
+    access_unit: Vec<u8>,
+    /// Where the NAL unit being added starts in `access_unit`.
+    nal_start: usize,
+    adding: Adding,
+    has_sps: bool,
+    keyframe: bool,
+    /// The last VPS, SPS and PPS seen. H.264 has no VPS.
+    parameter_sets: [Option<Vec<u8>>; 3],
+    resolution: Option<Resolution>,
+}
+
+impl H26xDepacketizer {
+    /// Depacketizes `codec`, H.264 or H.265, starting out with `parameter_sets`.
+    pub(super) fn new(codec: Codec, parameter_sets: &[Vec<u8>]) -> Self {
+        let mut depacketizer = Self {
+            codec,
+            access_unit: Vec::new(),
+            nal_start: 0,
+            adding: Adding::Units,
+            has_sps: false,
+            keyframe: false,
+            parameter_sets: [None, None, None],
+            resolution: None,
+        };
+        for set in parameter_sets {
+            depacketizer.begin_nal();
+            depacketizer.access_unit.extend_from_slice(set);
+            depacketizer.end_nal();
+        }
+        depacketizer.lose();
+        depacketizer
+    }
+
+    fn hevc(&self) -> bool {
+        self.codec == Codec::H265
+    }
+
+    fn header_length(&self) -> usize {
+        if self.hevc() {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn nal_kind(&self, nal: &[u8]) -> NalKind {
+        let Some(&header) = nal.first() else {
+            return NalKind::Other;
+        };
+        if self.hevc() {
+            match (header >> 1) & 0x3F {
+                16..=21 => NalKind::Keyframe,
+                32 => NalKind::ParameterSet(0),
+                33 => NalKind::ParameterSet(1),
+                34 => NalKind::ParameterSet(2),
+                _ => NalKind::Other,
+            }
+        } else {
+            match header & 0x1F {
+                5 => NalKind::Keyframe,
+                7 => NalKind::ParameterSet(1),
+                8 => NalKind::ParameterSet(2),
+                _ => NalKind::Other,
+            }
+        }
+    }
+
+    fn begin_nal(&mut self) {
+        self.access_unit.extend_from_slice(&START_CODE);
+        self.nal_start = self.access_unit.len();
+    }
+
+    fn end_nal(&mut self) {
+        let nal = &self.access_unit[self.nal_start..];
+        match self.nal_kind(nal) {
+            NalKind::Keyframe => self.keyframe = true,
+            NalKind::ParameterSet(index) => {
+                if index == 1 {
+                    self.has_sps = true;
+                    let size = if self.hevc() {
+                        h265_sps_resolution(nal)
+                    } else {
+                        h264_sps_resolution(nal)
+                    };
+                    self.resolution = size.or(self.resolution);
+                }
+                self.parameter_sets[index] = Some(nal.to_vec());
+            }
+            NalKind::Other => {}
+        }
+    }
+
+    fn single(&mut self, nal: &[u8]) {
+        if nal.len() < self.header_length() {
+            return;
+        }
+        self.begin_nal();
+        self.access_unit.extend_from_slice(nal);
+        self.end_nal();
+    }
+
+    /// Adds the NAL units of an aggregation packet, each behind a 16 bit size.
+    fn aggregated(&mut self, mut units: &[u8]) {
+        while let [high, low, rest @ ..] = units {
+            let size = usize::from(u16::from_be_bytes([*high, *low]));
+            let Some(nal) = rest.get(..size) else {
+                break;
+            };
+            self.single(nal);
+            units = &rest[size..];
+        }
+    }
+
+    /// Adds a fragment of a NAL unit whose header is `header`.
+    fn fragment(&mut self, header: &[u8], start: bool, end: bool, data: &[u8]) {
+        if start {
+            self.begin_nal();
+            self.access_unit.extend_from_slice(header);
+            self.adding = Adding::Fragment;
+        } else if self.adding != Adding::Fragment {
+            // The start of the NAL unit was lost.
+            return;
+        }
+        self.access_unit.extend_from_slice(data);
+        if end {
+            self.adding = Adding::Units;
+            self.end_nal();
+        }
+    }
+
+    /// Adds the NAL units of the payload of a packet.
+    fn depacketize(&mut self, payload: &[u8]) {
+        if self.hevc() {
+            match (payload[0] >> 1) & 0x3F {
+                48 => self.aggregated(&payload[2..]),
+                49 => {
+                    let Some(&[fu_header]) = payload.get(2..3) else {
+                        return;
+                    };
+                    let header = [(payload[0] & 0x81) | ((fu_header & 0x3F) << 1), payload[1]];
+                    let (start, end) = (fu_header & 0x80 != 0, fu_header & 0x40 != 0);
+                    self.fragment(&header, start, end, &payload[3..]);
+                }
+                // Payload content information only describes the packet.
+                50 => {}
+                _ => self.single(payload),
+            }
+        } else {
+            match payload[0] & 0x1F {
+                1..=23 => self.single(payload),
+                24 => self.aggregated(&payload[1..]),
+                28 => {
+                    let Some(&[fu_header]) = payload.get(1..2) else {
+                        return;
+                    };
+                    let header = [(payload[0] & 0xE0) | (fu_header & 0x1F)];
+                    let (start, end) = (fu_header & 0x80 != 0, fu_header & 0x40 != 0);
+                    self.fragment(&header, start, end, &payload[2..]);
+                }
+                // Interleaved packetization is not offered in the session description this
+                // client accepts.
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Depacketizer for H26xDepacketizer {
+    fn push(&mut self, payload: &[u8]) {
+        if payload.len() < self.header_length() || self.adding == Adding::Nothing {
+            return;
+        }
+        self.depacketize(payload);
+        if self.access_unit.len() > MAX_ACCESS_UNIT {
+            self.lose();
+            self.access_unit = Vec::new();
+            self.adding = Adding::Nothing;
+        }
+    }
+
+    fn finish(&mut self) -> Option<(Vec<u8>, Resolution)> {
+        if self.adding != Adding::Units {
+            // The end of the last NAL unit was lost, or the access unit was too large.
+            self.lose();
+            return None;
+        }
+        let mut access_unit = std::mem::take(&mut self.access_unit);
+        if self.keyframe && !self.has_sps {
+            let mut prefixed = Vec::with_capacity(access_unit.len());
+            for set in self.parameter_sets.iter().flatten() {
+                prefixed.extend_from_slice(&START_CODE);
+                prefixed.extend_from_slice(set);
+            }
+            prefixed.append(&mut access_unit);
+            access_unit = prefixed;
+        }
+        self.lose();
+        if access_unit.is_empty() {
+            return None;
+        }
+        Some((access_unit, self.resolution?))
+    }
+
+    fn lose(&mut self) {
+        self.access_unit.clear();
+        self.nal_start = 0;
+        self.adding = Adding::Units;
+        self.has_sps = false;
+        self.keyframe = false;
+    }
+}
+
+/// What the packets of the access unit are adding to it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Adding {
+    /// Whole NAL units.
+    Units,
+    /// A fragmented NAL unit.
+    Fragment,
+    /// Nothing, the access unit grew past [`MAX_ACCESS_UNIT`] and the rest of it is skipped.
+    Nothing,
+}
+
+#[derive(Copy, Clone)]
+enum NalKind {
+    Keyframe,
+    /// The VPS, SPS or PPS, by its index in [`H26xDepacketizer::parameter_sets`].
+    ParameterSet(usize),
+    Other,
+}
+
+/// Reads the bits of a NAL unit payload, with emulation prevention bytes removed.
+struct BitReader {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl BitReader {
+    fn new(payload: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(payload.len());
+        let mut zeros = 0;
+        for &byte in payload {
+            if zeros >= 2 && byte == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            data.push(byte);
+        }
+        Self { data, position: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Some(u32::from(bit))
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        (0..count).try_fold(0, |value, _| Some((value << 1) | self.bit()?))
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        self.position += count;
+        (self.position <= self.data.len() * 8).then_some(())
+    }
+
+    /// An unsigned Exp-Golomb code.
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    /// A signed Exp-Golomb code.
+    fn se(&mut self) -> Option<i64> {
+        let code = i64::from(self.ue()?);
+        Some(if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -code / 2
+        })
+    }
+}
+
+/// The cropped picture size of an H.264 SPS NAL unit, ITU-T H.264 7.3.2.1.1.
+fn h264_sps_resolution(nal: &[u8]) -> Option<Resolution> {
+    let mut bits = BitReader::new(nal.get(1..)?);
+    let profile = bits.bits(8)?;
+    bits.skip(16)?;
+    bits.ue()?;
+
+    let mut chroma_format = 1;
+    let mut separate_planes = false;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format = bits.ue()?;
+        if chroma_format == 3 {
+            separate_planes = bits.bit()? == 1;
+        }
+        bits.ue()?;
+        bits.ue()?;
+        bits.skip(1)?;
+        if bits.bit()? == 1 {
+            let lists = if chroma_format == 3 { 12 } else { 8 };
+            for list in 0..lists {
+                if bits.bit()? == 1 {
+                    skip_scaling_list(&mut bits, if list < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    bits.ue()?;
+    match bits.ue()? {
+        0 => {
+            bits.ue()?;
+        }
+        1 => {
+            bits.skip(1)?;
+            bits.se()?;
+            bits.se()?;
+            for _ in 0..bits.ue()? {
+                bits.se()?;
+            }
+        }
+        _ => {}
+    }
+    bits.ue()?;
+    bits.skip(1)?;
+    let width_in_macroblocks = bits.ue()? + 1;
+    let height_in_map_units = bits.ue()? + 1;
+    let frame_mbs_only = bits.bit()?;
+    if frame_mbs_only == 0 {
+        bits.skip(1)?;
+    }
+    bits.skip(1)?;
+
+    let mut width = width_in_macroblocks.checked_mul(16)?;
+    let mut height = (2 - frame_mbs_only)
+        .checked_mul(height_in_map_units)?
+        .checked_mul(16)?;
+    if bits.bit()? == 1 {
+        let (crop_x, crop_y) = if chroma_format == 0 || separate_planes {
+            (1, 2 - frame_mbs_only)
+        } else {
+            let sub_height = if chroma_format == 1 { 2 } else { 1 };
+            let sub_width = if chroma_format == 3 { 1 } else { 2 };
+            (sub_width, sub_height * (2 - frame_mbs_only))
+        };
+        let (left, right, top, bottom) = (bits.ue()?, bits.ue()?, bits.ue()?, bits.ue()?);
+        width = width.checked_sub(crop_x * left.checked_add(right)?)?;
+        height = height.checked_sub(crop_y * top.checked_add(bottom)?)?;
+    }
+    (width > 0 && height > 0).then(|| Resolution::new(width, height))
+}
+
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + bits.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/// The cropped picture size of an H.265 SPS NAL unit, ITU-T H.265 7.3.2.2.
+fn h265_sps_resolution(nal: &[u8]) -> Option<Resolution> {
+    let mut bits = BitReader::new(nal.get(2..)?);
+    bits.skip(4)?;
+    let sub_layers = bits.bits(3)? as usize;
+    bits.skip(1)?;
+
+    // profile_tier_level: the general profile and level, then which sub-layers have their own.
+    bits.skip(96)?;
+    let mut sub_layer_present = Vec::with_capacity(sub_layers);
+    for _ in 0..sub_layers {
+        sub_layer_present.push((bits.bit()? == 1, bits.bit()? == 1));
+    }
+    if sub_layers > 0 {
+        bits.skip(2 * (8 - sub_layers))?;
+    }
+    for (profile, level) in sub_layer_present {
+        if profile {
+            bits.skip(88)?;
+        }
+        if level {
+            bits.skip(8)?;
+        }
+    }
+
+    bits.ue()?;
+    let chroma_format = bits.ue()?;
+    if chroma_format == 3 {
+        bits.skip(1)?;
+    }
+    let mut width = bits.ue()?;
+    let mut height = bits.ue()?;
+    if bits.bit()? == 1 {
+        let sub_width = if chroma_format == 1 || chroma_format == 2 {
+            2
+        } else {
+            1
+        };
+        let sub_height = if chroma_format == 1 { 2 } else { 1 };
+        let (left, right, top, bottom) = (bits.ue()?, bits.ue()?, bits.ue()?, bits.ue()?);
+        width = width.checked_sub(sub_width * left.checked_add(right)?)?;
+        height = height.checked_sub(sub_height * top.checked_add(bottom)?)?;
+    }
+    (width > 0 && height > 0).then(|| Resolution::new(width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A baseline H.264 SPS of a single 16 by 16 macroblock.
+    const SPS: [u8; 6] = [0x67, 0x42, 0x00, 0x0A, 0xFB, 0x90];
+    const PPS: [u8; 4] = [0x68, 0xCE, 0x38, 0x80];
+
+    /// An H.264 FU-A packet carrying `data` of an IDR slice.
+    fn fu_a(start: bool, end: bool, data: &[u8]) -> Vec<u8> {
+        let mut fu_header = 5;
+        if start {
+            fu_header |= 0x80;
+        }
+        if end {
+            fu_header |= 0x40;
+        }
+        let mut packet = vec![0x7C, fu_header];
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn annex_b(nal_units: &[&[u8]]) -> Vec<u8> {
+        nal_units
+            .iter()
+            .flat_map(|nal| START_CODE.iter().chain(nal.iter()).copied())
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_resolution_of_an_h264_sps() {
+        assert_eq!(h264_sps_resolution(&SPS), Some(Resolution::new(16, 16)));
+        assert_eq!(h264_sps_resolution(&SPS[..4]), None);
+    }
+
+    #[test]
+    fn reassembles_fragmented_nal_units() {
+        let mut depacketizer = H26xDepacketizer::new(Codec::H264, &[SPS.to_vec(), PPS.to_vec()]);
+        depacketizer.push(&fu_a(true, false, &[1, 2]));
+        depacketizer.push(&fu_a(false, false, &[3, 4]));
+        depacketizer.push(&fu_a(false, true, &[5]));
+
+        // A keyframe without parameter sets gets those of the session description.
+        let (access_unit, resolution) = depacketizer.finish().unwrap();
+        assert_eq!(access_unit, annex_b(&[&SPS, &PPS, &[0x65, 1, 2, 3, 4, 5]]));
+        assert_eq!(resolution, Resolution::new(16, 16));
+    }
+
+    #[test]
+    fn splits_aggregation_packets() {
+        let mut depacketizer = H26xDepacketizer::new(Codec::H264, &[]);
+        let mut stap_a = vec![0x78];
+        for nal in [&SPS[..], &PPS[..]] {
+            stap_a.extend_from_slice(&u16::try_from(nal.len()).unwrap().to_be_bytes());
+            stap_a.extend_from_slice(nal);
+        }
+        depacketizer.push(&stap_a);
+        depacketizer.push(&[0x65, 1, 2]);
+
+        let (access_unit, resolution) = depacketizer.finish().unwrap();
+        assert_eq!(access_unit, annex_b(&[&SPS, &PPS, &[0x65, 1, 2]]));
+        assert_eq!(resolution, Resolution::new(16, 16));
+    }
+
+    #[test]
+    fn drops_nal_units_missing_a_fragment() {
+        let mut depacketizer = H26xDepacketizer::new(Codec::H264, &[SPS.to_vec(), PPS.to_vec()]);
+        depacketizer.push(&fu_a(true, false, &[1, 2]));
+        assert!(depacketizer.finish().is_none());
+
+        depacketizer.push(&fu_a(false, true, &[3, 4]));
+        assert!(depacketizer.finish().is_none());
+
+        depacketizer.push(&fu_a(true, true, &[6]));
+        assert!(depacketizer.finish().is_some());
+    }
+
+    #[test]
+    fn drops_access_units_over_the_size_limit() {
+        let mut depacketizer = H26xDepacketizer::new(Codec::H264, &[SPS.to_vec(), PPS.to_vec()]);
+        let megabyte = vec![0xAB; 1024 * 1024];
+        depacketizer.push(&fu_a(true, false, &[1]));
+        for _ in 0..=MAX_ACCESS_UNIT / megabyte.len() {
+            depacketizer.push(&fu_a(false, false, &megabyte));
+        }
+        depacketizer.push(&fu_a(false, true, &[2]));
+        assert!(depacketizer.access_unit.capacity() < MAX_ACCESS_UNIT);
+        assert!(depacketizer.finish().is_none());
+
+        depacketizer.push(&fu_a(true, true, &[3]));
+        let (access_unit, _) = depacketizer.finish().unwrap();
+        assert!(access_unit.ends_with(&[0x65, 3]));
+    }
+}
@@ -0,0 +1,279 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The RTP payload format of JPEG (RFC 2435), which sends only the scan of baseline images.
+//! The headers are rebuilt from the type, quality and size in every packet, following the
+//! appendices of the RFC.
+
+use super::rtp::Depacketizer;
+use nokhwa_core::types::Resolution;
+use std::collections::HashMap;
+
+/// The luma quantization table of quality 50, in zigzag order.
+const LUMA_QUANTIZER: [u8; 64] = [
+    16, 11, 12, 14, 12, 10, 16, 14, 13, 14, 18, 17, 16, 19, 24, 40, 26, 24, 22, 22, 24, 49, 35, 37,
+    29, 40, 58, 51, 61, 60, 57, 51, 56, 55, 64, 72, 92, 78, 64, 68, 87, 69, 55, 56, 80, 109, 81,
+    87, 95, 98, 103, 104, 103, 62, 77, 113, 121, 112, 100, 120, 92, 101, 103, 99,
+];
+
+/// The chroma quantization table of quality 50, in zigzag order.
+const CHROMA_QUANTIZER: [u8; 64] = [
+    17, 18, 18, 24, 21, 24, 47, 26, 26, 47, 99, 66, 56, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// The Huffman tables of ITU-T T.81 Annex K.3: code counts per length, then symbols.
+const LUMA_DC_CODES: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const LUMA_DC_SYMBOLS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const CHROMA_DC_CODES: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const CHROMA_DC_SYMBOLS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const LUMA_AC_CODES: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D];
+const LUMA_AC_SYMBOLS: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52, 0xD1, 0xF0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7,
+    0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5,
+    0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2,
+    0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+    0xF9, 0xFA,
+];
+const CHROMA_AC_CODES: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_SYMBOLS: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33, 0x52, 0xF0,
+    0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18, 0x19, 0x1A, 0x26,
+    0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5,
+    0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3,
+    0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA,
+    0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+    0xF9, 0xFA,
+];
+
+/// What the packets of the frame being assembled say about it.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct FrameHeader {
+    kind: u8,
+    quality: u8,
+    width: u16,
+    height: u16,
+    restart_interval: Option<u16>,
+}
+
+/// Reassembles JPEG images, putting headers in front of the scan data sent over RTP.
+#[derive(Default)]
+pub(super) struct JpegDepacketizer {
+    header: Option<FrameHeader>,
+    /// The quantization tables of the frame being assembled.
+    tables: Option<Vec<u8>>,
+    scan: Vec<u8>,
+    /// Whether a packet of the frame was lost or not understood.
+    broken: bool,
+    /// Tables sent in band, by quality, for frames that refer to them without sending them.
+    sent_tables: HashMap<u8, Vec<u8>>,
+}
+
+/// A packet of a frame.
+struct Fragment<'a> {
+    header: FrameHeader,
+    /// Where `scan` goes in the scan data of the frame.
+    offset: u32,
+    /// The quantization tables, sent in the first packet of a frame.
+    tables: Option<Vec<u8>>,
+    scan: &'a [u8],
+}
+
+impl JpegDepacketizer {
+    fn read<'a>(&mut self, payload: &'a [u8]) -> Option<Fragment<'a>> {
+        let main = payload.get(..8)?;
+        let offset = u32::from_be_bytes([0, main[1], main[2], main[3]]);
+        let mut header = FrameHeader {
+            kind: main[4],
+            quality: main[5],
+            width: u16::from(main[6]) * 8,
+            height: u16::from(main[7]) * 8,
+            restart_interval: None,
+        };
+        let mut rest = &payload[8..];
+
+        // Types 64 to 127 are types 0 to 63 with restart markers.
+        if (64..128).contains(&header.kind) {
+            let restart = rest.get(..4)?;
+            header.restart_interval = Some(u16::from_be_bytes([restart[0], restart[1]]));
+            header.kind -= 64;
+            rest = &rest[4..];
+        }
+        if header.kind > 1 || header.width == 0 || header.height == 0 {
+            return None;
+        }
+
+        let mut tables = None;
+        if header.quality >= 128 && offset == 0 {
+            let table_header = rest.get(..4)?;
+            // Only 8 bit tables are used by baseline images.
+            if table_header[1] != 0 {
+                return None;
+            }
+            let length = usize::from(u16::from_be_bytes([table_header[2], table_header[3]]));
+            let sent = rest.get(4..4 + length)?;
+            rest = &rest[4 + length..];
+            if length > 0 {
+                self.sent_tables.insert(header.quality, sent.to_vec());
+            }
+            tables = Some(self.sent_tables.get(&header.quality)?.clone());
+        }
+        Some(Fragment {
+            header,
+            offset,
+            tables,
+            scan: rest,
+        })
+    }
+}
+
+impl Depacketizer for JpegDepacketizer {
+    fn push(&mut self, payload: &[u8]) {
+        if self.broken {
+            return;
+        }
+        let Some(fragment) = self.read(payload) else {
+            self.broken = true;
+            return;
+        };
+        if fragment.offset == 0 {
+            self.header = Some(fragment.header);
+            self.tables = fragment.tables;
+            self.scan.clear();
+        }
+        // Fragments must follow each other in order and belong to the same image.
+        if self.header != Some(fragment.header) || fragment.offset as usize != self.scan.len() {
+            self.broken = true;
+            return;
+        }
+        self.scan.extend_from_slice(fragment.scan);
+    }
+
+    fn finish(&mut self) -> Option<(Vec<u8>, Resolution)> {
+        let header = self.header.take();
+        let tables = self.tables.take();
+        let scan = std::mem::take(&mut self.scan);
+        if std::mem::take(&mut self.broken) || scan.is_empty() {
+            return None;
+        }
+        let header = header?;
+
+        let tables = match tables {
+            Some(tables) => tables,
+            None if header.quality < 128 => default_tables(header.quality),
+            None => return None,
+        };
+        let mut image = headers(&header, &tables)?;
+        image.extend_from_slice(&scan);
+        if !image.ends_with(&[0xFF, 0xD9]) {
+            image.extend_from_slice(&[0xFF, 0xD9]);
+        }
+
+        let resolution = Resolution::new(u32::from(header.width), u32::from(header.height));
+        Some((image, resolution))
+    }
+
+    fn lose(&mut self) {
+        self.header = None;
+        self.tables = None;
+        self.scan.clear();
+        self.broken = false;
+    }
+}
+
+/// The luma and chroma tables for `quality` below 100, RFC 2435 appendix A.
+fn default_tables(quality: u8) -> Vec<u8> {
+    let quality = u32::from(quality.clamp(1, 99));
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    LUMA_QUANTIZER
+        .iter()
+        .chain(CHROMA_QUANTIZER.iter())
+        .map(|&value| ((u32::from(value) * scale + 50) / 100).clamp(1, 255) as u8)
+        .collect()
+}
+
+/// The headers of a baseline JPEG image up to its scan, RFC 2435 appendix B.
+fn headers(header: &FrameHeader, tables: &[u8]) -> Option<Vec<u8>> {
+    if tables.len() < 64 {
+        return None;
+    }
+    let mut image = vec![0xFF, 0xD8];
+
+    // One table of 64 values for luma, and one for chroma if sent.
+    let table_count = (tables.len() / 64).min(2);
+    for (index, table) in (0..).zip(tables.chunks_exact(64).take(table_count)) {
+        image.extend_from_slice(&[0xFF, 0xDB, 0, 67, index]);
+        image.extend_from_slice(table);
+    }
+
+    if let Some(interval) = header.restart_interval {
+        image.extend_from_slice(&[0xFF, 0xDD, 0, 4]);
+        image.extend_from_slice(&interval.to_be_bytes());
+    }
+
+    // Type 0 is 4:2:2, type 1 is 4:2:0.
+    let luma_sampling = if header.kind == 0 { 0x21 } else { 0x22 };
+    let chroma_table = u8::from(table_count == 2);
+    image.extend_from_slice(&[0xFF, 0xC0, 0, 17, 8]);
+    image.extend_from_slice(&header.height.to_be_bytes());
+    image.extend_from_slice(&header.width.to_be_bytes());
+    image.extend_from_slice(&[
+        3,
+        0,
+        luma_sampling,
+        0,
+        1,
+        0x11,
+        chroma_table,
+        2,
+        0x11,
+        chroma_table,
+    ]);
+
+    huffman_table(&mut image, 0x00, &LUMA_DC_CODES, &LUMA_DC_SYMBOLS);
+    huffman_table(&mut image, 0x10, &LUMA_AC_CODES, &LUMA_AC_SYMBOLS);
+    huffman_table(&mut image, 0x01, &CHROMA_DC_CODES, &CHROMA_DC_SYMBOLS);
+    huffman_table(&mut image, 0x11, &CHROMA_AC_CODES, &CHROMA_AC_SYMBOLS);
+
+    image.extend_from_slice(&[0xFF, 0xDA, 0, 12, 3, 0, 0x00, 1, 0x11, 2, 0x11, 0, 63, 0]);
+    Some(image)
+}
+
+// The standard tables are far shorter than a segment can be.
+#[allow(clippy::cast_possible_truncation)]
+fn huffman_table(image: &mut Vec<u8>, class_and_id: u8, codes: &[u8; 16], symbols: &[u8]) {
+    let length = (3 + codes.len() + symbols.len()) as u16;
+    image.extend_from_slice(&[0xFF, 0xC4]);
+    image.extend_from_slice(&length.to_be_bytes());
+    image.push(class_and_id);
+    image.extend_from_slice(codes);
+    image.extend_from_slice(symbols);
+}
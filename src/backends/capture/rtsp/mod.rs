@@ -0,0 +1,528 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A camera that plays an RTSP stream, as served by most IP cameras, without `OpenCV` or
+//! `GStreamer`.
+//!
+//! The camera describes, sets up and plays the first video stream of an `rtsp://` URL that is in
+//! H.264, H.265 or JPEG, with RTP interleaved on the RTSP connection or over UDP. The RTP
+//! packets are put back together into [`FrameFormat::H264`] and [`FrameFormat::H265`] access
+//! units in Annex B form, or into [`FrameFormat::MJpeg`] images, each with its presentation time
+//! as its [driver timestamp](FrameBuffer::driver_timestamp). Cameras asking for basic or digest
+//! authentication are answered with the user name and password of the URL, or those given to
+//! [`RtspCamera::with_credentials`].
+
+mod h26x;
+mod jpeg;
+mod rtp;
+mod sdp;
+mod session;
+
+use crate::backends::capture::network::{
+    reconnect, Authenticator, Credentials, Interrupt, NetworkStream, Player, Url,
+};
+use nokhwa_core::{
+    camera::{Camera, Capture, Setting},
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    platform::{Backends, PlatformTrait},
+    properties::{ControlId, ControlValue, Properties},
+    stream::{
+        events::{StopReason, StreamEvent},
+        frame_channel, BackpressurePolicy, FrameSender, Stream,
+    },
+    supervisor::BackoffPolicy,
+    types::{CameraFormat, CameraIndex, CameraInformation, FrameRate, Resolution},
+};
+use rtp::RtpReceiver;
+use sdp::Description;
+use session::Session;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The frame rate reported for streams whose session description does not announce one.
+const DEFAULT_FRAME_RATE: FrameRate = FrameRate::frame_rate(30);
+
+/// How long connecting, each request, and waiting for packets once playing may take by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How RTP packets are carried from the camera.
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum RtspTransport {
+    /// Interleaved on the RTSP connection. Slower to recover from congestion, but goes through
+    /// firewalls and NAT, and loses nothing.
+    #[default]
+    Tcp,
+    /// Over a pair of UDP ports. Packets may be lost, dropping the frames they were part of.
+    Udp,
+}
+
+/// A camera streaming over RTSP, see the [module documentation](self).
+///
+/// The camera is played when it is opened until its first frame, to learn its resolution. Its
+/// only format is that resolution, in the format of its codec, at the frame rate of its session
+/// description or a nominal 30 frames per second: the camera decides how fast frames arrive. If
+/// the camera changes its resolution mid-stream, the stream reports
+/// [`StreamEvent::FormatChanged`].
+///
+/// Streams are played over [`RtspTransport::Tcp`] by default, falling back to UDP if the camera
+/// cannot interleave. When the connection is lost, the stream reports
+/// [`StreamEvent::DeviceDisconnected`] and reconnects following its [`BackoffPolicy`], reporting
+/// [`StreamEvent::Started`] once it is back. Presentation times start over from zero on every
+/// connection. If the policy gives up, the stream stops with the last error.
+pub struct RtspCamera {
+    info: CameraInformation,
+    url: Url,
+    /// Answers the challenge the camera made when it was opened, so streams authenticate right
+    /// away.
+    authenticator: Authenticator,
+    transport: RtspTransport,
+    format: CameraFormat,
+    timeout: Duration,
+    backoff: BackoffPolicy,
+    properties: Properties,
+    player: Option<Player>,
+}
+
+impl RtspCamera {
+    /// Opens the camera at `url`, with the URL as its [`CameraIndex`]. A user name and password
+    /// in the URL are used to authenticate.
+    ///
+    /// # Errors
+    /// If the URL is not an `rtsp://` URL, or the camera does not play a video stream in a
+    /// supported codec.
+    pub fn new(url: &str) -> NokhwaResult<Self> {
+        Self::with_index(CameraIndex::String(url.to_string()), url)
+    }
+
+    /// Opens the camera at `url` as the camera at `index`.
+    ///
+    /// # Errors
+    /// If the URL is not an `rtsp://` URL, or the camera does not play a video stream in a
+    /// supported codec.
+    pub fn with_index(index: CameraIndex, url: &str) -> NokhwaResult<Self> {
+        let parsed = Url::parse(url, "rtsp", 554)
+            .map_err(|why| NokhwaError::OpenDeviceError(url.to_string(), why))?;
+        let credentials = parsed.credentials().cloned();
+        Self::open(index, url, parsed, credentials)
+    }
+
+    /// Opens the camera at `url`, authenticating as `username` with `password` rather than with
+    /// what the URL holds.
+    ///
+    /// # Errors
+    /// If the URL is not an `rtsp://` URL, or the camera does not play a video stream in a
+    /// supported codec.
+    pub fn with_credentials(url: &str, username: &str, password: &str) -> NokhwaResult<Self> {
+        let parsed = Url::parse(url, "rtsp", 554)
+            .map_err(|why| NokhwaError::OpenDeviceError(url.to_string(), why))?;
+        let credentials = Credentials::new(username.to_string(), password.to_string());
+        Self::open(
+            CameraIndex::String(url.to_string()),
+            url,
+            parsed,
+            Some(credentials),
+        )
+    }
+
+    fn open(
+        index: CameraIndex,
+        url: &str,
+        parsed: Url,
+        credentials: Option<Credentials>,
+    ) -> NokhwaResult<Self> {
+        let error = |why: String| NokhwaError::OpenDeviceError(url.to_string(), why);
+
+        let mut authenticator = Authenticator::new(credentials);
+        let mut transport = RtspTransport::default();
+        let (mut session, description) = Session::play(
+            &parsed,
+            &mut authenticator,
+            &mut transport,
+            DEFAULT_TIMEOUT,
+            &Interrupt::default(),
+        )
+        .map_err(error)?;
+
+        let mut receiver = RtpReceiver::new(description.codec, &description.media);
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        let resolution = loop {
+            if Instant::now() >= deadline {
+                return Err(error(
+                    "camera sent no frame of a known resolution".to_string(),
+                ));
+            }
+            let Some(packet) = session.next_packet().map_err(error)? else {
+                continue;
+            };
+            if let Some(frame) = receiver.receive(&packet).pop() {
+                break frame.resolution;
+            }
+        };
+        session.teardown();
+
+        Ok(Self {
+            info: CameraInformation::new(
+                url.to_string(),
+                "RTSP".to_string(),
+                url.to_string(),
+                index,
+            ),
+            url: parsed,
+            authenticator,
+            transport,
+            format: CameraFormat::new(
+                resolution,
+                description.codec.frame_format(),
+                description.media.frame_rate.unwrap_or(DEFAULT_FRAME_RATE),
+            ),
+            timeout: DEFAULT_TIMEOUT,
+            backoff: BackoffPolicy::default(),
+            properties: Properties::new(HashMap::new()),
+            player: None,
+        })
+    }
+
+    /// The [`CameraInformation`] of this camera.
+    #[must_use]
+    pub fn info(&self) -> &CameraInformation {
+        &self.info
+    }
+
+    /// The format of the first frame the camera sent.
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    /// How RTP packets are carried. After opening, the transport that worked.
+    #[must_use]
+    pub fn transport(&self) -> RtspTransport {
+        self.transport
+    }
+
+    /// How RTP packets are carried, falling back to UDP if the camera cannot interleave them.
+    /// Applies to streams opened afterwards.
+    pub fn set_transport(&mut self, transport: RtspTransport) {
+        self.transport = transport;
+    }
+
+    /// How long connecting, each request, and waiting for packets once playing may take before
+    /// the connection is considered lost. Applies to streams opened afterwards.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// How a lost connection is retried. Applies to streams opened afterwards.
+    pub fn set_backoff(&mut self, backoff: BackoffPolicy) {
+        self.backoff = backoff;
+    }
+}
+
+impl Setting for RtspCamera {
+    fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError> {
+        Ok(vec![self.format])
+    }
+
+    fn enumerate_resolution_and_frame_rates(
+        &self,
+        frame_format: FrameFormat,
+    ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError> {
+        if frame_format != self.format.format() {
+            return Ok(HashMap::new());
+        }
+        Ok(HashMap::from([(
+            self.format.resolution(),
+            vec![self.format.frame_rate()],
+        )]))
+    }
+
+    /// Accepts the format of the camera at any frame rate, which the camera ignores.
+    ///
+    /// # Errors
+    /// If the resolution or [`FrameFormat`] are not those of the camera.
+    fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError> {
+        if camera_format.resolution() == self.format.resolution()
+            && camera_format.format() == self.format.format()
+        {
+            Ok(())
+        } else {
+            Err(NokhwaError::SetPropertyError {
+                property: "format".to_string(),
+                value: camera_format.to_string(),
+                error: "network cameras stream in the format they are configured with".to_string(),
+            })
+        }
+    }
+
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn set_property(
+        &mut self,
+        property: &ControlId,
+        value: ControlValue,
+    ) -> Result<(), NokhwaError> {
+        Err(NokhwaError::SetPropertyError {
+            property: property.to_string(),
+            value: value.to_string(),
+            error: "network cameras have no controls".to_string(),
+        })
+    }
+}
+
+impl Capture for RtspCamera {
+    fn open_stream(&mut self) -> Result<Stream, NokhwaError> {
+        self.open_stream_with_policy(BackpressurePolicy::default())
+    }
+
+    /// Connects to the camera and starts playing.
+    ///
+    /// # Errors
+    /// If a stream is already open, or the camera cannot be played. Connections lost later are
+    /// retried on the stream.
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        if self.player.as_ref().is_some_and(Player::is_running) {
+            return Err(NokhwaError::OpenStreamError(
+                "a stream is already open".to_string(),
+            ));
+        }
+
+        let mut connection = Connection {
+            url: self.url.clone(),
+            authenticator: self.authenticator.clone(),
+            transport: self.transport,
+            timeout: self.timeout,
+            interrupt: Arc::new(Interrupt::default()),
+        };
+        let playing = connection.open().map_err(NokhwaError::OpenStreamError)?;
+
+        let (sender, receiver) = frame_channel(policy);
+        let (format, backoff) = (self.format, self.backoff);
+        let interrupt = connection.interrupt.clone();
+        let (player, handle) = Player::spawn("nokhwa-rtsp", interrupt, move |running| {
+            let receiving = Receiving {
+                frames: sender,
+                connection,
+                format,
+                backoff,
+                running,
+            };
+            receiving.run(playing);
+        })?;
+
+        self.player = Some(player.clone());
+        Ok(Stream::new(Box::new(NetworkStream::new(
+            receiver, player, handle,
+        ))))
+    }
+
+    fn close_stream(&mut self) -> Result<(), NokhwaError> {
+        if let Some(player) = self.player.take() {
+            player.stop();
+        }
+        Ok(())
+    }
+}
+
+impl Camera for RtspCamera {}
+
+impl Drop for RtspCamera {
+    fn drop(&mut self) {
+        let _ = self.close_stream();
+    }
+}
+
+/// A [`PlatformTrait`] that lists network cameras as [`RtspCamera`]s.
+///
+/// The cameras are at indices `0..` in the order their URLs were given. Querying does not
+/// contact them. A [`CameraIndex::String`] opens the camera at that URL, whether it is listed or
+/// not.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct RtspPlatform {
+    urls: Vec<String>,
+}
+
+impl RtspPlatform {
+    /// Creates a platform listing the cameras at `urls`.
+    #[must_use]
+    pub fn new(urls: Vec<String>) -> Self {
+        Self { urls }
+    }
+
+    /// Adds the camera at `url` to the end of the list.
+    pub fn add(&mut self, url: impl Into<String>) {
+        self.urls.push(url.into());
+    }
+}
+
+impl PlatformTrait for RtspPlatform {
+    const PLATFORM: Backends = Backends::Custom("rtsp");
+    type Camera = RtspCamera;
+
+    fn block_on_permission(&mut self) -> NokhwaResult<()> {
+        Ok(())
+    }
+
+    fn check_permission_given(&mut self) -> bool {
+        true
+    }
+
+    fn query(&mut self) -> NokhwaResult<Vec<CameraInformation>> {
+        Ok(self
+            .urls
+            .iter()
+            .zip(0..)
+            .map(|(url, index)| {
+                CameraInformation::new(
+                    url.clone(),
+                    "RTSP".to_string(),
+                    url.clone(),
+                    CameraIndex::Index(index),
+                )
+            })
+            .collect())
+    }
+
+    fn open(&mut self, index: &CameraIndex) -> NokhwaResult<Self::Camera> {
+        match index {
+            CameraIndex::Index(number) => {
+                let url = usize::try_from(*number)
+                    .ok()
+                    .and_then(|number| self.urls.get(number))
+                    .ok_or_else(|| {
+                        NokhwaError::OpenDeviceError(
+                            index.to_string(),
+                            "no such camera".to_string(),
+                        )
+                    })?;
+                RtspCamera::with_index(index.clone(), url)
+            }
+            CameraIndex::String(url) => RtspCamera::new(url),
+        }
+    }
+}
+
+/// What is needed to play the camera again.
+struct Connection {
+    url: Url,
+    authenticator: Authenticator,
+    transport: RtspTransport,
+    timeout: Duration,
+    interrupt: Arc<Interrupt>,
+}
+
+impl Connection {
+    fn open(&mut self) -> Result<(Session, Description), String> {
+        Session::play(
+            &self.url,
+            &mut self.authenticator,
+            &mut self.transport,
+            self.timeout,
+            &self.interrupt,
+        )
+    }
+}
+
+/// The receiving thread of one stream.
+struct Receiving {
+    frames: FrameSender,
+    connection: Connection,
+    format: CameraFormat,
+    backoff: BackoffPolicy,
+    running: Arc<AtomicBool>,
+}
+
+impl Receiving {
+    fn run(mut self, mut playing: (Session, Description)) {
+        self.frames.emit(StreamEvent::Started(self.format));
+        loop {
+            let (mut session, description) = playing;
+            self.receive(&mut session, &description);
+            if !self.is_running() {
+                // Whether this gets through depends on how the stream was stopped: closing the
+                // connection ends the session as well.
+                session.teardown();
+                break;
+            }
+            self.frames.emit(StreamEvent::DeviceDisconnected);
+            match self.reconnect() {
+                Ok(reconnected) => playing = reconnected,
+                Err(reason) => {
+                    self.frames.stop(reason);
+                    return;
+                }
+            }
+            self.frames.emit(StreamEvent::Started(self.format));
+        }
+        self.frames.stop(StopReason::Requested);
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire) && !self.frames.is_disconnected()
+    }
+
+    /// Delivers frames until the connection is lost or the stream is stopped.
+    fn receive(&mut self, session: &mut Session, description: &Description) {
+        let frame_format = description.codec.frame_format();
+        let mut receiver = RtpReceiver::new(description.codec, &description.media);
+        while self.is_running() {
+            let packet = match session.next_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => continue,
+                Err(_) => return,
+            };
+            let frames = receiver.receive(&packet);
+            self.frames.skip(receiver.take_dropped());
+            for frame in frames {
+                if frame.resolution != self.format.resolution()
+                    || frame_format != self.format.format()
+                {
+                    self.format =
+                        CameraFormat::new(frame.resolution, frame_format, self.format.frame_rate());
+                    self.frames.emit(StreamEvent::FormatChanged(self.format));
+                }
+                let mut buffer = FrameBuffer::from_vec(frame.resolution, frame.data, frame_format);
+                buffer.set_driver_timestamp(Some(frame.presentation));
+                if self.frames.send(buffer).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Connects again following the backoff policy, or returns why the stream stops.
+    fn reconnect(&mut self) -> Result<(Session, Description), StopReason> {
+        let (running, frames) = (&self.running, &self.frames);
+        let connection = &mut self.connection;
+        reconnect(
+            &self.backoff,
+            || running.load(Ordering::Acquire) && !frames.is_disconnected(),
+            || connection.open(),
+        )
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{
+    h26x::H26xDepacketizer,
+    jpeg::JpegDepacketizer,
+    sdp::{Codec, Media},
+};
+use nokhwa_core::types::Resolution;
+use std::time::Duration;
+
+/// Puts frames back together from the payloads of their RTP packets.
+pub(super) trait Depacketizer: Send {
+    /// Adds the payload of the next packet of the frame being assembled.
+    fn push(&mut self, payload: &[u8]);
+
+    /// Ends the frame being assembled, returning it with its resolution unless a part of it was
+    /// lost or its resolution is not known yet.
+    fn finish(&mut self) -> Option<(Vec<u8>, Resolution)>;
+
+    /// Drops the frame being assembled, after one of its packets was lost.
+    fn lose(&mut self);
+}
+
+/// A frame put back together from RTP packets.
+pub(super) struct RtpFrame {
+    pub(super) data: Vec<u8>,
+    pub(super) resolution: Resolution,
+    /// The presentation time, from the RTP timestamp of the first packet received.
+    pub(super) presentation: Duration,
+}
+
+/// Turns the RTP packets of one stream into frames.
+///
+/// Packets are expected in order, as TCP delivers them and a local network nearly always does.
+/// A frame missing a packet is dropped, along with the rest of its packets.
+pub(super) struct RtpReceiver {
+    payload_type: u8,
+    clock_rate: u64,
+    depacketizer: Box<dyn Depacketizer>,
+    last_sequence: Option<u16>,
+    /// The RTP timestamp of the frame being assembled.
+    timestamp: Option<u32>,
+    /// The RTP timestamp of a frame whose packets are being skipped after a loss.
+    skipping: Option<u32>,
+    /// The timestamp of the first packet, and how far timestamps have advanced since, counting
+    /// wraparounds.
+    first_timestamp: Option<u32>,
+    elapsed: i64,
+    /// Frames dropped since [`RtpReceiver::take_dropped`] was last called.
+    dropped: u64,
+}
+
+impl RtpReceiver {
+    pub(super) fn new(codec: Codec, media: &Media) -> Self {
+        let depacketizer: Box<dyn Depacketizer> = match codec {
+            Codec::H264 | Codec::H265 => {
+                Box::new(H26xDepacketizer::new(codec, &media.parameter_sets))
+            }
+            Codec::Jpeg => Box::<JpegDepacketizer>::default(),
+        };
+        Self {
+            payload_type: media.payload_type,
+            clock_rate: u64::from(media.clock_rate.max(1)),
+            depacketizer,
+            last_sequence: None,
+            timestamp: None,
+            skipping: None,
+            first_timestamp: None,
+            elapsed: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Reads an RTP packet, returning the frames it completes. Packets that are not RTP or of
+    /// another payload type are ignored.
+    pub(super) fn receive(&mut self, packet: &[u8]) -> Vec<RtpFrame> {
+        let mut frames = Vec::new();
+        let Some(packet) = RtpPacket::parse(packet) else {
+            return frames;
+        };
+        if packet.payload_type != self.payload_type {
+            return frames;
+        }
+
+        let lost = self
+            .last_sequence
+            .is_some_and(|last| packet.sequence != last.wrapping_add(1));
+        self.last_sequence = Some(packet.sequence);
+
+        if lost {
+            // The frame being assembled is dropped, and so is the frame of this packet, whose
+            // start may have been lost.
+            let assembling = self.timestamp.take();
+            if assembling.is_some() {
+                self.depacketizer.lose();
+            }
+            if self.skipping != Some(packet.timestamp) {
+                self.dropped += 1;
+            }
+            if assembling.is_some_and(|timestamp| timestamp != packet.timestamp) {
+                self.dropped += 1;
+            }
+            self.skipping = Some(packet.timestamp);
+        } else if self
+            .timestamp
+            .is_some_and(|timestamp| timestamp != packet.timestamp)
+        {
+            // A new timestamp starts a new frame, even if the marker of the last one was lost.
+            frames.extend(self.finish());
+        }
+        if self.skipping == Some(packet.timestamp) {
+            return frames;
+        }
+        self.skipping = None;
+
+        self.timestamp = Some(packet.timestamp);
+        self.depacketizer.push(packet.payload);
+        if packet.marker {
+            frames.extend(self.finish());
+        }
+        frames
+    }
+
+    /// How many frames were dropped since the last call, because packets were lost or a frame
+    /// could not be put back together.
+    pub(super) fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+
+    fn finish(&mut self) -> Option<RtpFrame> {
+        let timestamp = self.timestamp.take()?;
+        let Some((data, resolution)) = self.depacketizer.finish() else {
+            self.dropped += 1;
+            return None;
+        };
+        Some(RtpFrame {
+            data,
+            resolution,
+            presentation: self.presentation(timestamp),
+        })
+    }
+
+    /// The time since the first packet, at `timestamp`.
+    fn presentation(&mut self, timestamp: u32) -> Duration {
+        let first = *self.first_timestamp.get_or_insert(timestamp);
+        // Timestamps wrap around every 13 hours at 90 kHz, and may step back for B-frames.
+        let since_first = i64::from(timestamp.wrapping_sub(first));
+        let laps = (self.elapsed - since_first + (1 << 31)).div_euclid(1 << 32);
+        self.elapsed = since_first + laps * (1 << 32);
+
+        let ticks = u64::try_from(self.elapsed).unwrap_or(0);
+        Duration::from_nanos(
+            u64::try_from(u128::from(ticks) * 1_000_000_000 / u128::from(self.clock_rate))
+                .unwrap_or(u64::MAX),
+        )
+    }
+}
+
+/// The fields of an RTP packet that are needed to depacketize it, RFC 3550 5.1.
+struct RtpPacket<'a> {
+    marker: bool,
+    payload_type: u8,
+    sequence: u16,
+    timestamp: u32,
+    payload: &'a [u8],
+}
+
+impl<'a> RtpPacket<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        let header = packet.get(..12)?;
+        if header[0] >> 6 != 2 {
+            return None;
+        }
+        let padding = header[0] & 0x20 != 0;
+        let extension = header[0] & 0x10 != 0;
+        let csrc_count = usize::from(header[0] & 0x0F);
+
+        let mut start = 12 + 4 * csrc_count;
+        if extension {
+            let extension_header = packet.get(start..start + 4)?;
+            let words = usize::from(u16::from_be_bytes([
+                extension_header[2],
+                extension_header[3],
+            ]));
+            start += 4 + 4 * words;
+        }
+        let mut end = packet.len();
+        if padding {
+            end = end.checked_sub(usize::from(*packet.last()?))?;
+        }
+
+        Some(Self {
+            marker: header[1] & 0x80 != 0,
+            payload_type: header[1] & 0x7F,
+            sequence: u16::from_be_bytes([header[2], header[3]]),
+            timestamp: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            payload: packet.get(start..end)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JPEG_PAYLOAD_TYPE: u8 = 26;
+
+    fn receiver() -> RtpReceiver {
+        let media = Media {
+            codec: Some(Codec::Jpeg),
+            payload_type: JPEG_PAYLOAD_TYPE,
+            clock_rate: 90_000,
+            control: String::new(),
+            frame_rate: None,
+            parameter_sets: Vec::new(),
+        };
+        RtpReceiver::new(Codec::Jpeg, &media)
+    }
+
+    /// An RTP packet carrying `scan` at `offset` of a 16 by 8 JPEG image.
+    fn packet(sequence: u16, timestamp: u32, marker: bool, offset: u8, scan: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, JPEG_PAYLOAD_TYPE | if marker { 0x80 } else { 0 }];
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 1]);
+        packet.extend_from_slice(&[0, 0, 0, offset, 0, 50, 2, 1]);
+        packet.extend_from_slice(scan);
+        packet
+    }
+
+    #[test]
+    fn puts_frames_back_together() {
+        let mut receiver = receiver();
+        assert!(receiver
+            .receive(&packet(7, 9000, false, 0, &[1, 2]))
+            .is_empty());
+        let frames = receiver.receive(&packet(8, 9000, true, 2, &[3, 4]));
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].resolution, Resolution::new(16, 8));
+        assert!(frames[0].data.ends_with(&[1, 2, 3, 4, 0xFF, 0xD9]));
+        assert_eq!(frames[0].presentation, Duration::ZERO);
+        assert_eq!(receiver.take_dropped(), 0);
+    }
+
+    #[test]
+    fn ignores_other_packets() {
+        let mut receiver = receiver();
+        let mut other = packet(1, 0, true, 0, &[1]);
+        // Payload type 96, with the marker set.
+        other[1] = 0xE0;
+        assert!(receiver.receive(&other).is_empty());
+        assert!(receiver.receive(&[0x80, 26]).is_empty());
+        assert_eq!(receiver.take_dropped(), 0);
+    }
+
+    #[test]
+    fn drops_frames_missing_a_packet() {
+        let mut receiver = receiver();
+        assert!(receiver.receive(&packet(1, 0, false, 0, &[1])).is_empty());
+        // Packet 2 is lost, so the rest of the frame is skipped.
+        assert!(receiver.receive(&packet(3, 0, true, 2, &[3])).is_empty());
+        assert_eq!(receiver.take_dropped(), 1);
+
+        assert_eq!(receiver.receive(&packet(4, 3000, true, 0, &[4])).len(), 1);
+        assert_eq!(receiver.take_dropped(), 0);
+    }
+
+    #[test]
+    fn counts_frames_lost_entirely() {
+        let mut receiver = receiver();
+        assert_eq!(receiver.receive(&packet(65535, 0, true, 0, &[1])).len(), 1);
+        // The sequence number wraps around, then the frame at 6000 starts with a lost packet.
+        assert_eq!(receiver.receive(&packet(0, 3000, true, 0, &[2])).len(), 1);
+        assert!(receiver.receive(&packet(2, 6000, true, 1, &[3])).is_empty());
+        assert_eq!(receiver.take_dropped(), 1);
+
+        // Both the frame being assembled and the frame of the next packet are lost.
+        assert!(receiver
+            .receive(&packet(3, 9000, false, 0, &[4]))
+            .is_empty());
+        assert!(receiver
+            .receive(&packet(5, 12000, true, 1, &[5]))
+            .is_empty());
+        assert_eq!(receiver.take_dropped(), 2);
+    }
+
+    #[test]
+    fn presentation_times_survive_timestamp_wraparound() {
+        let mut receiver = receiver();
+        let first = u32::MAX - 89_999;
+        assert_eq!(receiver.presentation(first), Duration::ZERO);
+        assert_eq!(
+            receiver.presentation(first.wrapping_add(180_000)),
+            Duration::from_secs(2)
+        );
+        // B-frames step back in time.
+        assert_eq!(
+            receiver.presentation(first.wrapping_add(90_000)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            receiver.presentation(first.wrapping_add(270_000)),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn parses_header_extensions_and_padding() {
+        let mut packet = vec![0xB1, 0x80 | JPEG_PAYLOAD_TYPE, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3];
+        // One contributing source, an extension of one word, then the payload and padding.
+        packet.extend_from_slice(&[0, 0, 0, 4]);
+        packet.extend_from_slice(&[0xBE, 0xDE, 0, 1, 9, 9, 9, 9]);
+        packet.extend_from_slice(&[7, 8]);
+        packet.extend_from_slice(&[0, 0, 3]);
+
+        let parsed = RtpPacket::parse(&packet).unwrap();
+        assert!(parsed.marker);
+        assert_eq!(parsed.payload_type, JPEG_PAYLOAD_TYPE);
+        assert_eq!(parsed.sequence, 1);
+        assert_eq!(parsed.timestamp, 2);
+        assert_eq!(parsed.payload, &[7, 8]);
+    }
+}
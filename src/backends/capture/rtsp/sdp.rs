@@ -0,0 +1,320 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use base64::Engine;
+use nokhwa_core::{frame_format::FrameFormat, types::FrameRate};
+use std::num::NonZeroI32;
+
+/// The payload type RFC 3551 assigns to JPEG.
+const STATIC_JPEG: u8 = 26;
+
+/// The video codecs that can be depacketized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Codec {
+    H264,
+    H265,
+    Jpeg,
+}
+
+impl Codec {
+    fn from_encoding(encoding: &str) -> Option<Self> {
+        match encoding.to_ascii_uppercase().as_str() {
+            "H264" => Some(Codec::H264),
+            "H265" | "HEVC" => Some(Codec::H265),
+            "JPEG" => Some(Codec::Jpeg),
+            _ => None,
+        }
+    }
+
+    pub(super) fn frame_format(self) -> FrameFormat {
+        match self {
+            Codec::H264 => FrameFormat::H264,
+            Codec::H265 => FrameFormat::H265,
+            Codec::Jpeg => FrameFormat::MJpeg,
+        }
+    }
+}
+
+/// The video stream a session description offers.
+#[derive(Clone, Debug)]
+pub(super) struct Media {
+    /// `None` for codecs that cannot be depacketized.
+    pub(super) codec: Option<Codec>,
+    pub(super) payload_type: u8,
+    /// The rate of the RTP clock, in Hz.
+    pub(super) clock_rate: u32,
+    /// The URL to set the stream up with.
+    pub(super) control: String,
+    pub(super) frame_rate: Option<FrameRate>,
+    /// The parameter sets from `sprop-*` parameters, without start codes: the VPS, SPS and PPS of
+    /// H.265, or the SPS and PPS of H.264.
+    pub(super) parameter_sets: Vec<Vec<u8>>,
+}
+
+impl Media {
+    fn new(payload_type: u8, control: String) -> Self {
+        Self {
+            // Static payload types need no `rtpmap`.
+            codec: (payload_type == STATIC_JPEG).then_some(Codec::Jpeg),
+            payload_type,
+            clock_rate: 90_000,
+            control,
+            frame_rate: None,
+            parameter_sets: Vec::new(),
+        }
+    }
+
+    /// Takes up an `a=` line of this stream.
+    fn attribute(&mut self, attribute: &str, base: &str) {
+        let (name, value) = attribute.split_once(':').unwrap_or((attribute, ""));
+        let payload_type = self.payload_type;
+        let for_payload = |value: &str| {
+            let (format, rest) = value.split_once(' ')?;
+            let format = format.parse::<u8>().ok()?;
+            (format == payload_type).then(|| rest.trim().to_string())
+        };
+        match name {
+            "control" => self.control = resolve(base, value.trim()),
+            "rtpmap" => {
+                let Some(map) = for_payload(value) else {
+                    return;
+                };
+                let mut fields = map.split('/');
+                self.codec = fields.next().and_then(Codec::from_encoding);
+                if let Some(clock_rate) = fields.next().and_then(|rate| rate.parse().ok()) {
+                    self.clock_rate = clock_rate;
+                }
+            }
+            "fmtp" => {
+                let Some(parameters) = for_payload(value) else {
+                    return;
+                };
+                for parameter in parameters.split(';') {
+                    let Some((key, value)) = parameter.trim().split_once('=') else {
+                        continue;
+                    };
+                    let key = key.trim().to_ascii_lowercase();
+                    if matches!(
+                        key.as_str(),
+                        "sprop-parameter-sets" | "sprop-vps" | "sprop-sps" | "sprop-pps"
+                    ) {
+                        self.parameter_sets
+                            .extend(value.split(',').filter_map(|set| {
+                                base64::engine::general_purpose::STANDARD
+                                    .decode(set.trim())
+                                    .ok()
+                                    .filter(|set| !set.is_empty())
+                            }));
+                    }
+                }
+            }
+            "framerate" | "x-framerate" => self.frame_rate = parse_frame_rate(value.trim()),
+            _ => {}
+        }
+    }
+}
+
+/// What a session description offers to play.
+pub(super) struct Description {
+    /// The URL to control the whole session with.
+    pub(super) control: String,
+    pub(super) codec: Codec,
+    pub(super) media: Media,
+}
+
+/// Reads the first video stream in a supported codec out of the session description `sdp`.
+/// Relative control URLs are resolved against `base`.
+pub(super) fn parse(sdp: &str, base: &str) -> Result<Description, String> {
+    let mut base = base.to_string();
+    let mut offered: Vec<Media> = Vec::new();
+    // Whether the lines being read describe a stream rather than the session, and if so whether
+    // it is a video stream.
+    let mut in_media = false;
+    let mut in_video = false;
+
+    for line in sdp.lines().map(str::trim) {
+        let Some((kind, value)) = line.split_once('=') else {
+            continue;
+        };
+        match kind {
+            "m" => {
+                in_media = true;
+                let mut fields = value.split_whitespace();
+                in_video = fields.next() == Some("video");
+                // The format list comes after the port and protocol. Only the first format is
+                // played.
+                let payload_type = fields.nth(2).and_then(|format| format.parse().ok());
+                match payload_type {
+                    Some(payload_type) if in_video => {
+                        offered.push(Media::new(payload_type, base.clone()));
+                    }
+                    _ => in_video = false,
+                }
+            }
+            "a" if !in_media => {
+                if let Some(control) = value.strip_prefix("control:") {
+                    base = resolve(&base, control.trim());
+                }
+            }
+            "a" if in_video => {
+                if let Some(media) = offered.last_mut() {
+                    media.attribute(value, &base);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if offered.is_empty() {
+        return Err("camera offers no video stream".to_string());
+    }
+    offered
+        .into_iter()
+        .find_map(|media| {
+            Some(Description {
+                control: base.clone(),
+                codec: media.codec?,
+                media,
+            })
+        })
+        .ok_or_else(|| "camera offers no video in H.264, H.265 or JPEG".to_string())
+}
+
+/// Parses a decimal frame rate like `25` or `29.97`.
+fn parse_frame_rate(value: &str) -> Option<FrameRate> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let fraction = &fraction[..fraction.len().min(3)];
+    let denominator = 10_i32.pow(u32::try_from(fraction.len()).ok()?);
+    let numerator = whole
+        .parse::<i32>()
+        .ok()?
+        .checked_mul(denominator)?
+        .checked_add(if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse::<i32>().ok()?
+        })?;
+    let denominator = NonZeroI32::new(denominator)?;
+    (numerator > 0).then(|| FrameRate::new(numerator, denominator))
+}
+
+/// Resolves the `control` attribute against the URL `base`.
+pub(super) fn resolve(base: &str, control: &str) -> String {
+    if control.contains("://") {
+        return control.to_string();
+    }
+    if control == "*" || control.is_empty() {
+        return base.to_string();
+    }
+    if control.starts_with('/') {
+        let authority_end = base
+            .find("://")
+            .and_then(|start| base[start + 3..].find('/').map(|end| start + 3 + end))
+            .unwrap_or(base.len());
+        return format!("{}{control}", &base[..authority_end]);
+    }
+    if base.ends_with('/') {
+        format!("{base}{control}")
+    } else {
+        format!("{base}/{control}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SDP: &str = "v=0\r\n\
+        o=- 0 0 IN IP4 192.168.1.10\r\n\
+        s=Camera\r\n\
+        a=control:*\r\n\
+        m=audio 0 RTP/AVP 0\r\n\
+        a=control:trackID=0\r\n\
+        m=video 0 RTP/AVP 96\r\n\
+        a=rtpmap:96 H264/90000\r\n\
+        a=fmtp:96 packetization-mode=1; sprop-parameter-sets=Z0IACvuQ,aM44gA==\r\n\
+        a=framerate:29.97\r\n\
+        a=control:trackID=1\r\n";
+
+    #[test]
+    fn reads_the_first_video_stream() {
+        let description = parse(SDP, "rtsp://camera/live").unwrap();
+        assert_eq!(description.control, "rtsp://camera/live");
+        assert_eq!(description.codec, Codec::H264);
+        assert_eq!(description.media.payload_type, 96);
+        assert_eq!(description.media.clock_rate, 90_000);
+        assert_eq!(description.media.control, "rtsp://camera/live/trackID=1");
+        assert_eq!(
+            description.media.frame_rate,
+            Some(FrameRate::new(29970, NonZeroI32::new(1000).unwrap()))
+        );
+        assert_eq!(
+            description.media.parameter_sets,
+            [
+                vec![0x67, 0x42, 0x00, 0x0A, 0xFB, 0x90],
+                vec![0x68, 0xCE, 0x38, 0x80]
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_streams_in_other_codecs() {
+        let sdp = "v=0\r\n\
+            m=video 0 RTP/AVP 97\r\n\
+            a=rtpmap:97 VP8/90000\r\n\
+            m=video 0 RTP/AVP 26\r\n";
+        let description = parse(sdp, "rtsp://camera/").unwrap();
+        assert_eq!(description.codec, Codec::Jpeg);
+        assert_eq!(description.media.payload_type, 26);
+
+        let sdp = "v=0\r\nm=video 0 RTP/AVP 97\r\na=rtpmap:97 VP8/90000\r\n";
+        assert!(parse(sdp, "rtsp://camera/").is_err());
+        assert!(parse("v=0\r\nm=audio 0 RTP/AVP 0\r\n", "rtsp://camera/").is_err());
+    }
+
+    #[test]
+    fn parses_frame_rates() {
+        assert_eq!(parse_frame_rate("25"), Some(FrameRate::frame_rate(25)));
+        assert_eq!(
+            parse_frame_rate("12.5"),
+            Some(FrameRate::new(125, NonZeroI32::new(10).unwrap()))
+        );
+        assert_eq!(parse_frame_rate("0"), None);
+        assert_eq!(parse_frame_rate("fast"), None);
+    }
+
+    #[test]
+    fn resolves_control_urls() {
+        assert_eq!(resolve("rtsp://camera/live", "*"), "rtsp://camera/live");
+        assert_eq!(resolve("rtsp://camera/live", ""), "rtsp://camera/live");
+        assert_eq!(
+            resolve("rtsp://camera/live", "track1"),
+            "rtsp://camera/live/track1"
+        );
+        assert_eq!(
+            resolve("rtsp://camera/live/", "track1"),
+            "rtsp://camera/live/track1"
+        );
+        assert_eq!(
+            resolve("rtsp://camera/live", "/other"),
+            "rtsp://camera/other"
+        );
+        assert_eq!(
+            resolve("rtsp://camera/live", "rtsp://proxy/x"),
+            "rtsp://proxy/x"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{
+    sdp::{self, Description},
+    RtspTransport,
+};
+use crate::backends::capture::network::{
+    connect, read_head, Authenticator, Buffered, Head, Interrupt, Url,
+};
+use std::{
+    io::{ErrorKind, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, UdpSocket},
+    time::{Duration, Instant},
+};
+
+/// How often a session is kept alive, unless the camera says how long it lasts without
+/// requests: half the default of a minute.
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// How long to wait for a UDP packet before checking whether the stream was stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The largest UDP packet.
+const MAX_DATAGRAM: usize = 65_536;
+
+/// A response to a request.
+struct Response {
+    head: Head,
+    body: Vec<u8>,
+}
+
+/// What the camera sends over the connection.
+enum Message {
+    Response(Response),
+    /// Data interleaved with the responses, on a channel set up with `SETUP`.
+    Interleaved {
+        channel: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// Where RTP packets arrive.
+enum Channel {
+    /// Interleaved on the connection, on this channel.
+    Interleaved(u8),
+    Udp {
+        rtp: UdpSocket,
+        /// Kept bound so the camera can send its reports, which are not read.
+        _rtcp: UdpSocket,
+        /// The address the camera sends from.
+        source: IpAddr,
+    },
+}
+
+/// An RTSP session playing one video stream, RFC 2326.
+pub(super) struct Session {
+    socket: TcpStream,
+    reader: Buffered<TcpStream>,
+    authenticator: Authenticator,
+    /// The `CSeq` of the last request.
+    sequence: u32,
+    /// The session identifier the camera gave in response to `SETUP`.
+    id: Option<String>,
+    /// The URL controlling the whole session.
+    control: String,
+    channel: Channel,
+    timeout: Duration,
+    /// How often the session is kept alive, and when it last was.
+    keepalive: Duration,
+    kept_alive: Instant,
+    last_packet: Instant,
+}
+
+impl Session {
+    /// Describes, sets up and plays the first supported video stream at `url`, trying
+    /// `transport` and falling back to UDP if the camera cannot interleave. `transport` is left
+    /// at what worked, and `authenticator` answers the challenges of the camera.
+    pub(super) fn play(
+        url: &Url,
+        authenticator: &mut Authenticator,
+        transport: &mut RtspTransport,
+        timeout: Duration,
+        interrupt: &Interrupt,
+    ) -> Result<(Self, Description), String> {
+        let socket = connect(url, timeout, interrupt)?;
+        let reader = Buffered::new(socket.try_clone().map_err(|why| why.to_string())?);
+        let mut session = Self {
+            socket,
+            reader,
+            authenticator: authenticator.clone(),
+            sequence: 0,
+            id: None,
+            control: url.to_string(),
+            channel: Channel::Interleaved(0),
+            timeout,
+            keepalive: DEFAULT_KEEPALIVE,
+            kept_alive: Instant::now(),
+            last_packet: Instant::now(),
+        };
+
+        let described = session.request(
+            "DESCRIBE",
+            &url.to_string(),
+            &[("Accept", "application/sdp")],
+        )?;
+        let base = described
+            .head
+            .header("content-base")
+            .or_else(|| described.head.header("content-location"))
+            .map_or_else(|| url.to_string(), str::to_string);
+        let description = sdp::parse(&String::from_utf8_lossy(&described.body), &base)?;
+        session.control.clone_from(&description.control);
+
+        session.setup(&description.media.control, transport)?;
+        session.request("PLAY", &session.control.clone(), &[("Range", "npt=0.000-")])?;
+        session.kept_alive = Instant::now();
+        session.last_packet = Instant::now();
+
+        *authenticator = session.authenticator.clone();
+        Ok((session, description))
+    }
+
+    /// Sets the stream up, over TCP first if asked to.
+    fn setup(&mut self, control: &str, transport: &mut RtspTransport) -> Result<(), String> {
+        if *transport == RtspTransport::Tcp {
+            let response = self.exchange(
+                "SETUP",
+                control,
+                &[("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1")],
+            )?;
+            // 461 Unsupported Transport: the camera only streams over UDP.
+            if response.head.status != 461 {
+                let response = success("SETUP", response)?;
+                let channel = response
+                    .head
+                    .header("transport")
+                    .and_then(|transport| parameter(transport, "interleaved"))
+                    .and_then(|channels| channels.split('-').next()?.parse().ok())
+                    .unwrap_or(0);
+                self.channel = Channel::Interleaved(channel);
+                self.start(&response.head);
+                return Ok(());
+            }
+            *transport = RtspTransport::Udp;
+        }
+
+        let peer = self.socket.peer_addr().map_err(|why| why.to_string())?.ip();
+        let (packets, reports) = bind_pair(peer)?;
+        let port = packets.local_addr().map_err(|why| why.to_string())?.port();
+        let response = self.request(
+            "SETUP",
+            control,
+            &[(
+                "Transport",
+                &format!("RTP/AVP;unicast;client_port={port}-{}", port + 1),
+            )],
+        )?;
+        let source = response
+            .head
+            .header("transport")
+            .and_then(|transport| parameter(transport, "source"))
+            .and_then(|source| source.parse().ok())
+            .unwrap_or(peer);
+        packets
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|why| why.to_string())?;
+        self.channel = Channel::Udp {
+            rtp: packets,
+            _rtcp: reports,
+            source,
+        };
+        self.start(&response.head);
+        Ok(())
+    }
+
+    /// Takes up the session identifier and timeout of a `SETUP` response.
+    fn start(&mut self, head: &Head) {
+        let Some(session) = head.header("session") else {
+            return;
+        };
+        let mut fields = session.split(';');
+        self.id = fields.next().map(|id| id.trim().to_string());
+        self.keepalive = fields
+            .filter_map(|field| field.trim().split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("timeout"))
+            .and_then(|(_, seconds)| seconds.trim().parse::<u64>().ok())
+            .map_or(DEFAULT_KEEPALIVE, |seconds| {
+                Duration::from_secs(seconds / 2).max(Duration::from_secs(1))
+            });
+    }
+
+    /// Waits for the next RTP packet. Over UDP, returns `None` after a short while without one,
+    /// so that the caller can check whether it should stop.
+    ///
+    /// Fails when the connection is lost, or no packet arrives within the timeout.
+    pub(super) fn next_packet(&mut self) -> Result<Option<Vec<u8>>, String> {
+        if self.kept_alive.elapsed() >= self.keepalive {
+            self.keep_alive()?;
+        }
+
+        match &self.channel {
+            &Channel::Interleaved(rtp) => loop {
+                // Responses to keepalives and RTCP reports are skipped.
+                if let Message::Interleaved { channel, data } = self.read_message()? {
+                    if channel == rtp {
+                        return Ok(Some(data));
+                    }
+                }
+            },
+            Channel::Udp { rtp, source, .. } => {
+                let mut packet = vec![0; MAX_DATAGRAM];
+                match rtp.recv_from(&mut packet) {
+                    Ok((length, from)) if from.ip() == *source => {
+                        self.last_packet = Instant::now();
+                        packet.truncate(length);
+                        return Ok(Some(packet));
+                    }
+                    Ok(_) => {}
+                    Err(why)
+                        if matches!(why.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(why) => return Err(why.to_string()),
+                }
+                if self.last_packet.elapsed() > self.timeout {
+                    return Err(format!("no packets for {:?}", self.timeout));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Keeps the session from timing out on the camera with an `OPTIONS` request.
+    fn keep_alive(&mut self) -> Result<(), String> {
+        self.kept_alive = Instant::now();
+        let control = self.control.clone();
+        match self.channel {
+            // The response comes between packets, and is skipped when reading them.
+            Channel::Interleaved(_) => self.send("OPTIONS", &control, &[]).map(|_| ()),
+            Channel::Udp { .. } => self.request("OPTIONS", &control, &[]).map(|_| ()),
+        }
+    }
+
+    /// Ends the session on the camera, without waiting for its response.
+    pub(super) fn teardown(mut self) {
+        let control = self.control.clone();
+        let _ = self.send("TEARDOWN", &control, &[]);
+    }
+
+    /// Sends a request and reads its response, which must be successful.
+    fn request(
+        &mut self,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Response, String> {
+        let response = self.exchange(method, uri, headers)?;
+        if response.head.status == 401 && !self.authenticator.has_credentials() {
+            return Err(format!(
+                "{method}: 401 Unauthorized, the camera needs a user name and password"
+            ));
+        }
+        success(method, response)
+    }
+
+    /// Sends a request and reads its response, answering an authentication challenge once.
+    fn exchange(
+        &mut self,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<Response, String> {
+        let mut challenged = false;
+        loop {
+            let sequence = self.send(method, uri, headers)?;
+            let response = loop {
+                if let Message::Response(response) = self.read_message()? {
+                    let answers = response
+                        .head
+                        .header("cseq")
+                        .and_then(|cseq| cseq.trim().parse::<u32>().ok());
+                    // Responses to earlier requests, like keepalives, are skipped.
+                    if answers.is_none() || answers == Some(sequence) {
+                        break response;
+                    }
+                }
+            };
+            if response.head.status == 401
+                && !challenged
+                && self.authenticator.challenged(&response.head.headers)
+            {
+                challenged = true;
+                continue;
+            }
+            return Ok(response);
+        }
+    }
+
+    /// Sends a request, returning its `CSeq`.
+    fn send(&mut self, method: &str, uri: &str, headers: &[(&str, &str)]) -> Result<u32, String> {
+        self.sequence += 1;
+        let mut request = format!(
+            "{method} {uri} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: nokhwa/{}\r\n",
+            self.sequence,
+            env!("CARGO_PKG_VERSION"),
+        );
+        let session = self.id.as_deref().map(|id| ("Session", id));
+        let authorization = self.authenticator.authorization(method, uri);
+        let authorization = authorization
+            .as_deref()
+            .map(|authorization| ("Authorization", authorization));
+        for (name, value) in headers.iter().copied().chain(session).chain(authorization) {
+            request.push_str(name);
+            request.push_str(": ");
+            request.push_str(value);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        self.socket
+            .write_all(request.as_bytes())
+            .map_err(|why| why.to_string())?;
+        Ok(self.sequence)
+    }
+
+    /// Reads the next response or interleaved packet off the connection.
+    fn read_message(&mut self) -> Result<Message, String> {
+        read_message(&mut self.reader).map_err(|why| why.to_string())
+    }
+}
+
+fn read_message(reader: &mut Buffered<TcpStream>) -> std::io::Result<Message> {
+    if reader.peek()? == b'$' {
+        let header = reader.read_vec(4)?;
+        let length = usize::from(u16::from_be_bytes([header[2], header[3]]));
+        return Ok(Message::Interleaved {
+            channel: header[1],
+            data: reader.read_vec(length)?,
+        });
+    }
+    let head = read_head(reader, "RTSP")?;
+    let length = head
+        .header("content-length")
+        .and_then(|length| length.trim().parse().ok())
+        .unwrap_or(0);
+    let body = reader.read_vec(length)?;
+    Ok(Message::Response(Response { head, body }))
+}
+
+/// The response, if it is successful.
+fn success(method: &str, response: Response) -> Result<Response, String> {
+    match response.head.status {
+        200..=299 => Ok(response),
+        status => Err(format!("{method}: {status} {}", response.head.reason)),
+    }
+}
+
+/// The value of the `name` parameter of a `Transport` header.
+fn parameter<'a>(transport: &'a str, name: &str) -> Option<&'a str> {
+    transport.split(';').find_map(|field| {
+        let (key, value) = field.split_once('=')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Binds the even and odd pair of UDP ports RTP and RTCP are received on, in the address family
+/// of `peer`.
+fn bind_pair(peer: IpAddr) -> Result<(UdpSocket, UdpSocket), String> {
+    let any = match peer {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    for _ in 0..16 {
+        let rtp = UdpSocket::bind((any, 0)).map_err(|why| why.to_string())?;
+        let port = rtp.local_addr().map_err(|why| why.to_string())?.port();
+        if port % 2 != 0 || port == u16::MAX {
+            continue;
+        }
+        if let Ok(rtcp) = UdpSocket::bind((any, port + 1)) {
+            return Ok((rtp, rtcp));
+        }
+    }
+    Err("no pair of UDP ports is free".to_string())
+}
@@ -0,0 +1,747 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A software camera that renders test patterns, for developing and testing without a device.
+
+use nokhwa_core::{
+    camera::{Camera, Capture, Setting},
+    encoder::{can_encode, encode_rgb888},
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    platform::{Backends, PlatformTrait},
+    properties::{
+        ControlBody, ControlFlags, ControlId, ControlType, ControlValue, ControlValueDescriptor,
+        Properties,
+    },
+    ranges::Range,
+    stream::{
+        events::{StopReason, StreamEvent},
+        frame_channel,
+        producer::{frame_interval, Pacer, Producer, ProducerStream},
+        BackpressurePolicy, FrameSender, Stream,
+    },
+    types::{CameraFormat, CameraIndex, CameraInformation, FrameRate, Resolution},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::AtomicBool, Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Selects the [`SyntheticPattern`], as its index in [`SyntheticPattern::ALL`].
+pub const SYNTHETIC_PATTERN_CONTROL: ControlId = ControlId::PlatformSpecific(0);
+/// Turns the burned-in frame counter on or off.
+pub const SYNTHETIC_COUNTER_CONTROL: ControlId = ControlId::PlatformSpecific(1);
+
+const RESOLUTIONS: [Resolution; 4] = [
+    Resolution::new(320, 240),
+    Resolution::new(640, 480),
+    Resolution::new(1280, 720),
+    Resolution::new(1920, 1080),
+];
+const FRAME_RATES: [FrameRate; 3] = [
+    FrameRate::frame_rate(15),
+    FrameRate::frame_rate(30),
+    FrameRate::frame_rate(60),
+];
+
+/// The picture a [`SyntheticCamera`] renders.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub enum SyntheticPattern {
+    /// SMPTE color bars.
+    #[default]
+    ColorBars,
+    /// Red increasing to the right and green increasing downwards, with blue pulsing over time.
+    Gradient,
+    /// A white box bouncing around a gray background.
+    MovingBox,
+}
+
+impl SyntheticPattern {
+    pub const ALL: &'static [SyntheticPattern] = &[
+        SyntheticPattern::ColorBars,
+        SyntheticPattern::Gradient,
+        SyntheticPattern::MovingBox,
+    ];
+
+    fn control_value(self) -> i64 {
+        match self {
+            SyntheticPattern::ColorBars => 0,
+            SyntheticPattern::Gradient => 1,
+            SyntheticPattern::MovingBox => 2,
+        }
+    }
+}
+
+/// What the generator draws, shared between the camera and its stream so that controls apply to
+/// a running stream.
+#[derive(Copy, Clone, Debug)]
+struct Picture {
+    format: CameraFormat,
+    pattern: SyntheticPattern,
+    counter: bool,
+    brightness: i64,
+}
+
+/// A software camera that renders test patterns at its configured format.
+///
+/// Frames can be produced in any [`FrameFormat`] the [`encoder`](nokhwa_core::encoder) supports,
+/// at any resolution and frame rate. Every frame can carry a burned-in frame counter, which makes
+/// dropped or reordered frames easy to spot.
+///
+/// The camera has fake controls:
+/// - [`ControlId::ExposureBias`] offsets the brightness of the picture, from -128 to 127.
+/// - [`SYNTHETIC_PATTERN_CONTROL`] selects the [`SyntheticPattern`].
+/// - [`SYNTHETIC_COUNTER_CONTROL`] turns the frame counter on or off.
+///
+/// Changing the format or a control while streaming takes effect on the next frame, a format
+/// change is reported as [`StreamEvent::FormatChanged`].
+pub struct SyntheticCamera {
+    info: CameraInformation,
+    picture: Arc<Mutex<Picture>>,
+    properties: Properties,
+    generator: Option<Producer>,
+}
+
+impl SyntheticCamera {
+    /// Creates a camera streaming 640x480 YUYV at 30 frames per second.
+    #[must_use]
+    pub fn new(index: CameraIndex) -> Self {
+        let format = CameraFormat::new(
+            Resolution::new(640, 480),
+            FrameFormat::Yuyv422,
+            FrameRate::frame_rate(30),
+        );
+        Self::with_format(index, format)
+    }
+
+    /// Creates a camera streaming in `format`.
+    #[must_use]
+    pub fn with_format(index: CameraIndex, format: CameraFormat) -> Self {
+        let picture = Picture {
+            format,
+            pattern: SyntheticPattern::default(),
+            counter: true,
+            brightness: 0,
+        };
+
+        Self {
+            info: camera_information(index),
+            picture: Arc::new(Mutex::new(picture)),
+            properties: controls(),
+            generator: None,
+        }
+    }
+
+    /// The [`CameraInformation`] of this camera.
+    #[must_use]
+    pub fn info(&self) -> &CameraInformation {
+        &self.info
+    }
+
+    /// The format frames are rendered in.
+    #[must_use]
+    pub fn format(&self) -> CameraFormat {
+        self.picture().format
+    }
+
+    /// The pattern that is rendered.
+    #[must_use]
+    pub fn pattern(&self) -> SyntheticPattern {
+        self.picture().pattern
+    }
+
+    /// Renders the frame a stream would deliver as frame number `frame_number`, e.g. to compare
+    /// against in tests.
+    ///
+    /// # Errors
+    /// If the current format cannot be encoded.
+    pub fn render_frame(&self, frame_number: u64) -> NokhwaResult<FrameBuffer> {
+        render(&self.picture(), frame_number)
+    }
+
+    fn picture(&self) -> std::sync::MutexGuard<'_, Picture> {
+        self.picture.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Setting for SyntheticCamera {
+    fn enumerate_formats(&self) -> Result<Vec<CameraFormat>, NokhwaError> {
+        let mut formats = Vec::new();
+        for frame_format in encodable_formats() {
+            for resolution in RESOLUTIONS {
+                for frame_rate in FRAME_RATES {
+                    formats.push(CameraFormat::new(resolution, frame_format, frame_rate));
+                }
+            }
+        }
+        Ok(formats)
+    }
+
+    fn enumerate_resolution_and_frame_rates(
+        &self,
+        frame_format: FrameFormat,
+    ) -> Result<HashMap<Resolution, Vec<FrameRate>>, NokhwaError> {
+        if !can_encode(frame_format) {
+            return Ok(HashMap::new());
+        }
+        Ok(RESOLUTIONS
+            .into_iter()
+            .map(|resolution| (resolution, FRAME_RATES.to_vec()))
+            .collect())
+    }
+
+    fn set_format(&self, camera_format: CameraFormat) -> Result<(), NokhwaError> {
+        let error = |error: &str| NokhwaError::SetPropertyError {
+            property: "format".to_string(),
+            value: camera_format.to_string(),
+            error: error.to_string(),
+        };
+
+        if !can_encode(camera_format.format()) {
+            return Err(error("no encoder for this frame format"));
+        }
+        if camera_format.width() == 0 || camera_format.height() == 0 {
+            return Err(error("resolution is empty"));
+        }
+        if frame_interval(camera_format.frame_rate()).is_none() {
+            return Err(error("frame rate is not positive"));
+        }
+
+        self.picture().format = camera_format;
+        Ok(())
+    }
+
+    fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    fn set_property(
+        &mut self,
+        property: &ControlId,
+        value: ControlValue,
+    ) -> Result<(), NokhwaError> {
+        self.properties.set_control_value(property, value.clone())?;
+
+        let mut picture = self.picture();
+        match (property, value) {
+            (ControlId::ExposureBias, ControlValue::Integer(brightness)) => {
+                picture.brightness = brightness;
+            }
+            (&SYNTHETIC_PATTERN_CONTROL, ControlValue::Integer(index)) => {
+                if let Some(pattern) = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| SyntheticPattern::ALL.get(index))
+                {
+                    picture.pattern = *pattern;
+                }
+            }
+            (&SYNTHETIC_COUNTER_CONTROL, ControlValue::Boolean(counter)) => {
+                picture.counter = counter;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Capture for SyntheticCamera {
+    fn open_stream(&mut self) -> Result<Stream, NokhwaError> {
+        self.open_stream_with_policy(BackpressurePolicy::default())
+    }
+
+    fn open_stream_with_policy(
+        &mut self,
+        policy: BackpressurePolicy,
+    ) -> Result<Stream, NokhwaError> {
+        if self.generator.as_ref().is_some_and(Producer::is_running) {
+            return Err(NokhwaError::OpenStreamError(
+                "a stream is already open".to_string(),
+            ));
+        }
+
+        let (sender, receiver) = frame_channel(policy);
+        let picture = self.picture.clone();
+        let (generator, handle) = Producer::spawn("nokhwa-synthetic", move |running| {
+            generate(&sender, &picture, &running);
+        })?;
+        self.generator = Some(generator.clone());
+        Ok(Stream::new(Box::new(ProducerStream::new(
+            receiver, generator, handle,
+        ))))
+    }
+
+    fn close_stream(&mut self) -> Result<(), NokhwaError> {
+        if let Some(generator) = self.generator.take() {
+            generator.stop();
+        }
+        Ok(())
+    }
+}
+
+impl Camera for SyntheticCamera {}
+
+impl Drop for SyntheticCamera {
+    fn drop(&mut self) {
+        let _ = self.close_stream();
+    }
+}
+
+/// A [`PlatformTrait`] that lists a fixed number of [`SyntheticCamera`]s.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct SyntheticPlatform {
+    cameras: u32,
+}
+
+impl SyntheticPlatform {
+    /// Creates a platform with `cameras` cameras, at indices `0..cameras`.
+    #[must_use]
+    pub fn new(cameras: u32) -> Self {
+        Self { cameras }
+    }
+}
+
+impl Default for SyntheticPlatform {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl PlatformTrait for SyntheticPlatform {
+    const PLATFORM: Backends = Backends::Custom("synthetic");
+    type Camera = SyntheticCamera;
+
+    fn block_on_permission(&mut self) -> NokhwaResult<()> {
+        Ok(())
+    }
+
+    fn check_permission_given(&mut self) -> bool {
+        true
+    }
+
+    fn query(&mut self) -> NokhwaResult<Vec<CameraInformation>> {
+        Ok((0..self.cameras)
+            .map(|index| camera_information(CameraIndex::Index(index)))
+            .collect())
+    }
+
+    fn open(&mut self, index: &CameraIndex) -> NokhwaResult<Self::Camera> {
+        match index.as_index() {
+            Ok(number) if number < self.cameras => Ok(SyntheticCamera::new(index.clone())),
+            _ => Err(NokhwaError::OpenDeviceError(
+                index.to_string(),
+                "no such synthetic camera".to_string(),
+            )),
+        }
+    }
+}
+
+fn camera_information(index: CameraIndex) -> CameraInformation {
+    CameraInformation::new(
+        format!("Synthetic Camera {index}"),
+        "Software test pattern camera".to_string(),
+        format!("synthetic:{index}"),
+        index,
+    )
+}
+
+fn encodable_formats() -> impl Iterator<Item = FrameFormat> {
+    FrameFormat::ALL
+        .iter()
+        .copied()
+        .filter(|frame_format| can_encode(*frame_format))
+}
+
+fn controls() -> Properties {
+    let integer = |preferred, minimum, maximum| {
+        ControlBody::new(
+            ControlType::Integer,
+            HashSet::from([ControlFlags::Slider]),
+            ControlValueDescriptor::Integer(Range::new(
+                preferred,
+                Some(minimum),
+                Some(maximum),
+                Some(1),
+            )),
+            Some(ControlValue::Integer(preferred)),
+            Some(ControlValue::Integer(preferred)),
+        )
+    };
+    let last_pattern = SyntheticPattern::ALL
+        .iter()
+        .map(|pattern| pattern.control_value())
+        .max()
+        .unwrap_or_default();
+
+    Properties::new(HashMap::from([
+        (ControlId::ExposureBias, integer(0, -128, 127)),
+        (
+            SYNTHETIC_PATTERN_CONTROL,
+            integer(SyntheticPattern::default().control_value(), 0, last_pattern),
+        ),
+        (
+            SYNTHETIC_COUNTER_CONTROL,
+            ControlBody::new(
+                ControlType::BinaryMenu,
+                HashSet::new(),
+                ControlValueDescriptor::Boolean,
+                Some(ControlValue::Boolean(true)),
+                Some(ControlValue::Boolean(true)),
+            ),
+        ),
+    ]))
+}
+
+/// Renders frames at the frame rate of the current format until stopped or the stream is gone.
+fn generate(frames: &FrameSender, picture: &Mutex<Picture>, running: &AtomicBool) {
+    let current = || *picture.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let mut format = current().format;
+    frames.emit(StreamEvent::Started(format));
+
+    let mut frame_number = 0;
+    let mut pacer = Pacer::new();
+    while pacer.wait(running) && !frames.is_disconnected() {
+        let now = Instant::now();
+        let picture = current();
+        if picture.format != format {
+            format = picture.format;
+            frames.emit(StreamEvent::FormatChanged(format));
+        }
+        match render(&picture, frame_number) {
+            Ok(frame) => {
+                if frames.send(frame).is_err() {
+                    break;
+                }
+            }
+            Err(why) => {
+                frames.stop(StopReason::Error(why));
+                break;
+            }
+        }
+        frame_number += 1;
+
+        let interval = frame_interval(format.frame_rate()).unwrap_or(Duration::from_secs(1));
+        pacer.schedule(now, interval);
+    }
+
+    frames.stop(StopReason::Requested);
+}
+
+fn render(picture: &Picture, frame_number: u64) -> NokhwaResult<FrameBuffer> {
+    let format = picture.format;
+    let mut canvas = Canvas::new(format.width() as usize, format.height() as usize);
+    match picture.pattern {
+        SyntheticPattern::ColorBars => canvas.color_bars(),
+        SyntheticPattern::Gradient => canvas.gradient(frame_number),
+        SyntheticPattern::MovingBox => canvas.moving_box(frame_number),
+    }
+    if picture.counter {
+        canvas.counter(frame_number);
+    }
+    canvas.brighten(picture.brightness);
+
+    encode_rgb888(format.resolution(), &canvas.rgb, format.format())
+}
+
+/// 75% SMPTE color bars: gray, yellow, cyan, green, magenta, red, blue.
+const BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+/// The strip below the bars, the bars in reverse with black in between.
+const CASTELLATIONS: [[u8; 3]; 7] = [
+    [0, 0, 191],
+    [19, 19, 19],
+    [191, 0, 191],
+    [19, 19, 19],
+    [0, 191, 191],
+    [19, 19, 19],
+    [191, 191, 191],
+];
+
+/// 3x5 bitmaps of the digits, read from the top left, row by row.
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_010_010_010,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+/// A tightly packed RGB image being drawn.
+struct Canvas {
+    rgb: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            rgb: vec![0; width * height * 3],
+            width,
+            height,
+        }
+    }
+
+    /// Fills the rectangle at `x`, `y`, clipped to the canvas.
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        let (right, bottom) = ((x + width).min(self.width), (y + height).min(self.height));
+        for row in y..bottom {
+            for column in x..right {
+                let idx = (row * self.width + column) * 3;
+                self.rgb[idx..idx + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    fn color_bars(&mut self) {
+        let bars_bottom = self.height * 2 / 3;
+        let castellations_bottom = self.height * 3 / 4;
+
+        for x in 0..self.width {
+            let bar = x * 7 / self.width;
+            // The bottom strip is laid out in twelfths of a bar: -I, white, +Q, black, the three
+            // PLUGE steps and black.
+            let twelfth = x * 84 / self.width;
+            let bottom = match twelfth {
+                0..=14 => [0, 33, 76],
+                15..=29 => [255, 255, 255],
+                30..=44 => [50, 0, 106],
+                60..=63 => [9, 9, 9],
+                68..=71 => [29, 29, 29],
+                _ => [19, 19, 19],
+            };
+
+            self.fill(x, 0, 1, bars_bottom, BARS[bar]);
+            self.fill(
+                x,
+                bars_bottom,
+                1,
+                castellations_bottom - bars_bottom,
+                CASTELLATIONS[bar],
+            );
+            self.fill(
+                x,
+                castellations_bottom,
+                1,
+                self.height - castellations_bottom,
+                bottom,
+            );
+        }
+    }
+
+    fn gradient(&mut self, frame_number: u64) {
+        let blue = bounce(frame_number * 4, 255) as u8;
+        let (x_span, y_span) = (self.width.max(2) - 1, self.height.max(2) - 1);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let idx = (y * self.width + x) * 3;
+                self.rgb[idx] = (x * 255 / x_span) as u8;
+                self.rgb[idx + 1] = (y * 255 / y_span) as u8;
+                self.rgb[idx + 2] = blue;
+            }
+        }
+    }
+
+    fn moving_box(&mut self, frame_number: u64) {
+        self.fill(0, 0, self.width, self.height, [64, 64, 64]);
+
+        let size = (self.width.min(self.height) / 4).max(1);
+        let speed = (self.width / 160).max(1) as u64;
+        let x = bounce(frame_number * speed, (self.width - size) as u64);
+        let y = bounce(frame_number * speed * 3 / 4, (self.height - size) as u64);
+        self.fill(x as usize, y as usize, size, size, [255, 255, 255]);
+    }
+
+    /// Burns `frame_number` into the top left corner, in white on black.
+    fn counter(&mut self, frame_number: u64) {
+        let digits = frame_number.to_string();
+        let scale = (self.height / 120).max(1);
+        let (margin, advance) = (2 * scale, 4 * scale);
+
+        self.fill(
+            margin,
+            margin,
+            digits.len() * advance + scale,
+            7 * scale,
+            [0, 0, 0],
+        );
+        for (place, digit) in digits.bytes().enumerate() {
+            let bitmap = DIGITS[usize::from(digit - b'0')];
+            let left = margin + scale + place * advance;
+            for cell in 0..15 {
+                if bitmap & (1 << (14 - cell)) != 0 {
+                    let (column, row) = (cell % 3, cell / 3);
+                    self.fill(
+                        left + column * scale,
+                        margin + scale + row * scale,
+                        scale,
+                        scale,
+                        [255, 255, 255],
+                    );
+                }
+            }
+        }
+    }
+
+    fn brighten(&mut self, offset: i64) {
+        if offset == 0 {
+            return;
+        }
+        let offset = offset.clamp(-255, 255) as i16;
+        for value in &mut self.rgb {
+            *value = (i16::from(*value) + offset).clamp(0, 255) as u8;
+        }
+    }
+}
+
+/// Moves back and forth between 0 and `span` as `position` increases.
+fn bounce(position: u64, span: u64) -> u64 {
+    if span == 0 {
+        return 0;
+    }
+    let position = position % (2 * span);
+    if position <= span {
+        position
+    } else {
+        2 * span - position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(width: u32, height: u32) -> SyntheticCamera {
+        let format = CameraFormat::new(
+            Resolution::new(width, height),
+            FrameFormat::Rgb888,
+            FrameRate::frame_rate(60),
+        );
+        SyntheticCamera::with_format(CameraIndex::Index(0), format)
+    }
+
+    fn pixel(frame: &FrameBuffer, x: usize, y: usize) -> &[u8] {
+        let idx = (y * frame.resolution().width() as usize + x) * 3;
+        &frame.buffer()[idx..idx + 3]
+    }
+
+    #[test]
+    fn streams_the_frames_it_renders() {
+        let mut camera = camera(32, 16);
+        let stream = camera
+            .open_stream_with_policy(BackpressurePolicy::DropNewest(8))
+            .unwrap();
+        for frame_number in 0..3 {
+            let frame = stream.poll_frame().unwrap();
+            assert_eq!(frame.sequence(), frame_number);
+            assert_eq!(
+                frame.buffer(),
+                camera.render_frame(frame_number).unwrap().buffer()
+            );
+        }
+        assert!(matches!(
+            stream.events().try_recv(),
+            Ok(StreamEvent::Started(format)) if format == camera.format()
+        ));
+        stream.stop_stream().unwrap();
+    }
+
+    #[test]
+    fn format_changes_apply_to_a_running_stream() {
+        let mut camera = camera(32, 16);
+        let stream = camera
+            .open_stream_with_policy(BackpressurePolicy::LatestOnly)
+            .unwrap();
+        stream.poll_frame().unwrap();
+
+        let smaller = CameraFormat::new(
+            Resolution::new(16, 8),
+            FrameFormat::Rgb888,
+            FrameRate::frame_rate(60),
+        );
+        camera.set_format(smaller).unwrap();
+        let resized = (0..10)
+            .map(|_| stream.poll_frame().unwrap())
+            .find(|frame| frame.resolution() == smaller.resolution());
+        assert!(resized.is_some());
+        assert!(stream
+            .events()
+            .try_iter()
+            .any(|event| matches!(event, StreamEvent::FormatChanged(format) if format == smaller)));
+        stream.stop_stream().unwrap();
+    }
+
+    #[test]
+    fn controls_change_the_picture() {
+        let mut camera = camera(14, 6);
+        camera
+            .set_property(&SYNTHETIC_COUNTER_CONTROL, ControlValue::Boolean(false))
+            .unwrap();
+        assert_eq!(pixel(&camera.render_frame(0).unwrap(), 0, 0), [191; 3]);
+
+        camera
+            .set_property(&ControlId::ExposureBias, ControlValue::Integer(10))
+            .unwrap();
+        assert_eq!(pixel(&camera.render_frame(0).unwrap(), 0, 0), [201; 3]);
+
+        camera
+            .set_property(&SYNTHETIC_PATTERN_CONTROL, ControlValue::Integer(2))
+            .unwrap();
+        assert_eq!(camera.pattern(), SyntheticPattern::MovingBox);
+    }
+
+    #[test]
+    fn rejects_formats_it_cannot_produce() {
+        let camera = camera(32, 16);
+        let format = camera.format();
+        for rejected in [
+            CameraFormat::new(format.resolution(), FrameFormat::MJpeg, format.frame_rate()),
+            CameraFormat::new(Resolution::new(0, 16), format.format(), format.frame_rate()),
+            CameraFormat::new(
+                format.resolution(),
+                format.format(),
+                FrameRate::frame_rate(0),
+            ),
+        ] {
+            assert!(camera.set_format(rejected).is_err(), "{rejected}");
+        }
+        assert_eq!(camera.format(), format);
+    }
+
+    #[test]
+    fn bounces_between_the_ends() {
+        let positions = (0..10)
+            .map(|position| bounce(position, 4))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [0, 1, 2, 3, 4, 3, 2, 1, 0, 1]);
+        assert_eq!(bounce(7, 0), 0);
+    }
+}
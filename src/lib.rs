@@ -46,6 +46,10 @@ mod query;
 #[cfg_attr(feature = "docs-features", doc(cfg(feature = "output-threaded")))]
 pub mod threaded;
 
+#[cfg(feature = "output-http")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "output-http")))]
+pub mod mjpeg_server;
+
 pub use camera::Camera;
 pub use init::*;
 pub use nokhwa_core::frame_buffer::FrameBuffer;
@@ -54,6 +58,9 @@ pub use query::*;
 #[cfg(feature = "output-threaded")]
 #[cfg_attr(feature = "docs-features", doc(cfg(feature = "output-threaded")))]
 pub use threaded::CallbackCamera;
+#[cfg(feature = "output-http")]
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "output-http")))]
+pub use mjpeg_server::MjpegServer;
 
 pub mod utils {
     pub use nokhwa_core::types::*;
@@ -0,0 +1,748 @@
+/*
+ * Copyright 2022 l1npengtul <l1npengtul@protonmail.com> / The Nokhwa Contributors
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Serves a [`Stream`] as multipart MJPEG over HTTP, the format browsers, VLC and most network
+//! video recorders show as a live view.
+//!
+//! The stream is polled on a relay thread, which keeps only the latest frame as a JPEG. Every
+//! viewer has its own thread that sends the latest frame whenever it has finished sending the
+//! previous one, so a slow viewer skips frames instead of holding back the others.
+
+#[cfg(feature = "decoding-yuv")]
+use image::Rgb;
+use image::{codecs::jpeg::JpegEncoder, ExtendedColorType};
+#[cfg(feature = "decoding-yuv")]
+use nokhwa_core::decoder::{
+    yuv::{PlanarYuvDecoder, Yuv422Decoder},
+    StaticDecoder,
+};
+use nokhwa_core::{
+    error::{NokhwaError, NokhwaResult},
+    frame_buffer::FrameBuffer,
+    frame_format::FrameFormat,
+    stream::Stream,
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+/// The JPEG quality frames that are not already MJPEG are encoded with, by default.
+pub const DEFAULT_QUALITY: u8 = 80;
+
+/// How many clients are served at once, by default.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+
+const BOUNDARY: &str = "nokhwa-frame";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a client gets to send its request, and a viewer to take a frame.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a snapshot request waits for the first frame.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+
+/// Serves the frames of a [`Stream`] as multipart MJPEG (`multipart/x-mixed-replace`) on a
+/// local HTTP port, for remote monitoring.
+///
+/// The live view is served at `/`. A snapshot endpoint returning the latest frame as a single
+/// JPEG can be enabled with [`MjpegServer::set_snapshot_path`]. Each viewer is sent the latest
+/// frame as soon as it has taken the previous one, so a viewer on a slow link drops frames without
+/// slowing down the others.
+///
+/// Every client is served on its own thread, so at most [`MjpegServer::max_connections`] are
+/// served at once. Clients connecting beyond that are answered with `503 Service Unavailable`.
+///
+/// [`FrameFormat::MJpeg`] frames are served as they are. [`FrameFormat::Rgb888`],
+/// [`FrameFormat::RgbA8888`] and [`FrameFormat::Luma8`] frames are encoded to JPEG, and so are
+/// YUV frames if the `decoding-yuv` feature is enabled. Frames in any other format are skipped.
+///
+/// The server stops when [`MjpegServer::stop`] is called or it is dropped, which also stops the
+/// [`Stream`].
+#[cfg_attr(feature = "docs-features", doc(cfg(feature = "output-http")))]
+pub struct MjpegServer {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    relay: Option<JoinHandle<NokhwaResult<()>>>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl MjpegServer {
+    /// Starts serving `stream` on `address`, e.g. `"0.0.0.0:8080"`. Use port `0` to let the
+    /// operating system pick a free port, see [`MjpegServer::local_addr`].
+    ///
+    /// # Errors
+    /// If `address` cannot be bound or the server threads cannot be started, this will error.
+    pub fn bind(stream: Stream, address: impl ToSocketAddrs) -> Result<Self, NokhwaError> {
+        let listener = TcpListener::bind(address)
+            .map_err(|why| NokhwaError::GeneralError(format!("failed to bind server: {why}")))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|why| NokhwaError::GeneralError(why.to_string()))?;
+        // Polled, so that the acceptor notices the server stopping.
+        listener
+            .set_nonblocking(true)
+            .map_err(|why| NokhwaError::GeneralError(why.to_string()))?;
+
+        let shared = Arc::new(Shared {
+            running: AtomicBool::new(true),
+            latest: Mutex::new(Latest::default()),
+            published: Condvar::new(),
+            snapshot_path: Mutex::new(None),
+            quality: AtomicU8::new(DEFAULT_QUALITY),
+            max_connections: AtomicUsize::new(DEFAULT_MAX_CONNECTIONS),
+            clients: Mutex::new(HashMap::new()),
+            next_client: AtomicU64::new(0),
+        });
+
+        let relay = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("nokhwa-mjpeg-server".to_string())
+                .spawn(move || relay(stream, &shared))
+                .map_err(|why| NokhwaError::GeneralError(why.to_string()))?
+        };
+        let acceptor = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("nokhwa-mjpeg-accept".to_string())
+                .spawn(move || accept(&listener, &shared))
+        };
+
+        let mut server = Self {
+            shared,
+            local_addr,
+            relay: Some(relay),
+            acceptor: None,
+        };
+        match acceptor {
+            Ok(acceptor) => server.acceptor = Some(acceptor),
+            Err(why) => {
+                let _ = server.shutdown();
+                return Err(NokhwaError::GeneralError(why.to_string()));
+            }
+        }
+        Ok(server)
+    }
+
+    /// The address the server is listening on.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The path of the snapshot endpoint, if it is enabled.
+    #[must_use]
+    pub fn snapshot_path(&self) -> Option<String> {
+        self.shared
+            .snapshot_path
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Serves the latest frame as a single JPEG at `path`, e.g. `/snapshot.jpg`, or disables the
+    /// snapshot endpoint if `None`. It is disabled by default.
+    pub fn set_snapshot_path(&self, path: Option<&str>) {
+        let path = path.map(|path| {
+            let path = path.split('?').next().unwrap_or_default();
+            if path.starts_with('/') {
+                path.to_string()
+            } else {
+                format!("/{path}")
+            }
+        });
+        *self
+            .shared
+            .snapshot_path
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = path;
+    }
+
+    /// The JPEG quality frames that are not already MJPEG are encoded with, from 1 to 100.
+    #[must_use]
+    pub fn quality(&self) -> u8 {
+        self.shared.quality.load(Ordering::Relaxed)
+    }
+
+    /// Sets the JPEG quality frames that are not already MJPEG are encoded with, by default
+    /// [`DEFAULT_QUALITY`]. It is clamped to 1 to 100.
+    pub fn set_quality(&self, quality: u8) {
+        self.shared
+            .quality
+            .store(quality.clamp(1, 100), Ordering::Relaxed);
+    }
+
+    /// How many clients are served at once, live viewers and snapshot requests alike.
+    #[must_use]
+    pub fn max_connections(&self) -> usize {
+        self.shared.max_connections.load(Ordering::Relaxed)
+    }
+
+    /// Sets how many clients are served at once, by default [`DEFAULT_MAX_CONNECTIONS`]. Clients
+    /// already being served are not disconnected when it is lowered.
+    pub fn set_max_connections(&self, max_connections: usize) {
+        self.shared
+            .max_connections
+            .store(max_connections, Ordering::Relaxed);
+    }
+
+    /// How many viewers are watching the live view.
+    #[must_use]
+    pub fn viewer_count(&self) -> usize {
+        self.shared
+            .clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|client| client.viewing)
+            .count()
+    }
+
+    /// Whether the stream is still delivering frames to the server.
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.shared.is_streaming()
+    }
+
+    /// Disconnects every viewer, stops listening and stops the stream.
+    ///
+    /// # Errors
+    /// The error stopping the stream failed with, if any.
+    pub fn stop(mut self) -> Result<(), NokhwaError> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), NokhwaError> {
+        {
+            // Stored under the lock, so no client waiting for a frame misses the wakeup.
+            let _latest = self
+                .shared
+                .latest
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            self.shared.running.store(false, Ordering::Release);
+        }
+        self.shared.published.notify_all();
+        // Unblocks clients stuck on a connection that does not send or take anything.
+        for client in self
+            .shared
+            .clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+        {
+            let _ = client.connection.shutdown(Shutdown::Both);
+        }
+
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        match self.relay.take() {
+            Some(relay) => relay.join().unwrap_or_else(|_| {
+                Err(NokhwaError::StreamShutdownError(
+                    "MJPEG server thread panicked".to_string(),
+                ))
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MjpegServer {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+struct Shared {
+    running: AtomicBool,
+    latest: Mutex<Latest>,
+    /// Notified whenever a frame is published, the stream ends or the server stops.
+    published: Condvar,
+    snapshot_path: Mutex<Option<String>>,
+    quality: AtomicU8,
+    max_connections: AtomicUsize,
+    clients: Mutex<HashMap<u64, Client>>,
+    next_client: AtomicU64,
+}
+
+impl Shared {
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Whether the server is running and the stream has not ended.
+    fn is_streaming(&self) -> bool {
+        self.is_running()
+            && !self
+                .latest
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .ended
+    }
+}
+
+#[derive(Default)]
+struct Latest {
+    jpeg: Option<Arc<[u8]>>,
+    /// Counts the frames published, so viewers can tell whether they have sent the latest.
+    sequence: u64,
+    ended: bool,
+}
+
+fn relay(stream: Stream, shared: &Shared) -> NokhwaResult<()> {
+    while shared.is_running() {
+        match stream.poll_frame_timeout(POLL_INTERVAL) {
+            Ok(frame) => {
+                // Frames that cannot be turned into a JPEG are skipped.
+                let Ok(jpeg) = encode(&frame, shared.quality.load(Ordering::Relaxed)) else {
+                    continue;
+                };
+                let mut latest = shared.latest.lock().unwrap_or_else(PoisonError::into_inner);
+                latest.jpeg = Some(jpeg);
+                latest.sequence += 1;
+                shared.published.notify_all();
+            }
+            Err(NokhwaError::FrameTimeoutError(_)) => {}
+            Err(_) => break,
+        }
+    }
+
+    shared
+        .latest
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .ended = true;
+    shared.published.notify_all();
+    // Stopped here rather than by dropping it, so the server can report why stopping failed.
+    stream.stop_stream()
+}
+
+fn accept(listener: &TcpListener, shared: &Arc<Shared>) {
+    let mut clients: Vec<JoinHandle<()>> = Vec::new();
+    while shared.is_running() {
+        clients.retain(|client| !client.is_finished());
+        match listener.accept() {
+            Ok((connection, _))
+                if clients.len() >= shared.max_connections.load(Ordering::Relaxed) =>
+            {
+                refuse(connection);
+            }
+            Ok((connection, _)) => {
+                let shared = shared.clone();
+                let client = std::thread::Builder::new()
+                    .name("nokhwa-mjpeg-client".to_string())
+                    .spawn(move || serve(connection, &shared));
+                if let Ok(client) = client {
+                    clients.push(client);
+                }
+            }
+            // Nothing to accept yet, or the process is out of file descriptors for now.
+            Err(_) => std::thread::sleep(POLL_INTERVAL),
+        }
+    }
+
+    for client in clients {
+        let _ = client.join();
+    }
+}
+
+/// Answers a client over the connection limit with `503 Service Unavailable`, on the accepting
+/// thread. The connection is left non-blocking, so a client that does not read cannot hold up
+/// accepting others.
+fn refuse(mut connection: TcpStream) {
+    if connection.set_nonblocking(true).is_err() {
+        return;
+    }
+    let _ = respond_status(
+        &mut connection,
+        "503 Service Unavailable",
+        "Retry-After: 1\r\n",
+    );
+    let _ = connection.shutdown(Shutdown::Write);
+    // Whatever part of the request arrived is read, so closing does not reset the connection
+    // before the client has read the answer.
+    let _ = io::copy(&mut connection.take(MAX_REQUEST_SIZE), &mut io::sink());
+}
+
+/// A connection to a client, kept so the server can disconnect it when stopping.
+struct Client {
+    connection: TcpStream,
+    viewing: bool,
+}
+
+fn serve(connection: TcpStream, shared: &Shared) {
+    let id = shared.next_client.fetch_add(1, Ordering::Relaxed);
+    {
+        let mut clients = shared
+            .clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Checked under the lock, so stopping cannot miss this connection.
+        if !shared.is_running() {
+            return;
+        }
+        let Ok(connection) = connection.try_clone() else {
+            return;
+        };
+        clients.insert(
+            id,
+            Client {
+                connection,
+                viewing: false,
+            },
+        );
+    }
+
+    // A failed request only concerns its own client.
+    let _ = respond(connection, id, shared);
+    shared
+        .clients
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&id);
+}
+
+fn respond(mut connection: TcpStream, id: u64, shared: &Shared) -> io::Result<()> {
+    // Accepted connections inherit non-blocking mode from the listener on some platforms.
+    connection.set_nonblocking(false)?;
+    connection.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    connection.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    connection.set_nodelay(true)?;
+
+    let Some((method, path)) = read_request(&connection)? else {
+        return Ok(());
+    };
+    if method != "GET" {
+        return respond_status(&mut connection, "405 Method Not Allowed", "Allow: GET\r\n");
+    }
+
+    let snapshot_path = shared
+        .snapshot_path
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    if snapshot_path.as_deref() == Some(path.as_str()) {
+        respond_snapshot(&mut connection, shared)
+    } else if path == "/" {
+        if let Some(client) = shared
+            .clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(&id)
+        {
+            client.viewing = true;
+        }
+        respond_live(&mut connection, shared)
+    } else {
+        respond_status(&mut connection, "404 Not Found", "")
+    }
+}
+
+/// Reads the head of a request, returning its method and path. Returns `None` if the client
+/// closed the connection or sent a head that is too large.
+fn read_request(connection: &TcpStream) -> io::Result<Option<(String, String)>> {
+    let mut reader = BufReader::new(connection.take(MAX_REQUEST_SIZE));
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 {
+        return Ok(None);
+    }
+    // The headers are not needed, but the whole head is read so the client is not reset when
+    // the connection closes with part of it still unread.
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        if header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let path = target.split('?').next().unwrap_or_default();
+    Ok(Some((method.to_string(), path.to_string())))
+}
+
+fn respond_live(connection: &mut TcpStream, shared: &Shared) -> io::Result<()> {
+    write!(
+        connection,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\n\
+         Cache-Control: no-cache, no-store\r\n\
+         Connection: close\r\n\r\n"
+    )?;
+
+    let mut sent = 0;
+    loop {
+        match latest_after(shared, sent, CLIENT_TIMEOUT) {
+            Some((jpeg, sequence)) => {
+                write!(
+                    connection,
+                    "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                    jpeg.len()
+                )?;
+                connection.write_all(&jpeg)?;
+                connection.write_all(b"\r\n")?;
+                sent = sequence;
+            }
+            // The stream stalled, keep waiting for it.
+            None if shared.is_streaming() => {}
+            None => return Ok(()),
+        }
+    }
+}
+
+fn respond_snapshot(connection: &mut TcpStream, shared: &Shared) -> io::Result<()> {
+    let Some((jpeg, _)) = latest_after(shared, 0, SNAPSHOT_TIMEOUT) else {
+        return respond_status(connection, "503 Service Unavailable", "");
+    };
+    write!(
+        connection,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: image/jpeg\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-cache, no-store\r\n\
+         Connection: close\r\n\r\n",
+        jpeg.len()
+    )?;
+    connection.write_all(&jpeg)
+}
+
+fn respond_status(connection: &mut TcpStream, status: &str, headers: &str) -> io::Result<()> {
+    write!(
+        connection,
+        "HTTP/1.1 {status}\r\n\
+         {headers}\
+         Content-Type: text/plain\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n\
+         {status}",
+        status.len()
+    )
+}
+
+/// Waits at most `timeout` for a frame newer than `sequence`, returning it and its sequence.
+/// Returns `None` if there is none by then, or the server stopped.
+fn latest_after(shared: &Shared, sequence: u64, timeout: Duration) -> Option<(Arc<[u8]>, u64)> {
+    let latest = shared.latest.lock().unwrap_or_else(PoisonError::into_inner);
+    let (latest, _) = shared
+        .published
+        .wait_timeout_while(latest, timeout, |latest| {
+            shared.is_running() && !latest.ended && latest.sequence <= sequence
+        })
+        .unwrap_or_else(PoisonError::into_inner);
+    if !shared.is_running() || latest.sequence <= sequence {
+        return None;
+    }
+    latest.jpeg.clone().map(|jpeg| (jpeg, latest.sequence))
+}
+
+fn encode(frame: &FrameBuffer, quality: u8) -> Result<Arc<[u8]>, NokhwaError> {
+    let (pixels, color) = match frame.source_frame_format() {
+        FrameFormat::MJpeg => return Ok(Arc::from(frame.buffer())),
+        FrameFormat::Rgb888 => (packed(frame), ExtendedColorType::Rgb8),
+        FrameFormat::Luma8 => (packed(frame), ExtendedColorType::L8),
+        // The JPEG encoder has no alpha channel.
+        FrameFormat::RgbA8888 => (
+            packed(frame)
+                .chunks_exact(4)
+                .flat_map(|pixel| &pixel[..3])
+                .copied()
+                .collect(),
+            ExtendedColorType::Rgb8,
+        ),
+        #[cfg(feature = "decoding-yuv")]
+        FrameFormat::Yuyv422 | FrameFormat::Uyvy422 | FrameFormat::Yvyu422 => (
+            Yuv422Decoder::<Rgb<u8>>::decode_static(frame)?.into_raw(),
+            ExtendedColorType::Rgb8,
+        ),
+        #[cfg(feature = "decoding-yuv")]
+        FrameFormat::Nv12
+        | FrameFormat::Nv21
+        | FrameFormat::I420
+        | FrameFormat::Yv12
+        | FrameFormat::Yvu9 => (
+            PlanarYuvDecoder::<Rgb<u8>>::decode_static(frame)?.into_raw(),
+            ExtendedColorType::Rgb8,
+        ),
+        format => {
+            return Err(NokhwaError::ConversionError(format!(
+                "cannot serve {format} frames as JPEG"
+            )))
+        }
+    };
+
+    let resolution = frame.resolution();
+    // The encoder panics on a buffer that does not fit the resolution.
+    let size = resolution.width() as usize
+        * resolution.height() as usize
+        * usize::from(color.channel_count());
+    if pixels.len() != size {
+        return Err(NokhwaError::ProcessFrameError {
+            src: frame.source_frame_format(),
+            destination: "JPEG".to_string(),
+            error: "frame does not fit resolution".to_string(),
+        });
+    }
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, quality)
+        .encode(&pixels, resolution.width(), resolution.height(), color)
+        .map_err(|why| NokhwaError::ProcessFrameError {
+            src: frame.source_frame_format(),
+            destination: "JPEG".to_string(),
+            error: why.to_string(),
+        })?;
+    Ok(jpeg.into())
+}
+
+/// The pixels of a single plane frame, without any row padding.
+fn packed(frame: &FrameBuffer) -> Vec<u8> {
+    frame.plane_rows(0).collect::<Vec<_>>().concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nokhwa_core::{
+        stream::{frame_channel, BackpressurePolicy, FrameReceiver, FrameSender, StreamInnerTrait},
+        types::Resolution,
+    };
+    use std::time::Instant;
+
+    const JPEG: [u8; 6] = [0xFF, 0xD8, 1, 2, 3, 0xFF];
+
+    struct Feed(FrameReceiver);
+
+    impl StreamInnerTrait for Feed {
+        fn receiver(&self) -> &FrameReceiver {
+            &self.0
+        }
+
+        fn stop(&mut self) -> NokhwaResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Serves a stream that delivers [`JPEG`] every few milliseconds.
+    fn server() -> MjpegServer {
+        let (sender, receiver) = frame_channel(BackpressurePolicy::default());
+        std::thread::spawn(move || feed(&sender));
+        MjpegServer::bind(Stream::new(Box::new(Feed(receiver))), "127.0.0.1:0").unwrap()
+    }
+
+    fn feed(sender: &FrameSender) {
+        let frame = FrameBuffer::from_vec(Resolution::new(8, 8), JPEG.to_vec(), FrameFormat::MJpeg);
+        while sender.send(frame.clone()).is_ok() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Requests `path`, returning the connection with the head of the response read.
+    fn get(server: &MjpegServer, path: &str) -> (BufReader<TcpStream>, String) {
+        let mut connection = TcpStream::connect(server.local_addr()).unwrap();
+        connection.set_read_timeout(Some(SNAPSHOT_TIMEOUT)).unwrap();
+        write!(connection, "GET {path} HTTP/1.1\r\nHost: camera\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(connection);
+        let head = read_head(&mut reader);
+        (reader, head)
+    }
+
+    fn read_head(reader: &mut BufReader<TcpStream>) -> String {
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            assert_ne!(reader.read_line(&mut head).unwrap(), 0, "{head}");
+        }
+        head
+    }
+
+    fn content_length(head: &str) -> usize {
+        head.lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn serves_the_live_view() {
+        let server = server();
+        let (mut reader, head) = get(&server, "/");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("multipart/x-mixed-replace; boundary={BOUNDARY}")));
+
+        for _ in 0..2 {
+            let part = read_head(&mut reader);
+            assert!(part.starts_with(&format!("--{BOUNDARY}\r\n")));
+            assert!(part.contains("Content-Type: image/jpeg\r\n"));
+            let mut jpeg = vec![0; content_length(&part) + 2];
+            reader.read_exact(&mut jpeg).unwrap();
+            assert_eq!(jpeg, [&JPEG[..], b"\r\n"].concat());
+        }
+        assert_eq!(server.viewer_count(), 1);
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn serves_snapshots() {
+        let server = server();
+        let (_, head) = get(&server, "/snapshot.jpg");
+        assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        server.set_snapshot_path(Some("snapshot.jpg"));
+        let (mut reader, head) = get(&server, "/snapshot.jpg?size=full");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: image/jpeg\r\n"));
+        let mut jpeg = Vec::new();
+        reader.read_to_end(&mut jpeg).unwrap();
+        assert_eq!(jpeg.len(), content_length(&head));
+        assert_eq!(jpeg, JPEG);
+    }
+
+    #[test]
+    fn refuses_clients_over_the_limit() {
+        let server = server();
+        server.set_max_connections(1);
+        let (viewer, head) = get(&server, "/");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let (_, head) = get(&server, "/");
+        assert!(head.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // Served again once the viewer has left.
+        drop(viewer);
+        let start = Instant::now();
+        loop {
+            let (_, head) = get(&server, "/");
+            if head.starts_with("HTTP/1.1 200 OK\r\n") {
+                break;
+            }
+            assert!(start.elapsed() < SNAPSHOT_TIMEOUT, "{head}");
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}